@@ -0,0 +1,211 @@
+use crate::vars;
+use anyhow::Context;
+use axum::extract::Path;
+use axum_server::tls_rustls::RustlsConfig;
+use http::StatusCode;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus,
+};
+use log::{error, info};
+use rcgen::{CertificateParams, DistinguishedName, KeyPair};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+// Renew the certificate when it's older than 60 days (Let's Encrypt issues 90-day certificates)
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 60 * 24 * 60);
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60 * 12);
+
+// Pending HTTP-01 challenges, token -> key authorization
+static CHALLENGES: LazyLock<Mutex<HashMap<String, String>>> = LazyLock::new(Default::default);
+
+pub fn cert_path() -> PathBuf {
+    PathBuf::from(vars::acme_dir()).join("cert.pem")
+}
+
+pub fn key_path() -> PathBuf {
+    PathBuf::from(vars::acme_dir()).join("key.pem")
+}
+
+fn account_path() -> PathBuf {
+    PathBuf::from(vars::acme_dir()).join("account.json")
+}
+
+// Answer `/.well-known/acme-challenge/{token}`
+pub async fn challenge_handler(Path(token): Path<String>) -> Result<String, StatusCode> {
+    let challenges = CHALLENGES.lock().unwrap_or_else(|e| e.into_inner());
+
+    challenges.get(&token).cloned().ok_or(StatusCode::NOT_FOUND)
+}
+
+// Check if the stored certificate is missing or due for renewal
+pub fn needs_renewal() -> bool {
+    std::fs::metadata(cert_path())
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| modified.elapsed().unwrap_or_default() >= RENEW_AFTER)
+        .unwrap_or(true)
+}
+
+// Renew the certificate in the background and reload it into the running server
+pub async fn renew_periodically(config: RustlsConfig) {
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        if !needs_renewal() {
+            continue;
+        }
+
+        let result = match provision().await {
+            Ok(()) => config
+                .reload_from_pem_file(cert_path(), key_path())
+                .await
+                .context("failed to reload the certificate"),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(()) => info!("renewed the ACME certificate"),
+            Err(e) => error!("failed to renew the ACME certificate: {:?}", e),
+        }
+    }
+}
+
+// Issue a certificate for the configured domains through the HTTP-01 challenge
+pub async fn provision() -> anyhow::Result<()> {
+    std::fs::create_dir_all(vars::acme_dir())
+        .context(format!("failed to create `{}` directory", vars::acme_dir()))?;
+    let account = load_account().await?;
+    let identifiers: Vec<Identifier> = vars::acme_domains()
+        .iter()
+        .map(|domain| Identifier::Dns(domain.clone()))
+        .collect();
+    let mut order = account
+        .new_order(&NewOrder {
+            identifiers: &identifiers,
+        })
+        .await
+        .context("failed to create ACME order")?;
+
+    let mut ready_urls = vec![];
+    let mut tokens = vec![];
+    for authz in order
+        .authorizations()
+        .await
+        .context("failed to get ACME authorizations")?
+    {
+        match authz.status {
+            AuthorizationStatus::Pending => {}
+            AuthorizationStatus::Valid => continue,
+            status => anyhow::bail!("unexpected ACME authorization status: {:?}", status),
+        }
+        let challenge = authz
+            .challenges
+            .iter()
+            .find(|c| c.r#type == ChallengeType::Http01)
+            .context("no HTTP-01 challenge offered")?;
+        CHALLENGES.lock().unwrap_or_else(|e| e.into_inner()).insert(
+            challenge.token.clone(),
+            order.key_authorization(challenge).as_str().to_owned(),
+        );
+        tokens.push(challenge.token.clone());
+        ready_urls.push(challenge.url.clone());
+    }
+    for url in &ready_urls {
+        order
+            .set_challenge_ready(url)
+            .await
+            .context("failed to set ACME challenge ready")?;
+    }
+
+    let result = finish(&mut order).await;
+    let mut challenges = CHALLENGES.lock().unwrap_or_else(|e| e.into_inner());
+    for token in tokens {
+        challenges.remove(&token);
+    }
+
+    result
+}
+
+async fn finish(order: &mut instant_acme::Order) -> anyhow::Result<()> {
+    let mut delay = Duration::from_millis(500);
+    loop {
+        tokio::time::sleep(delay).await;
+        let state = order
+            .refresh()
+            .await
+            .context("failed to refresh ACME order")?;
+        match state.status {
+            OrderStatus::Ready => break,
+            OrderStatus::Invalid => anyhow::bail!("ACME order is invalid: {:?}", state.error),
+            _ if delay > Duration::from_secs(30) => anyhow::bail!("ACME order timed out"),
+            _ => delay *= 2,
+        }
+    }
+
+    let mut params =
+        CertificateParams::new(vars::acme_domains().clone()).context("invalid ACME domains")?;
+    params.distinguished_name = DistinguishedName::new();
+    let key_pair = KeyPair::generate().context("failed to generate key pair")?;
+    let csr = params
+        .serialize_request(&key_pair)
+        .context("failed to create CSR")?;
+    order
+        .finalize(csr.der())
+        .await
+        .context("failed to finalize ACME order")?;
+    let cert_chain = loop {
+        match order
+            .certificate()
+            .await
+            .context("failed to download certificate")?
+        {
+            Some(cert_chain) => break cert_chain,
+            None => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    };
+
+    std::fs::write(key_path(), key_pair.serialize_pem()).context("failed to save the key")?;
+    std::fs::write(cert_path(), cert_chain).context("failed to save the certificate")?;
+    info!("issued ACME certificate for {:?}", vars::acme_domains());
+
+    Ok(())
+}
+
+// Restore the stored account or register a new one
+async fn load_account() -> anyhow::Result<Account> {
+    if let Ok(content) = std::fs::read_to_string(account_path()) {
+        let credentials: AccountCredentials =
+            serde_json::from_str(&content).context("failed to parse ACME account")?;
+
+        return Account::from_credentials(credentials)
+            .await
+            .context("failed to restore ACME account");
+    }
+
+    let contact = format!("mailto:{}", vars::acme_email());
+    let contact: &[&str] = if vars::acme_email().is_empty() {
+        &[]
+    } else {
+        &[&contact]
+    };
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        vars::acme_directory_url(),
+        None,
+    )
+    .await
+    .context("failed to create ACME account")?;
+    std::fs::write(
+        account_path(),
+        serde_json::to_string(&credentials).context("failed to serialize ACME account")?,
+    )
+    .context("failed to save ACME account")?;
+
+    Ok(account)
+}
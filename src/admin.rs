@@ -0,0 +1,72 @@
+use crate::{metrics, rules};
+use anyhow::Context;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use log::info;
+use serde_json::json;
+
+// Serve the admin API on a separate address
+pub async fn serve(bind: &str) -> anyhow::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(|| async { metrics::render() }))
+        .route("/rules", get(list_rules))
+        .route("/rules/test", post(test_rules));
+    let listener = tokio::net::TcpListener::bind(bind)
+        .await
+        .context("failed to bind to admin address")?;
+
+    info!("admin listening on: http://{}", bind);
+
+    axum::serve(listener, app)
+        .await
+        .context("failed to run admin server")
+}
+
+// Active rules with the hit counters
+async fn list_rules() -> Json<serde_json::Value> {
+    let rules = rules::rules()
+        .iter()
+        .map(|compiled| {
+            json!({
+                "rule": compiled.rule,
+                "hits": metrics::value("miragend_rule_hits_total", &[("rule", &compiled.rule.name)]),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Json(json!({ "rules": rules }))
+}
+
+#[derive(serde::Deserialize)]
+struct TestRequest {
+    // Draft rules, the active rules are used if absent
+    rules: Option<Vec<rules::Rule>>,
+    request: rules::Subject,
+}
+
+// Test a sample request against the draft rules without activating them
+async fn test_rules(Json(input): Json<TestRequest>) -> Response {
+    let matched = match input.rules {
+        Some(draft) => match rules::compile(&draft) {
+            Ok(compiled) => rules::find_in(&compiled, &input.request).cloned(),
+            Err(e) => {
+                return (
+                    StatusCode::BAD_REQUEST,
+                    Json(json!({ "error": format!("{:#}", e) })),
+                )
+                    .into_response();
+            }
+        },
+        None => rules::find_in(rules::rules(), &input.request).cloned(),
+    };
+
+    Json(json!({
+        "matched": matched.as_ref().map(|rule| &rule.name),
+        "strategy": matched.as_ref().map(|rule| &rule.strategy),
+    }))
+    .into_response()
+}
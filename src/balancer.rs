@@ -0,0 +1,181 @@
+use crate::{config::mask_url, metrics, vars};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        LazyLock, Mutex,
+    },
+    time::Instant,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Balance {
+    // Always prefer the first healthy upstream
+    Failover,
+    RoundRobin,
+    LeastConnections,
+}
+
+#[derive(Default)]
+struct Upstream {
+    active: AtomicUsize,
+    unhealthy_since: Mutex<Option<Instant>>,
+}
+
+static UPSTREAMS: LazyLock<Vec<Upstream>> = LazyLock::new(|| {
+    vars::upstream_base_urls()
+        .iter()
+        .map(|_| Upstream::default())
+        .collect()
+});
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+// Decrease the active connections on drop
+pub struct Guard(usize);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        UPSTREAMS[self.0].active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Indexes of the upstreams in the order to try, unhealthy ones are the last resort
+pub fn order() -> Vec<usize> {
+    let unhealthy_secs = vars::upstream_unhealthy_secs();
+    let healthy: Vec<bool> = UPSTREAMS
+        .iter()
+        .map(|upstream| upstream.is_healthy(unhealthy_secs))
+        .collect();
+    let active: Vec<usize> = UPSTREAMS
+        .iter()
+        .map(|upstream| upstream.active.load(Ordering::Relaxed))
+        .collect();
+
+    order_of(
+        vars::upstream_balance(),
+        NEXT.fetch_add(1, Ordering::Relaxed),
+        &active,
+        &healthy,
+    )
+}
+
+fn order_of(balance: Balance, next: usize, active: &[usize], healthy: &[bool]) -> Vec<usize> {
+    let len = active.len();
+    let mut indexes: Vec<usize> = match balance {
+        Balance::Failover | Balance::LeastConnections => (0..len).collect(),
+        Balance::RoundRobin => (0..len).map(|i| (next + i) % len).collect(),
+    };
+    if balance == Balance::LeastConnections {
+        // Stable, ties keep the priority order
+        indexes.sort_by_key(|i| active[*i]);
+    }
+    indexes.sort_by_key(|i| !healthy[*i]);
+
+    indexes
+}
+
+pub fn acquire(index: usize) -> Guard {
+    UPSTREAMS[index].active.fetch_add(1, Ordering::Relaxed);
+
+    Guard(index)
+}
+
+// Report the result of a request, failed upstreams are excluded for a while
+pub fn report(index: usize, ok: bool) {
+    if UPSTREAMS[index].report(ok) {
+        metrics::inc(
+            "miragend_upstream_unhealthy_total",
+            &[("upstream", &mask_url(&vars::upstream_base_urls()[index]))],
+        );
+    }
+}
+
+impl Upstream {
+    // Record the result, `true` if the upstream just turned unhealthy
+    fn report(&self, ok: bool) -> bool {
+        let mut unhealthy_since = self
+            .unhealthy_since
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if ok {
+            *unhealthy_since = None;
+        } else if unhealthy_since.is_none() {
+            *unhealthy_since = Some(Instant::now());
+
+            return true;
+        }
+
+        false
+    }
+
+    // Failed upstreams are tried again after `unhealthy_secs`
+    fn is_healthy(&self, unhealthy_secs: u64) -> bool {
+        let unhealthy_since = self
+            .unhealthy_since
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+
+        unhealthy_since
+            .map(|since| since.elapsed().as_secs() >= unhealthy_secs)
+            .unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order() {
+        let active = [3, 0, 1];
+        let healthy = [true, true, true];
+        assert_eq!(order_of(Balance::Failover, 5, &active, &healthy), [0, 1, 2]);
+        assert_eq!(
+            order_of(Balance::RoundRobin, 0, &active, &healthy),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            order_of(Balance::RoundRobin, 4, &active, &healthy),
+            [1, 2, 0]
+        );
+        assert_eq!(
+            order_of(Balance::LeastConnections, 0, &active, &healthy),
+            [1, 2, 0]
+        );
+
+        let healthy = [true, false, true];
+        assert_eq!(order_of(Balance::Failover, 0, &active, &healthy), [0, 2, 1]);
+        assert_eq!(
+            order_of(Balance::LeastConnections, 0, &active, &healthy),
+            [2, 0, 1]
+        );
+    }
+
+    #[test]
+    fn test_health() {
+        let upstreams: Vec<Upstream> = (0..3).map(|_| Upstream::default()).collect();
+        let order = |unhealthy_secs: u64| {
+            let healthy: Vec<bool> = upstreams
+                .iter()
+                .map(|upstream| upstream.is_healthy(unhealthy_secs))
+                .collect();
+
+            order_of(Balance::Failover, 0, &[0, 0, 0], &healthy)
+        };
+        assert_eq!(order(60), [0, 1, 2]);
+
+        // The failed upstream is tried last, the next one takes its place
+        assert!(upstreams[0].report(false));
+        assert!(!upstreams[0].report(false));
+        assert!(!upstreams[0].is_healthy(60));
+        assert_eq!(order(60), [1, 2, 0]);
+        // Back in its place once the exclusion expires
+        assert!(upstreams[0].is_healthy(0));
+        assert_eq!(order(0), [0, 1, 2]);
+
+        // A success ends the exclusion
+        assert!(!upstreams[0].report(true));
+        assert!(upstreams[0].is_healthy(60));
+        assert_eq!(order(60), [0, 1, 2]);
+    }
+}
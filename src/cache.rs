@@ -0,0 +1,488 @@
+use crate::{classification::Classification, fetching, mappings::Mapping, metrics, vars};
+use http::{header, HeaderMap, HeaderName, StatusCode};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    sync::{LazyLock, Mutex},
+    time::Instant,
+};
+
+// Headers of the stored responses a `304 Not Modified` doesn't update
+const NOT_MODIFIED_KEPT_HEADERS: [HeaderName; 4] = [
+    header::CONTENT_LENGTH,
+    header::CONTENT_ENCODING,
+    header::CONTENT_TYPE,
+    header::TRANSFER_ENCODING,
+];
+
+static CACHE: LazyLock<Mutex<HashMap<Key, Entry>>> = LazyLock::new(Default::default);
+// Raw upstream responses for the conditional revalidation
+static UPSTREAM_CACHE: LazyLock<Mutex<HashMap<String, Entry>>> = LazyLock::new(Default::default);
+// Keys of the stale entries being refreshed in the background
+static REVALIDATING: LazyLock<Mutex<HashSet<Key>>> = LazyLock::new(Default::default);
+
+// Transformed responses are cached separately per variant
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub url: String,
+    pub strategy: &'static str,
+    pub classification: Classification,
+    pub mapping_version: String,
+    // The session-consistent pages are cached before the transformation
+    pub seeded: bool,
+}
+
+// Cached response with the entity tag of its body
+pub struct Cached {
+    pub resp: fetching::Response,
+    pub etag: String,
+}
+
+struct Entry {
+    resp: fetching::Response,
+    // Entity tag of the transformed body, empty for the raw upstream responses
+    etag: String,
+    inserted_at: Instant,
+    // Seconds the entry is kept after expiring
+    stale_secs: u64,
+    // For the LRU eviction
+    last_used: Instant,
+    // Values of the request headers named by the `Vary` of the response
+    vary: Vec<(String, String)>,
+}
+
+impl Entry {
+    fn cached(&self) -> Cached {
+        Cached {
+            resp: self.resp.clone(),
+            etag: self.etag.clone(),
+        }
+    }
+
+    // Check if the request is of the same variant as the stored one
+    fn matches(&self, req_headers: &HeaderMap) -> bool {
+        vary_values(&self.resp.headers, req_headers).as_ref() == Some(&self.vary)
+    }
+}
+
+impl Key {
+    pub fn new(
+        url: &str,
+        strategy: &'static str,
+        classification: Classification,
+        mapping: &Mapping,
+        seeded: bool,
+    ) -> Self {
+        Self {
+            url: url.to_owned(),
+            strategy,
+            classification,
+            mapping_version: mapping.version_id(),
+            seeded,
+        }
+    }
+}
+
+pub fn enabled() -> bool {
+    vars::cache_ttl_secs() > 0
+}
+
+// Check if the request asks to skip the cached response
+pub fn bypassed(req_headers: &HeaderMap) -> bool {
+    let header = vars::cache_bypass_header();
+
+    !header.is_empty() && req_headers.contains_key(header)
+}
+
+// Get the cached response (with the transformed body)
+pub fn get(key: &Key, req_headers: &HeaderMap) -> Option<Cached> {
+    if !enabled() {
+        return None;
+    }
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let resp = lookup(&mut cache, key, req_headers, vars::cache_ttl_secs());
+    let result = if resp.is_some() { "hit" } else { "miss" };
+    metrics::inc("miragend_cache_requests_total", &[("result", result)]);
+
+    resp
+}
+
+// Get the cached response regardless of its age, for the upstream outages
+pub fn get_stale(key: &Key, req_headers: &HeaderMap) -> Option<Cached> {
+    if !enabled() {
+        return None;
+    }
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+
+    lookup(&mut cache, key, req_headers, u64::MAX)
+}
+
+// Get the cached response expired for less than `stale_secs`
+pub fn get_stale_within(key: &Key, stale_secs: u64, req_headers: &HeaderMap) -> Option<Cached> {
+    if !enabled() || stale_secs == 0 {
+        return None;
+    }
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let resp = lookup(
+        &mut cache,
+        key,
+        req_headers,
+        vars::cache_ttl_secs() + stale_secs,
+    );
+    if resp.is_some() {
+        metrics::inc("miragend_cache_requests_total", &[("result", "stale")]);
+    }
+
+    resp
+}
+
+// The entry younger than `max_age_secs` of the same variant, the requests with credentials
+// are never served from the cache
+fn lookup(
+    cache: &mut HashMap<Key, Entry>,
+    key: &Key,
+    req_headers: &HeaderMap,
+    max_age_secs: u64,
+) -> Option<Cached> {
+    if !shared(req_headers) {
+        return None;
+    }
+
+    cache
+        .get_mut(key)
+        .filter(|entry| entry.inserted_at.elapsed().as_secs() < max_age_secs)
+        .filter(|entry| entry.matches(req_headers))
+        .map(|entry| {
+            entry.last_used = Instant::now();
+
+            entry.cached()
+        })
+}
+
+// Mark the key as being refreshed, `false` if a refresh is already running
+pub fn begin_revalidation(key: &Key) -> bool {
+    let mut revalidating = REVALIDATING.lock().unwrap_or_else(|e| e.into_inner());
+
+    revalidating.insert(key.clone())
+}
+
+pub fn end_revalidation(key: &Key) {
+    let mut revalidating = REVALIDATING.lock().unwrap_or_else(|e| e.into_inner());
+    revalidating.remove(key);
+}
+
+pub fn put(
+    key: Key,
+    resp: &fetching::Response,
+    body: &str,
+    etag: &str,
+    stale_secs: u64,
+    req_headers: &HeaderMap,
+) {
+    // Partial or error responses are not cached
+    if !enabled() || resp.status != StatusCode::OK {
+        return;
+    }
+    let Some(vary) = shareable(resp, req_headers) else {
+        return;
+    };
+    let max_size = vars::cache_max_size();
+    if body.len() as u64 > max_size {
+        return;
+    }
+
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    // Purge expired entries
+    cache.retain(|_, entry| {
+        entry.inserted_at.elapsed().as_secs() < vars::cache_ttl_secs() + entry.stale_secs
+    });
+    let now = Instant::now();
+    cache.insert(
+        key,
+        Entry {
+            resp: fetching::Response {
+                body: body.to_owned(),
+                ..resp.clone()
+            },
+            etag: etag.to_owned(),
+            inserted_at: now,
+            stale_secs,
+            last_used: now,
+            vary,
+        },
+    );
+
+    evict(&mut cache, max_size);
+}
+
+// Key of the raw upstream response, `None` if the request can't be revalidated
+pub fn upstream_key(url: &str, req_headers: &HeaderMap) -> Option<String> {
+    if !vars::upstream_revalidate()
+        || req_headers.contains_key(header::RANGE)
+        || req_headers.contains_key(header::AUTHORIZATION)
+    {
+        return None;
+    }
+    // The content may differ by cookies
+    let cookie = req_headers
+        .get(header::COOKIE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    Some(format!("{}\n{}", url, cookie))
+}
+
+// The unexpired response of the same variant as the request
+pub fn get_upstream(key: &str, req_headers: &HeaderMap) -> Option<fetching::Response> {
+    let mut cache = UPSTREAM_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .get_mut(key)
+        .filter(|entry| entry.inserted_at.elapsed().as_secs() < vars::upstream_cache_ttl_secs())
+        .filter(|entry| entry.matches(req_headers))
+        .map(|entry| {
+            entry.last_used = Instant::now();
+
+            entry.resp.clone()
+        })
+}
+
+// Only the responses with validators are kept, the revalidated ones are fresh again
+pub fn put_upstream(key: String, resp: &fetching::Response, req_headers: &HeaderMap) {
+    if resp.status != StatusCode::OK
+        || !(resp.headers.contains_key(header::ETAG)
+            || resp.headers.contains_key(header::LAST_MODIFIED))
+    {
+        return;
+    }
+    let Some(vary) = vary_values(&resp.headers, req_headers) else {
+        return;
+    };
+    let max_size = vars::upstream_cache_max_size();
+    if resp.body.len() as u64 > max_size {
+        return;
+    }
+
+    let mut cache = UPSTREAM_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .retain(|_, entry| entry.inserted_at.elapsed().as_secs() < vars::upstream_cache_ttl_secs());
+    let now = Instant::now();
+    cache.insert(
+        key,
+        Entry {
+            resp: resp.clone(),
+            etag: String::new(),
+            inserted_at: now,
+            stale_secs: 0,
+            last_used: now,
+            vary,
+        },
+    );
+    evict(&mut cache, max_size);
+}
+
+// Update the stored headers with those of the `304 Not Modified`
+pub fn merge_not_modified(stored: &mut HeaderMap, headers: &HeaderMap) {
+    for name in headers.keys() {
+        if NOT_MODIFIED_KEPT_HEADERS.contains(name) {
+            continue;
+        }
+        stored.remove(name);
+        for value in headers.get_all(name) {
+            stored.append(name, value.clone());
+        }
+    }
+}
+
+// Check if the request carries no credentials, the responses to those may be personalized
+fn shared(req_headers: &HeaderMap) -> bool {
+    !req_headers.contains_key(header::COOKIE) && !req_headers.contains_key(header::AUTHORIZATION)
+}
+
+// The `Vary` values of the response to keep for the other clients,
+// `None` if it's personalized or private
+fn shareable(resp: &fetching::Response, req_headers: &HeaderMap) -> Option<Vec<(String, String)>> {
+    let private = resp
+        .headers
+        .get_all(header::CACHE_CONTROL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|directive| directive.trim().to_ascii_lowercase())
+        .any(|directive| {
+            directive == "no-store" || directive == "private" || directive.starts_with("private=")
+        });
+    if !shared(req_headers) || private || resp.headers.contains_key(header::SET_COOKIE) {
+        return None;
+    }
+
+    vary_values(&resp.headers, req_headers)
+}
+
+// Values of the request headers named by `Vary`, `None` if the response varies by anything
+fn vary_values(resp_headers: &HeaderMap, req_headers: &HeaderMap) -> Option<Vec<(String, String)>> {
+    let mut values = vec![];
+    for vary in resp_headers.get_all(header::VARY) {
+        for name in vary.to_str().ok()?.split(',').map(str::trim) {
+            if name == "*" {
+                return None;
+            }
+            if name.is_empty() {
+                continue;
+            }
+            let name = name.to_ascii_lowercase();
+            let value = req_headers
+                .get_all(name.as_str())
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(", ");
+            values.push((name, value));
+        }
+    }
+
+    Some(values)
+}
+
+// Evict the least recently used entries until the total size fits
+fn evict<K: Clone + Eq + Hash>(cache: &mut HashMap<K, Entry>, max_size: u64) {
+    let mut size: u64 = cache
+        .values()
+        .map(|entry| entry.resp.body.len() as u64)
+        .sum();
+    while size > max_size {
+        let Some(key) = cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        if let Some(entry) = cache.remove(&key) {
+            size -= entry.resp.body.len() as u64;
+            metrics::inc("miragend_cache_evictions_total", &[]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+
+        headers
+    }
+
+    #[test]
+    fn test_vary_values() {
+        let req = headers(&[("accept-language", "en"), ("user-agent", "a")]);
+        assert_eq!(vary_values(&HeaderMap::new(), &req), Some(vec![]));
+        assert_eq!(
+            vary_values(&headers(&[("vary", "Accept-Language, Origin")]), &req),
+            Some(vec![
+                ("accept-language".to_owned(), "en".to_owned()),
+                ("origin".to_owned(), "".to_owned())
+            ])
+        );
+        assert_eq!(vary_values(&headers(&[("vary", "*")]), &req), None);
+    }
+
+    fn response(pairs: &[(&'static str, &'static str)]) -> fetching::Response {
+        fetching::Response {
+            status: StatusCode::OK,
+            headers: headers(pairs),
+            content_type: fetching::ContentType::Html,
+            body: "<p>Hello</p>".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_shareable() {
+        let req = headers(&[("accept-language", "en")]);
+        assert_eq!(shareable(&response(&[]), &req), Some(vec![]));
+        assert_eq!(
+            shareable(&response(&[]), &headers(&[("cookie", "session=a")])),
+            None
+        );
+        assert_eq!(
+            shareable(&response(&[]), &headers(&[("authorization", "Basic YQ==")])),
+            None
+        );
+        assert_eq!(
+            shareable(&response(&[("set-cookie", "session=a")]), &req),
+            None
+        );
+        assert_eq!(
+            shareable(&response(&[("cache-control", "max-age=60, Private")]), &req),
+            None
+        );
+        assert_eq!(
+            shareable(&response(&[("cache-control", "no-store")]), &req),
+            None
+        );
+        assert_eq!(shareable(&response(&[("vary", "*")]), &req), None);
+    }
+
+    #[test]
+    fn test_lookup() {
+        let key = Key {
+            url: "http://localhost/page".to_owned(),
+            strategy: "obfuscation",
+            classification: Classification::Bot,
+            mapping_version: "default".to_owned(),
+            seeded: false,
+        };
+        let resp = response(&[("vary", "Accept-Language")]);
+        let req = headers(&[("accept-language", "en")]);
+        let now = Instant::now();
+        let mut cache = HashMap::new();
+        cache.insert(
+            key.clone(),
+            Entry {
+                etag: String::new(),
+                inserted_at: now,
+                stale_secs: 0,
+                last_used: now,
+                vary: shareable(&resp, &req).unwrap(),
+                resp,
+            },
+        );
+
+        assert!(lookup(&mut cache, &key, &req, 60).is_some());
+        // The client with a session never gets the page of another client
+        let with_cookie = headers(&[("accept-language", "en"), ("cookie", "session=a")]);
+        assert!(lookup(&mut cache, &key, &with_cookie, 60).is_none());
+        let other_language = headers(&[("accept-language", "fr")]);
+        assert!(lookup(&mut cache, &key, &other_language, 60).is_none());
+        assert!(lookup(&mut cache, &key, &req, 0).is_none());
+    }
+
+    #[test]
+    fn test_merge_not_modified() {
+        let mut stored = headers(&[
+            ("content-type", "text/html; charset=utf-8"),
+            ("cache-control", "max-age=60"),
+            ("etag", "\"a\""),
+        ]);
+        merge_not_modified(
+            &mut stored,
+            &headers(&[
+                ("content-type", "text/plain"),
+                ("cache-control", "max-age=120"),
+                ("date", "Fri, 16 Oct 2026 00:00:00 GMT"),
+            ]),
+        );
+        assert_eq!(stored["content-type"], "text/html; charset=utf-8");
+        assert_eq!(stored["cache-control"], "max-age=120");
+        assert_eq!(stored["etag"], "\"a\"");
+        assert!(stored.contains_key("date"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::{config, rules::glob_match, strategies};
+use http::{header, HeaderMap, HeaderValue};
+use std::sync::LazyLock;
+
+// Overrides of the upstream `Cache-Control` from the `cache_control` section, the first match
+// applies
+static OVERRIDES: LazyLock<Vec<Override>> = LazyLock::new(|| {
+    let overrides: Vec<Override> = config::section("cache_control")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `cache_control` section")
+        .unwrap_or_default();
+    for item in &overrides {
+        if !item.value.is_empty() && HeaderValue::from_str(&item.value).is_err() {
+            panic!("invalid `Cache-Control` value `{}`", item.value);
+        }
+        for strategy in &item.strategies {
+            if strategies::name(strategy).is_none() {
+                panic!(
+                    "invalid strategy `{}` of the `Cache-Control` override of `{}`",
+                    strategy, item.path
+                );
+            }
+        }
+    }
+
+    overrides
+});
+
+#[derive(Debug, serde::Deserialize)]
+struct Override {
+    // Path pattern, `*` matches any characters
+    path: String,
+    // Served strategies, any if empty
+    #[serde(default)]
+    strategies: Vec<String>,
+    // An empty value strips the header
+    value: String,
+}
+
+pub fn force_init() {
+    LazyLock::force(&OVERRIDES);
+}
+
+// Replace `Cache-Control` and drop `Expires` by the first matching override
+pub fn apply(headers: &mut HeaderMap, path: &str, strategy: &str) {
+    apply_overrides(&OVERRIDES, headers, path, strategy);
+}
+
+fn apply_overrides(overrides: &[Override], headers: &mut HeaderMap, path: &str, strategy: &str) {
+    let Some(item) = overrides.iter().find(|item| {
+        glob_match(&item.path, path)
+            && (item.strategies.is_empty()
+                || item
+                    .strategies
+                    .iter()
+                    .any(|s| strategies::name(s) == Some(strategy)))
+    }) else {
+        return;
+    };
+    // `Expires` would still be honored by the HTTP/1.0 caches
+    headers.remove(header::EXPIRES);
+    match HeaderValue::from_str(&item.value) {
+        Ok(value) if !item.value.is_empty() => {
+            headers.insert(header::CACHE_CONTROL, value);
+        }
+        _ => {
+            headers.remove(header::CACHE_CONTROL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_overrides() {
+        let overrides = vec![
+            Override {
+                path: "*".to_owned(),
+                strategies: vec!["obfus".to_owned()],
+                value: "no-store".to_owned(),
+            },
+            Override {
+                path: "/static/*".to_owned(),
+                strategies: vec![],
+                value: "public, max-age=31536000".to_owned(),
+            },
+        ];
+        let upstream = || {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static("max-age=60"),
+            );
+            headers.insert(
+                header::EXPIRES,
+                HeaderValue::from_static("Thu, 01 Jan 2099 00:00:00 GMT"),
+            );
+            headers
+        };
+
+        let mut headers = upstream();
+        apply_overrides(&overrides, &mut headers, "/page", "obfuscation");
+        assert_eq!(headers[header::CACHE_CONTROL], "no-store");
+        assert!(headers.get(header::EXPIRES).is_none());
+
+        let mut headers = upstream();
+        apply_overrides(&overrides, &mut headers, "/static/a.css", "passthrough");
+        assert_eq!(headers[header::CACHE_CONTROL], "public, max-age=31536000");
+
+        let mut headers = upstream();
+        apply_overrides(&overrides, &mut headers, "/page", "passthrough");
+        assert_eq!(headers[header::CACHE_CONTROL], "max-age=60");
+        assert!(headers.get(header::EXPIRES).is_some());
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    headers,
+    vars::{self, CONTENT_TYPE_VALUE_TEXT_HTML},
+};
+use axum::body::Body;
+use hmac::{Hmac, Mac};
+use http::{header, HeaderMap, Response, StatusCode};
+use sha2::Sha256;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Cookie carrying the solved challenge
+pub const PASS_COOKIE: &str = "miragend_pass";
+// Cookie marking the client has received the challenge page
+pub const CHALLENGED_COOKIE: &str = "miragend_challenged";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Check if the request carries a valid pass cookie
+pub fn verify(req_headers: &HeaderMap) -> bool {
+    let Some(value) = headers::get_cookie(req_headers, PASS_COOKIE) else {
+        return false;
+    };
+    // Format: `{timestamp}.{signature}.{nonce}`
+    let mut parts = value.splitn(3, '.');
+    let (Some(timestamp), Some(signature), Some(nonce)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    let Ok(issued_at) = timestamp.parse::<u64>() else {
+        return false;
+    };
+    if issued_at + vars::challenge_ttl_secs() < now_secs() {
+        // Expired
+        return false;
+    }
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+    if build_mac(timestamp, user_agent(req_headers))
+        .verify_slice(&signature)
+        .is_err()
+    {
+        return false;
+    }
+    if nonce.parse::<u64>().is_err() {
+        return false;
+    }
+
+    fnv1a(value).leading_zeros() >= vars::challenge_difficulty()
+}
+
+// Check if the client has already received the challenge page
+pub fn is_challenged(req_headers: &HeaderMap) -> bool {
+    headers::get_cookie(req_headers, CHALLENGED_COOKIE).is_some()
+}
+
+// Build the challenge page containing the proof-of-work script
+pub fn build_resp(req_headers: &HeaderMap) -> Result<Response<Body>, http::Error> {
+    let timestamp = now_secs().to_string();
+    let signature = encode_hex(
+        &build_mac(&timestamp, user_agent(req_headers))
+            .finalize()
+            .into_bytes(),
+    );
+    let token = format!("{}.{}", timestamp, signature);
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, CONTENT_TYPE_VALUE_TEXT_HTML)
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(
+            header::SET_COOKIE,
+            format!("{}=1; Path=/; SameSite=Lax; HttpOnly", CHALLENGED_COOKIE),
+        )
+        .body(Body::from(build_page(&token)))
+}
+
+fn build_page(token: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Just a moment...</title></head>
+<body>
+<p>Checking your browser, please wait...</p>
+<noscript>Please enable JavaScript to continue.</noscript>
+<script>
+(function () {{
+  var token = "{token}", difficulty = {difficulty};
+  function fnv1a(s) {{
+    var h = 0x811c9dc5;
+    for (var i = 0; i < s.length; i++) {{
+      h ^= s.charCodeAt(i);
+      h = Math.imul(h, 0x01000193) >>> 0;
+    }}
+    return h >>> 0;
+  }}
+  var nonce = 0;
+  while (difficulty > 0 && fnv1a(token + "." + nonce) >>> (32 - difficulty) !== 0) {{
+    nonce++;
+  }}
+  document.cookie = "{cookie}=" + token + "." + nonce + "; path=/; max-age={ttl}; SameSite=Lax";
+  location.reload();
+}})();
+</script>
+</body>
+</html>
+"#,
+        token = token,
+        difficulty = vars::challenge_difficulty(),
+        cookie = PASS_COOKIE,
+        ttl = vars::challenge_ttl_secs(),
+    )
+}
+
+fn build_mac(timestamp: &str, user_agent: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(vars::challenge_secret().as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(timestamp.as_bytes());
+    mac.update(b"|");
+    mac.update(user_agent.as_bytes());
+
+    mac
+}
+
+fn user_agent(req_headers: &HeaderMap) -> &str {
+    req_headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+// 32-bit FNV-1a, the same algorithm is implemented by the challenge script
+fn fnv1a(text: &str) -> u32 {
+    text.bytes().fold(0x811c9dc5, |hash, b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut output, b| {
+        let _ = write!(output, "{:02x}", b);
+        output
+    })
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(""), 0x811c9dc5);
+        assert_eq!(fnv1a("a"), 0xe40c292c);
+        assert_eq!(fnv1a("foobar"), 0xbf9cf968);
+    }
+
+    #[test]
+    fn test_hex() {
+        let bytes = [0x00, 0x7f, 0xff, 0x10];
+        assert_eq!(encode_hex(&bytes), "007fff10");
+        assert_eq!(decode_hex("007fff10"), Some(bytes.to_vec()));
+        assert_eq!(decode_hex("0"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
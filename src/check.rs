@@ -0,0 +1,127 @@
+use crate::{
+    doctor::{finish, Outcome, Report},
+    mappings, patch_content, vars,
+};
+use std::{any::Any, panic, path::Path};
+
+// Validate the configuration offline, for the CI and deploy pipelines
+pub fn run() -> anyhow::Result<()> {
+    let mut report = Report::default();
+    // The invalid values panic during the initialization, report them instead
+    panic::set_hook(Box::new(|_| {}));
+
+    match guarded(vars::upstream_base_urls) {
+        Ok(urls) => {
+            for url in urls {
+                check_upstream_url(&mut report, url);
+            }
+        }
+        Err(message) => report.print(Outcome::Fail, "upstream url", message),
+    }
+
+    match guarded(|| {
+        mappings::force_init();
+        vars::obfuscator_config()
+    }) {
+        Ok(config) => report.print(
+            Outcome::Pass,
+            "obfuscation mapping",
+            format!(
+                "{} ranges loaded (version {})",
+                config.mappers.len(),
+                config.version
+            ),
+        ),
+        Err(message) => report.print(Outcome::Fail, "obfuscation mapping", message),
+    }
+
+    check_patch_content(&mut report, vars::patch_content_file());
+
+    match guarded(crate::check_config) {
+        Ok(Ok(())) => report.print(Outcome::Pass, "configuration", "all values are valid"),
+        Ok(Err(e)) => report.print(Outcome::Fail, "configuration", format!("{:#}", e)),
+        Err(message) => report.print(Outcome::Fail, "configuration", message),
+    }
+    let _ = panic::take_hook();
+
+    finish(report)
+}
+
+// Run the closure, turning a panic into its message
+fn guarded<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(panic::AssertUnwindSafe(f)).map_err(|e| panic_message(&*e))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown error".to_owned()
+    }
+}
+
+fn check_upstream_url(report: &mut Report, url: &str) {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if !["http", "https"].contains(&parsed.scheme()) => report.print(
+            Outcome::Fail,
+            "upstream url",
+            format!("`{}` must use `http` or `https`", url),
+        ),
+        // IP addresses are valid hosts too
+        Ok(parsed) if parsed.host().is_none() => report.print(
+            Outcome::Fail,
+            "upstream url",
+            format!("`{}` has no host", url),
+        ),
+        Ok(_) => report.print(Outcome::Pass, "upstream url", format!("`{}` is valid", url)),
+        Err(e) => report.print(
+            Outcome::Fail,
+            "upstream url",
+            format!("`{}` is invalid: {}", url, e),
+        ),
+    }
+}
+
+// The proxy falls back to the built-in content silently, so check the file here
+fn check_patch_content(report: &mut Report, file: &str) {
+    if file.is_empty() {
+        report.print(Outcome::Pass, "patch content", "using the built-in content");
+        return;
+    }
+    if patch_content::is_remote(file) {
+        match reqwest::Url::parse(file) {
+            Ok(_) => report.print(
+                Outcome::Pass,
+                "patch content",
+                format!("`{}` will be fetched on the first patched request", file),
+            ),
+            Err(e) => report.print(
+                Outcome::Fail,
+                "patch content",
+                format!("`{}` is invalid: {}", file, e),
+            ),
+        }
+        return;
+    }
+
+    match std::fs::read_to_string(Path::new(file)) {
+        Ok(content) if content.trim().is_empty() => report.print(
+            Outcome::Warn,
+            "patch content",
+            format!("`{}` is empty", file),
+        ),
+        Ok(_) if !(file.ends_with(".md") || file.ends_with(".html")) => report.print(
+            Outcome::Warn,
+            "patch content",
+            format!("`{}` will be inserted as plain text", file),
+        ),
+        Ok(_) => report.print(Outcome::Pass, "patch content", format!("`{}` loaded", file)),
+        Err(e) => report.print(
+            Outcome::Fail,
+            "patch content",
+            format!("failed to read `{}`: {}", file, e),
+        ),
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{challenge, honeypot, vars};
+use http::{header, HeaderMap};
+use std::net::IpAddr;
+
+// Classification bucket of the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::Display)]
+pub enum Classification {
+    // Passed the challenge, receives the original content
+    #[strum(serialize = "human-passthrough")]
+    Human,
+    // Receives the content transformed by the strategy
+    #[strum(serialize = "bot-obfuscated")]
+    Bot,
+    // Matched the crawler allowlist, receives the original content
+    #[strum(serialize = "crawler-allowlisted")]
+    Crawler,
+}
+
+impl Classification {
+    // Outbound bandwidth limit of the bucket, `None` means unlimited
+    pub fn bandwidth_limit(self) -> Option<u64> {
+        let name = self.to_string();
+        vars::bandwidth_limits()
+            .iter()
+            .find(|(classification, _)| *classification == name)
+            .map(|(_, bytes_per_sec)| *bytes_per_sec)
+            .filter(|bytes_per_sec| *bytes_per_sec > 0)
+    }
+}
+
+pub fn classify(req_headers: &HeaderMap, client_ip: IpAddr) -> Classification {
+    if honeypot::is_flagged(client_ip) {
+        // Followed a honeypot link
+        return Classification::Bot;
+    }
+
+    let user_agent = req_headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_lowercase();
+
+    if vars::crawler_allowlist()
+        .iter()
+        .any(|keyword| !keyword.is_empty() && user_agent.contains(keyword))
+    {
+        Classification::Crawler
+    } else if challenge::verify(req_headers) {
+        Classification::Human
+    } else {
+        Classification::Bot
+    }
+}
@@ -1,8 +1,206 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use miragend::config::DumpFormat;
+use std::net::IpAddr;
 
 #[derive(Debug, Parser)]
 #[command(
     version,
     about = "Reverse proxy for patching web pages and fighting AI bots"
 )]
-pub struct Args {}
+pub struct Args {
+    /// Path of the config file (TOML), overrides `MIRAGEND_CONFIG_FILE`
+    #[arg(short, long, global = true)]
+    pub config: Option<String>,
+    #[command(flatten)]
+    pub overrides: Overrides,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// Declare the flags overriding the `MIRAGEND_*` variables, `--foo-bar` sets `MIRAGEND_FOO_BAR`
+macro_rules! overrides {
+    ($($field:ident => $var:literal: $help:literal,)*) => {
+        #[derive(Debug, clap::Args)]
+        #[command(next_help_heading = "Configuration (overrides the environment variables and the config file)")]
+        pub struct Overrides {
+            $(
+                #[arg(long, global = true, value_name = "VALUE", help = $help)]
+                pub $field: Option<String>,
+            )*
+        }
+
+        impl Overrides {
+            // Must be called before any variable is read
+            pub fn apply(&self) {
+                $(
+                    if let Some(value) = &self.$field {
+                        std::env::set_var($var, value);
+                    }
+                )*
+            }
+        }
+    };
+}
+
+overrides! {
+    log => "MIRAGEND_LOG": "Log level and module filters, e.g. `warn,miragend::fetching=debug`",
+    access_log_file => "MIRAGEND_ACCESS_LOG_FILE": "Write the access log to the rotating file",
+    access_log_format => "MIRAGEND_ACCESS_LOG_FORMAT": "Access log format: `default` or `combined`",
+    access_log_max_size => "MIRAGEND_ACCESS_LOG_MAX_SIZE": "Rotate the access log beyond this size in bytes",
+    access_log_rotate => "MIRAGEND_ACCESS_LOG_ROTATE": "Rotate the access log `hourly`, `daily` or `never`",
+    access_log_keep => "MIRAGEND_ACCESS_LOG_KEEP": "Number of the rotated access logs to keep",
+    access_log_compress => "MIRAGEND_ACCESS_LOG_COMPRESS": "Compress the rotated access logs",
+    bind => "MIRAGEND_BIND": "Listener addresses, `address[=strategy],...`",
+    tls_cert => "MIRAGEND_TLS_CERT": "TLS certificate file",
+    tls_key => "MIRAGEND_TLS_KEY": "TLS private key file",
+    acme_domains => "MIRAGEND_ACME_DOMAINS": "Domains of the ACME certificate",
+    acme_email => "MIRAGEND_ACME_EMAIL": "Contact email of the ACME account",
+    acme_dir => "MIRAGEND_ACME_DIR": "Directory storing the ACME account and certificates",
+    acme_directory_url => "MIRAGEND_ACME_DIRECTORY_URL": "ACME directory URL",
+    acme_http_bind => "MIRAGEND_ACME_HTTP_BIND": "Address answering the ACME HTTP challenges",
+    admin_bind => "MIRAGEND_ADMIN_BIND": "Address of the admin API",
+    upstream => "MIRAGEND_UPSTREAM_BASE_URL": "Base URLs of the upstream, comma-separated",
+    upstream_balance => "MIRAGEND_UPSTREAM_BALANCE": "Balance of the upstreams: `failover`, `round-robin` or `least-connections`",
+    unknown_content => "MIRAGEND_UNKNOWN_CONTENT": "Answer of the upstream content neither HTML, JSON nor text: `passthrough`, `block` or `error`",
+    max_body_bytes => "MIRAGEND_MAX_BODY_BYTES": "Largest request or response body buffered",
+    upstream_unhealthy_secs => "MIRAGEND_UPSTREAM_UNHEALTHY_SECS": "Seconds a failed upstream is skipped",
+    upstream_ca_file => "MIRAGEND_UPSTREAM_CA_FILE": "CA certificate trusted for the upstream",
+    upstream_insecure => "MIRAGEND_UPSTREAM_INSECURE": "Skip the verification of the upstream certificate",
+    upstream_client_cert => "MIRAGEND_UPSTREAM_CLIENT_CERT": "Client certificate for the upstream",
+    upstream_client_key => "MIRAGEND_UPSTREAM_CLIENT_KEY": "Client key for the upstream",
+    upstream_resolve => "MIRAGEND_UPSTREAM_RESOLVE": "Resolve the upstream hosts, `host:port:address,...`",
+    upstream_headers => "MIRAGEND_UPSTREAM_HEADERS": "Extra headers sent upstream, `header=value,...`",
+    upstream_revalidate => "MIRAGEND_UPSTREAM_REVALIDATE": "Revalidate the cached upstream responses",
+    upstream_cache_ttl_secs => "MIRAGEND_UPSTREAM_CACHE_TTL_SECS": "Lifetime of the cached upstream responses",
+    upstream_cache_max_size => "MIRAGEND_UPSTREAM_CACHE_MAX_SIZE": "Maximum size of the cached upstream responses in bytes",
+    connect_timeout_secs => "MIRAGEND_CONNECT_TIMEOUT_SECS": "Timeout of connecting the upstream",
+    follow_redirects => "MIRAGEND_FOLLOW_REDIRECTS": "Follow the upstream redirects",
+    retry_count => "MIRAGEND_RETRY_COUNT": "Retries of the failed upstream requests",
+    retry_backoff_ms => "MIRAGEND_RETRY_BACKOFF_MS": "Backoff between the retries",
+    retry_statuses => "MIRAGEND_RETRY_STATUSES": "Upstream statuses to retry",
+    response_headers => "MIRAGEND_RESPONSE_HEADERS": "Extra response headers, `header=value,...`",
+    remove_response_headers => "MIRAGEND_REMOVE_RESPONSE_HEADERS": "Response headers to remove",
+    security_headers => "MIRAGEND_SECURITY_HEADERS": "Add the security headers missing from the responses",
+    hsts => "MIRAGEND_HSTS": "`Strict-Transport-Security` of the security headers, omitted if empty",
+    x_content_type_options => "MIRAGEND_X_CONTENT_TYPE_OPTIONS": "`X-Content-Type-Options` of the security headers, omitted if empty",
+    referrer_policy => "MIRAGEND_REFERRER_POLICY": "`Referrer-Policy` of the security headers, omitted if empty",
+    permissions_policy => "MIRAGEND_PERMISSIONS_POLICY": "`Permissions-Policy` of the security headers, omitted if empty",
+    decision_headers => "MIRAGEND_DECISION_HEADERS": "Headers exposing the decision, `header=template,...`",
+    trusted_proxies => "MIRAGEND_TRUSTED_PROXIES": "Proxies trusted to set `X-Forwarded-For`",
+    cookie_domain => "MIRAGEND_COOKIE_DOMAIN": "Domain of the cookies",
+    cookie_path => "MIRAGEND_COOKIE_PATH": "Path of the cookies",
+    cookie_secure => "MIRAGEND_COOKIE_SECURE": "Mark the cookies secure",
+    cookie_samesite => "MIRAGEND_COOKIE_SAMESITE": "SameSite of the cookies",
+    strategy => "MIRAGEND_STRATEGY": "Default strategy of the bots",
+    observe => "MIRAGEND_OBSERVE": "Only log the decisions, serve the original content",
+    patch_target => "MIRAGEND_PATCH_TARGET": "IDs or selectors of the patch target, tried in order",
+    patch_mode => "MIRAGEND_PATCH_MODE": "Patch mode: `replace`, `prepend`, `append`, `before` or `after`",
+    patch_content_file => "MIRAGEND_PATCH_CONTENT_FILE": "Patch content file or HTTP(S) URL (`.md`, `.html` or text)",
+    patch_content_ttl_secs => "MIRAGEND_PATCH_CONTENT_TTL_SECS": "Lifetime of the remote patch content",
+    patch_remove_nodes => "MIRAGEND_PATCH_REMOVE_NODES": "IDs or selectors of the nodes removed by the patch",
+    patch_remove_meta_tags => "MIRAGEND_PATCH_REMOVE_META_TAGS": "Meta tags removed by the patch",
+    obfuscation_mode => "MIRAGEND_OBFUSCATION_MODE": "Obfuscation mode: `char`, `markov`, `word`, `css-shuffle` or `fontmap`",
+    obfuscation_mapping_file => "MIRAGEND_OBFUSCATION_MAPPING_FILE": "Obfuscation mapping CSV files",
+    obfuscation_charset => "MIRAGEND_OBFUSCATION_CHARSET": "Built-in mapping sets",
+    obfuscation_ratio => "MIRAGEND_OBFUSCATION_RATIO": "Ratio of the obfuscated characters",
+    obfuscation_digits => "MIRAGEND_OBFUSCATION_DIGITS": "Obfuscate the digits: `false`, `true` or `magnitude`",
+    obfuscation_preserve_patterns => "MIRAGEND_OBFUSCATION_PRESERVE_PATTERNS": "Regexes of the text kept unchanged",
+    obfuscation_ignore_nodes => "MIRAGEND_OBFUSCATION_IGNORE_NODES": "Nodes not obfuscated",
+    obfuscation_ignore_after_node => "MIRAGEND_OBFUSCATION_IGNORE_AFTER_NODE": "ID of the node after which nothing is obfuscated",
+    obfuscation_ignore_title => "MIRAGEND_OBFUSCATION_IGNORE_TITLE": "Keep the title unchanged",
+    obfuscation_ignore_len => "MIRAGEND_OBFUSCATION_IGNORE_LEN": "Length of the leading text kept unchanged",
+    obfuscation_meta_tags => "MIRAGEND_OBFUSCATION_META_TAGS": "Meta tags obfuscated",
+    obfuscation_attributes => "MIRAGEND_OBFUSCATION_ATTRIBUTES": "Attributes obfuscated",
+    obfuscation_scramble_links => "MIRAGEND_OBFUSCATION_SCRAMBLE_LINKS": "Point the link targets at the honeypot trap URLs",
+    obfuscation_decoys => "MIRAGEND_OBFUSCATION_DECOYS": "Number of the decoy nodes",
+    fontmap_font => "MIRAGEND_FONTMAP_FONT": "Source font of the `fontmap` mode",
+    fontmap_path => "MIRAGEND_FONTMAP_PATH": "Path serving the scrambled font",
+    fontmap_fallback => "MIRAGEND_FONTMAP_FALLBACK": "Fallback font family of the `fontmap` mode",
+    mapping_version_header => "MIRAGEND_MAPPING_VERSION_HEADER": "Header exposing the mapping version",
+    session_consistent => "MIRAGEND_SESSION_CONSISTENT": "Obfuscate consistently per visitor",
+    session_cookie => "MIRAGEND_SESSION_COOKIE": "Cookie of the visitor session",
+    special_page_style => "MIRAGEND_SPECIAL_PAGE_STYLE": "Style of the error pages",
+    title_template => "MIRAGEND_TITLE_TEMPLATE": "Title of the transformed pages, e.g. `{{original}} - Archived`",
+    title_template_og_title => "MIRAGEND_TITLE_TEMPLATE_OG_TITLE": "Apply the title template to `og:title` too",
+    decoy_favicon => "MIRAGEND_DECOY_FAVICON": "Icon URL of the transformed pages, `placeholder` uses the bundled image",
+    decoy_image => "MIRAGEND_DECOY_IMAGE": "Preview image URL of the transformed pages, `placeholder` uses the bundled image",
+    decoy_placeholder_path => "MIRAGEND_DECOY_PLACEHOLDER_PATH": "Path serving the bundled placeholder image",
+    inject_online_script => "MIRAGEND_INJECT_ONLINE_SCRIPT": "Script URL injected into the pages",
+    x_robots_tag => "MIRAGEND_X_ROBOTS_TAG": "`X-Robots-Tag` of the bot-facing responses, `strip` removes it",
+    canonical_rewrite => "MIRAGEND_CANONICAL_REWRITE": "Base URL of the canonical links to the upstreams, `proxy` uses the proxy origin",
+    upstream_url_rewrite => "MIRAGEND_UPSTREAM_URL_REWRITE": "Rewrite the absolute URLs to the upstreams, `relative` or `proxy`",
+    strip_scripts => "MIRAGEND_STRIP_SCRIPTS": "Remove all the scripts of the transformed pages",
+    xml_obfuscation_paths => "MIRAGEND_XML_OBFUSCATION_PATHS": "Path patterns of the XML documents whose text is obfuscated",
+    js_obfuscation_paths => "MIRAGEND_JS_OBFUSCATION_PATHS": "Path patterns of the scripts whose string literals are obfuscated",
+    strip_script_srcs => "MIRAGEND_STRIP_SCRIPT_SRCS": "Patterns of the script sources removed, e.g. `*analytics*`",
+    fallback_dir => "MIRAGEND_FALLBACK_DIR": "Static site served when the upstream is down",
+    challenge_secret => "MIRAGEND_CHALLENGE_SECRET": "Secret signing the challenge cookies",
+    challenge_difficulty => "MIRAGEND_CHALLENGE_DIFFICULTY": "Difficulty of the challenge",
+    challenge_ttl_secs => "MIRAGEND_CHALLENGE_TTL_SECS": "Lifetime of the passed challenges",
+    crawler_allowlist => "MIRAGEND_CRAWLER_ALLOWLIST": "Crawlers proxied normally",
+    bandwidth_limits => "MIRAGEND_BANDWIDTH_LIMITS": "Bandwidth limits, `classification=bytes_per_sec,...`",
+    cache_ttl_secs => "MIRAGEND_CACHE_TTL_SECS": "Lifetime of the cached pages, `0` to disable",
+    cache_stale_secs => "MIRAGEND_CACHE_STALE_SECS": "Seconds the expired pages are still served",
+    cache_max_size => "MIRAGEND_CACHE_MAX_SIZE": "Maximum size of the cache in bytes",
+    cache_bypass_header => "MIRAGEND_CACHE_BYPASS_HEADER": "Request header skipping the cache",
+    tarpit_bytes_per_sec => "MIRAGEND_TARPIT_BYTES_PER_SEC": "Speed of the `tarpit` strategy",
+    maze_link_prefix => "MIRAGEND_MAZE_LINK_PREFIX": "Path prefix of the maze pages",
+    block_status => "MIRAGEND_BLOCK_STATUS": "Status of the `block` strategy",
+    honeypot_prefix => "MIRAGEND_HONEYPOT_PREFIX": "Path prefix of the honeypot links",
+    honeypot_ban_secs => "MIRAGEND_HONEYPOT_BAN_SECS": "Seconds the trapped clients are flagged",
+    honeypot_strategy => "MIRAGEND_HONEYPOT_STRATEGY": "Strategy of the trapped clients",
+    decision_script => "MIRAGEND_DECISION_SCRIPT": "Rhai script deciding the strategies",
+    decision_script_max_operations => "MIRAGEND_DECISION_SCRIPT_MAX_OPERATIONS": "Operations a script call may run",
+    plugins => "MIRAGEND_PLUGINS": "WASM plugins, comma-separated",
+    plugin_fuel => "MIRAGEND_PLUGIN_FUEL": "Instructions a plugin call may run",
+    plugin_memory_bytes => "MIRAGEND_PLUGIN_MEMORY_BYTES": "Largest memory of a plugin",
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Configuration tools
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Write a commented config file and a starter mapping file into the current directory
+    Init {
+        /// Overwrite the existing files
+        #[arg(long)]
+        force: bool,
+    },
+    /// Validate the configuration, exits non-zero on any failure
+    Check,
+    /// Perform one proxied request and print the decision and the response
+    Fetch {
+        /// Path of the request
+        path: String,
+        /// Print the forwarded headers instead of sending the request
+        #[arg(long)]
+        dry_run: bool,
+        /// User agent of the simulated client
+        #[arg(short = 'A', long)]
+        user_agent: Option<String>,
+        /// Extra request header, e.g. `-H "Cookie: a=1"`
+        #[arg(short = 'H', long = "header")]
+        headers: Vec<String>,
+        /// IP address of the simulated client
+        #[arg(long, default_value = "127.0.0.1")]
+        ip: IpAddr,
+    },
+    /// Check the live upstream against the current configuration
+    Doctor {
+        /// Path of the sample page
+        #[arg(short, long, default_value = "/")]
+        path: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Print the effective configuration (env + file + defaults)
+    Dump {
+        #[arg(short, long, value_enum, default_value = "toml")]
+        format: DumpFormat,
+    },
+}
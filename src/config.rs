@@ -0,0 +1,126 @@
+use crate::vars;
+use anyhow::Context;
+use std::{fs, sync::OnceLock};
+
+// Prefix of all environment variables
+pub const ENV_PREFIX: &str = "MIRAGEND_";
+// Tables of the config file (structured sections)
+static SECTIONS: OnceLock<toml::Table> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum DumpFormat {
+    Toml,
+    Json,
+}
+
+// Load the config file, top-level keys are mapped to `MIRAGEND_*` environment variables.
+// Environment variables (including the `.env` file) take precedence over the config file.
+// The loaded file is returned, the logger may be set up from the config file.
+pub fn load(file: Option<&str>) -> anyhow::Result<Option<String>> {
+    let file = match file {
+        Some(file) => file.to_owned(),
+        None => match std::env::var("MIRAGEND_CONFIG_FILE") {
+            Ok(file) if !file.is_empty() => file,
+            _ => {
+                let _ = SECTIONS.set(toml::Table::new());
+
+                return Ok(None);
+            }
+        },
+    };
+
+    let content = fs::read_to_string(&file).context(format!("failed to read `{}`", file))?;
+    let table: toml::Table = content
+        .parse()
+        .context(format!("failed to parse `{}`", file))?;
+
+    let mut sections = toml::Table::new();
+    for (key, value) in table {
+        if let toml::Value::Table(_) = value {
+            sections.insert(key, value);
+            continue;
+        }
+        // Tables in arrays are structured sections too
+        if let toml::Value::Array(ref items) = value {
+            if items.iter().any(|item| item.is_table()) {
+                sections.insert(key, value);
+                continue;
+            }
+        }
+
+        let name = format!("{}{}", ENV_PREFIX, key.to_uppercase());
+        if std::env::var_os(&name).is_none() {
+            std::env::set_var(&name, value_to_env(&value));
+        }
+    }
+    let _ = SECTIONS.set(sections);
+
+    Ok(Some(file))
+}
+
+// Get a structured section from the config file
+pub fn section(name: &str) -> Option<&'static toml::Value> {
+    SECTIONS.get().and_then(|sections| sections.get(name))
+}
+
+fn value_to_env(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Array(items) => items.iter().map(value_to_env).collect::<Vec<_>>().join(","),
+        v => v.to_string(),
+    }
+}
+
+// Print the effective configuration
+pub fn dump(format: DumpFormat) -> anyhow::Result<()> {
+    let mut map = serde_json::Map::new();
+    for entry in vars::entries() {
+        let value = if entry.secret {
+            mask(entry.value)
+        } else {
+            entry.value
+        };
+        map.insert(entry.key.to_lowercase(), value);
+    }
+    if let Some(sections) = SECTIONS.get() {
+        for (key, value) in sections {
+            // Sections merged into an entry are already dumped
+            if map.contains_key(key) {
+                continue;
+            }
+            map.insert(
+                key.clone(),
+                serde_json::to_value(value).context("failed to convert config section")?,
+            );
+        }
+    }
+
+    let output = match format {
+        DumpFormat::Toml => toml::to_string(&map).context("failed to serialize config as TOML")?,
+        DumpFormat::Json => {
+            serde_json::to_string_pretty(&map).context("failed to serialize config as JSON")?
+        }
+    };
+    println!("{}", output);
+
+    Ok(())
+}
+
+fn mask(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) if s.is_empty() => serde_json::Value::String(s),
+        _ => serde_json::Value::String("******".to_owned()),
+    }
+}
+
+// Mask the password part of the URL
+pub fn mask_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("******"));
+
+            url.to_string()
+        }
+        _ => url.to_owned(),
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{config, rules::glob_match};
+use axum::body::Body;
+use http::{header, HeaderMap, HeaderValue, Method, Request, Response, StatusCode};
+use std::sync::LazyLock;
+
+// CORS policies of the paths from the `cors` section, the first match applies
+static POLICIES: LazyLock<Vec<Policy>> = LazyLock::new(|| {
+    let policies: Vec<Policy> = config::section("cors")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `cors` section")
+        .unwrap_or_default();
+    for policy in &policies {
+        for value in policy
+            .allow_origins
+            .iter()
+            .chain(&policy.allow_methods)
+            .chain(&policy.allow_headers)
+        {
+            if HeaderValue::from_str(value).is_err() {
+                panic!(
+                    "invalid CORS value `{}` of the path `{}`",
+                    value, policy.path
+                );
+            }
+        }
+    }
+
+    policies
+});
+// Replaced by the policy, the upstream values rarely match the proxy host
+const UPSTREAM_HEADERS: [header::HeaderName; 5] = [
+    header::ACCESS_CONTROL_ALLOW_ORIGIN,
+    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+    header::ACCESS_CONTROL_ALLOW_METHODS,
+    header::ACCESS_CONTROL_ALLOW_HEADERS,
+    header::ACCESS_CONTROL_MAX_AGE,
+];
+
+#[derive(Debug, serde::Deserialize)]
+struct Policy {
+    // Path pattern, `*` matches any characters
+    path: String,
+    // Origins like `https://app.example.com`, `*` allows any
+    allow_origins: Vec<String>,
+    #[serde(default = "default_methods")]
+    allow_methods: Vec<String>,
+    #[serde(default)]
+    allow_headers: Vec<String>,
+    #[serde(default)]
+    allow_credentials: bool,
+    // Seconds the preflight answer is cached by the browser
+    max_age: Option<u64>,
+}
+
+fn default_methods() -> Vec<String> {
+    vec!["GET".to_owned(), "HEAD".to_owned(), "POST".to_owned()]
+}
+
+pub fn force_init() {
+    LazyLock::force(&POLICIES);
+}
+
+// Answer the preflight requests of the paths with a policy, the upstream is not asked
+pub fn preflight(request: &Request<Body>) -> Option<Response<Body>> {
+    preflight_by(&POLICIES, request)
+}
+
+fn preflight_by(policies: &[Policy], request: &Request<Body>) -> Option<Response<Body>> {
+    let headers = request.headers();
+    if request.method() != Method::OPTIONS
+        || !headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        return None;
+    }
+    let policy = find(policies, request.uri().path())?;
+
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::NO_CONTENT;
+    let resp_headers = resp.headers_mut();
+    if allow_origin(policy, headers.get(header::ORIGIN), resp_headers) {
+        let join = |values: &[String]| HeaderValue::from_str(&values.join(", ")).ok();
+        if let Some(methods) = join(&policy.allow_methods) {
+            resp_headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, methods);
+        }
+        if let Some(allowed) =
+            join(&policy.allow_headers).filter(|_| !policy.allow_headers.is_empty())
+        {
+            resp_headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, allowed);
+        }
+        if let Some(max_age) = policy.max_age {
+            resp_headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age));
+        }
+    }
+
+    Some(resp)
+}
+
+// Replace the CORS headers of the response by the matching policy
+pub fn apply(headers: &mut HeaderMap, path: &str, origin: Option<&HeaderValue>) {
+    if let Some(policy) = find(&POLICIES, path) {
+        for name in &UPSTREAM_HEADERS {
+            headers.remove(name);
+        }
+        allow_origin(policy, origin, headers);
+    }
+}
+
+fn find<'a>(policies: &'a [Policy], path: &str) -> Option<&'a Policy> {
+    policies
+        .iter()
+        .find(|policy| glob_match(&policy.path, path))
+}
+
+// Set the allowed origin, `false` if the origin is not allowed
+fn allow_origin(policy: &Policy, origin: Option<&HeaderValue>, headers: &mut HeaderMap) -> bool {
+    let any = policy.allow_origins.iter().any(|allowed| allowed == "*");
+    // The responses differ by the origin unless any origin gets the wildcard
+    if !any || policy.allow_credentials {
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+    let Some(origin) = origin else {
+        return false;
+    };
+    let allowed = any
+        || policy
+            .allow_origins
+            .iter()
+            .any(|allowed| allowed.as_bytes().eq_ignore_ascii_case(origin.as_bytes()));
+    if !allowed {
+        return false;
+    }
+    // The wildcard is refused with the credentials
+    if any && !policy.allow_credentials {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            HeaderValue::from_static("*"),
+        );
+    } else {
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+    }
+    if policy.allow_credentials {
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+            HeaderValue::from_static("true"),
+        );
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policies() -> Vec<Policy> {
+        vec![Policy {
+            path: "/api/*".to_owned(),
+            allow_origins: vec!["https://app.test".to_owned()],
+            allow_methods: default_methods(),
+            allow_headers: vec!["Content-Type".to_owned()],
+            allow_credentials: true,
+            max_age: Some(600),
+        }]
+    }
+
+    #[test]
+    fn test_preflight_by() {
+        let policies = policies();
+        let preflight = |path: &str, origin: &str| {
+            let request = Request::options(path)
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(Body::empty())
+                .unwrap();
+            preflight_by(&policies, &request)
+        };
+
+        let resp = preflight("/api/items", "https://app.test").unwrap();
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.test"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, HEAD, POST"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "Content-Type"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let resp = preflight("/api/items", "https://evil.test").unwrap();
+        assert!(resp
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+        assert!(preflight("/page", "https://app.test").is_none());
+    }
+
+    #[test]
+    fn test_preflight_through_router() {
+        use tower::ServiceExt;
+
+        // The dispatch of the proxy, without the upstream
+        let handler = |request: Request<Body>| async move {
+            preflight_by(&policies(), &request).unwrap_or_else(|| Response::new(Body::empty()))
+        };
+        let send = |request: Request<Body>| {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(crate::routes(handler).oneshot(request))
+                .unwrap()
+        };
+
+        let resp = send(
+            Request::options("/api/items")
+                .header(header::ORIGIN, "https://app.test")
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+                .body(Body::empty())
+                .unwrap(),
+        );
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            resp.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.test"
+        );
+        let resp = send(
+            Request::post("/api/items")
+                .header(header::ORIGIN, "https://app.test")
+                .body(Body::from("{}"))
+                .unwrap(),
+        );
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
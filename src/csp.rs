@@ -0,0 +1,247 @@
+use http::{header, HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256};
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Allow the sources in the enforced and the reported policies of the response
+pub fn allow(headers: &mut HeaderMap, scripts: &[String], styles: &[String]) {
+    for name in [
+        header::CONTENT_SECURITY_POLICY,
+        header::CONTENT_SECURITY_POLICY_REPORT_ONLY,
+    ] {
+        let values = headers.get_all(&name).iter().cloned().collect::<Vec<_>>();
+        if values.is_empty() {
+            continue;
+        }
+        headers.remove(&name);
+        for value in values {
+            let allowed = value
+                .to_str()
+                .ok()
+                .and_then(|policies| {
+                    HeaderValue::from_str(&allowed(policies, scripts, styles)).ok()
+                })
+                .unwrap_or(value);
+            headers.append(&name, allowed);
+        }
+    }
+}
+
+// Whether the enforced policies let the `style` attributes through
+pub fn allows_style_attrs(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::CONTENT_SECURITY_POLICY)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|policies| policies.split(','))
+        .all(|policy| {
+            let directives = directives(policy);
+            ["style-src-attr", "style-src", "default-src"]
+                .iter()
+                .find_map(|&name| directives.iter().find(|(n, _)| n == name))
+                .map_or(true, |(_, values)| allows_inline(values))
+        })
+}
+
+// Source expression of the URL: its origin, or `'self'` if relative
+pub fn url_source(url: &str) -> String {
+    if let Some(rest) = url.strip_prefix("//") {
+        return rest
+            .split(['/', '?', '#'])
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+    }
+    match reqwest::Url::parse(url) {
+        Ok(url) => url.origin().ascii_serialization(),
+        Err(_) => "'self'".to_owned(),
+    }
+}
+
+// Source expression of the inline code
+pub fn hash_source(code: &str) -> String {
+    format!("'sha256-{}'", base64(&Sha256::digest(code.as_bytes())))
+}
+
+fn allowed(policies: &str, scripts: &[String], styles: &[String]) -> String {
+    policies
+        .split(',')
+        .map(|policy| allowed_policy(policy, scripts, styles))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// The element directive applies first, then the general one, then `default-src`
+fn allowed_policy(policy: &str, scripts: &[String], styles: &[String]) -> String {
+    let mut directives = directives(policy);
+    let find = |directives: &[(String, Vec<String>)], name: &str| {
+        directives.iter().position(|(n, _)| n == name)
+    };
+
+    for (sources, elem, general) in [
+        (scripts, "script-src-elem", "script-src"),
+        (styles, "style-src-elem", "style-src"),
+    ] {
+        if sources.is_empty() {
+            continue;
+        }
+        let index = match find(&directives, elem).or_else(|| find(&directives, general)) {
+            Some(index) => index,
+            // Copied to keep `default-src` as strict for the other resources
+            None => match find(&directives, "default-src") {
+                Some(default) => {
+                    let values = directives[default].1.clone();
+                    directives.push((general.to_owned(), values));
+                    directives.len() - 1
+                }
+                None => continue,
+            },
+        };
+        allow_sources(&mut directives[index].1, sources);
+    }
+
+    directives
+        .into_iter()
+        .map(|(name, values)| {
+            std::iter::once(name)
+                .chain(values)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+// Names and values of the directives, the names are lowercased
+fn directives(policy: &str) -> Vec<(String, Vec<String>)> {
+    policy
+        .split(';')
+        .filter_map(|directive| {
+            let mut tokens = directive.split_ascii_whitespace();
+            let name = tokens.next()?.to_ascii_lowercase();
+
+            Some((name, tokens.map(str::to_owned).collect::<Vec<_>>()))
+        })
+        .collect()
+}
+
+// A nonce or a hash disables `'unsafe-inline'`
+fn allows_inline(values: &[String]) -> bool {
+    values
+        .iter()
+        .any(|v| v.eq_ignore_ascii_case("'unsafe-inline'"))
+        && !values.iter().any(|v| {
+            let v = v.to_ascii_lowercase();
+            v.starts_with("'nonce-") || v.starts_with("'sha")
+        })
+}
+
+// The host sources are ignored under `'strict-dynamic'`, nothing can be done about it
+fn allow_sources(values: &mut Vec<String>, sources: &[String]) {
+    // Don't add a hash if the inline code is already allowed
+    let allows_inline = allows_inline(values);
+    values.retain(|v| !v.eq_ignore_ascii_case("'none'"));
+    for source in sources {
+        if allows_inline && source.starts_with("'sha256-") {
+            continue;
+        }
+        if !values.iter().any(|v| v.eq_ignore_ascii_case(source)) {
+            values.push(source.clone());
+        }
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_CHARS[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allowed_policy() {
+        let scripts = vec!["https://cdn.test".to_owned(), hash_source("run()")];
+        let styles = vec!["'self'".to_owned()];
+        assert_eq!(
+            allowed_policy("default-src 'self'; img-src *", &scripts, &styles),
+            format!(
+                "default-src 'self'; img-src *; script-src 'self' https://cdn.test {}; style-src 'self'",
+                scripts[1]
+            )
+        );
+        assert_eq!(
+            allowed_policy(
+                "script-src 'none'; style-src 'unsafe-inline' https://a.test",
+                &scripts,
+                &styles
+            ),
+            format!(
+                "script-src https://cdn.test {}; style-src 'unsafe-inline' https://a.test 'self'",
+                scripts[1]
+            )
+        );
+        // The inline code is already allowed
+        assert_eq!(
+            allowed_policy("script-src 'unsafe-inline'", &scripts, &[]),
+            "script-src 'unsafe-inline' https://cdn.test"
+        );
+        assert_eq!(allowed_policy("img-src *", &scripts, &styles), "img-src *");
+    }
+
+    #[test]
+    fn test_allows_style_attrs() {
+        let policy = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::CONTENT_SECURITY_POLICY,
+                HeaderValue::from_str(value).unwrap(),
+            );
+            headers
+        };
+        assert!(allows_style_attrs(&HeaderMap::new()));
+        assert!(allows_style_attrs(&policy("script-src 'self'")));
+        assert!(allows_style_attrs(&policy(
+            "default-src 'self'; style-src 'self' 'unsafe-inline'"
+        )));
+        assert!(!allows_style_attrs(&policy("default-src 'self'")));
+        assert!(!allows_style_attrs(&policy(
+            "style-src 'unsafe-inline'; style-src-attr 'none'"
+        )));
+        assert!(!allows_style_attrs(&policy(
+            "style-src 'unsafe-inline' 'nonce-abc'"
+        )));
+        assert!(!allows_style_attrs(&policy("img-src *, style-src 'self'")));
+    }
+
+    #[test]
+    fn test_sources() {
+        assert_eq!(
+            url_source("https://cdn.test:8443/a.js?v=1"),
+            "https://cdn.test:8443"
+        );
+        assert_eq!(url_source("//cdn.test/a.js"), "cdn.test");
+        assert_eq!(url_source("/a.js"), "'self'");
+        assert_eq!(
+            hash_source(""),
+            "'sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU='"
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+}
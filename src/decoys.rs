@@ -0,0 +1,136 @@
+use crate::{
+    html_ops::{self, DOMOps, NodeOps},
+    vars,
+};
+use axum::body::Body;
+use html5ever::local_name;
+use http::{header, Response, StatusCode};
+use markup5ever_rcdom::Handle;
+use std::rc::Rc;
+
+// Gray preview image served when a decoy is `placeholder`
+const PLACEHOLDER_PNG: &[u8] = include_bytes!("../placeholder.png");
+const PLACEHOLDER: &str = "placeholder";
+// Link types of the site icons
+const ICON_RELS: [&str; 2] = ["icon", "apple-touch-icon"];
+// Meta tags of the preview images, by `property` or `name`
+const IMAGE_METAS: [&str; 5] = [
+    "og:image",
+    "og:image:url",
+    "og:image:secure_url",
+    "twitter:image",
+    "twitter:image:src",
+];
+
+pub fn enabled() -> bool {
+    !vars::decoy_favicon().is_empty() || !vars::decoy_image().is_empty()
+}
+
+pub fn is_placeholder_path(path: &str) -> bool {
+    (vars::decoy_favicon() == PLACEHOLDER || vars::decoy_image() == PLACEHOLDER)
+        && path == vars::decoy_placeholder_path()
+}
+
+pub fn build_resp() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "image/png")
+        .header(header::CACHE_CONTROL, "public, max-age=86400")
+        .body(Body::from(PLACEHOLDER_PNG))
+        .unwrap_or_else(|_| {
+            crate::special_response::build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
+        })
+}
+
+// Point the icons and the preview images at the decoys, an icon link is added if missing
+pub fn replace(document: &Handle) {
+    let url = |decoy: &'static str| match decoy {
+        PLACEHOLDER => vars::decoy_placeholder_path(),
+        url => url,
+    };
+    replace_all(
+        document,
+        Some(vars::decoy_favicon())
+            .filter(|decoy| !decoy.is_empty())
+            .map(url),
+        Some(vars::decoy_image())
+            .filter(|decoy| !decoy.is_empty())
+            .map(url),
+    );
+}
+
+fn replace_all(document: &Handle, favicon: Option<&str>, image: Option<&str>) {
+    let Some(head) = Rc::clone(document).get_head() else {
+        return;
+    };
+    if let Some(favicon) = favicon {
+        let mut replaced = false;
+        for mut link in Rc::clone(&head).find_all_by_selector("link[href]") {
+            let is_icon = link
+                .get_attribute(&local_name!("rel"))
+                .unwrap_or_default()
+                .split_ascii_whitespace()
+                .any(|token| ICON_RELS.iter().any(|rel| token.eq_ignore_ascii_case(rel)));
+            if is_icon {
+                link.set_attribute(&local_name!("href"), favicon.into());
+                replaced = true;
+            }
+        }
+        // Browsers request `/favicon.ico` without an icon link
+        if !replaced {
+            let link = html_ops::build_element(
+                local_name!("link"),
+                vec![
+                    (local_name!("rel"), "icon".into()),
+                    (local_name!("href"), favicon.into()),
+                ],
+                vec![],
+            );
+            let mut children = head.children.borrow_mut();
+            children.push(link);
+            children.push(html_ops::build_newline());
+        }
+    }
+    if let Some(image) = image {
+        for mut meta in head.find_meta_tags() {
+            let is_image = [local_name!("property"), local_name!("name")]
+                .iter()
+                .filter_map(|attr| meta.get_attribute(attr))
+                .any(|key| IMAGE_METAS.iter().any(|m| key.eq_ignore_ascii_case(m)));
+            if is_image {
+                meta.set_attribute(&local_name!("content"), image.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_ops::DOMBuilder;
+
+    #[test]
+    fn test_replace_all() {
+        let dom = r#"<html><head>
+            <link rel="shortcut icon" href="/favicon.ico">
+            <meta property="og:image" content="/a.png">
+            <meta name="twitter:image" content="/a.png">
+            <meta property="og:title" content="A">
+            </head><body></body></html>"#
+            .build_document()
+            .unwrap();
+        replace_all(&dom.document, Some("/decoy.ico"), Some("/decoy.png"));
+        let html = html_ops::serialize_to_html(dom).unwrap();
+        assert!(html.contains(r#"<link rel="shortcut icon" href="/decoy.ico">"#));
+        assert!(html.contains(r#"<meta property="og:image" content="/decoy.png">"#));
+        assert!(html.contains(r#"<meta name="twitter:image" content="/decoy.png">"#));
+        assert!(html.contains(r#"<meta property="og:title" content="A">"#));
+
+        let dom = "<html><head></head><body></body></html>"
+            .build_document()
+            .unwrap();
+        replace_all(&dom.document, Some("/decoy.ico"), None);
+        let html = html_ops::serialize_to_html(dom).unwrap();
+        assert!(html.contains(r#"<link rel="icon" href="/decoy.ico">"#));
+    }
+}
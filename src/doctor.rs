@@ -0,0 +1,295 @@
+use crate::{
+    headers,
+    html_ops::{DOMBuilder, DOMOps},
+    request, vars,
+};
+use http::{header, HeaderMap};
+use markup5ever_rcdom::NodeData;
+use std::rc::Rc;
+
+// Minimum ratio of the mapped characters in the sample page
+const MIN_MAPPING_COVERAGE: f64 = 0.5;
+
+pub(crate) enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Default)]
+pub(crate) struct Report {
+    failures: usize,
+}
+
+impl Report {
+    pub(crate) fn print(&mut self, outcome: Outcome, subject: &str, detail: impl AsRef<str>) {
+        let label = match outcome {
+            Outcome::Pass => "\x1b[32mPASS\x1b[0m",
+            Outcome::Warn => "\x1b[33mWARN\x1b[0m",
+            Outcome::Fail => {
+                self.failures += 1;
+
+                "\x1b[31mFAIL\x1b[0m"
+            }
+        };
+
+        println!("[{}] {}: {}", label, subject, detail.as_ref());
+    }
+}
+
+// Check the live upstream against the current configuration
+pub async fn run(path: &str) -> anyhow::Result<()> {
+    let mut report = Report::default();
+    let url = format!("{}{}", vars::upstream_base_url(), path);
+    let is_https = url.starts_with("https://");
+
+    let resp = match request::get(&url, headers::build_from_request(&HeaderMap::new())).await {
+        Ok(resp) => {
+            report.print(
+                Outcome::Pass,
+                "reachability",
+                format!("{} responded with {}", url, resp.status()),
+            );
+
+            resp
+        }
+        Err(request::RequestError::Timeout) => {
+            report.print(
+                Outcome::Fail,
+                "reachability",
+                format!(
+                    "{} timed out after {}s, check the upstream or increase `MIRAGEND_CONNECT_TIMEOUT_SECS`",
+                    url,
+                    vars::connect_timeout_secs()
+                ),
+            );
+
+            return finish(report);
+        }
+        Err(request::RequestError::Reqwest(e)) => {
+            report.print(
+                Outcome::Fail,
+                "reachability",
+                format!("failed to request {}: {}", url, error_chain(&e)),
+            );
+            if is_https {
+                report.print(
+                    Outcome::Warn,
+                    "tls",
+                    "the error may be caused by an invalid certificate of the upstream",
+                );
+            }
+
+            return finish(report);
+        }
+    };
+
+    if is_https {
+        report.print(Outcome::Pass, "tls", "the upstream certificate is valid");
+    } else {
+        report.print(Outcome::Warn, "tls", "the upstream is not using HTTPS");
+    }
+
+    let content_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+    if content_type.starts_with("text/html") {
+        report.print(Outcome::Pass, "content-type", &content_type);
+    } else if content_type.is_empty() {
+        report.print(
+            Outcome::Warn,
+            "content-type",
+            "missing header, the page will be treated as HTML",
+        );
+    } else {
+        report.print(
+            Outcome::Fail,
+            "content-type",
+            format!(
+                "`{}` is not HTML, try another sample path with `--path`",
+                content_type
+            ),
+        );
+
+        return finish(report);
+    }
+
+    let html = match request::text(resp).await {
+        Ok(html) => html,
+        Err(e) => {
+            report.print(
+                Outcome::Fail,
+                "body",
+                format!("failed to read the page: {}", e),
+            );
+
+            return finish(report);
+        }
+    };
+    let dom = match html.as_str().build_document() {
+        Ok(dom) => dom,
+        Err(e) => {
+            report.print(
+                Outcome::Fail,
+                "body",
+                format!("failed to parse the page: {}", e),
+            );
+
+            return finish(report);
+        }
+    };
+
+    check_patch_targets(&mut report, &dom.document, vars::patch_targets());
+    for selector in vars::obfuscation_ignore_nodes() {
+        check_selector(&mut report, &dom.document, "ignore node", selector);
+    }
+    check_node(
+        &mut report,
+        &dom.document,
+        "ignore after node",
+        vars::obfuscation_ignore_after_node(),
+    );
+
+    let (mapped, total) = mapping_coverage(&dom.document);
+    if total == 0 {
+        report.print(
+            Outcome::Warn,
+            "mapping coverage",
+            "no text found in the sample page",
+        );
+    } else {
+        let coverage = mapped as f64 / total as f64;
+        let detail = format!(
+            "{:.1}% of {} characters are mapped",
+            coverage * 100.0,
+            total
+        );
+        if coverage >= MIN_MAPPING_COVERAGE {
+            report.print(Outcome::Pass, "mapping coverage", detail);
+        } else {
+            report.print(
+                Outcome::Warn,
+                "mapping coverage",
+                format!(
+                    "{}, add ranges for the page's scripts to `MIRAGEND_OBFUSCATION_MAPPING_FILE`",
+                    detail
+                ),
+            );
+        }
+    }
+
+    finish(report)
+}
+
+fn check_node(report: &mut Report, document: &markup5ever_rcdom::Handle, subject: &str, id: &str) {
+    if id.is_empty() {
+        return;
+    }
+
+    if Rc::clone(document).get_element_by_id(id).is_some() {
+        report.print(Outcome::Pass, subject, format!("`#{}` found", id));
+    } else {
+        report.print(
+            Outcome::Fail,
+            subject,
+            format!("`#{}` not found in the sample page", id),
+        );
+    }
+}
+
+// The first target found receives the patch content
+fn check_patch_targets(
+    report: &mut Report,
+    document: &markup5ever_rcdom::Handle,
+    targets: &[String],
+) {
+    let found = targets
+        .iter()
+        .find(|target| Rc::clone(document).find_by_selector(target).is_some());
+    match found {
+        Some(target) if Some(target) == targets.first() => {
+            report.print(Outcome::Pass, "patch target", format!("`{}` found", target))
+        }
+        Some(target) => report.print(
+            Outcome::Warn,
+            "patch target",
+            format!("`{}` found after the missing ones", target),
+        ),
+        None => report.print(
+            Outcome::Warn,
+            "patch target",
+            "no target found in the sample page, the body contents will be replaced",
+        ),
+    }
+}
+
+fn check_selector(
+    report: &mut Report,
+    document: &markup5ever_rcdom::Handle,
+    subject: &str,
+    selector: &str,
+) {
+    if selector.is_empty() {
+        return;
+    }
+
+    if Rc::clone(document).find_by_selector(selector).is_some() {
+        report.print(Outcome::Pass, subject, format!("`{}` found", selector));
+    } else {
+        report.print(
+            Outcome::Fail,
+            subject,
+            format!("`{}` not found in the sample page", selector),
+        );
+    }
+}
+
+// Count the mapped and total non-whitespace characters of the text nodes
+fn mapping_coverage(document: &markup5ever_rcdom::Handle) -> (usize, usize) {
+    let mut text_nodes = vec![];
+    crate::collect_obfuscation_nodes(
+        document,
+        &mut text_nodes,
+        &mut vec![],
+        vars::obfuscation_ignore_nodes(),
+        false,
+        false,
+    );
+
+    let config = vars::obfuscator_config();
+    let (mut mapped, mut total) = (0, 0);
+    for (node, _) in text_nodes {
+        if let NodeData::Text { ref contents } = node.data {
+            for c in contents.borrow().chars().filter(|c| !c.is_whitespace()) {
+                total += 1;
+                if config.is_mapped(c) {
+                    mapped += 1;
+                }
+            }
+        }
+    }
+
+    (mapped, total)
+}
+
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        message = format!("{}: {}", message, e);
+        source = e.source();
+    }
+
+    message
+}
+
+pub(crate) fn finish(report: Report) -> anyhow::Result<()> {
+    if report.failures > 0 {
+        anyhow::bail!("{} check(s) failed", report.failures);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,248 @@
+//! Embedding API, runs the page-poisoning pipeline in other Rust services without the
+//! standalone proxy.
+//!
+//! ```ignore
+//! use miragend::embed::{ContentType, Miragend, Obfuscation};
+//!
+//! let miragend = Miragend::builder()
+//!     .strategy(Obfuscation::new().ratio(0.5))
+//!     .build();
+//! let poisoned = miragend.transform("<p>Hello</p>", ContentType::Html)?;
+//! ```
+//!
+//! The options not covered by the builder are read from the `MIRAGEND_*` variables, like the
+//! proxy does.
+use crate::{
+    load_patch_html, metrics::Timings, obfuscation::ObfuscatorConfig, profiles, vars, PatchConfig,
+    Served, Strategy as Pipeline,
+};
+
+pub use crate::{fetching::ContentType, strategies::Strategy};
+
+/// Replace the characters with the similar-looking ones of the mapping.
+#[derive(Clone)]
+pub struct Obfuscation {
+    config: ObfuscatorConfig,
+}
+
+impl Obfuscation {
+    /// Use the global mapping of `MIRAGEND_OBFUSCATION_MAPPING_FILE`.
+    pub fn new() -> Self {
+        Self {
+            config: vars::obfuscator_config().with_ratio(vars::obfuscation_ratio()),
+        }
+    }
+
+    /// Use the mapping of the CSV content instead of the global one.
+    pub fn mapping_csv(self, content: &str) -> Self {
+        let config = ObfuscatorConfig::load_from_csv(content).with_ratio(self.config.ratio);
+        let config = match self.config.seed {
+            Some(seed) => config.with_seed(seed),
+            None => config,
+        };
+
+        Self { config }
+    }
+
+    /// Ratio of the replaced characters, from `0.0` to `1.0`.
+    pub fn ratio(self, ratio: f64) -> Self {
+        Self {
+            config: self.config.with_ratio(ratio.clamp(0.0, 1.0)),
+        }
+    }
+
+    /// Replace the characters consistently for the same seed, e.g. per visitor.
+    pub fn seed(self, seed: u64) -> Self {
+        Self {
+            config: self.config.with_seed(seed),
+        }
+    }
+}
+
+impl Default for Obfuscation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Strategy for Obfuscation {
+    fn name(&self) -> &str {
+        "obfuscation"
+    }
+
+    fn transform(&self, body: &str, content_type: ContentType) -> anyhow::Result<String> {
+        run(&Pipeline::Obfuscation, &self.config, body, content_type)
+    }
+}
+
+/// Replace the children of the target node with the patch content.
+#[derive(Clone)]
+pub struct Patch {
+    targets: Vec<String>,
+    content: String,
+}
+
+impl Patch {
+    /// Use `MIRAGEND_PATCH_TARGET` and `MIRAGEND_PATCH_CONTENT_FILE`.
+    pub fn new() -> Self {
+        Self {
+            targets: vars::patch_targets().clone(),
+            content: load_patch_html(vars::patch_content_file()),
+        }
+    }
+
+    /// ID or selector of the node receiving the content, replaces the previous targets.
+    pub fn target(self, target: impl Into<String>) -> Self {
+        Self {
+            targets: vec![target.into()],
+            ..self
+        }
+    }
+
+    /// Fallback target tried when the previous ones are not found.
+    pub fn or_target(mut self, target: impl Into<String>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// HTML inserted into the target node.
+    pub fn content(self, html: impl Into<String>) -> Self {
+        Self {
+            content: html.into(),
+            ..self
+        }
+    }
+}
+
+impl Default for Patch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Strategy for Patch {
+    fn name(&self) -> &str {
+        "patch"
+    }
+
+    fn transform(&self, body: &str, content_type: ContentType) -> anyhow::Result<String> {
+        let strategy = Pipeline::Patch(PatchConfig {
+            targets: self.targets.clone(),
+            content: self.content.clone(),
+            mode: vars::patch_mode(),
+            remove_nodes: vars::patch_remove_nodes(),
+            remove_meta_tags: vars::patch_remove_meta_tags(),
+        });
+
+        run(&strategy, &vars::obfuscator_config(), body, content_type)
+    }
+}
+
+/// Leave the content unchanged.
+#[derive(Clone, Default)]
+pub struct Passthrough;
+
+impl Strategy for Passthrough {
+    fn name(&self) -> &str {
+        "passthrough"
+    }
+
+    fn transform(&self, body: &str, _content_type: ContentType) -> anyhow::Result<String> {
+        Ok(body.to_owned())
+    }
+}
+
+fn run(
+    strategy: &Pipeline<'_>,
+    obfuscator: &ObfuscatorConfig,
+    body: &str,
+    content_type: ContentType,
+) -> anyhow::Result<String> {
+    crate::transform(
+        body,
+        &content_type,
+        &Served {
+            path: "",
+            public_origin: None,
+            style_attrs: true,
+        },
+        strategy,
+        obfuscator,
+        profiles::select(""),
+        &mut Timings::start(),
+    )
+}
+
+/// The pipeline with its strategy, built by [`Miragend::builder`].
+pub struct Miragend {
+    strategy: Box<dyn Strategy>,
+}
+
+/// Builder of [`Miragend`].
+#[derive(Default)]
+pub struct MiragendBuilder {
+    strategy: Option<Box<dyn Strategy>>,
+}
+
+impl MiragendBuilder {
+    /// Strategy applied to the content, [`Obfuscation`] by default.
+    pub fn strategy(self, strategy: impl Strategy + 'static) -> Self {
+        Self {
+            strategy: Some(Box::new(strategy)),
+        }
+    }
+
+    pub fn build(self) -> Miragend {
+        Miragend {
+            strategy: self
+                .strategy
+                .unwrap_or_else(|| Box::new(Obfuscation::new())),
+        }
+    }
+}
+
+impl Miragend {
+    pub fn builder() -> MiragendBuilder {
+        MiragendBuilder::default()
+    }
+
+    /// Name of the strategy.
+    pub fn strategy(&self) -> &str {
+        self.strategy.name()
+    }
+
+    /// Transform the body of the given content type with the strategy.
+    pub fn transform(&self, body: &str, content_type: ContentType) -> anyhow::Result<String> {
+        self.strategy.transform(body, content_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lowercase letters to uppercase letters
+    const MAPPING: &str = "source_start,source_end,target_start,target_end,comment\n\
+        0061,007a,0041,005a,\"lowercase to uppercase\"\n";
+
+    #[test]
+    fn test_transform() {
+        let miragend = Miragend::builder()
+            .strategy(Obfuscation::new().mapping_csv(MAPPING).ratio(1.0))
+            .build();
+        assert_eq!(miragend.strategy(), "obfuscation");
+        let transformed = miragend
+            .transform("hello world", ContentType::Text)
+            .unwrap();
+        assert_ne!(transformed, "hello world");
+        assert!(transformed.chars().all(|c| !c.is_lowercase()));
+
+        let passthrough = Miragend::builder().strategy(Passthrough).build();
+        assert_eq!(
+            passthrough
+                .transform("<p>hello</p>", ContentType::Html)
+                .unwrap(),
+            "<p>hello</p>"
+        );
+    }
+}
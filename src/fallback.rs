@@ -0,0 +1,93 @@
+use crate::vars;
+use axum::body::Body;
+use http::{header, Response, StatusCode};
+use std::path::{Path, PathBuf};
+
+pub fn enabled() -> bool {
+    !vars::fallback_dir().is_empty()
+}
+
+// Serve the file of the path from the fallback directory
+pub fn load(path: &str) -> Option<Response<Body>> {
+    let file = resolve(Path::new(vars::fallback_dir()), path)?;
+    let content = std::fs::read(&file).ok()?;
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type(&file))
+        // The real page is served again once the upstream recovers
+        .header(header::CACHE_CONTROL, "no-store")
+        .body(Body::from(content))
+        .ok()
+}
+
+// Directories are served by the `index.html`, `/about` may be served by `about.html`
+fn resolve(dir: &Path, path: &str) -> Option<PathBuf> {
+    let mut file = dir.to_path_buf();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => continue,
+            // Never leave the directory
+            ".." => return None,
+            segment if segment.contains('\\') => return None,
+            segment => file.push(segment),
+        }
+    }
+    if file.is_dir() {
+        file.push("index.html");
+    } else if !file.is_file() && file.extension().is_none() {
+        file.set_extension("html");
+    }
+
+    file.is_file().then_some(file)
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("html" | "htm") => vars::CONTENT_TYPE_VALUE_TEXT_HTML,
+        Some("css") => "text/css; charset=utf-8",
+        Some("js" | "mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let dir = std::env::temp_dir().join(format!("miragend-fallback-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("docs")).unwrap();
+        std::fs::write(dir.join("index.html"), "home").unwrap();
+        std::fs::write(dir.join("about.html"), "about").unwrap();
+        std::fs::write(dir.join("docs/index.html"), "docs").unwrap();
+
+        assert_eq!(resolve(&dir, "/"), Some(dir.join("index.html")));
+        assert_eq!(resolve(&dir, "/about"), Some(dir.join("about.html")));
+        assert_eq!(resolve(&dir, "/docs/"), Some(dir.join("docs/index.html")));
+        assert_eq!(resolve(&dir, "/missing"), None);
+        assert_eq!(resolve(&dir, "/docs/../../etc/passwd"), None);
+        assert_eq!(
+            content_type(&dir.join("about.html")),
+            vars::CONTENT_TYPE_VALUE_TEXT_HTML
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,12 +1,20 @@
-use crate::request;
-use http::{HeaderMap, StatusCode};
-use log::error;
+use crate::{
+    balancer, cache, config, headers, headers::AppendHeaders, js, metrics, request, streaming,
+    vars, xml,
+};
+use axum::body::{Body, Bytes};
+use http::{header, HeaderMap, HeaderValue, Method, StatusCode};
+use log::{error, warn};
+use std::time::Duration;
 
 pub enum Loaded {
     Special(StatusCode),
     Forward(Response),
+    // Not transformable, sent as is
+    Untouched(http::Response<Body>),
 }
 
+#[derive(Clone)]
 pub struct Response {
     pub status: StatusCode,
     pub headers: HeaderMap,
@@ -18,10 +26,47 @@ pub struct Response {
 pub enum ContentType {
     Html,
     Json,
+    Text,
+    Xml,
+    Js,
+    Css,
 }
 
-pub async fn load(url: &str, headers: HeaderMap) -> Loaded {
-    let resp = match request::get(url, headers).await {
+// Answer of the upstream responses of the unsupported content types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::Display)]
+#[strum(serialize_all = "kebab-case")]
+pub enum UnknownContent {
+    // Forward the body as is
+    Passthrough,
+    // Answer with the status of the blocked requests
+    Block,
+    // Answer with a bad gateway error
+    Error,
+}
+
+// Load the path from the upstreams in priority order
+pub async fn load(path: &str, mut headers: HeaderMap) -> Loaded {
+    let url = &format!("{}{}", vars::upstream_base_url(), path);
+    let cache_key = cache::upstream_key(url, &headers);
+    // The request headers select the variant, before the validators are added
+    let req_headers = cache_key.is_some().then(|| headers.clone());
+    let cached = cache_key
+        .as_deref()
+        .zip(req_headers.as_ref())
+        .and_then(|(key, req_headers)| cache::get_upstream(key, req_headers));
+    if let Some(cached) = &cached {
+        // Revalidate with the validators of the cached response
+        headers.remove(header::IF_NONE_MATCH);
+        headers.remove(header::IF_MODIFIED_SINCE);
+        if let Some(etag) = cached.headers.get(header::ETAG) {
+            headers.insert(header::IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = cached.headers.get(header::LAST_MODIFIED) {
+            headers.insert(header::IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+
+    let resp = match send_with_failover(&Method::GET, path, headers, Bytes::new()).await {
         Ok(resp) => resp,
 
         Err(request::RequestError::Timeout) => {
@@ -33,41 +78,112 @@ pub async fn load(url: &str, headers: HeaderMap) -> Loaded {
             return Loaded::Special(StatusCode::BAD_GATEWAY);
         }
     };
+    if let (StatusCode::NOT_MODIFIED, Some(mut cached)) = (resp.status(), cached) {
+        metrics::inc("miragend_upstream_revalidations_total", &[]);
+        cache::merge_not_modified(&mut cached.headers, resp.headers());
+        if let (Some(key), Some(req_headers)) = (cache_key, &req_headers) {
+            cache::put_upstream(key, &cached, req_headers);
+        }
 
-    // 读取 content-type，如果为空或 `text/html`，则返回 body
-    let content_type = match resp.headers().get("content-type") {
-        None => ContentType::Html,
-        Some(header) => match header.to_str() {
-            Ok(value) => {
-                if value.starts_with("text/html") {
-                    ContentType::Html
-                } else if value.starts_with("application/json") {
-                    ContentType::Json
-                } else {
-                    error!("unsupported content-type: {}", value);
-
-                    return Loaded::Special(StatusCode::BAD_GATEWAY);
-                }
-            }
-            Err(e) => {
-                error!("illegal content-type: {}", e);
-
-                return Loaded::Special(StatusCode::BAD_GATEWAY);
-            }
-        },
-    };
+        return Loaded::Forward(cached);
+    }
 
     let status = resp.status();
     let headers = resp.headers().clone();
-    let body = match resp.text().await {
-        Ok(body) => body,
-        Err(e) => {
-            // 读取响应体失败
-            error!("failed to read response body: {}", e);
+    let loaded = buffer(status, headers, streaming::forwarded(resp), path, true).await;
+    if let (Loaded::Forward(resp), Some(key), Some(req_headers)) =
+        (&loaded, cache_key, &req_headers)
+    {
+        cache::put_upstream(key, resp, req_headers);
+    }
 
-            return Loaded::Special(StatusCode::BAD_GATEWAY);
+    loaded
+}
+
+// Read the response of the inner service, unsupported content is left untouched
+pub async fn read(resp: http::Response<Body>, path: &str) -> Loaded {
+    let (parts, body) = resp.into_parts();
+
+    buffer(parts.status, parts.headers, body, path, false).await
+}
+
+// Buffer, decompress and decode the transformable content, the rest is sent as is without
+// buffering. The unsupported content of the upstreams is answered as configured, so is the
+// transformable content that can't be decompressed or buffered.
+async fn buffer(
+    status: StatusCode,
+    mut headers: HeaderMap,
+    body: Body,
+    path: &str,
+    upstream: bool,
+) -> Loaded {
+    let answer_unsupported = |e: &str, headers: &HeaderMap, body: Body| {
+        if upstream {
+            unsupported(e, path, status, headers, body)
+        } else {
+            untouched(status, headers, body)
         }
     };
+    let max_body_bytes = vars::max_body_bytes();
+    let oversized = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|length| length > max_body_bytes);
+    let encoding = headers
+        .get(header::CONTENT_ENCODING)
+        .map(|v| v.to_str().unwrap_or_default().trim().to_ascii_lowercase())
+        .filter(|encoding| encoding != "identity");
+    // The content sent as is isn't buffered, the streams would never end
+    match declared_content_type(&headers) {
+        _ if is_stream(&headers) => return untouched(status, &headers, body),
+        Some(Ok(content_type)) if !transforms(&content_type, path) => {
+            return untouched(status, &headers, body);
+        }
+        Some(Err(e)) if !status.is_redirection() => {
+            return answer_unsupported(&e, &headers, body);
+        }
+        // The transformable content must never reach the bots untransformed
+        _ if oversized => {
+            let e = format!("content-length over {} bytes", max_body_bytes);
+            return unsupported(&e, path, status, &headers, body);
+        }
+        _ => {}
+    }
+    let timeout = Duration::from_secs(vars::connect_timeout_secs());
+    let bytes =
+        match tokio::time::timeout(timeout, axum::body::to_bytes(body, max_body_bytes)).await {
+            Ok(Ok(bytes)) => bytes,
+            Ok(Err(e)) => {
+                // 读取响应体失败
+                error!("failed to read response body: {}", e);
+
+                return Loaded::Special(StatusCode::BAD_GATEWAY);
+            }
+            Err(_) => return Loaded::Special(StatusCode::GATEWAY_TIMEOUT),
+        };
+    let bytes = match encoding {
+        Some(encoding) => match decompress(&encoding, &bytes, max_body_bytes) {
+            Ok(decompressed) => {
+                headers.remove(header::CONTENT_ENCODING);
+                headers.remove(header::CONTENT_LENGTH);
+
+                Bytes::from(decompressed)
+            }
+            Err(e) => return unsupported(&e, path, status, &headers, bytes),
+        },
+        None => bytes,
+    };
+    let content_type = match content_type(&headers, &bytes) {
+        Ok(content_type) => content_type,
+        // The body of redirects is forwarded as is
+        Err(_) if status.is_redirection() => ContentType::Text,
+        Err(e) => return answer_unsupported(&e, &headers, Body::from(bytes)),
+    };
+    if !transforms(&content_type, path) {
+        return untouched(status, &headers, bytes);
+    }
+    let body = decode(&mut headers, &bytes, &content_type);
+
     Loaded::Forward(Response {
         status,
         headers,
@@ -75,3 +191,601 @@ pub async fn load(url: &str, headers: HeaderMap) -> Loaded {
         body,
     })
 }
+
+// Decompress the body by its `Content-Encoding`, bounded by the limit
+fn decompress(encoding: &str, bytes: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+
+    let reader: Box<dyn Read + '_> = match encoding {
+        "gzip" | "x-gzip" => Box::new(flate2::read::GzDecoder::new(bytes)),
+        "deflate" => Box::new(flate2::read::ZlibDecoder::new(bytes)),
+        _ => return Err(format!("unsupported content-encoding: {}", encoding)),
+    };
+    let mut decompressed = vec![];
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| format!("failed to decompress the body: {}", e))?;
+    if decompressed.len() > limit {
+        return Err(format!("decompressed body over {} bytes", limit));
+    }
+
+    Ok(decompressed)
+}
+
+// Answer the unsupported content as configured
+fn unsupported(
+    e: &str,
+    path: &str,
+    status: StatusCode,
+    headers: &HeaderMap,
+    body: impl Into<Body>,
+) -> Loaded {
+    let unknown_content = vars::unknown_content();
+    warn!("{} of `{}`, answered by `{}`", e, path, unknown_content);
+
+    match unknown_content {
+        UnknownContent::Passthrough => untouched(status, headers, body),
+        UnknownContent::Block => Loaded::Special(
+            StatusCode::from_u16(vars::block_status()).unwrap_or(StatusCode::FORBIDDEN),
+        ),
+        UnknownContent::Error => Loaded::Special(StatusCode::BAD_GATEWAY),
+    }
+}
+
+fn untouched(status: StatusCode, headers: &HeaderMap, body: impl Into<Body>) -> Loaded {
+    let built = http::Response::builder()
+        .status(status)
+        .append_raw_headers(headers)
+        .body(body.into());
+    match built {
+        Ok(resp) => Loaded::Untouched(resp),
+        Err(e) => {
+            error!("failed to create response: {}", e);
+
+            Loaded::Special(StatusCode::BAD_GATEWAY)
+        }
+    }
+}
+
+// Send the request as is for the passthrough rules, the response body is neither checked nor buffered
+pub async fn load_raw(
+    method: &Method,
+    path: &str,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<http::Response<Body>, StatusCode> {
+    let resp = match send_with_failover(method, path, headers, body).await {
+        Ok(resp) => resp,
+        Err(request::RequestError::Timeout) => return Err(StatusCode::GATEWAY_TIMEOUT),
+        Err(request::RequestError::Reqwest(e)) => {
+            error!("{}", e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    let builder = http::Response::builder()
+        .status(resp.status())
+        .append_raw_headers(resp.headers());
+
+    builder.body(streaming::forwarded(resp)).map_err(|e| {
+        error!("failed to create response: {}", e);
+
+        StatusCode::BAD_GATEWAY
+    })
+}
+
+// Try the next upstream on errors or 5xx responses, the last result is returned.
+// The non-idempotent requests are only sent again if they never reached the upstream
+async fn send_with_failover(
+    method: &Method,
+    path: &str,
+    mut headers: HeaderMap,
+    body: Bytes,
+) -> Result<reqwest::Response, request::RequestError> {
+    let upstreams = vars::upstream_base_urls();
+    let order = balancer::order();
+    for (i, &index) in order.iter().enumerate() {
+        let base_url = &upstreams[index];
+        headers::set_upstream_host(&mut headers, &vars::upstream_domains()[index]);
+        let result = {
+            let _guard = balancer::acquire(index);
+            send_with_retry(
+                method,
+                &format!("{}{}", base_url, path),
+                headers.clone(),
+                body.clone(),
+            )
+            .await
+        };
+        let failed = match &result {
+            Ok(resp) => resp.status().is_server_error(),
+            Err(_) => true,
+        };
+        balancer::report(index, !failed);
+        let replayable = method.is_idempotent() || is_unsent(&result);
+        let Some(next) = order.get(i + 1).filter(|_| failed && replayable) else {
+            return result;
+        };
+
+        warn!(
+            "upstream {} failed, trying {}",
+            config::mask_url(base_url),
+            config::mask_url(&upstreams[*next])
+        );
+        metrics::inc(
+            "miragend_upstream_failovers_total",
+            &[("upstream", &config::mask_url(base_url))],
+        );
+    }
+
+    unreachable!("no upstream configured")
+}
+
+// Retry on connect errors and the configured statuses, with exponential backoff
+async fn send_with_retry(
+    method: &Method,
+    url: &str,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<reqwest::Response, request::RequestError> {
+    let mut attempt = 0;
+    loop {
+        let result = request::send(method.clone(), url, headers.clone(), body.clone()).await;
+        let outcome = match &result {
+            Ok(resp) => Outcome::Status(resp.status()),
+            result if is_unsent(result) => Outcome::Unsent,
+            _ => Outcome::Failed,
+        };
+        let Some(backoff) = retry_backoff(
+            method,
+            outcome,
+            attempt,
+            vars::retry_count(),
+            vars::retry_statuses(),
+            Duration::from_millis(vars::retry_backoff_ms()),
+        ) else {
+            return result;
+        };
+
+        attempt += 1;
+        warn!(
+            "retrying {} ({}/{}) in {}ms",
+            url,
+            attempt,
+            vars::retry_count(),
+            backoff.as_millis()
+        );
+        metrics::inc("miragend_upstream_retries_total", &[]);
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+// Result of an attempt as seen by the retries
+#[derive(Debug, Clone, Copy)]
+enum Outcome {
+    Status(StatusCode),
+    // Failed to connect
+    Unsent,
+    // Timed out or failed after being sent
+    Failed,
+}
+
+// Delay before the next attempt, `None` if the result is final.
+// Retrying timeouts would multiply the latency.
+fn retry_backoff(
+    method: &Method,
+    outcome: Outcome,
+    attempt: u32,
+    retry_count: u32,
+    retry_statuses: &[u16],
+    backoff: Duration,
+) -> Option<Duration> {
+    let retryable = match outcome {
+        Outcome::Status(status) => {
+            method.is_idempotent() && retry_statuses.contains(&status.as_u16())
+        }
+        Outcome::Unsent => true,
+        Outcome::Failed => false,
+    };
+
+    (retryable && attempt < retry_count)
+        .then(|| backoff.saturating_mul(2u32.saturating_pow(attempt)))
+}
+
+// The request failed to connect, so the upstream never received it
+fn is_unsent(result: &Result<reqwest::Response, request::RequestError>) -> bool {
+    matches!(result, Err(request::RequestError::Reqwest(e)) if e.is_connect())
+}
+
+// The XML and the scripts are only transformed on the configured paths, the stylesheets never
+fn transforms(content_type: &ContentType, path: &str) -> bool {
+    match content_type {
+        ContentType::Xml => xml::transforms(path),
+        ContentType::Js => js::transforms(path),
+        ContentType::Css => false,
+        _ => true,
+    }
+}
+
+// Decode the body as UTF-8, the charset of the `Content-Type` header is corrected if transcoded
+fn decode(headers: &mut HeaderMap, bytes: &[u8], content_type: &ContentType) -> String {
+    let encoding = charset(headers, bytes, content_type).unwrap_or(encoding_rs::UTF_8);
+    let (body, _, had_errors) = encoding.decode(bytes);
+    if had_errors {
+        warn!("malformed {} sequences in the body", encoding.name());
+    }
+    if encoding != encoding_rs::UTF_8 {
+        let mime = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .unwrap_or("text/html")
+            .trim()
+            .to_owned();
+        if let Ok(value) = HeaderValue::from_str(&format!("{}; charset=utf-8", mime)) {
+            headers.insert(header::CONTENT_TYPE, value);
+        }
+    }
+
+    body.into_owned()
+}
+
+// Detect the charset from the `Content-Type` header, then the meta tag of the HTML
+fn charset(
+    headers: &HeaderMap,
+    bytes: &[u8],
+    content_type: &ContentType,
+) -> Option<&'static encoding_rs::Encoding> {
+    let label = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(charset_param)
+        .or_else(|| match content_type {
+            ContentType::Html => meta_charset(bytes, "charset="),
+            ContentType::Xml => meta_charset(bytes, "encoding="),
+            _ => None,
+        });
+
+    match label {
+        Some(label) => encoding_rs::Encoding::for_label(label.as_bytes()),
+        None if std::str::from_utf8(bytes).is_ok() => Some(encoding_rs::UTF_8),
+        None => None,
+    }
+}
+
+fn charset_param(value: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches(['"', '\'']).to_owned())
+    })
+}
+
+// Scan the leading bytes for `<meta charset="...">` or `<meta http-equiv content="...; charset=...">`,
+// or for `<?xml encoding="..."?>` by the key
+fn meta_charset(bytes: &[u8], key: &str) -> Option<String> {
+    let head = String::from_utf8_lossy(&bytes[..bytes.len().min(1024)]).to_lowercase();
+    let start = head.find(key)? + key.len();
+    let label: String = head[start..]
+        .trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || ['-', '_', ':', '.'].contains(c))
+        .collect();
+
+    (!label.is_empty()).then_some(label)
+}
+
+// The declared content type, or the one sniffed from the body if the header is missing
+fn content_type(headers: &HeaderMap, body: &[u8]) -> Result<ContentType, String> {
+    declared_content_type(headers).unwrap_or_else(|| sniff(body))
+}
+
+pub(crate) fn declared_content_type(headers: &HeaderMap) -> Option<Result<ContentType, String>> {
+    let header = headers.get(header::CONTENT_TYPE)?;
+
+    Some(match header.to_str() {
+        Ok(value) => {
+            if value.starts_with("text/html") {
+                Ok(ContentType::Html)
+            } else if value.starts_with("application/json") {
+                Ok(ContentType::Json)
+            } else if value.starts_with("text/plain") {
+                Ok(ContentType::Text)
+            } else if is_xml(value) {
+                Ok(ContentType::Xml)
+            } else if is_js(value) {
+                Ok(ContentType::Js)
+            } else if value.starts_with("text/css") {
+                Ok(ContentType::Css)
+            } else {
+                Err(format!("unsupported content-type: {}", value))
+            }
+        }
+        Err(e) => Err(format!("illegal content-type: {}", e)),
+    })
+}
+
+// `text/xml`, `application/xml` and the `+xml` types like `application/rss+xml`
+fn is_xml(value: &str) -> bool {
+    let mime = value.split(';').next().unwrap_or_default().trim();
+
+    ["text/xml", "application/xml"]
+        .iter()
+        .any(|xml| mime.eq_ignore_ascii_case(xml))
+        || mime.to_ascii_lowercase().ends_with("+xml")
+}
+
+// The event streams and the other long-lived responses
+fn is_stream(headers: &HeaderMap) -> bool {
+    let Some(value) = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = value.split(';').next().unwrap_or_default().trim();
+
+    [
+        "text/event-stream",
+        "application/x-ndjson",
+        "multipart/x-mixed-replace",
+    ]
+    .iter()
+    .any(|stream| mime.eq_ignore_ascii_case(stream))
+}
+
+// `application/javascript` and its legacy aliases
+fn is_js(value: &str) -> bool {
+    let mime = value.split(';').next().unwrap_or_default().trim();
+
+    [
+        "application/javascript",
+        "text/javascript",
+        "application/x-javascript",
+        "application/ecmascript",
+        "text/ecmascript",
+    ]
+    .iter()
+    .any(|js| mime.eq_ignore_ascii_case(js))
+}
+
+// Guess the content type from the leading bytes, an empty body is still taken as HTML
+fn sniff(body: &[u8]) -> Result<ContentType, String> {
+    const MAGIC: [(&[u8], &str); 8] = [
+        (b"\x89PNG", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"RIFF", "image/webp"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"\0asm", "application/wasm"),
+    ];
+    if let Some((_, mime)) = MAGIC.iter().find(|(magic, _)| body.starts_with(magic)) {
+        return Err(format!("unsupported sniffed content-type: {}", mime));
+    }
+    let body = body.strip_prefix(b"\xef\xbb\xbf").unwrap_or(body);
+    let start = body
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .map_or(&[][..], |i| &body[i..]);
+
+    match start.first() {
+        None => Ok(ContentType::Html),
+        Some(b'<') if start.starts_with(b"<?xml") => Ok(ContentType::Xml),
+        Some(b'<') => Ok(ContentType::Html),
+        Some(b'{' | b'[') => Ok(ContentType::Json),
+        // A character may be cut at the end of the sample
+        _ if body.contains(&0)
+            || std::str::from_utf8(&body[..body.len().min(1024)])
+                .is_err_and(|e| e.error_len().is_some()) =>
+        {
+            Err("unsupported sniffed content-type: application/octet-stream".to_owned())
+        }
+        _ => Ok(ContentType::Text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b""), Ok(ContentType::Html));
+        assert_eq!(
+            sniff(b"\xef\xbb\xbf\n <!doctype html>"),
+            Ok(ContentType::Html)
+        );
+        assert_eq!(sniff(b" {\"a\": 1}"), Ok(ContentType::Json));
+        assert_eq!(sniff("纯文本".as_bytes()), Ok(ContentType::Text));
+        assert_eq!(
+            sniff(b"<?xml version=\"1.0\"?><urlset/>"),
+            Ok(ContentType::Xml)
+        );
+        assert!(sniff(b"\x89PNG\r\n\x1a\n").is_err());
+        assert!(sniff(b"abc\0def").is_err());
+    }
+
+    #[test]
+    fn test_is_stream() {
+        let headers = |content_type: &'static str| {
+            HeaderMap::from_iter([(header::CONTENT_TYPE, HeaderValue::from_static(content_type))])
+        };
+        assert!(is_stream(&headers("text/event-stream; charset=utf-8")));
+        assert!(is_stream(&headers("application/x-ndjson")));
+        assert!(!is_stream(&headers("text/html")));
+        assert!(!is_stream(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_charset() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            "text/html; charset=GBK".parse().unwrap(),
+        );
+        assert_eq!(
+            charset(&headers, b"", &ContentType::Html),
+            Some(encoding_rs::GBK)
+        );
+
+        let html = br#"<html><head><meta charset="big5"></head></html>"#;
+        assert_eq!(
+            charset(&HeaderMap::new(), html, &ContentType::Html),
+            Some(encoding_rs::BIG5)
+        );
+        let html = br#"<meta http-equiv="Content-Type" content="text/html; charset=iso-8859-1">"#;
+        assert_eq!(
+            charset(&HeaderMap::new(), html, &ContentType::Html),
+            Some(encoding_rs::WINDOWS_1252)
+        );
+        assert_eq!(
+            charset(&HeaderMap::new(), b"plain", &ContentType::Text),
+            Some(encoding_rs::UTF_8)
+        );
+        assert_eq!(
+            charset(&HeaderMap::new(), &[0xff, 0xfe, 0x00], &ContentType::Json),
+            None
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::CONTENT_TYPE,
+            "text/plain; charset=gbk".parse().unwrap(),
+        );
+        let (bytes, _, _) = encoding_rs::GBK.encode("你好");
+        assert_eq!(decode(&mut headers, &bytes, &ContentType::Text), "你好");
+        assert_eq!(
+            headers.get(header::CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let backoff = Duration::from_millis(100);
+        let retry = |method: &Method, outcome: Outcome, attempt: u32| {
+            retry_backoff(method, outcome, attempt, 2, &[502, 503], backoff)
+        };
+        let unavailable = Outcome::Status(StatusCode::SERVICE_UNAVAILABLE);
+
+        // Doubled on every attempt, until the count is reached
+        assert_eq!(
+            retry(&Method::GET, unavailable, 0),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            retry(&Method::GET, unavailable, 1),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(retry(&Method::GET, unavailable, 2), None);
+        assert_eq!(
+            retry(
+                &Method::GET,
+                Outcome::Status(StatusCode::INTERNAL_SERVER_ERROR),
+                0
+            ),
+            None
+        );
+        assert_eq!(
+            retry(&Method::GET, Outcome::Status(StatusCode::OK), 0),
+            None
+        );
+        // The non-idempotent requests are only sent again if they never reached the upstream
+        assert_eq!(retry(&Method::POST, unavailable, 0), None);
+        assert_eq!(
+            retry(&Method::POST, Outcome::Unsent, 1),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(retry(&Method::GET, Outcome::Failed, 0), None);
+        assert_eq!(
+            retry_backoff(&Method::GET, Outcome::Unsent, 0, 0, &[], backoff),
+            None
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let read = |headers: &[(header::HeaderName, &'static str)], body: Vec<u8>| {
+            let mut resp = http::Response::new(Body::from(body));
+            for (name, value) in headers {
+                resp.headers_mut()
+                    .insert(name, HeaderValue::from_static(value));
+            }
+
+            runtime.block_on(super::read(resp, "/"))
+        };
+        assert!(matches!(
+            read(&[(header::CONTENT_TYPE, "text/html")], b"<p>hi</p>".to_vec()),
+            Loaded::Forward(Response { body, .. }) if body == "<p>hi</p>"
+        ));
+        assert!(matches!(
+            read(&[(header::CONTENT_TYPE, "image/png")], vec![]),
+            Loaded::Untouched(_)
+        ));
+    }
+
+    #[test]
+    fn test_read_compressed() {
+        use std::io::Write;
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let read = |encoding: &'static str, body: Vec<u8>| {
+            let mut resp = http::Response::new(Body::from(body));
+            let headers = resp.headers_mut();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+            headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+
+            runtime.block_on(super::read(resp, "/"))
+        };
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(b"<p>hi</p>").unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert!(matches!(
+            read("gzip", gzipped),
+            Loaded::Forward(Response { body, headers, .. })
+                if body == "<p>hi</p>" && !headers.contains_key(header::CONTENT_ENCODING)
+        ));
+        // Answered as the unsupported content, `error` by default
+        assert!(matches!(
+            read("br", b"\x1b".to_vec()),
+            Loaded::Special(StatusCode::BAD_GATEWAY)
+        ));
+        assert!(matches!(
+            read("gzip", b"not gzip".to_vec()),
+            Loaded::Special(StatusCode::BAD_GATEWAY)
+        ));
+    }
+
+    #[test]
+    fn test_read_oversized() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let mut resp = http::Response::new(Body::empty());
+        let headers = resp.headers_mut();
+        headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        headers.insert(
+            header::CONTENT_LENGTH,
+            HeaderValue::from_static("999999999999"),
+        );
+        assert!(matches!(
+            runtime.block_on(super::read(resp, "/")),
+            Loaded::Special(StatusCode::BAD_GATEWAY)
+        ));
+    }
+}
@@ -0,0 +1,420 @@
+use crate::vars;
+use anyhow::Context;
+use axum::body::Body;
+use http::{header, Response, StatusCode};
+use log::info;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::OnceLock};
+
+const FONT_FAMILY: &str = "miragend-fontmap";
+// Characters are only permuted within their own range, the ciphertext still looks like text
+const PERMUTED_RANGES: [std::ops::RangeInclusive<char>; 3] = ['a'..='z', 'A'..='Z', '0'..='9'];
+
+static FONT_MAP: OnceLock<FontMap> = OnceLock::new();
+
+struct FontMap {
+    // Plaintext char -> ciphertext char
+    cipher: HashMap<char, char>,
+    font: Vec<u8>,
+    // Changes with the font, for the cache busting of the font URL
+    version: String,
+}
+
+pub fn enabled() -> bool {
+    vars::obfuscation_mode() == "fontmap"
+}
+
+// Generate the scrambled font, call on startup
+pub fn load() -> anyhow::Result<()> {
+    if !enabled() || FONT_MAP.get().is_some() {
+        return Ok(());
+    }
+    if vars::fontmap_font().is_empty() {
+        anyhow::bail!("`MIRAGEND_FONTMAP_FONT` is required by the `fontmap` obfuscation mode");
+    }
+
+    let source = std::fs::read(vars::fontmap_font())
+        .context(format!("failed to read `{}`", vars::fontmap_font()))?;
+    let font_map = build(&source, &secret_seed())?;
+    info!(
+        "generated scrambled font from {} ({} characters permuted)",
+        vars::fontmap_font(),
+        font_map.cipher.len()
+    );
+    let _ = FONT_MAP.set(font_map);
+
+    Ok(())
+}
+
+// Replicas sharing the challenge secret generate the same font
+fn secret_seed() -> [u8; 32] {
+    Sha256::digest(format!("fontmap:{}", vars::challenge_secret()).as_bytes()).into()
+}
+
+fn build(source: &[u8], seed: &[u8; 32]) -> anyhow::Result<FontMap> {
+    let font = Font::parse(source)?;
+    let cmap = font.table(b"cmap").context("missing `cmap` table")?;
+    let glyph_of = |c: char| lookup_glyph(cmap, c as u32).filter(|&gid| gid != 0);
+
+    let mut rng = StdRng::from_seed(*seed);
+    let mut cipher = HashMap::new();
+    let mut mapping = vec![];
+    for range in PERMUTED_RANGES {
+        let plain: Vec<(char, u16)> = range
+            .filter_map(|c| glyph_of(c).map(|gid| (c, gid)))
+            .collect();
+        let mut shuffled: Vec<char> = plain.iter().map(|(c, _)| *c).collect();
+        shuffled.shuffle(&mut rng);
+        for ((c, gid), encoded) in plain.into_iter().zip(shuffled) {
+            cipher.insert(c, encoded);
+            // The ciphertext char is rendered with the glyph of the plaintext char
+            mapping.push((encoded as u32, gid));
+        }
+    }
+    if cipher.is_empty() {
+        anyhow::bail!("the font has no glyph to permute");
+    }
+
+    let mut tables = font.tables.clone();
+    for (tag, data) in tables.iter_mut() {
+        match &*tag {
+            b"cmap" => *data = build_cmap(&mut mapping),
+            // The glyph names reveal the mapping
+            b"post" if data.len() >= 32 => {
+                let mut post = data[..32].to_vec();
+                post[..4].copy_from_slice(&0x0003_0000u32.to_be_bytes());
+                *data = post;
+            }
+            _ => {}
+        }
+    }
+    let font = serialize(font.sfnt_version, &mut tables);
+    let version = hex(&Sha256::digest(&font)[..4]);
+
+    Ok(FontMap {
+        cipher,
+        font,
+        version,
+    })
+}
+
+// Rewrite the text with the ciphertext chars
+pub fn encode(text: &str) -> String {
+    match FONT_MAP.get() {
+        Some(font_map) => text
+            .chars()
+            .map(|c| font_map.cipher.get(&c).copied().unwrap_or(c))
+            .collect(),
+        None => text.to_owned(),
+    }
+}
+
+pub fn is_font_path(path: &str) -> bool {
+    enabled() && path == vars::fontmap_path()
+}
+
+pub fn build_resp() -> Response<Body> {
+    let Some(font_map) = FONT_MAP.get() else {
+        return crate::special_response::build_resp_with_fallback(StatusCode::NOT_FOUND);
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "font/ttf")
+        // The URL is versioned
+        .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+        .body(Body::from(font_map.font.clone()))
+        .unwrap_or_else(|_| {
+            crate::special_response::build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
+        })
+}
+
+// The `@font-face` rendering the ciphertext as the original text,
+// forced on every element since the page's own rules would otherwise override it
+pub fn style() -> Option<String> {
+    let font_map = FONT_MAP.get()?;
+
+    Some(format!(
+        "@font-face{{font-family:{family};src:url(\"{path}?v={version}\") format(\"truetype\")}}*{{font-family:{family},{fallback} !important}}",
+        family = FONT_FAMILY,
+        path = vars::fontmap_path(),
+        version = font_map.version,
+        fallback = vars::fontmap_fallback(),
+    ))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+struct Font {
+    sfnt_version: u32,
+    tables: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Font {
+    fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let sfnt_version = read_u32(data, 0).context("invalid font")?;
+        if ![0x0001_0000, u32::from_be_bytes(*b"true")].contains(&sfnt_version) {
+            anyhow::bail!("only TrueType fonts are supported");
+        }
+        let num_tables = read_u16(data, 4).context("invalid font")? as usize;
+        let mut tables = Vec::with_capacity(num_tables);
+        for i in 0..num_tables {
+            let record = 12 + i * 16;
+            let tag: [u8; 4] = data
+                .get(record..record + 4)
+                .and_then(|tag| tag.try_into().ok())
+                .context("invalid table record")?;
+            let offset = read_u32(data, record + 8).context("invalid table record")? as usize;
+            let length = read_u32(data, record + 12).context("invalid table record")? as usize;
+            let table = data.get(offset..offset + length).context(format!(
+                "truncated `{}` table",
+                String::from_utf8_lossy(&tag)
+            ))?;
+            tables.push((tag, table.to_vec()));
+        }
+
+        Ok(Self {
+            sfnt_version,
+            tables,
+        })
+    }
+
+    fn table(&self, tag: &[u8; 4]) -> Option<&[u8]> {
+        self.tables
+            .iter()
+            .find(|(t, _)| t == tag)
+            .map(|(_, data)| data.as_slice())
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+// Find the glyph in the Unicode subtables of format 4 or 12
+fn lookup_glyph(cmap: &[u8], code: u32) -> Option<u16> {
+    let num_tables = read_u16(cmap, 2)? as usize;
+    let mut subtables: Vec<(u16, u16, usize)> = (0..num_tables)
+        .filter_map(|i| {
+            let record = 4 + i * 8;
+            Some((
+                read_u16(cmap, record)?,
+                read_u16(cmap, record + 2)?,
+                read_u32(cmap, record + 4)? as usize,
+            ))
+        })
+        .filter(|(platform, encoding, _)| {
+            *platform == 0 || (*platform == 3 && [1, 10].contains(encoding))
+        })
+        .collect();
+    // Prefer the full repertoire subtables
+    subtables.sort_by_key(|(_, encoding, _)| std::cmp::Reverse(*encoding));
+
+    subtables.into_iter().find_map(|(_, _, offset)| {
+        let subtable = cmap.get(offset..)?;
+        match read_u16(subtable, 0)? {
+            4 => lookup_format4(subtable, code),
+            12 => lookup_format12(subtable, code),
+            _ => None,
+        }
+    })
+}
+
+fn lookup_format4(subtable: &[u8], code: u32) -> Option<u16> {
+    let code = u16::try_from(code).ok()?;
+    let seg_count = read_u16(subtable, 6)? as usize / 2;
+    let end_codes = 14;
+    let start_codes = end_codes + seg_count * 2 + 2;
+    let id_deltas = start_codes + seg_count * 2;
+    let id_range_offsets = id_deltas + seg_count * 2;
+    for i in 0..seg_count {
+        let end = read_u16(subtable, end_codes + i * 2)?;
+        if end < code {
+            continue;
+        }
+        let start = read_u16(subtable, start_codes + i * 2)?;
+        if start > code {
+            return None;
+        }
+        let delta = read_u16(subtable, id_deltas + i * 2)?;
+        let range_offset_pos = id_range_offsets + i * 2;
+        let range_offset = read_u16(subtable, range_offset_pos)? as usize;
+        if range_offset == 0 {
+            return Some(code.wrapping_add(delta));
+        }
+        let glyph_pos = range_offset_pos + range_offset + (code - start) as usize * 2;
+        let glyph = read_u16(subtable, glyph_pos)?;
+
+        return (glyph != 0).then(|| glyph.wrapping_add(delta));
+    }
+
+    None
+}
+
+fn lookup_format12(subtable: &[u8], code: u32) -> Option<u16> {
+    let num_groups = read_u32(subtable, 12)? as usize;
+    (0..num_groups).find_map(|i| {
+        let group = 16 + i * 12;
+        let start = read_u32(subtable, group)?;
+        let end = read_u32(subtable, group + 4)?;
+        let start_glyph = read_u32(subtable, group + 8)?;
+
+        (start..=end)
+            .contains(&code)
+            .then(|| u16::try_from(start_glyph + code - start).ok())
+            .flatten()
+    })
+}
+
+// Build a `cmap` table with a single Windows Unicode BMP subtable
+fn build_cmap(mapping: &mut [(u32, u16)]) -> Vec<u8> {
+    mapping.sort();
+    // Every code is a segment, the last one is the required 0xFFFF segment
+    let mut segments: Vec<(u16, u16)> = mapping
+        .iter()
+        .filter_map(|&(code, gid)| Some((u16::try_from(code).ok()?, gid.wrapping_sub(code as u16))))
+        .collect();
+    segments.push((0xFFFF, 1));
+    let seg_count = segments.len();
+    let entry_selector = seg_count.ilog2() as u16;
+    let search_range = 2 * (1u16 << entry_selector);
+    let length = 16 + seg_count * 8;
+
+    let mut subtable = Vec::with_capacity(length);
+    for value in [
+        4,
+        length as u16,
+        0,
+        (seg_count * 2) as u16,
+        search_range,
+        entry_selector,
+        (seg_count * 2) as u16 - search_range,
+    ] {
+        subtable.extend(value.to_be_bytes());
+    }
+    segments
+        .iter()
+        .for_each(|(code, _)| subtable.extend(code.to_be_bytes()));
+    subtable.extend(0u16.to_be_bytes());
+    segments
+        .iter()
+        .for_each(|(code, _)| subtable.extend(code.to_be_bytes()));
+    segments
+        .iter()
+        .for_each(|(_, delta)| subtable.extend(delta.to_be_bytes()));
+    segments
+        .iter()
+        .for_each(|_| subtable.extend(0u16.to_be_bytes()));
+
+    let mut cmap = vec![];
+    cmap.extend(0u16.to_be_bytes());
+    cmap.extend(1u16.to_be_bytes());
+    cmap.extend(3u16.to_be_bytes());
+    cmap.extend(1u16.to_be_bytes());
+    cmap.extend(12u32.to_be_bytes());
+    cmap.extend(subtable);
+
+    cmap
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(u32::from_be_bytes(word))
+    })
+}
+
+// Write the font file with the checksums recomputed
+fn serialize(sfnt_version: u32, tables: &mut [([u8; 4], Vec<u8>)]) -> Vec<u8> {
+    tables.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let num_tables = tables.len() as u16;
+    let entry_selector = (num_tables.max(1)).ilog2() as u16;
+    let search_range = 16 * (1u16 << entry_selector);
+
+    let mut font = vec![];
+    font.extend(sfnt_version.to_be_bytes());
+    for value in [
+        num_tables,
+        search_range,
+        entry_selector,
+        num_tables * 16 - search_range,
+    ] {
+        font.extend(value.to_be_bytes());
+    }
+    let mut offset = 12 + tables.len() * 16;
+    let mut head_offset = None;
+    for (tag, data) in tables.iter_mut() {
+        if tag == b"head" && data.len() >= 12 {
+            // Zeroed while computing the checksums
+            data[8..12].copy_from_slice(&[0; 4]);
+            head_offset = Some(offset);
+        }
+        font.extend(*tag);
+        font.extend(checksum(data).to_be_bytes());
+        font.extend((offset as u32).to_be_bytes());
+        font.extend((data.len() as u32).to_be_bytes());
+        offset += data.len().div_ceil(4) * 4;
+    }
+    for (_, data) in tables.iter() {
+        font.extend(data);
+        font.resize(font.len().div_ceil(4) * 4, 0);
+    }
+    if let Some(head_offset) = head_offset {
+        let adjustment = 0xB1B0_AFBAu32.wrapping_sub(checksum(&font));
+        font[head_offset + 8..head_offset + 12].copy_from_slice(&adjustment.to_be_bytes());
+    }
+
+    font
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_cmap() {
+        let mut mapping = vec![
+            (u32::from('b'), 7),
+            (u32::from('a'), 3),
+            (u32::from('Z'), 60),
+        ];
+        let cmap = build_cmap(&mut mapping);
+        assert_eq!(lookup_glyph(&cmap, u32::from('a')), Some(3));
+        assert_eq!(lookup_glyph(&cmap, u32::from('b')), Some(7));
+        assert_eq!(lookup_glyph(&cmap, u32::from('Z')), Some(60));
+        assert_eq!(lookup_glyph(&cmap, u32::from('c')), None);
+    }
+
+    #[test]
+    fn test_style() {
+        let _ = FONT_MAP.set(FontMap {
+            cipher: HashMap::new(),
+            font: vec![],
+            version: "1a2b3c4d".to_owned(),
+        });
+        let style = style().unwrap();
+        assert!(style.starts_with(&format!(
+            "@font-face{{font-family:{};src:url(\"{}?v={}\")",
+            FONT_FAMILY,
+            vars::fontmap_path(),
+            FONT_MAP.get().unwrap().version
+        )));
+        assert!(style.ends_with(&format!(
+            "*{{font-family:{},{} !important}}",
+            FONT_FAMILY,
+            vars::fontmap_fallback()
+        )));
+    }
+}
@@ -1,5 +1,7 @@
-use crate::vars;
-use http::{header, HeaderMap};
+use crate::{rules, vars};
+use http::{header, HeaderMap, HeaderValue};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
 
 pub fn build_from_request(source_headers: &HeaderMap) -> HeaderMap {
     let mut headers = HeaderMap::new();
@@ -12,32 +14,563 @@ pub fn build_from_request(source_headers: &HeaderMap) -> HeaderMap {
 
         headers.insert(key, value.clone());
     }
+    for (key, value) in vars::upstream_headers() {
+        headers.insert(key, value.clone());
+    }
+
+    headers
+}
+
+// Point the `Host` header to the given upstream
+pub fn set_upstream_host(headers: &mut HeaderMap, domain: &HeaderValue) {
+    if headers.contains_key(header::HOST) {
+        headers.insert(header::HOST, domain.clone());
+    }
+}
+
+// Tell the upstream about the client, like a standard reverse proxy
+pub fn set_forwarded(
+    headers: &mut HeaderMap,
+    conn_addr: SocketAddr,
+    host: Option<&str>,
+    proto: &'static str,
+) {
+    // Unix socket connections have no address, the proxy in front already set these headers
+    if conn_addr.ip().is_unspecified() {
+        return;
+    }
+    let ip = conn_addr.ip();
+    let trusted = is_trusted_proxy(ip);
+
+    let forwarded_for = match headers.get("X-Forwarded-For").and_then(|v| v.to_str().ok()) {
+        Some(value) => format!("{}, {}", value, ip),
+        None => ip.to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+        headers.insert("X-Forwarded-For", value);
+    }
+    // Only trusted proxies can tell the original protocol and host
+    if !trusted || !headers.contains_key("X-Forwarded-Proto") {
+        headers.insert("X-Forwarded-Proto", HeaderValue::from_static(proto));
+    }
+    if !trusted || !headers.contains_key("X-Forwarded-Host") {
+        headers.remove("X-Forwarded-Host");
+        if let Some(value) = host.and_then(|host| HeaderValue::from_str(host).ok()) {
+            headers.insert("X-Forwarded-Host", value);
+        }
+    }
+
+    // RFC 7239, IPv6 addresses must be quoted
+    let node = match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    };
+    let mut element = format!("for={};proto={}", node, proto);
+    if let Some(host) = host {
+        element.push_str(&format!(";host=\"{}\"", host));
+    }
+    let forwarded = match headers.get(header::FORWARDED).and_then(|v| v.to_str().ok()) {
+        Some(value) if trusted => format!("{}, {}", value, element),
+        _ => element,
+    };
+    if let Ok(value) = HeaderValue::from_str(&forwarded) {
+        headers.insert(header::FORWARDED, value);
+    }
+}
+
+fn is_trusted_proxy(ip: IpAddr) -> bool {
+    vars::trusted_proxies()
+        .iter()
+        .any(|(network, prefix)| rules::cidr_contains(*network, *prefix, ip))
+}
+
+// Protocol of the listeners
+pub fn proto() -> &'static str {
+    if vars::tls_cert().is_empty() && vars::acme_domains().is_empty() {
+        "http"
+    } else {
+        "https"
+    }
+}
+
+// Origin of the proxy seen by the client, the forwarded values of trusted proxies are preferred
+pub fn public_origin(
+    req_headers: &HeaderMap,
+    uri: &http::Uri,
+    conn_addr: SocketAddr,
+) -> Option<String> {
+    let forwarded = |name: &str| {
+        req_headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|v| v.trim())
+            .filter(|_| is_trusted_proxy(conn_addr.ip()) || conn_addr.ip().is_unspecified())
+    };
+    let proto = forwarded("X-Forwarded-Proto").unwrap_or(proto());
+    let host = forwarded("X-Forwarded-Host")
+        .or(req_headers.get(header::HOST).and_then(|v| v.to_str().ok()))
+        .or(uri.authority().map(|a| a.as_str()))?;
+
+    Some(format!("{}://{}", proto, host))
+}
+
+// Point the redirects to the upstreams at the proxy
+pub fn rewrite_location(resp_headers: &mut HeaderMap, public_origin: Option<&str>) {
+    let Some(location) = resp_headers
+        .get(header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return;
+    };
+    let rewritten = rewritten_location(location, vars::upstream_base_urls(), public_origin);
+    if let Some(value) = rewritten.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        resp_headers.insert(header::LOCATION, value);
+    }
+}
+
+fn rewritten_location(
+    location: &str,
+    base_urls: &[String],
+    public_origin: Option<&str>,
+) -> Option<String> {
+    base_urls.iter().find_map(|base_url| {
+        let rest = strip_prefix_ignore_case(location, base_url.trim_end_matches('/'))?;
+        if !(rest.is_empty() || rest.starts_with(['/', '?', '#'])) {
+            return None;
+        }
+        let rest = if rest.starts_with('/') {
+            rest.to_owned()
+        } else {
+            format!("/{}", rest)
+        };
+
+        // A relative reference still works without the public origin
+        Some(format!("{}{}", public_origin.unwrap_or_default(), rest))
+    })
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+
+    head.eq_ignore_ascii_case(prefix)
+        .then(|| &text[prefix.len()..])
+}
+
+// Get the client IP, `X-Forwarded-For` is only honored from the trusted proxies
+pub fn client_ip(headers: &HeaderMap, conn_addr: SocketAddr) -> IpAddr {
+    forwarded_client(headers, conn_addr.ip(), is_trusted_proxy)
+}
+
+// Walk the forwarded chain from the right, the first untrusted hop is the client
+fn forwarded_client(
+    headers: &HeaderMap,
+    peer: IpAddr,
+    is_trusted: impl Fn(IpAddr) -> bool,
+) -> IpAddr {
+    // Unix socket connections have no address, the proxy in front is trusted
+    if !peer.is_unspecified() && !is_trusted(peer) {
+        return peer;
+    }
+    let hops = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .collect::<Vec<_>>();
+
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        // A malformed hop can't be trusted to tell the one before it
+        let Ok(ip) = hop.trim().parse() else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    client
+}
+
+// Get the value of a cookie from the request headers
+pub fn get_cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| {
+            let (key, value) = pair.trim().split_once('=')?;
+            (key == name).then_some(value)
+        })
+}
+
+// Strong ETag of a transformed body, visitors with different seeds get different tags
+pub fn entity_tag(body: &str, seed: Option<u64>) -> String {
+    let mut hasher = Sha256::new();
+    if let Some(seed) = seed {
+        hasher.update(seed.to_be_bytes());
+    }
+    hasher.update(body.as_bytes());
+    let digest = hasher.finalize();
+
+    format!(
+        "\"{}\"",
+        digest[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+// Check the `If-None-Match` of the request with the weak comparison
+pub fn none_match(headers: &HeaderMap, etag: &str) -> bool {
+    let strip_weak = |tag: &str| tag.trim().trim_start_matches("W/").to_owned();
+    let etag = strip_weak(etag);
 
     headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || strip_weak(tag) == etag)
 }
 
 pub trait AppendHeaders {
     fn append_headers(self, headers: &HeaderMap) -> Self;
+    // Append the headers of an unchanged body, the entity headers are kept
+    fn append_unchanged_headers(self, headers: &HeaderMap) -> Self;
+    // Append the headers of a body forwarded byte-for-byte, including its encoding
+    fn append_raw_headers(self, headers: &HeaderMap) -> Self;
 }
 
 // Ignore the response headers that should not be forwarded
-const IGNORE_RESPONSE_HEADERS: [header::HeaderName; 6] = [
+const IGNORE_RESPONSE_HEADERS: [header::HeaderName; 8] = [
     header::CONNECTION,        // Keep-Alive is not supported
     header::CONTENT_LENGTH,    // The page has been modified
     header::CONTENT_ENCODING,  // The page has been modified
-    header::ETAG,              // The page has been modified
+    header::ETAG,              // Regenerated for the modified page
     header::LAST_MODIFIED,     // The page has been modified
     header::TRANSFER_ENCODING, // Determine by proxy server
+    header::ACCEPT_RANGES,     // The page has been modified
+    header::CONTENT_RANGE,     // The page has been modified
+];
+// Ignore the response headers that should not be forwarded when the body is unchanged
+const IGNORE_UNCHANGED_RESPONSE_HEADERS: [header::HeaderName; 4] = [
+    header::CONNECTION,        // Keep-Alive is not supported
+    header::CONTENT_LENGTH,    // Determine by the buffered body
+    header::CONTENT_ENCODING,  // The body has been decoded
+    header::TRANSFER_ENCODING, // Determine by proxy server
+];
+// Ignore the hop-by-hop response headers when the body is forwarded byte-for-byte
+const IGNORE_RAW_RESPONSE_HEADERS: [header::HeaderName; 2] = [
+    header::CONNECTION,        // Keep-Alive is not supported
+    header::TRANSFER_ENCODING, // Determine by proxy server
 ];
 
 impl AppendHeaders for http::response::Builder {
     fn append_headers(self, headers: &HeaderMap) -> Self {
-        headers.iter().fold(self, |builder, (key, value)| {
-            if !IGNORE_RESPONSE_HEADERS.contains(key) {
-                builder.header(key, value)
-            } else {
-                builder
+        append_filtered(self, headers, &IGNORE_RESPONSE_HEADERS)
+    }
+
+    fn append_unchanged_headers(self, headers: &HeaderMap) -> Self {
+        append_filtered(self, headers, &IGNORE_UNCHANGED_RESPONSE_HEADERS)
+    }
+
+    fn append_raw_headers(self, headers: &HeaderMap) -> Self {
+        append_filtered(self, headers, &IGNORE_RAW_RESPONSE_HEADERS)
+    }
+}
+
+// Append the headers except the ignored and configured ones, then set the configured headers
+fn append_filtered(
+    builder: http::response::Builder,
+    headers: &HeaderMap,
+    ignored: &[header::HeaderName],
+) -> http::response::Builder {
+    let removed = vars::remove_response_headers();
+    let set = vars::response_headers();
+    let builder = headers.iter().fold(builder, |builder, (key, value)| {
+        if ignored.contains(key) || removed.contains(key) || set.iter().any(|(k, _)| k == key) {
+            builder
+        } else if key == header::SET_COOKIE {
+            let rewritten = value
+                .to_str()
+                .ok()
+                .and_then(|v| HeaderValue::from_str(&rewrite_set_cookie(v)).ok());
+            builder.header(key, rewritten.unwrap_or(value.clone()))
+        } else {
+            builder.header(key, value)
+        }
+    });
+
+    set.iter()
+        .fold(builder, |builder, (key, value)| builder.header(key, value))
+}
+
+// Add the missing security headers to any response, the ones of the upstream are kept unless
+// removed by `MIRAGEND_REMOVE_RESPONSE_HEADERS`
+pub fn set_security_headers(resp_headers: &mut HeaderMap) {
+    add_missing(resp_headers, vars::security_headers());
+}
+
+fn add_missing(resp_headers: &mut HeaderMap, headers: &[(header::HeaderName, HeaderValue)]) {
+    for (key, value) in headers {
+        if !resp_headers.contains_key(key) {
+            resp_headers.insert(key, value.clone());
+        }
+    }
+}
+
+// Scope the cookies of the upstream to the proxy
+fn rewrite_set_cookie(value: &str) -> String {
+    let upstream_domains = vars::upstream_domains()
+        .iter()
+        .filter_map(|domain| domain.to_str().ok())
+        .collect::<Vec<_>>();
+    let config = CookieRewrite {
+        upstream_domains: &upstream_domains,
+        domain: vars::cookie_domain(),
+        path: vars::cookie_path(),
+        secure: vars::cookie_secure(),
+        samesite: vars::cookie_samesite(),
+    };
+
+    config.apply(value)
+}
+
+struct CookieRewrite<'a> {
+    upstream_domains: &'a [&'a str],
+    domain: &'a str,
+    path: &'a str,
+    secure: Option<bool>,
+    samesite: &'a str,
+}
+
+impl CookieRewrite<'_> {
+    fn apply(&self, value: &str) -> String {
+        let mut parts = value.split(';').map(str::trim);
+        let mut output = vec![parts.next().unwrap_or_default().to_owned()];
+        let mut has_path = false;
+        for attr in parts.filter(|attr| !attr.is_empty()) {
+            let name = attr.split('=').next().unwrap_or_default().trim();
+            match name.to_ascii_lowercase().as_str() {
+                "domain" => {
+                    let domain = attr
+                        .split_once('=')
+                        .map(|(_, v)| v.trim())
+                        .unwrap_or_default();
+                    if !self.is_upstream_domain(domain) {
+                        output.push(attr.to_owned());
+                    } else if !self.domain.is_empty() {
+                        output.push(format!("Domain={}", self.domain));
+                    }
+                }
+                "path" if !self.path.is_empty() => {
+                    has_path = true;
+                    output.push(format!("Path={}", self.path));
+                }
+                // Added back below if configured
+                "secure" if self.secure.is_some() => {}
+                "samesite" if !self.samesite.is_empty() => {}
+                _ => output.push(attr.to_owned()),
             }
+        }
+        if !self.path.is_empty() && !has_path {
+            output.push(format!("Path={}", self.path));
+        }
+        if self.secure == Some(true) {
+            output.push("Secure".to_owned());
+        }
+        if !self.samesite.is_empty() {
+            output.push(format!("SameSite={}", self.samesite));
+        }
+
+        output.join("; ")
+    }
+
+    // `.example.com` covers `www.example.com`
+    fn is_upstream_domain(&self, domain: &str) -> bool {
+        let domain = domain.trim_start_matches('.').to_ascii_lowercase();
+        self.upstream_domains.iter().any(|upstream| {
+            let upstream = upstream
+                .split(':')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            upstream == domain || upstream.ends_with(&format!(".{}", domain))
         })
     }
 }
+
+// Set the decision headers for trusted proxies, the same headers are always removed for others
+pub fn set_decision_headers(
+    resp_headers: &mut HeaderMap,
+    conn_addr: SocketAddr,
+    values: &[(&str, &str)],
+) {
+    let trusted = is_trusted_proxy(conn_addr.ip());
+    for (name, template) in vars::decision_headers() {
+        resp_headers.remove(name);
+        if !trusted {
+            continue;
+        }
+
+        let value = values.iter().fold(template.clone(), |value, (key, v)| {
+            value.replace(&format!("{{{}}}", key), v)
+        });
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            resp_headers.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_client() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Forwarded-For",
+            HeaderValue::from_static("1.1.1.1, 2.2.2.2, 10.0.0.2"),
+        );
+        let is_trusted = |ip: IpAddr| ip.to_string().starts_with("10.");
+        let peer = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        // The spoofed chain of an untrusted peer is ignored
+        assert_eq!(
+            forwarded_client(&headers, peer("3.3.3.3"), is_trusted),
+            peer("3.3.3.3")
+        );
+        // The trusted hops are skipped, the client is the first untrusted one from the right
+        assert_eq!(
+            forwarded_client(&headers, peer("10.0.0.1"), is_trusted),
+            peer("2.2.2.2")
+        );
+        assert_eq!(
+            forwarded_client(&HeaderMap::new(), peer("10.0.0.1"), is_trusted),
+            peer("10.0.0.1")
+        );
+    }
+
+    #[test]
+    fn test_entity_tag() {
+        let tag = entity_tag("<p>hello</p>", None);
+        assert_eq!(tag.len(), 18);
+        assert!(tag.starts_with('"') && tag.ends_with('"'));
+        assert_eq!(tag, entity_tag("<p>hello</p>", None));
+        assert_ne!(tag, entity_tag("<p>hello!</p>", None));
+        assert_ne!(tag, entity_tag("<p>hello</p>", Some(1)));
+    }
+
+    #[test]
+    fn test_none_match() {
+        let mut headers = HeaderMap::new();
+        assert!(!none_match(&headers, "\"abc\""));
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"xyz\", W/\"abc\""),
+        );
+        assert!(none_match(&headers, "\"abc\""));
+        assert!(!none_match(&headers, "\"abd\""));
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("*"));
+        assert!(none_match(&headers, "\"abd\""));
+    }
+
+    #[test]
+    fn test_rewrite_set_cookie() {
+        let mut config = CookieRewrite {
+            upstream_domains: &["www.example.com"],
+            domain: "",
+            path: "",
+            secure: None,
+            samesite: "",
+        };
+        assert_eq!(
+            config.apply("id=1; Domain=.example.com; Path=/app; Secure"),
+            "id=1; Path=/app; Secure"
+        );
+        assert_eq!(
+            config.apply("id=1; Domain=other.com"),
+            "id=1; Domain=other.com"
+        );
+
+        config.domain = "proxy.test";
+        config.path = "/";
+        config.secure = Some(false);
+        config.samesite = "Lax";
+        assert_eq!(
+            config.apply("id=1; domain=www.example.com; Secure; SameSite=None; HttpOnly"),
+            "id=1; Domain=proxy.test; HttpOnly; Path=/; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn test_rewritten_location() {
+        let base_urls = ["http://origin.internal:8000".to_owned()];
+        let origin = Some("https://example.com");
+        assert_eq!(
+            rewritten_location(
+                "http://origin.internal:8000/login?next=/",
+                &base_urls,
+                origin
+            ),
+            Some("https://example.com/login?next=/".to_owned())
+        );
+        assert_eq!(
+            rewritten_location("HTTP://ORIGIN.internal:8000", &base_urls, None),
+            Some("/".to_owned())
+        );
+        assert_eq!(
+            rewritten_location("http://origin.internal:80001/", &base_urls, origin),
+            None
+        );
+        assert_eq!(rewritten_location("/relative", &base_urls, origin), None);
+    }
+
+    #[test]
+    fn test_append_filtered() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from_static("10"));
+        headers.insert(header::ETAG, HeaderValue::from_static("\"a\""));
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        let builder = http::Response::builder().append_headers(&headers);
+        let appended = builder.headers_ref().unwrap();
+        assert!(!appended.contains_key(header::CONTENT_LENGTH));
+        assert!(!appended.contains_key(header::ETAG));
+        assert_eq!(appended[header::CACHE_CONTROL], "no-cache");
+
+        let builder = http::Response::builder().append_unchanged_headers(&headers);
+        let appended = builder.headers_ref().unwrap();
+        assert!(!appended.contains_key(header::CONTENT_LENGTH));
+        assert_eq!(appended[header::ETAG], "\"a\"");
+    }
+
+    #[test]
+    fn test_add_missing() {
+        let mut resp_headers = HeaderMap::new();
+        resp_headers.insert(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        );
+        add_missing(
+            &mut resp_headers,
+            &[
+                (
+                    header::REFERRER_POLICY,
+                    HeaderValue::from_static("strict-origin-when-cross-origin"),
+                ),
+                (
+                    header::X_CONTENT_TYPE_OPTIONS,
+                    HeaderValue::from_static("nosniff"),
+                ),
+            ],
+        );
+        assert_eq!(resp_headers[header::REFERRER_POLICY], "no-referrer");
+        assert_eq!(resp_headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    }
+}
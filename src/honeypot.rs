@@ -0,0 +1,143 @@
+use crate::{
+    html_ops::{self, DOMOps},
+    vars,
+};
+use html5ever::local_name;
+use log::info;
+use markup5ever_rcdom::Handle;
+use rand::Rng;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    rc::Rc,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+// Flagged client IPs with the flagging time
+static FLAGGED: LazyLock<Mutex<HashMap<IpAddr, Instant>>> = LazyLock::new(Default::default);
+// Bound of the flagged clients, the trap links can be followed from any number of addresses
+const MAX_FLAGGED: usize = 65536;
+
+pub fn enabled() -> bool {
+    !vars::honeypot_prefix().is_empty()
+}
+
+// Check if the path is a trap link
+pub fn is_trap(path: &str) -> bool {
+    enabled() && path.starts_with(vars::honeypot_prefix())
+}
+
+// Record the client in the ban list
+pub fn flag(ip: IpAddr) {
+    info!("client {} followed a honeypot link, flagged", ip);
+
+    let ttl = Duration::from_secs(vars::honeypot_ban_secs());
+    let mut flagged = FLAGGED.lock().unwrap_or_else(|e| e.into_inner());
+    insert_capped(&mut flagged, ip, ttl, MAX_FLAGGED);
+}
+
+// Evict the expired clients when full, then the earliest flagged
+fn insert_capped(flagged: &mut HashMap<IpAddr, Instant>, ip: IpAddr, ttl: Duration, max: usize) {
+    if flagged.len() >= max && !flagged.contains_key(&ip) {
+        flagged.retain(|_, flagged_at| flagged_at.elapsed() < ttl);
+        if flagged.len() >= max {
+            let earliest = flagged
+                .iter()
+                .min_by_key(|(_, flagged_at)| **flagged_at)
+                .map(|(ip, _)| *ip);
+            if let Some(earliest) = earliest {
+                flagged.remove(&earliest);
+            }
+        }
+    }
+    flagged.insert(ip, Instant::now());
+}
+
+pub fn is_flagged(ip: IpAddr) -> bool {
+    if !enabled() {
+        return false;
+    }
+
+    let ttl = Duration::from_secs(vars::honeypot_ban_secs());
+    let mut flagged = FLAGGED.lock().unwrap_or_else(|e| e.into_inner());
+    match flagged.get(&ip) {
+        Some(flagged_at) if flagged_at.elapsed() < ttl => true,
+        Some(_) => {
+            // Expired
+            flagged.remove(&ip);
+
+            false
+        }
+        None => false,
+    }
+}
+
+// Build a trap URL, or a random non-existent one if the honeypot is disabled
+pub fn trap_url(rng: &mut impl Rng) -> String {
+    let prefix = if enabled() {
+        vars::honeypot_prefix()
+    } else {
+        "/"
+    };
+
+    format!("{}{}", prefix, random_token(rng))
+}
+
+fn random_token(rng: &mut impl Rng) -> String {
+    rng.sample_iter(rand::distributions::Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+// Append an invisible trap link to the body
+pub fn inject_link(handle: Handle) {
+    let Some(body) = handle.get_body() else {
+        return;
+    };
+
+    let token = random_token(&mut rand::thread_rng());
+    let link = html_ops::build_element(
+        local_name!("a"),
+        vec![
+            (
+                local_name!("href"),
+                format!("{}{}", vars::honeypot_prefix(), token).into(),
+            ),
+            (local_name!("rel"), "nofollow".into()),
+            // Not a `style` attribute, the CSP of the page can't reveal it
+            (local_name!("hidden"), "".into()),
+            (local_name!("aria-hidden"), "true".into()),
+            (local_name!("tabindex"), "-1".into()),
+        ],
+        vec![html_ops::build_text(token.into())],
+    );
+    link.parent.set(Some(Rc::downgrade(&body)));
+    body.children.borrow_mut().push(link);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_capped() {
+        let ip = |n: u8| IpAddr::from([10, 0, 0, n]);
+        let ttl = Duration::from_secs(60);
+        let mut flagged = HashMap::new();
+        flagged.insert(ip(1), Instant::now() - Duration::from_secs(120));
+        flagged.insert(ip(2), Instant::now() - Duration::from_secs(30));
+        flagged.insert(ip(3), Instant::now() - Duration::from_secs(10));
+
+        // The expired client goes first
+        insert_capped(&mut flagged, ip(4), ttl, 3);
+        assert_eq!(flagged.len(), 3);
+        assert!(!flagged.contains_key(&ip(1)));
+        // Then the earliest flagged one
+        insert_capped(&mut flagged, ip(5), ttl, 3);
+        assert_eq!(flagged.len(), 3);
+        assert!(!flagged.contains_key(&ip(2)));
+        assert!(flagged.contains_key(&ip(5)));
+    }
+}
@@ -14,13 +14,17 @@ pub trait DOMBuilder {
 
 pub trait DOMOps {
     fn get_element_by_id(self, id: &str) -> Option<Rc<Node>>;
+    fn find_by_selector(self, selector: &str) -> Option<Rc<Node>>;
+    fn find_all_by_selector(self, selector: &str) -> Vec<Rc<Node>>;
     fn get_head(self) -> Option<Rc<Node>>;
+    fn get_body(self) -> Option<Rc<Node>>;
     fn find_meta_tags(self) -> Vec<Rc<Node>>;
 }
 
 pub trait NodeOps {
     fn get_attribute(&self, name: &LocalName) -> Option<Tendril<UTF8>>;
     fn set_attribute(&mut self, name: &LocalName, value: Tendril<UTF8>);
+    fn matches_selector(&self, selector: &str) -> bool;
 }
 
 impl DOMBuilder for &str {
@@ -61,6 +65,35 @@ impl DOMOps for Handle {
         None
     }
 
+    fn find_by_selector(self, selector: &str) -> Option<Rc<Node>> {
+        let children = self.children.borrow();
+        for child in children.iter() {
+            if child.matches_selector(selector) {
+                return Some(Rc::clone(child));
+            }
+
+            if let Some(node) = Self::find_by_selector(Rc::clone(child), selector) {
+                return Some(node);
+            }
+        }
+
+        None
+    }
+
+    fn find_all_by_selector(self, selector: &str) -> Vec<Rc<Node>> {
+        let mut found = Vec::new();
+        let children = self.children.borrow();
+        for child in children.iter() {
+            if child.matches_selector(selector) {
+                found.push(Rc::clone(child));
+            }
+
+            found.append(&mut Self::find_all_by_selector(Rc::clone(child), selector));
+        }
+
+        found
+    }
+
     fn get_head(self) -> Option<Rc<Node>> {
         let children = self.children.borrow();
         for child in children.iter() {
@@ -78,6 +111,23 @@ impl DOMOps for Handle {
         None
     }
 
+    fn get_body(self) -> Option<Rc<Node>> {
+        let children = self.children.borrow();
+        for child in children.iter() {
+            if let Element { name, .. } = &child.data {
+                if name.local == local_name!("body") {
+                    return Some(Rc::clone(child));
+                }
+
+                if let Some(node) = Self::get_body(Rc::clone(child)) {
+                    return Some(node);
+                }
+            }
+        }
+
+        None
+    }
+
     fn find_meta_tags(self) -> Vec<Rc<Node>> {
         let mut meta_tags = Vec::new();
         let children = self.children.borrow();
@@ -118,6 +168,70 @@ impl NodeOps for Rc<Node> {
             }
         }
     }
+
+    // Simple selectors: `#id`, `.class`, `[attr]`, `[attr=value]` and their compounds like
+    // `div.ad[data-slot]`, a bare name is an id as before and a lone tag is written as `<div>`
+    fn matches_selector(&self, selector: &str) -> bool {
+        const QUALIFIERS: [char; 3] = ['#', '.', '['];
+        let Element { ref name, .. } = self.data else {
+            return false;
+        };
+
+        let selector = selector.trim();
+        if let Some(tag) = selector.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+            return !tag.is_empty() && name.local.eq_ignore_ascii_case(tag);
+        }
+        let (tag, mut rest) =
+            selector.split_at(selector.find(QUALIFIERS).unwrap_or(selector.len()));
+        if rest.is_empty() {
+            return !tag.is_empty()
+                && self
+                    .get_attribute(&local_name!("id"))
+                    .is_some_and(|value| value.as_ref() == tag);
+        }
+        if !tag.is_empty() && !name.local.eq_ignore_ascii_case(tag) {
+            return false;
+        }
+
+        while let Some(kind) = rest.chars().next() {
+            let body = &rest[1..];
+            let (matched, after) = if kind == '[' {
+                let Some(end) = body.find(']') else {
+                    return false;
+                };
+                let matched = match body[..end].split_once('=') {
+                    Some((attr, expected)) => self
+                        .get_attribute(&LocalName::from(attr.trim()))
+                        .is_some_and(|value| {
+                            value.as_ref() == expected.trim().trim_matches(['"', '\''])
+                        }),
+                    None => self
+                        .get_attribute(&LocalName::from(body[..end].trim()))
+                        .is_some(),
+                };
+
+                (matched, &body[end + 1..])
+            } else {
+                let (value, after) = body.split_at(body.find(QUALIFIERS).unwrap_or(body.len()));
+                let matched = !value.is_empty()
+                    && if kind == '#' {
+                        self.get_attribute(&local_name!("id"))
+                            .is_some_and(|id| id.as_ref() == value)
+                    } else {
+                        self.get_attribute(&local_name!("class"))
+                            .is_some_and(|class| class.split_ascii_whitespace().any(|c| c == value))
+                    };
+
+                (matched, after)
+            };
+            if !matched {
+                return false;
+            }
+            rest = after;
+        }
+
+        true
+    }
 }
 
 pub fn extract_contents(handle: &Handle) -> Vec<Rc<Node>> {
@@ -139,25 +253,108 @@ pub fn extract_contents(handle: &Handle) -> Vec<Rc<Node>> {
     }
 }
 
-// todo: 转换为通用函数生成，此 API 作为快捷方式。
-pub fn build_script(url: Tendril<UTF8>) -> Rc<Node> {
-    Node::new(Element {
-        name: QualName::new(None, ns!(html), local_name!("script")),
-        attrs: RefCell::new(vec![Attribute {
-            name: QualName::new(None, ns!(), local_name!("src")),
-            value: url,
-        }]),
+pub fn build_element(
+    name: LocalName,
+    attrs: Vec<(LocalName, Tendril<UTF8>)>,
+    children: Vec<Rc<Node>>,
+) -> Rc<Node> {
+    let node = Node::new(Element {
+        name: QualName::new(None, ns!(html), name),
+        attrs: RefCell::new(
+            attrs
+                .into_iter()
+                .map(|(name, value)| Attribute {
+                    name: QualName::new(None, ns!(), name),
+                    value,
+                })
+                .collect(),
+        ),
         template_contents: RefCell::new(None),
         mathml_annotation_xml_integration_point: false,
-    })
+    });
+    for child in children {
+        child.parent.set(Some(Rc::downgrade(&node)));
+        node.children.borrow_mut().push(child);
+    }
+
+    node
 }
 
-pub fn build_newline() -> Rc<Node> {
+pub fn build_script(url: Tendril<UTF8>) -> Rc<Node> {
+    build_element(
+        local_name!("script"),
+        vec![(local_name!("src"), url)],
+        vec![],
+    )
+}
+
+pub fn build_inline_script(code: Tendril<UTF8>) -> Rc<Node> {
+    build_element(local_name!("script"), vec![], vec![build_text(code)])
+}
+
+pub fn build_stylesheet(url: Tendril<UTF8>) -> Rc<Node> {
+    build_element(
+        local_name!("link"),
+        vec![
+            (local_name!("rel"), "stylesheet".into()),
+            (local_name!("href"), url),
+        ],
+        vec![],
+    )
+}
+
+pub fn build_style(css: Tendril<UTF8>) -> Rc<Node> {
+    build_element(local_name!("style"), vec![], vec![build_text(css)])
+}
+
+// Replace the node in the children of its parent
+pub fn replace_node(node: &Handle, new_node: Rc<Node>) {
+    let Some(parent) = node.parent.take().and_then(|parent| parent.upgrade()) else {
+        return;
+    };
+    let mut children = parent.children.borrow_mut();
+    if let Some(index) = children.iter().position(|child| Rc::ptr_eq(child, node)) {
+        new_node.parent.set(Some(Rc::downgrade(&parent)));
+        children[index] = new_node;
+    }
+}
+
+// Remove the node from the children of its parent
+pub fn detach(node: &Handle) {
+    let Some(parent) = node.parent.take().and_then(|parent| parent.upgrade()) else {
+        return;
+    };
+    parent
+        .children
+        .borrow_mut()
+        .retain(|child| !Rc::ptr_eq(child, node));
+}
+
+pub fn build_text(text: Tendril<UTF8>) -> Rc<Node> {
     Node::new(markup5ever_rcdom::NodeData::Text {
-        contents: RefCell::new("\n".into()),
+        contents: RefCell::new(text),
     })
 }
 
+pub fn build_newline() -> Rc<Node> {
+    build_text("\n".into())
+}
+
+// Text of the nodes in document order, the scripts and the styles are not text
+pub fn text_content(handle: &Handle) -> String {
+    let mut text = String::new();
+    for child in handle.children.borrow().iter() {
+        match &child.data {
+            markup5ever_rcdom::NodeData::Text { contents } => text.push_str(&contents.borrow()),
+            Element { name, .. }
+                if name.local == local_name!("script") || name.local == local_name!("style") => {}
+            _ => text.push_str(&text_content(child)),
+        }
+    }
+
+    text
+}
+
 pub fn serialize_to_html(dom: RcDom) -> anyhow::Result<String> {
     let mut buf = Vec::new();
     let document: SerializableHandle = Rc::clone(&dom.document).into();
@@ -166,6 +363,45 @@ pub fn serialize_to_html(dom: RcDom) -> anyhow::Result<String> {
     String::from_utf8(buf).context("failed to convert HTML to string")
 }
 
+// Candidates of a `srcset` as their URL and descriptors, a URL may contain commas
+pub fn parse_srcset(srcset: &str) -> Vec<(&str, &str)> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            return candidates;
+        }
+        let (url, after) = rest.split_at(
+            rest.find(|c: char| c.is_ascii_whitespace())
+                .unwrap_or(rest.len()),
+        );
+        // Trailing commas end the candidate without descriptors
+        let trimmed = url.trim_end_matches(',');
+        if trimmed.len() < url.len() {
+            candidates.push((trimmed, ""));
+            rest = after;
+            continue;
+        }
+        // The descriptors end at a comma outside the parentheses
+        let mut in_parens = false;
+        let end = after
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '(' => in_parens = true,
+                    ')' => in_parens = false,
+                    ',' if !in_parens => return true,
+                    _ => {}
+                }
+                false
+            })
+            .map_or(after.len(), |(i, _)| i);
+        candidates.push((url, after[..end].trim()));
+        rest = &after[end..];
+    }
+}
+
 #[cfg(test)]
 mod dom_builder_tests {
     use super::*;
@@ -201,6 +437,26 @@ mod dom_builder_tests {
     }
 }
 
+#[cfg(test)]
+mod srcset_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_srcset() {
+        assert_eq!(
+            parse_srcset(" a.png 1x, b,c.png  2x ,d.png,, e.png (max-width: 1px, x) 100w"),
+            vec![
+                ("a.png", "1x"),
+                ("b,c.png", "2x"),
+                ("d.png", ""),
+                ("e.png", "(max-width: 1px, x) 100w"),
+            ]
+        );
+        assert_eq!(parse_srcset("a.png,b.png"), vec![("a.png,b.png", "")]);
+        assert!(parse_srcset(" , ").is_empty());
+    }
+}
+
 #[cfg(test)]
 mod dom_opts_tests {
     use super::*;
@@ -318,6 +574,80 @@ mod node_ops_tests {
         assert_eq!(id.unwrap(), "hello".into());
     }
 
+    #[test]
+    fn test_matches_selector() {
+        let html = r#"
+            <html>
+                <body>
+                    <nav id="menu" class="site-nav sticky">
+                        <p>Hello, World!</p>
+                    </nav>
+                </body>
+            </html>"#;
+
+        let dom = html.build_document().unwrap();
+        let nav = Rc::clone(&dom.document).get_element_by_id("menu").unwrap();
+        for selector in ["#menu", "menu", ".site-nav", ".sticky", "<nav>", "<NAV>"] {
+            assert!(nav.matches_selector(selector), "{}", selector);
+        }
+        for selector in [
+            "nav#menu",
+            "nav.site-nav.sticky",
+            "[id]",
+            "nav[class=\"site-nav sticky\"]",
+            "#menu.sticky",
+        ] {
+            assert!(nav.matches_selector(selector), "{}", selector);
+        }
+        for selector in [
+            "",
+            "nav",
+            "<>",
+            "<div>",
+            "#nav",
+            ".site",
+            "div",
+            "#",
+            "div#menu",
+            "nav.sticky.top",
+            "[hidden]",
+            "nav[id=nav]",
+            "nav[id",
+        ] {
+            assert!(!nav.matches_selector(selector), "{}", selector);
+        }
+        let found = Rc::clone(&dom.document).find_by_selector(".sticky");
+        assert!(found.is_some_and(|node| Rc::ptr_eq(&node, &nav)));
+    }
+
+    #[test]
+    fn test_detach() {
+        let html = r#"
+            <html>
+                <body>
+                    <article><p>Hello</p><aside class="ad">Buy</aside></article>
+                    <aside class="ad">Now</aside>
+                </body>
+            </html>"#;
+
+        let dom = html.build_document().unwrap();
+        let ads = Rc::clone(&dom.document).find_all_by_selector("aside.ad");
+        assert_eq!(ads.len(), 2);
+        for ad in &ads {
+            detach(ad);
+        }
+        assert!(Rc::clone(&dom.document).find_by_selector(".ad").is_none());
+        assert!(Rc::clone(&dom.document).find_by_selector("<p>").is_some());
+    }
+
+    #[test]
+    fn test_text_content() {
+        let dom = "<html><head><title>T</title><style>p{}</style></head><body><p>Hello <b>World</b></p><script>x()</script></body></html>"
+            .build_document()
+            .unwrap();
+        assert_eq!(text_content(&dom.document), "THello World");
+    }
+
     #[test]
     fn test_set_attribute() {
         let html = r#"
@@ -0,0 +1,304 @@
+use crate::{
+    config, csp,
+    html_ops::{self, DOMOps, NodeOps},
+    vars,
+};
+use html5ever::local_name;
+use http::HeaderMap;
+use markup5ever_rcdom::{Handle, Node, NodeData};
+use std::{collections::BTreeMap, rc::Rc, sync::LazyLock};
+
+// Injections of the transformed pages from the `injections` section, in order
+static INJECTIONS: LazyLock<Vec<Injection>> = LazyLock::new(|| {
+    let mut injections: Vec<Injection> = config::section("injections")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `injections` section")
+        .unwrap_or_default();
+    let url = vars::inject_online_script();
+    if !url.is_empty() {
+        injections.push(Injection {
+            kind: Kind::Script,
+            position: Position::HeadEnd,
+            content: url.to_owned(),
+        });
+    }
+
+    injections
+});
+
+// Script and style sources of the injections, allowed by the CSP of the transformed pages
+static CSP_SOURCES: LazyLock<(Vec<String>, Vec<String>)> = LazyLock::new(|| {
+    let (scripts, styles): (Vec<_>, Vec<_>) = INJECTIONS
+        .iter()
+        .partition(|injection| matches!(injection.kind, Kind::Script | Kind::InlineScript));
+    let sources = |injections: Vec<&Injection>| {
+        injections
+            .into_iter()
+            .map(Injection::csp_source)
+            .collect::<Vec<_>>()
+    };
+
+    (sources(scripts), sources(styles))
+});
+// Meta tags of every proxied page from the `meta_tags` section, `name = "content"`
+static META_TAGS: LazyLock<BTreeMap<String, String>> = LazyLock::new(|| {
+    config::section("meta_tags")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `meta_tags` section")
+        .unwrap_or_default()
+});
+
+#[derive(Debug, serde::Deserialize)]
+pub struct Injection {
+    kind: Kind,
+    #[serde(default)]
+    position: Position,
+    // URL of the external kinds, code of the inline kinds
+    content: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Kind {
+    Script,
+    InlineScript,
+    Stylesheet,
+    InlineStyle,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum Position {
+    HeadStart,
+    #[default]
+    HeadEnd,
+    BodyEnd,
+}
+
+pub fn force_init() {
+    LazyLock::force(&INJECTIONS);
+    LazyLock::force(&META_TAGS);
+    LazyLock::force(&CSP_SOURCES);
+}
+
+// Let the upstream CSP allow the injections and the other inline styles of the page
+pub fn allow_in_csp(headers: &mut HeaderMap, inline_styles: &[String]) {
+    let (scripts, styles) = &*CSP_SOURCES;
+    if INJECTIONS.is_empty() && inline_styles.is_empty() {
+        return;
+    }
+    let styles = styles
+        .iter()
+        .cloned()
+        .chain(inline_styles.iter().map(|style| csp::hash_source(style)))
+        .collect::<Vec<_>>();
+    csp::allow(headers, scripts, &styles);
+}
+
+pub fn has_meta_tags() -> bool {
+    !META_TAGS.is_empty()
+}
+
+pub fn inject_meta_tags(document: &Handle) {
+    set_meta_tags(document, &META_TAGS);
+}
+
+// The existing tags of the same names are updated instead of duplicated
+fn set_meta_tags(document: &Handle, tags: &BTreeMap<String, String>) {
+    let Some(head) = Rc::clone(document).get_head() else {
+        return;
+    };
+    let mut existing = Rc::clone(&head).find_meta_tags();
+    for (name, content) in tags {
+        let found = existing.iter_mut().find(|meta| {
+            meta.get_attribute(&local_name!("name"))
+                .is_some_and(|value| value.eq_ignore_ascii_case(name))
+        });
+        match found {
+            Some(meta) => meta.set_attribute(&local_name!("content"), content.as_str().into()),
+            None => {
+                let meta = html_ops::build_element(
+                    local_name!("meta"),
+                    vec![
+                        (local_name!("name"), name.as_str().into()),
+                        (local_name!("content"), content.as_str().into()),
+                    ],
+                    vec![],
+                );
+                let mut children = head.children.borrow_mut();
+                children.push(meta);
+                children.push(html_ops::build_newline());
+            }
+        }
+    }
+}
+
+// Apply `MIRAGEND_TITLE_TEMPLATE` to the title, and to `og:title` if enabled
+pub fn rewrite_title(document: &Handle) {
+    let template = vars::title_template();
+    if !template.is_empty() {
+        apply_title_template(document, template, vars::title_template_og_title());
+    }
+}
+
+fn apply_title_template(document: &Handle, template: &str, og_title: bool) {
+    let Some(head) = Rc::clone(document).get_head() else {
+        return;
+    };
+    let render = |original: &str| template.replace("{{original}}", original.trim());
+    let title = head.children.borrow().iter().find(|child| {
+        matches!(&child.data, NodeData::Element { name, .. } if name.local == local_name!("title"))
+    }).cloned();
+    if let Some(title) = title {
+        let original = title
+            .children
+            .borrow()
+            .iter()
+            .filter_map(|child| match &child.data {
+                NodeData::Text { contents } => Some(contents.borrow().to_string()),
+                _ => None,
+            })
+            .collect::<String>();
+        *title.children.borrow_mut() = vec![html_ops::build_text(render(&original).into())];
+    }
+    if og_title {
+        for mut meta in head.find_meta_tags() {
+            let is_og_title = meta
+                .get_attribute(&local_name!("property"))
+                .is_some_and(|property| property.eq_ignore_ascii_case("og:title"));
+            if let Some(content) = meta
+                .get_attribute(&local_name!("content"))
+                .filter(|_| is_og_title)
+            {
+                meta.set_attribute(&local_name!("content"), render(&content).into());
+            }
+        }
+    }
+}
+
+pub fn inject(document: &Handle) {
+    inject_all(document, &INJECTIONS);
+}
+
+fn inject_all(document: &Handle, injections: &[Injection]) {
+    let head = Rc::clone(document).get_head();
+    let body = Rc::clone(document).get_body();
+    // The injections at the start of the head keep their order
+    let mut head_start = 0;
+    for injection in injections {
+        let node = injection.build();
+        match (&injection.position, &head, &body) {
+            (Position::HeadStart, Some(head), _) => {
+                head.children.borrow_mut().insert(head_start, node);
+                head_start += 1;
+            }
+            (Position::HeadEnd, Some(parent), _) | (Position::BodyEnd, _, Some(parent)) => {
+                let mut children = parent.children.borrow_mut();
+                children.push(node);
+                children.push(html_ops::build_newline());
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Injection {
+    fn build(&self) -> Rc<Node> {
+        let content = self.content.as_str().into();
+        match self.kind {
+            Kind::Script => html_ops::build_script(content),
+            Kind::InlineScript => html_ops::build_inline_script(content),
+            Kind::Stylesheet => html_ops::build_stylesheet(content),
+            Kind::InlineStyle => html_ops::build_style(content),
+        }
+    }
+
+    fn csp_source(&self) -> String {
+        match self.kind {
+            Kind::Script | Kind::Stylesheet => csp::url_source(&self.content),
+            Kind::InlineScript | Kind::InlineStyle => csp::hash_source(&self.content),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_ops::DOMBuilder;
+
+    #[test]
+    fn test_inject_all() {
+        let injections: Vec<Injection> = toml::from_str::<toml::Table>(
+            r#"
+            [[injections]]
+            kind = "stylesheet"
+            position = "head-start"
+            content = "/a.css"
+
+            [[injections]]
+            kind = "inline-style"
+            position = "head-start"
+            content = "p{color:red}"
+
+            [[injections]]
+            kind = "script"
+            content = "/a.js"
+
+            [[injections]]
+            kind = "inline-script"
+            position = "body-end"
+            content = "run()"
+            "#,
+        )
+        .unwrap()["injections"]
+            .clone()
+            .try_into()
+            .unwrap();
+
+        let dom = "<html><head><title>T</title></head><body><p>Hi</p></body></html>"
+            .build_document()
+            .unwrap();
+        inject_all(&dom.document, &injections);
+        assert_eq!(
+            html_ops::serialize_to_html(dom).unwrap(),
+            "<html><head>\
+                <link rel=\"stylesheet\" href=\"/a.css\"><style>p{color:red}</style>\
+                <title>T</title><script src=\"/a.js\"></script>\n\
+            </head><body><p>Hi</p><script>run()</script>\n</body></html>"
+        );
+    }
+
+    #[test]
+    fn test_set_meta_tags() {
+        let tags = BTreeMap::from([
+            ("robots".to_owned(), "noai, noimageai".to_owned()),
+            ("x-source".to_owned(), "miragend".to_owned()),
+        ]);
+        let dom = r#"<html><head><meta name="Robots" content="index"></head><body></body></html>"#
+            .build_document()
+            .unwrap();
+        set_meta_tags(&dom.document, &tags);
+        assert_eq!(
+            html_ops::serialize_to_html(dom).unwrap(),
+            "<html><head><meta name=\"Robots\" content=\"noai, noimageai\">\
+                <meta name=\"x-source\" content=\"miragend\">\n</head><body></body></html>"
+        );
+    }
+
+    #[test]
+    fn test_apply_title_template() {
+        let html = r#"<html><head><title> Home </title><meta property="og:title" content="Home"></head><body></body></html>"#;
+        let dom = html.build_document().unwrap();
+        apply_title_template(&dom.document, "{{original}} - Archived", false);
+        let serialized = html_ops::serialize_to_html(dom).unwrap();
+        assert!(serialized.contains("<title>Home - Archived</title>"));
+        assert!(serialized.contains(r#"content="Home">"#));
+
+        let dom = html.build_document().unwrap();
+        apply_title_template(&dom.document, "{{original}} - Archived", true);
+        let serialized = html_ops::serialize_to_html(dom).unwrap();
+        assert!(serialized.contains(r#"content="Home - Archived">"#));
+    }
+}
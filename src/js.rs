@@ -0,0 +1,286 @@
+// String literal pass over the scripts, the code is copied as is
+use crate::{rules::glob_match, vars};
+
+// Keywords after which a `/` starts a regular expression instead of a division
+const REGEX_KEYWORDS: [&str; 13] = [
+    "return",
+    "typeof",
+    "instanceof",
+    "in",
+    "of",
+    "new",
+    "delete",
+    "void",
+    "throw",
+    "case",
+    "do",
+    "else",
+    "yield",
+];
+
+// Only the scripts of these paths are transformed, the rest is forwarded byte-for-byte
+pub fn transforms(path: &str) -> bool {
+    vars::js_obfuscation_paths()
+        .iter()
+        .any(|pattern| glob_match(pattern, path))
+}
+
+// Rewrite the text of the string and template literals, the escape sequences are kept
+pub fn map_strings(js: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let bytes = js.as_bytes();
+    let mut output = String::with_capacity(js.len());
+    let mut regex_allowed = true;
+    // Brace depth of the code, and the depths the template substitutions were opened at
+    let mut depth = 0;
+    let mut substitutions: Vec<usize> = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &js[i..];
+        let end = match bytes[i] {
+            b'/' if rest.starts_with("//") => i + rest.find('\n').unwrap_or(rest.len()),
+            b'/' if rest.starts_with("/*") => i + rest.find("*/").map_or(rest.len(), |j| j + 2),
+            b'/' if regex_allowed => {
+                regex_allowed = false;
+                i + regex_len(rest)
+            }
+            quote @ (b'"' | b'\'') => {
+                let len = quoted_len(rest, quote);
+                let closed = len > 1 && bytes[i + len - 1] == quote;
+                let literal = &rest[1..if closed { len - 1 } else { len }];
+                output.push(quote as char);
+                map_literal(literal, quote, &mut output, &mut f);
+                if closed {
+                    output.push(quote as char);
+                }
+                regex_allowed = false;
+                i += len;
+                continue;
+            }
+            b'`' => {
+                output.push('`');
+                let (len, opened) =
+                    map_template(&js[i + 1..], &mut output, &mut f, &mut substitutions, depth);
+                regex_allowed = opened;
+                i += 1 + len;
+                continue;
+            }
+            // The substitution is closed, the template goes on
+            b'}' if substitutions.last() == Some(&depth) => {
+                substitutions.pop();
+                output.push('}');
+                let (len, opened) =
+                    map_template(&js[i + 1..], &mut output, &mut f, &mut substitutions, depth);
+                regex_allowed = opened;
+                i += 1 + len;
+                continue;
+            }
+            b'{' => {
+                depth += 1;
+                regex_allowed = true;
+                i + 1
+            }
+            b'}' => {
+                depth = depth.saturating_sub(1);
+                regex_allowed = true;
+                i + 1
+            }
+            b')' | b']' => {
+                regex_allowed = false;
+                i + 1
+            }
+            c if c.is_ascii_whitespace() => i + 1,
+            c if is_word_byte(c) => {
+                let len = rest
+                    .find(|c: char| c.is_ascii() && !is_word_byte(c as u8))
+                    .unwrap_or(rest.len());
+                regex_allowed = REGEX_KEYWORDS.contains(&&rest[..len]);
+                i + len
+            }
+            _ => {
+                regex_allowed = true;
+                i + rest.chars().next().map_or(1, char::len_utf8)
+            }
+        };
+        output.push_str(&js[i..end]);
+        i = end;
+    }
+
+    output
+}
+
+// Identifiers and numbers, the non-ASCII bytes are taken as identifier parts
+fn is_word_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || !c.is_ascii()
+}
+
+// Length of the regular expression with its flags, the `/` in a class doesn't end it
+fn regex_len(js: &str) -> usize {
+    let mut class = false;
+    let mut escaped = false;
+    for (i, c) in js.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => class = true,
+            ']' => class = false,
+            '/' if !class => {
+                let flags = js[i + 1..]
+                    .find(|c: char| !c.is_ascii_alphabetic())
+                    .unwrap_or(js.len() - i - 1);
+                return i + 1 + flags;
+            }
+            '\n' => return i,
+            _ => {}
+        }
+    }
+
+    js.len()
+}
+
+// Length of the quoted literal with its quotes, an unterminated literal ends at the line
+fn quoted_len(js: &str, quote: u8) -> usize {
+    let bytes = js.as_bytes();
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'\n' => return i,
+            c if c == quote => return i + 1,
+            _ => {}
+        }
+        i += 1;
+    }
+
+    js.len().min(i)
+}
+
+// Map the template text until its end or a substitution,
+// the length consumed is returned with whether a substitution is opened
+fn map_template(
+    js: &str,
+    output: &mut String,
+    f: &mut impl FnMut(&str) -> String,
+    substitutions: &mut Vec<usize>,
+    depth: usize,
+) -> (usize, bool) {
+    let bytes = js.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'`' => {
+                map_literal(&js[..i], b'`', output, f);
+                output.push('`');
+                return (i + 1, false);
+            }
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                map_literal(&js[..i], b'`', output, f);
+                output.push_str("${");
+                substitutions.push(depth);
+                return (i + 2, true);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    map_literal(js, b'`', output, f);
+
+    (js.len(), false)
+}
+
+fn map_literal(literal: &str, quote: u8, output: &mut String, f: &mut impl FnMut(&str) -> String) {
+    if !is_text(literal) {
+        output.push_str(literal);
+        return;
+    }
+    let mut rest = literal;
+    while !rest.is_empty() {
+        let end = rest.find('\\').unwrap_or(rest.len());
+        if !rest[..end].is_empty() {
+            escape_into(&f(&rest[..end]), quote, output);
+        }
+        rest = &rest[end..];
+        if rest.is_empty() {
+            break;
+        }
+        let escape = escape_len(rest);
+        output.push_str(&rest[..escape]);
+        rest = &rest[escape..];
+    }
+}
+
+// Length of the escape sequence at the start, with the hex digits of `\x`, `\u` and `\u{}`
+fn escape_len(literal: &str) -> usize {
+    let mut len = match literal[1..].chars().next() {
+        Some('x') => 3,
+        Some('u') if literal[2..].starts_with('{') => {
+            literal.find('}').map_or(literal.len(), |i| i + 1)
+        }
+        Some('u') => 6,
+        Some(c) => 1 + c.len_utf8(),
+        None => 1,
+    }
+    .min(literal.len());
+    // Malformed sequences may be followed by any character
+    while !literal.is_char_boundary(len) {
+        len += 1;
+    }
+
+    len
+}
+
+// Only the literals looking like prose are text, the keys, selectors, URLs and markup are code
+fn is_text(literal: &str) -> bool {
+    let prose =
+        literal.contains(' ') || literal.chars().any(|c| !c.is_ascii() && c.is_alphabetic());
+
+    prose
+        && literal.chars().any(char::is_alphabetic)
+        // The directives like `"use strict"`
+        && !literal
+            .strip_prefix("use ")
+            .is_some_and(|directive| directive.chars().all(|c| c.is_ascii_alphabetic()))
+        && !literal.contains(['<', '>', '{', '}', '='])
+        && !literal.contains("://")
+}
+
+// The mapped text must not end the literal early
+fn escape_into(text: &str, quote: u8, output: &mut String) {
+    for c in text.chars() {
+        match c {
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '$' if quote == b'`' => output.push_str("\\$"),
+            c if c == quote as char => {
+                output.push('\\');
+                output.push(c);
+            }
+            c => output.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_strings() {
+        let js = r#""use strict";
+// "a comment"
+const a = { key: "Hello world", url: "https://a.test/b c" }, b = 'it\'s 你好';
+const re = /"not a string"/g, c = x / 2 / y;
+el.innerHTML = `<p>${n}</p>`;
+el.title = `${n} items left ${f({ k: "Good day" })} now`;"#;
+        assert_eq!(
+            map_strings(js, |text| text.to_uppercase()),
+            r#""use strict";
+// "a comment"
+const a = { key: "HELLO WORLD", url: "https://a.test/b c" }, b = 'IT\'S 你好';
+const re = /"not a string"/g, c = x / 2 / y;
+el.innerHTML = `<p>${n}</p>`;
+el.title = `${n} ITEMS LEFT ${f({ k: "GOOD DAY" })} NOW`;"#
+        );
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{config, rules::glob_match};
+use serde_json::Value;
+use std::sync::LazyLock;
+
+// Patches of the JSON responses from the `json_patches` section
+static JSON_PATCHES: LazyLock<Vec<JsonPatch>> = LazyLock::new(|| {
+    config::section("json_patches")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `json_patches` section")
+        .unwrap_or_default()
+});
+
+#[derive(Debug, serde::Deserialize)]
+pub struct JsonPatch {
+    // Path pattern, `*` matches any characters
+    path: String,
+    // RFC 7396 merge patch, applied before the operations
+    #[serde(default)]
+    merge: Option<Value>,
+    // RFC 6902 operations
+    #[serde(default)]
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+pub fn force_init() {
+    LazyLock::force(&JSON_PATCHES);
+}
+
+// Find the first patch matching the path
+pub fn find(path: &str) -> Option<&'static JsonPatch> {
+    JSON_PATCHES
+        .iter()
+        .find(|patch| glob_match(&patch.path, path))
+}
+
+impl JsonPatch {
+    // The document is left untouched if any operation fails
+    pub fn apply(&self, doc: &Value) -> anyhow::Result<Value> {
+        let mut patched = doc.clone();
+        if let Some(merge) = &self.merge {
+            merge_patch(&mut patched, merge);
+        }
+        for operation in &self.operations {
+            operation.apply(&mut patched)?;
+        }
+
+        Ok(patched)
+    }
+}
+
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(serde_json::Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(target.entry(key).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+impl Operation {
+    fn apply(&self, doc: &mut Value) -> anyhow::Result<()> {
+        match self {
+            Operation::Add { path, value } => add(doc, path, value.clone()),
+            Operation::Remove { path } => remove(doc, path).map(|_| ()),
+            Operation::Replace { path, value } => {
+                let target = doc
+                    .pointer_mut(path)
+                    .ok_or_else(|| anyhow::anyhow!("path `{}` not found", path))?;
+                *target = value.clone();
+
+                Ok(())
+            }
+            Operation::Move { from, path } => {
+                let value = remove(doc, from)?;
+                add(doc, path, value)
+            }
+            Operation::Copy { from, path } => {
+                let value = doc
+                    .pointer(from)
+                    .cloned()
+                    .ok_or_else(|| anyhow::anyhow!("path `{}` not found", from))?;
+                add(doc, path, value)
+            }
+            Operation::Test { path, value } => {
+                if doc.pointer(path) == Some(value) {
+                    Ok(())
+                } else {
+                    anyhow::bail!("test of path `{}` failed", path)
+                }
+            }
+        }
+    }
+}
+
+// Split the pointer into the parent pointer and the unescaped last token
+fn split_pointer(path: &str) -> anyhow::Result<(&str, String)> {
+    let (parent, token) = path
+        .rsplit_once('/')
+        .ok_or_else(|| anyhow::anyhow!("invalid path `{}`", path))?;
+
+    Ok((parent, token.replace("~1", "/").replace("~0", "~")))
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> anyhow::Result<()> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+
+    let (parent, token) = split_pointer(path)?;
+    match doc.pointer_mut(parent) {
+        Some(Value::Object(map)) => {
+            map.insert(token, value);
+        }
+        Some(Value::Array(items)) => {
+            let index = if token == "-" {
+                items.len()
+            } else {
+                token
+                    .parse()
+                    .ok()
+                    .filter(|index| *index <= items.len())
+                    .ok_or_else(|| anyhow::anyhow!("invalid index of path `{}`", path))?
+            };
+            items.insert(index, value);
+        }
+        _ => anyhow::bail!("parent of path `{}` not found", path),
+    }
+
+    Ok(())
+}
+
+fn remove(doc: &mut Value, path: &str) -> anyhow::Result<Value> {
+    let (parent, token) = split_pointer(path)?;
+    let removed = match doc.pointer_mut(parent) {
+        Some(Value::Object(map)) => map.remove(&token),
+        Some(Value::Array(items)) => token
+            .parse()
+            .ok()
+            .filter(|index| *index < items.len())
+            .map(|index| items.remove(index)),
+        _ => None,
+    };
+
+    removed.ok_or_else(|| anyhow::anyhow!("path `{}` not found", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(merge: Option<Value>, operations: Value) -> JsonPatch {
+        JsonPatch {
+            path: "/*".to_owned(),
+            merge,
+            operations: serde_json::from_value(operations).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let doc = json!({"title": "Hello", "content": "World", "meta": {"a": 1, "b": 2}});
+        let patched = patch(
+            Some(json!({"content": "Removed", "meta": {"a": null}})),
+            json!([]),
+        )
+        .apply(&doc)
+        .unwrap();
+        assert_eq!(
+            patched,
+            json!({"title": "Hello", "content": "Removed", "meta": {"b": 2}})
+        );
+    }
+
+    #[test]
+    fn test_operations() {
+        let doc = json!({"items": [{"content": "a"}, {"content": "b"}], "x": 1});
+        let patched = patch(
+            None,
+            json!([
+                {"op": "replace", "path": "/items/0/content", "value": "removed"},
+                {"op": "add", "path": "/items/-", "value": {"content": "c"}},
+                {"op": "remove", "path": "/items/1"},
+                {"op": "move", "from": "/x", "path": "/y"},
+                {"op": "copy", "from": "/y", "path": "/z"},
+                {"op": "test", "path": "/z", "value": 1}
+            ]),
+        )
+        .apply(&doc)
+        .unwrap();
+        assert_eq!(
+            patched,
+            json!({"items": [{"content": "removed"}, {"content": "c"}], "y": 1, "z": 1})
+        );
+
+        let failed = patch(None, json!([{"op": "test", "path": "/x", "value": 2}]));
+        assert!(failed.apply(&doc).is_err());
+        let missing = patch(
+            None,
+            json!([{"op": "replace", "path": "/nope", "value": 2}]),
+        );
+        assert!(missing.apply(&doc).is_err());
+    }
+}
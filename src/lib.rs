@@ -0,0 +1,2240 @@
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::ConnectInfo;
+use axum::http::Request;
+use classification::Classification;
+use fetching::Loaded;
+use headers::AppendHeaders;
+use html5ever::LocalName;
+use html_ops::{DOMBuilder, DOMOps, NodeOps};
+use http::{Method, Response, StatusCode};
+use log::{error, info, warn};
+use logging::RoutedInfo;
+use markup5ever::local_name;
+use markup5ever_rcdom::{Handle, Node, NodeData::Element};
+use obfuscation::{MarkovModel, Obfuscator, ObfuscatorConfig};
+use profiles::Profile;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use std::net::{IpAddr, SocketAddr};
+use std::rc::Rc;
+use std::str::Chars;
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::sync::mpsc;
+
+#[cfg(feature = "acme")]
+pub mod acme;
+pub mod admin;
+mod balancer;
+mod cache;
+mod cache_control;
+mod challenge;
+pub mod check;
+mod classification;
+pub mod config;
+mod cors;
+mod csp;
+mod decoys;
+pub mod doctor;
+pub mod embed;
+mod fallback;
+mod fetching;
+mod fontmap;
+mod headers;
+mod honeypot;
+mod html_ops;
+mod injections;
+mod js;
+mod json_patch;
+mod links;
+pub mod logging;
+mod mappings;
+mod maze;
+mod metrics;
+pub mod middleware;
+mod obfuscation;
+mod patch_content;
+#[cfg(feature = "wasm")]
+mod plugins;
+mod profiles;
+mod request;
+mod robots;
+mod rules;
+pub mod scaffold;
+#[cfg(feature = "scripting")]
+mod scripting;
+mod session;
+pub mod simulate;
+mod special_response;
+pub mod strategies;
+mod streaming;
+pub mod vars;
+mod xml;
+
+// Fallback patch contents
+const FALLBACK_PATCH_MARKDOWN: &str = include_str!("../patch-content.md");
+const FALLBACK_PATCH_HTML: &str = include_str!("../patch-content.html");
+// Ignore obfuscation for these tags
+const IGNORE_OBFUSCATION_TAGS: [&str; 5] = ["script", "noscript", "style", "template", "iframe"];
+// Attributes opting the nodes out of (or into) the obfuscation from the site templates
+const IGNORE_ATTR: &str = "data-miragend-ignore";
+const FORCE_ATTR: &str = "data-miragend-obfuscate";
+// Comments disabling the obfuscation of the region between them
+const OFF_DIRECTIVE: &str = "miragend:off";
+const ON_DIRECTIVE: &str = "miragend:on";
+const JSON_LD_TYPE: &str = "application/ld+json";
+const RAW_TEXT_TAGS: [&str; 3] = ["title", "textarea", "option"];
+const SHUFFLE_CLASS: &str = "miragend-shuffle";
+const SHUFFLE_STYLE: &str =
+    ".miragend-shuffle{display:inline-flex;flex-wrap:wrap}.miragend-shuffle>span{white-space:pre}";
+const SHUFFLE_CHUNK_LEN: usize = 4;
+// Elements that can hold the decoy nodes
+const DECOY_CONTAINER_TAGS: [&str; 9] = [
+    "body", "main", "article", "section", "div", "p", "li", "td", "span",
+];
+// Strategy configuration
+#[derive(Clone)]
+enum Strategy<'a> {
+    // Patch
+    Patch(PatchConfig<'a>),
+    // Obfuscation
+    Obfuscation,
+    // Forward the original content
+    Passthrough,
+    // Obfuscation, the response is dripped slowly
+    Tarpit,
+    // Obfuscation, then the patch, the patch content stays readable
+    Chain(PatchConfig<'a>),
+}
+
+impl Strategy<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Strategy::Patch(_) => "patch",
+            Strategy::Obfuscation => "obfuscation",
+            Strategy::Passthrough => "passthrough",
+            Strategy::Tarpit => "tarpit",
+            Strategy::Chain(_) => "patch+obfuscation",
+        }
+    }
+}
+
+// Source of the original responses
+enum Source {
+    // Fetched from the upstream
+    Upstream,
+    // Produced by the inner service of the middleware
+    Inner(middleware::RunInner),
+}
+
+#[derive(Clone)]
+struct PatchConfig<'a> {
+    // IDs or selectors tried in order
+    targets: Vec<String>,
+    content: String,
+    // `replace`, `prepend`, `append`, `before` or `after` the target node
+    mode: &'a str,
+    remove_nodes: &'a Vec<&'a str>,
+    remove_meta_tags: &'a Vec<&'a str>,
+}
+
+// Validate and initialize the configuration, call before serving any request
+pub fn validate_config() -> anyhow::Result<()> {
+    check_config()?;
+
+    logging::open_access_log()
+}
+
+// Validate the configuration without writing anything, for the `check` command
+pub(crate) fn check_config() -> anyhow::Result<()> {
+    vars::force_init();
+    rules::force_init();
+    mappings::force_init();
+    profiles::force_init();
+    json_patch::force_init();
+    injections::force_init();
+    robots::force_init();
+    cors::force_init();
+    cache_control::force_init();
+    if vars::upstream_client_cert().is_empty() != vars::upstream_client_key().is_empty() {
+        anyhow::bail!(
+            "`MIRAGEND_UPSTREAM_CLIENT_CERT` and `MIRAGEND_UPSTREAM_CLIENT_KEY` must be set together"
+        );
+    }
+    request::force_init();
+    fontmap::load()?;
+    logging::check_access_log()?;
+    let mapping_header = vars::mapping_version_header();
+    if !mapping_header.is_empty() {
+        http::HeaderName::from_bytes(mapping_header.as_bytes())
+            .context("invalid `MIRAGEND_MAPPING_VERSION_HEADER` value")?;
+    }
+    if vars::tls_cert().is_empty() != vars::tls_key().is_empty() {
+        anyhow::bail!("`MIRAGEND_TLS_CERT` and `MIRAGEND_TLS_KEY` must be set together");
+    }
+    if !vars::acme_domains().is_empty() && cfg!(not(feature = "acme")) {
+        anyhow::bail!("`MIRAGEND_ACME_DOMAINS` requires building with the `acme` feature");
+    }
+    if !vars::plugins().is_empty() && cfg!(not(feature = "wasm")) {
+        anyhow::bail!("`MIRAGEND_PLUGINS` requires building with the `wasm` feature");
+    }
+    #[cfg(feature = "wasm")]
+    plugins::load()?;
+    if !vars::decision_script().is_empty() && cfg!(not(feature = "scripting")) {
+        anyhow::bail!("`MIRAGEND_DECISION_SCRIPT` requires building with the `scripting` feature");
+    }
+    #[cfg(feature = "scripting")]
+    scripting::load()?;
+    if vars::binds().is_empty() {
+        anyhow::bail!("`MIRAGEND_BIND` has no address");
+    }
+    let bypass_header = vars::cache_bypass_header();
+    if !bypass_header.is_empty() {
+        http::HeaderName::from_bytes(bypass_header.as_bytes())
+            .context("invalid `MIRAGEND_CACHE_BYPASS_HEADER` value")?;
+    }
+
+    Ok(())
+}
+
+// Default strategy of the listener that accepted the request, overrides `MIRAGEND_STRATEGY`
+#[derive(Clone)]
+pub struct ListenerStrategy(pub &'static str);
+
+// Decision of the request
+struct Decision {
+    strategy: &'static str,
+    // Name of the matched rule
+    rule: Option<&'static str>,
+    // Chosen by a passthrough rule, the content is proxied byte-for-byte
+    raw: bool,
+}
+
+// Router of the standalone proxy, the requests of every method reach the dispatch
+pub fn router() -> axum::Router {
+    routes(handler)
+}
+
+fn routes<H, T>(handler: H) -> axum::Router
+where
+    H: axum::handler::Handler<T, ()>,
+    T: 'static,
+{
+    axum::Router::new().route("/*path", axum::routing::any(handler))
+}
+
+// Handler of the standalone proxy
+pub async fn handler(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<Body>,
+) -> Response<Body> {
+    dispatch(addr, request, Source::Upstream).await
+}
+
+// Reload the obfuscation mapping files on SIGHUP or change, run in the background
+pub async fn watch_mapping_files() {
+    mappings::watch().await
+}
+
+// Re-render the patch content when its file changes, run in the background
+pub async fn watch_patch_content() {
+    patch_content::watch(vars::patch_content_file()).await
+}
+
+async fn dispatch(addr: SocketAddr, request: Request<Body>, source: Source) -> Response<Body> {
+    let mut resp = route(addr, request, source).await;
+    // Including the special, generated and challenge responses
+    headers::set_security_headers(resp.headers_mut());
+
+    resp
+}
+
+async fn route(addr: SocketAddr, mut request: Request<Body>, source: Source) -> Response<Body> {
+    if fontmap::is_font_path(request.uri().path()) {
+        return fontmap::build_resp();
+    }
+    if decoys::is_placeholder_path(request.uri().path()) {
+        return decoys::build_resp();
+    }
+    let client_ip = headers::client_ip(request.headers(), addr);
+    if honeypot::is_trap(request.uri().path()) {
+        honeypot::flag(client_ip);
+        RoutedInfo::new(
+            &StatusCode::NOT_FOUND,
+            request.method(),
+            request.uri(),
+            request.version(),
+            request.headers(),
+            addr,
+        )
+        .print_log();
+
+        return special_response::build_resp_with_fallback(StatusCode::NOT_FOUND);
+    }
+    if let Some(resp) = cors::preflight(&request) {
+        return resp;
+    }
+
+    let classification = classification::classify(request.headers(), client_ip);
+    let path = request.uri().path().to_owned();
+    let origin = request.headers().get(http::header::ORIGIN).cloned();
+    // The plugins run on the blocking threads, ahead of the rest of the decision
+    #[cfg(feature = "wasm")]
+    let chosen = if classification == Classification::Bot {
+        let pending = plugins::decide(&request, client_ip);
+        pending.await
+    } else {
+        None
+    };
+    #[cfg(not(feature = "wasm"))]
+    let chosen = None;
+    let decision = decide(classification, &request, client_ip, chosen);
+    let strategy = decision.strategy;
+    // Only the content of the reads is transformed, the other requests are proxied as is
+    let reads = matches!(*request.method(), Method::GET | Method::HEAD);
+    let classification_name = classification.to_string();
+    metrics::inc(
+        "miragend_decisions_total",
+        &[
+            ("strategy", strategy),
+            ("classification", &classification_name),
+            ("observe", &vars::observe().to_string()),
+        ],
+    );
+
+    let mut resp = if vars::observe() {
+        // Only log the decision, all clients receive the original content
+        info!(
+            "[Observe] would apply `{}` to {} client (rule: {}): \"{}\"",
+            strategy,
+            classification,
+            decision.rule.unwrap_or("-"),
+            request.uri()
+        );
+        metrics::inc(
+            "miragend_observed_total",
+            &[
+                ("strategy", strategy),
+                ("rule", decision.rule.unwrap_or("-")),
+            ],
+        );
+        request.extensions_mut().insert(Shadow {
+            strategy,
+            rule: decision.rule,
+        });
+
+        if reads {
+            handle(addr, request, Strategy::Passthrough, classification, source).await
+        } else {
+            raw_handler(addr, request, source).await
+        }
+    } else if decision.raw || (!reads && strategy == "passthrough") {
+        raw_handler(addr, request, source).await
+    } else if !reads {
+        // The bots can't reach the upstream around the transforms
+        RoutedInfo::new(
+            &StatusCode::METHOD_NOT_ALLOWED,
+            request.method(),
+            request.uri(),
+            request.version(),
+            request.headers(),
+            addr,
+        )
+        .print_log();
+
+        special_response::build_resp_with_fallback(StatusCode::METHOD_NOT_ALLOWED)
+    } else {
+        let ctx = strategies::Context::new(addr, request, classification, source);
+        match strategies::get(strategy).or_else(|| strategies::get(strategies::DEFAULT)) {
+            Some(strategy) => strategy.apply(ctx).await,
+            None => special_response::build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    };
+    headers::set_decision_headers(
+        resp.headers_mut(),
+        addr,
+        &[
+            ("strategy", strategy),
+            ("classification", &classification_name),
+            ("rule", decision.rule.unwrap_or("-")),
+        ],
+    );
+    // The observed bots receive the original responses
+    robots::apply(
+        resp.headers_mut(),
+        &path,
+        classification == Classification::Bot && !vars::observe(),
+    );
+    cors::apply(resp.headers_mut(), &path, origin.as_ref());
+    cache_control::apply(
+        resp.headers_mut(),
+        &path,
+        if vars::observe() {
+            "passthrough"
+        } else {
+            strategy
+        },
+    );
+
+    resp
+}
+
+// Decide the strategy of the request, rules take precedence over the global strategy,
+// `plugin` is the choice of the plugins
+fn decide(
+    classification: Classification,
+    request: &Request<Body>,
+    client_ip: IpAddr,
+    plugin: Option<(&'static str, &'static str)>,
+) -> Decision {
+    let subject = rules::Subject {
+        path: request.uri().path().to_owned(),
+        user_agent: request
+            .headers()
+            .get(http::header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_owned(),
+        ip: client_ip,
+    };
+    if classification != Classification::Bot {
+        // Humans and allowlisted crawlers are proxied normally, the passthrough rules apply to them too
+        let rule = rules::find(&subject)
+            .filter(|rule| rule.strategy == "passthrough")
+            .map(|rule| rule.name.as_str());
+
+        return Decision {
+            strategy: "passthrough",
+            rule,
+            raw: rule.is_some(),
+        };
+    }
+
+    // The script and the plugins take precedence over the rules
+    let chosen: Option<(&str, &str)> = None;
+    #[cfg(feature = "scripting")]
+    let chosen = chosen.or_else(|| scripting::decide(request, client_ip));
+    let chosen = chosen.or(plugin);
+    let mut raw = false;
+    let (strategy, rule) = if honeypot::is_flagged(client_ip) {
+        (vars::honeypot_strategy(), Some("honeypot"))
+    } else if let Some((strategy, plugin)) = chosen {
+        (strategy, Some(plugin))
+    } else {
+        match rules::find(&subject) {
+            Some(rule) => {
+                raw = rule.strategy == "passthrough";
+                (rule.strategy.as_str(), Some(rule.name.as_str()))
+            }
+            None => match request.extensions().get::<ListenerStrategy>() {
+                Some(ListenerStrategy(strategy)) => (*strategy, None),
+                None => (vars::strategy(), None),
+            },
+        }
+    };
+    let strategy = match strategy {
+        // Only first-time clients receive the challenge page
+        "challenge" if challenge::is_challenged(request.headers()) => "obfuscation",
+        s => strategies::name(s).unwrap_or_else(|| {
+            error!(
+                "invalid strategy: {}, fallback to {}",
+                s,
+                strategies::DEFAULT
+            );
+
+            strategies::DEFAULT
+        }),
+    };
+
+    Decision {
+        strategy,
+        rule,
+        raw,
+    }
+}
+
+async fn obfus_handler(
+    conn_addr: SocketAddr,
+    request: Request<Body>,
+    classification: Classification,
+    source: Source,
+) -> Response<Body> {
+    handle(
+        conn_addr,
+        request,
+        Strategy::Obfuscation,
+        classification,
+        source,
+    )
+    .await
+}
+
+// Proxy the request byte-for-byte with its method and body, without parsing or caching
+async fn raw_handler(
+    conn_addr: SocketAddr,
+    request: Request<Body>,
+    source: Source,
+) -> Response<Body> {
+    let method = &request.method().clone();
+    let path = &request.uri().clone();
+    let version = request.version();
+    let req_headers = &request.headers().clone();
+    let loaded = match source {
+        Source::Upstream => {
+            let mut upstream_headers = headers::build_from_request(req_headers);
+            let host = req_headers
+                .get(http::header::HOST)
+                .and_then(|v| v.to_str().ok())
+                .or(path.authority().map(|a| a.as_str()));
+            headers::set_forwarded(&mut upstream_headers, conn_addr, host, headers::proto());
+            let path_and_query = path.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+            // Buffered to be sent again on retries and failovers
+            match axum::body::to_bytes(request.into_body(), vars::max_body_bytes()).await {
+                Ok(body) => fetching::load_raw(method, path_and_query, upstream_headers, body)
+                    .await
+                    .map(|mut resp| {
+                        let public_origin = headers::public_origin(req_headers, path, conn_addr);
+                        headers::rewrite_location(resp.headers_mut(), public_origin.as_deref());
+
+                        resp
+                    }),
+                Err(e) => {
+                    warn!("failed to read request body: {}", e);
+
+                    Err(StatusCode::PAYLOAD_TOO_LARGE)
+                }
+            }
+        }
+        Source::Inner(run) => Ok(run(request).await),
+    };
+
+    match loaded {
+        Ok(resp) => {
+            RoutedInfo::new(
+                &resp.status(),
+                method,
+                path,
+                version,
+                req_headers,
+                conn_addr,
+            )
+            .sent(resp.headers())
+            .print_log();
+
+            resp
+        }
+        Err(status_code) => {
+            RoutedInfo::new(&status_code, method, path, version, req_headers, conn_addr)
+                .print_log();
+
+            special_response::build_resp_with_fallback(status_code)
+        }
+    }
+}
+
+fn challenge_handler(conn_addr: SocketAddr, request: Request<Body>) -> Response<Body> {
+    match challenge::build_resp(request.headers()) {
+        Ok(resp) => {
+            RoutedInfo::new(
+                &resp.status(),
+                request.method(),
+                request.uri(),
+                request.version(),
+                request.headers(),
+                conn_addr,
+            )
+            .sent(resp.headers())
+            .print_log();
+
+            resp
+        }
+        Err(e) => {
+            error!("{}", e);
+            special_response::build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn maze_handler(conn_addr: SocketAddr, request: Request<Body>) -> Response<Body> {
+    // No need to contact the upstream
+    match maze::build_resp(request.uri().path()) {
+        Ok(resp) => {
+            RoutedInfo::new(
+                &resp.status(),
+                request.method(),
+                request.uri(),
+                request.version(),
+                request.headers(),
+                conn_addr,
+            )
+            .sent(resp.headers())
+            .print_log();
+
+            resp
+        }
+        Err(e) => {
+            error!("{}", e);
+            special_response::build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn block_handler(conn_addr: SocketAddr, request: Request<Body>) -> Response<Body> {
+    let status_code = StatusCode::from_u16(vars::block_status()).unwrap_or(StatusCode::FORBIDDEN);
+    RoutedInfo::new(
+        &status_code,
+        request.method(),
+        request.uri(),
+        request.version(),
+        request.headers(),
+        conn_addr,
+    )
+    .print_log();
+
+    if status_code.as_u16() == 444 {
+        special_response::build_drop_resp()
+    } else {
+        special_response::build_resp_with_fallback(status_code)
+    }
+}
+
+async fn patch_handler(
+    conn_addr: SocketAddr,
+    request: Request<Body>,
+    classification: Classification,
+    source: Source,
+) -> Response<Body> {
+    patch_content::refresh(vars::patch_content_file()).await;
+
+    handle(
+        conn_addr,
+        request,
+        Strategy::Patch(patch_config()),
+        classification,
+        source,
+    )
+    .await
+}
+
+async fn chain_handler(
+    conn_addr: SocketAddr,
+    request: Request<Body>,
+    classification: Classification,
+    source: Source,
+) -> Response<Body> {
+    patch_content::refresh(vars::patch_content_file()).await;
+
+    handle(
+        conn_addr,
+        request,
+        Strategy::Chain(patch_config()),
+        classification,
+        source,
+    )
+    .await
+}
+
+fn patch_config() -> PatchConfig<'static> {
+    PatchConfig {
+        targets: vars::patch_targets().clone(),
+        content: patch_contents().html,
+        mode: vars::patch_mode(),
+        remove_nodes: vars::patch_remove_nodes(),
+        remove_meta_tags: vars::patch_remove_meta_tags(),
+    }
+}
+
+// Strategy that would apply to the request in the observe mode
+#[derive(Clone)]
+struct Shadow {
+    strategy: &'static str,
+    rule: Option<&'static str>,
+}
+
+// Marks the background request refreshing a stale cached page
+#[derive(Clone)]
+struct Revalidating;
+
+// Request replayed in the background to refresh a stale cached page
+struct Revalidation {
+    conn_addr: SocketAddr,
+    uri: http::Uri,
+    headers: http::HeaderMap,
+    strategy: Strategy<'static>,
+    classification: Classification,
+    key: cache::Key,
+}
+
+static REVALIDATIONS: OnceLock<mpsc::UnboundedSender<Revalidation>> = OnceLock::new();
+
+// Queue the refresh, the worker is started on the first use
+fn revalidate(revalidation: Revalidation) {
+    let sender = REVALIDATIONS.get_or_init(|| {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(run_revalidations(receiver));
+
+        sender
+    });
+    if let Err(e) = sender.send(revalidation) {
+        cache::end_revalidation(&e.0.key);
+    }
+}
+
+async fn run_revalidations(mut receiver: mpsc::UnboundedReceiver<Revalidation>) {
+    while let Some(revalidation) = receiver.recv().await {
+        tokio::spawn(async move {
+            let mut request = Request::new(Body::empty());
+            *request.uri_mut() = revalidation.uri;
+            *request.headers_mut() = revalidation.headers;
+            request.extensions_mut().insert(Revalidating);
+            handle(
+                revalidation.conn_addr,
+                request,
+                revalidation.strategy,
+                revalidation.classification,
+                Source::Upstream,
+            )
+            .await;
+            cache::end_revalidation(&revalidation.key);
+        });
+    }
+}
+
+async fn handle(
+    conn_addr: SocketAddr,
+    mut request: Request<Body>,
+    strategy: Strategy<'static>,
+    classification: Classification,
+    source: Source,
+) -> Response<Body> {
+    use fetching::ContentType::*;
+    use special_response::build_resp_with_fallback;
+
+    // The request is moved into the inner service, keep what the logs need
+    let mut timings = metrics::Timings::start();
+    let method = &request.method().clone();
+    let path = &request.uri().clone();
+    let version = request.version();
+    let req_headers = &request.headers().clone();
+    // HTTP/2 requests carry the absolute URI
+    let path_and_query = path.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let url = &match source {
+        Source::Upstream => format!("{}{}", vars::upstream_base_url(), path_and_query),
+        Source::Inner(_) => path_and_query.to_owned(),
+    };
+    let passthrough = matches!(strategy, Strategy::Passthrough);
+    let obfuscating = matches!(
+        strategy,
+        Strategy::Obfuscation | Strategy::Tarpit | Strategy::Chain(_)
+    );
+    let profile = profiles::select(path.path());
+    let mapping = profile
+        .mapping
+        .as_deref()
+        .and_then(mappings::get)
+        .unwrap_or_else(|| mappings::select(path.path()));
+    let seed = if obfuscating {
+        session::seed(
+            req_headers,
+            headers::client_ip(req_headers, conn_addr),
+            path.path(),
+        )
+    } else {
+        None
+    };
+    let mapped = mapping.config();
+    let tuned;
+    let obfuscator = match seed {
+        Some(seed) => {
+            tuned = mapped.with_seed(seed).with_ratio(profile.ratio);
+            &tuned
+        }
+        None if profile.ratio < 1.0 => {
+            tuned = mapped.with_ratio(profile.ratio);
+            &tuned
+        }
+        None => &*mapped,
+    };
+    if obfuscating {
+        metrics::inc(
+            "miragend_mapping_selections_total",
+            &[("mapping", &mapping.name), ("profile", &profile.name)],
+        );
+    }
+    // The inner service already answers with its own redirects
+    let public_origin = match source {
+        Source::Upstream => Some(headers::public_origin(req_headers, path, conn_addr)),
+        Source::Inner(_) => None,
+    };
+    let origin = public_origin.as_ref().and_then(Option::as_deref);
+    // The entity tag of the cached body is reused, instead of hashing it again
+    let build_resp = |resp: &fetching::Response, body: String, etag: Option<String>| {
+        let unchanged = passthrough && (resp.content_type != Html || !modifies_passthrough_pages());
+        let (mut builder, etag) = buffered_builder(resp, &body, unchanged, etag, seed);
+        let body = if let Strategy::Tarpit = strategy {
+            streaming::throttled(body, vars::tarpit_bytes_per_sec())
+        } else if let Some(bytes_per_sec) = classification.bandwidth_limit() {
+            streaming::throttled(body, bytes_per_sec)
+        } else {
+            Body::new(body)
+        };
+        if let (Some(public_origin), Some(headers)) = (&public_origin, builder.headers_mut()) {
+            headers::rewrite_location(headers, public_origin.as_deref());
+        }
+        // Only the transformed pages carry the injections
+        if let (true, Some(headers)) = (
+            !passthrough && resp.content_type == Html,
+            builder.headers_mut(),
+        ) {
+            injections::allow_in_csp(headers, &inline_styles());
+        }
+
+        let mapping_header = vars::mapping_version_header();
+        let builder = if obfuscating && !mapping_header.is_empty() {
+            builder.header(mapping_header, mapping.version_id())
+        } else {
+            builder
+        };
+
+        let builder = match etag {
+            // The client has the same transformed entity, only the complete pages are validated
+            Some(etag)
+                if resp.status == StatusCode::OK && headers::none_match(req_headers, &etag) =>
+            {
+                metrics::inc("miragend_not_modified_total", &[]);
+                // No body follows
+                let mut builder = builder;
+                if let Some(headers) = builder.headers_mut() {
+                    headers.remove(http::header::CONTENT_LENGTH);
+                }
+
+                return builder
+                    .status(StatusCode::NOT_MODIFIED)
+                    .header(http::header::ETAG, etag)
+                    .body(Body::empty())
+                    .context("failed to create response");
+            }
+            Some(etag) => builder.header(http::header::ETAG, etag),
+            None => builder,
+        };
+
+        builder.body(body).context("failed to create response")
+    };
+
+    let internal_err_log = move || {
+        RoutedInfo::new(
+            &StatusCode::INTERNAL_SERVER_ERROR,
+            method,
+            path,
+            version,
+            req_headers,
+            conn_addr,
+        )
+        .print_log();
+    };
+
+    // The canonical links of the cached pages point at the origin of the request
+    let cache_url = &match origin {
+        Some(origin) if links::rewrites_to_proxy() => format!("{} {}", origin, url),
+        _ => url.to_owned(),
+    };
+    // The seeded pages differ per visitor, so they are cached before the transformation
+    let seeded = seed.is_some();
+    let cache_key = cache::Key::new(cache_url, strategy.name(), classification, mapping, seeded);
+    let revalidating = request.extensions().get::<Revalidating>().is_some();
+    let shadow = request.extensions().get::<Shadow>().cloned();
+    let cached = if revalidating || cache::bypassed(req_headers) {
+        None
+    } else {
+        cache::get(&cache_key, req_headers).or_else(|| {
+            // Only the upstream requests can be replayed in the background
+            if !matches!(source, Source::Upstream) {
+                return None;
+            }
+            let stale = cache::get_stale_within(&cache_key, profile.stale_secs, req_headers)?;
+            if cache::begin_revalidation(&cache_key) {
+                revalidate(Revalidation {
+                    conn_addr,
+                    uri: path.clone(),
+                    headers: req_headers.clone(),
+                    strategy: strategy.clone(),
+                    classification,
+                    key: cache_key.clone(),
+                });
+            }
+
+            Some(stale)
+        })
+    };
+    let preseeded = match cached {
+        Some(cached) if seeded => Some(cached.resp),
+        // The cached entity is validated before fetching anything
+        Some(cached) => match build_resp(&cached.resp, cached.resp.body.clone(), Some(cached.etag))
+        {
+            Ok(resp) => {
+                RoutedInfo::new(
+                    &resp.status(),
+                    method,
+                    path,
+                    version,
+                    req_headers,
+                    conn_addr,
+                )
+                .sent(resp.headers())
+                .print_log();
+
+                return resp;
+            }
+            Err(e) => {
+                error!("{}", e);
+
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut from_cache = preseeded.is_some();
+    let transforming = !passthrough || modifies_passthrough_pages();
+    let fetch_started = Instant::now();
+    let loaded = match (preseeded, source) {
+        (Some(resp), _) => Loaded::Forward(resp),
+        (None, Source::Upstream) => {
+            let mut upstream_headers = headers::build_from_request(req_headers);
+            let host = req_headers
+                .get(http::header::HOST)
+                .and_then(|v| v.to_str().ok())
+                .or(path.authority().map(|a| a.as_str()));
+            headers::set_forwarded(&mut upstream_headers, conn_addr, host, headers::proto());
+            if transforming {
+                // Partial content can't be transformed
+                upstream_headers.remove(http::header::RANGE);
+                upstream_headers.remove(http::header::IF_RANGE);
+                // The client validates the regenerated ETag, not the upstream one
+                upstream_headers.remove(http::header::IF_NONE_MATCH);
+                upstream_headers.remove(http::header::IF_MODIFIED_SINCE);
+                // Compressed bodies can't be transformed
+                upstream_headers.remove(http::header::ACCEPT_ENCODING);
+            }
+
+            fetching::load(path_and_query, upstream_headers).await
+        }
+        (None, Source::Inner(run)) => {
+            if transforming {
+                let headers = request.headers_mut();
+                headers.remove(http::header::RANGE);
+                headers.remove(http::header::IF_RANGE);
+                headers.remove(http::header::IF_NONE_MATCH);
+                headers.remove(http::header::IF_MODIFIED_SINCE);
+                // Compressed bodies can't be transformed
+                headers.remove(http::header::ACCEPT_ENCODING);
+            }
+
+            fetching::read(run(request).await, path.path()).await
+        }
+    };
+    timings.add("fetch", fetch_started.elapsed());
+    // The stale copy of a seeded page is transformed again for the visitor
+    let loaded = match loaded {
+        Loaded::Special(status_code) if seeded && is_outage(status_code) => {
+            match cache::get_stale(&cache_key, req_headers) {
+                Some(cached) => {
+                    from_cache = true;
+
+                    Loaded::Forward(cached.resp)
+                }
+                None => Loaded::Special(status_code),
+            }
+        }
+        loaded => loaded,
+    };
+
+    match loaded {
+        Loaded::Forward(resp) => {
+            let raw = (seeded && !from_cache).then(|| resp.clone());
+            let transformed = match &resp.content_type {
+                // Forwarded redirects carry no content worth transforming
+                _ if resp.status.is_redirection() => Ok(resp.body.clone()),
+                content_type => transform(
+                    &resp.body,
+                    content_type,
+                    &Served {
+                        path: path.path(),
+                        public_origin: origin,
+                        style_attrs: csp::allows_style_attrs(&resp.headers),
+                    },
+                    &strategy,
+                    obfuscator,
+                    profile,
+                    &mut timings,
+                ),
+            };
+            #[cfg(feature = "wasm")]
+            let mut resp = resp;
+            #[cfg(feature = "wasm")]
+            let transformed = match transformed {
+                Ok(body) if !passthrough && !resp.status.is_redirection() => {
+                    Ok(plugins::transform(path.path(), &mut resp, body).await)
+                }
+                transformed => transformed,
+            };
+            if let Some(shadow) = &shadow {
+                observe_transform(shadow, &resp, path, origin, obfuscator, profile);
+            }
+            match transformed {
+                Ok(body) => {
+                    let etag = headers::entity_tag(&body, seed);
+                    if let Some(raw) = &raw {
+                        cache::put(
+                            cache_key,
+                            raw,
+                            &raw.body,
+                            "",
+                            profile.stale_secs,
+                            req_headers,
+                        );
+                    } else if !from_cache {
+                        cache::put(
+                            cache_key,
+                            &resp,
+                            &body,
+                            &etag,
+                            profile.stale_secs,
+                            req_headers,
+                        );
+                    }
+
+                    match build_resp(&resp, body, Some(etag)) {
+                        Ok(resp) => {
+                            timings.observe();
+                            RoutedInfo::new(
+                                &resp.status(),
+                                method,
+                                path,
+                                version,
+                                req_headers,
+                                conn_addr,
+                            )
+                            .sent(resp.headers())
+                            .timings(&timings)
+                            .print_log();
+
+                            resp
+                        }
+                        Err(e) => {
+                            internal_err_log();
+
+                            error!("{}", e);
+                            build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
+                        }
+                    }
+                }
+                Err(e) => {
+                    internal_err_log();
+
+                    error!("{}", e);
+                    build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+        Loaded::Untouched(resp) => {
+            timings.observe();
+            RoutedInfo::new(
+                &resp.status(),
+                method,
+                path,
+                version,
+                req_headers,
+                conn_addr,
+            )
+            .sent(resp.headers())
+            .timings(&timings)
+            .print_log();
+
+            resp
+        }
+        Loaded::Special(status_code) => {
+            if is_outage(status_code) {
+                // Prefer the last copy of the page regardless of its age, then the static site,
+                // the seeded copies are already tried
+                let stale = cache::get_stale(&cache_key, req_headers)
+                    .filter(|_| !seeded)
+                    .and_then(|cached| {
+                        build_resp(&cached.resp, cached.resp.body.clone(), Some(cached.etag)).ok()
+                    });
+                let static_site = || {
+                    fallback::enabled()
+                        .then(|| fallback::load(path.path()))
+                        .flatten()
+                };
+                if let Some(resp) = stale.or_else(static_site) {
+                    metrics::inc("miragend_fallback_responses_total", &[]);
+                    RoutedInfo::new(
+                        &resp.status(),
+                        method,
+                        path,
+                        version,
+                        req_headers,
+                        conn_addr,
+                    )
+                    .sent(resp.headers())
+                    .print_log();
+
+                    return resp;
+                }
+            }
+            RoutedInfo::new(&status_code, method, path, version, req_headers, conn_addr)
+                .print_log();
+
+            build_resp_with_fallback(status_code)
+        }
+    }
+}
+
+// Start the response of the buffered body. The unchanged bodies keep the entity headers of
+// the upstream, the transformed ones get the regenerated ETag, which is returned to be validated.
+// The body is fully buffered, so the length is always accurate.
+fn buffered_builder(
+    resp: &fetching::Response,
+    body: &str,
+    unchanged: bool,
+    etag: Option<String>,
+    seed: Option<u64>,
+) -> (http::response::Builder, Option<String>) {
+    let builder = Response::builder().status(resp.status);
+    let builder = if unchanged {
+        builder.append_unchanged_headers(&resp.headers)
+    } else {
+        builder.append_headers(&resp.headers)
+    };
+    let etag = (!unchanged && !resp.status.is_redirection())
+        .then(|| etag.unwrap_or_else(|| headers::entity_tag(body, seed)));
+
+    (
+        builder.header(http::header::CONTENT_LENGTH, body.len()),
+        etag,
+    )
+}
+
+fn is_outage(status_code: StatusCode) -> bool {
+    matches!(
+        status_code,
+        StatusCode::BAD_GATEWAY | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+// Log how much the shadowed strategy would change the content
+fn observe_transform(
+    shadow: &Shadow,
+    resp: &fetching::Response,
+    path: &http::Uri,
+    public_origin: Option<&str>,
+    obfuscator: &ObfuscatorConfig,
+    profile: &Profile,
+) {
+    let strategy = match shadow.strategy {
+        "obfuscation" => Strategy::Obfuscation,
+        "tarpit" => Strategy::Tarpit,
+        "patch" => Strategy::Patch(patch_config()),
+        "patch+obfuscation" => Strategy::Chain(patch_config()),
+        // The other strategies don't transform the content
+        _ => return,
+    };
+    if resp.status.is_redirection() {
+        return;
+    }
+    let transformed = transform(
+        &resp.body,
+        &resp.content_type,
+        &Served {
+            path: path.path(),
+            public_origin,
+            style_attrs: csp::allows_style_attrs(&resp.headers),
+        },
+        &strategy,
+        obfuscator,
+        profile,
+        &mut metrics::Timings::start(),
+    );
+    match transformed {
+        Ok(transformed) => {
+            // The pages are compared by their text, the serialization differs from the upstream's
+            let (original, transformed) = match resp.content_type {
+                fetching::ContentType::Html => (page_text(&resp.body), page_text(&transformed)),
+                _ => (resp.body.clone(), transformed),
+            };
+            let changed = changed_chars(&original, &transformed);
+            info!(
+                "[Observe] `{}` would change {} of {} characters (rule: {}): \"{}\"",
+                shadow.strategy,
+                changed,
+                original.chars().count(),
+                shadow.rule.unwrap_or("-"),
+                path
+            );
+            if changed > 0 {
+                metrics::inc(
+                    "miragend_observed_transforms_total",
+                    &[
+                        ("strategy", shadow.strategy),
+                        ("rule", shadow.rule.unwrap_or("-")),
+                    ],
+                );
+            }
+        }
+        Err(e) => warn!("[Observe] `{}` would fail: {}", shadow.strategy, e),
+    }
+}
+
+fn page_text(html: &str) -> String {
+    html.build_document()
+        .map(|dom| html_ops::text_content(&dom.document))
+        .unwrap_or_default()
+}
+
+// Count the differing characters, including the length difference
+fn changed_chars(original: &str, transformed: &str) -> usize {
+    let different = original
+        .chars()
+        .zip(transformed.chars())
+        .filter(|(a, b)| a != b)
+        .count();
+
+    different
+        + original
+            .chars()
+            .count()
+            .abs_diff(transformed.chars().count())
+}
+
+// Where the transformed content is served
+struct Served<'a> {
+    path: &'a str,
+    // Origin of the proxy seen by the client, if known
+    public_origin: Option<&'a str>,
+    // Whether the CSP of the page allows the `style` attributes
+    style_attrs: bool,
+}
+
+// Transform the content with the strategy, shared by the proxy and the embedding API
+fn transform(
+    body: &str,
+    content_type: &fetching::ContentType,
+    served: &Served<'_>,
+    strategy: &Strategy<'_>,
+    obfuscator: &ObfuscatorConfig,
+    profile: &Profile,
+    timings: &mut metrics::Timings,
+) -> anyhow::Result<String> {
+    use fetching::ContentType::*;
+
+    match content_type {
+        Html => handle_page(body, served, strategy, obfuscator, profile, timings),
+        Json => timings.time("transform", || {
+            handle_json(body, served.path, strategy, obfuscator)
+        }),
+        Text => timings.time("transform", || Ok(handle_text(body, strategy, obfuscator))),
+        Xml => timings.time("transform", || Ok(handle_xml(body, strategy, obfuscator))),
+        Js => timings.time("transform", || Ok(handle_js(body, strategy, obfuscator))),
+        // The stylesheets hold no content worth transforming
+        Css => Ok(body.to_owned()),
+    }
+}
+
+fn handle_page<'a>(
+    html: &str,
+    served: &Served<'_>,
+    strategy: &'a Strategy<'_>,
+    obfuscator: &ObfuscatorConfig,
+    profile: &Profile,
+    timings: &mut metrics::Timings,
+) -> anyhow::Result<String> {
+    if let Strategy::Passthrough = strategy {
+        if !modifies_passthrough_pages() {
+            return Ok(html.to_owned());
+        }
+
+        // Only inject the honeypot link and the meta tags, and rewrite the links
+        let dom = timings
+            .time("parse", || html.build_document())
+            .context("failed to parse document")?;
+        if honeypot::enabled() {
+            honeypot::inject_link(Rc::clone(&dom.document));
+        }
+        injections::inject_meta_tags(&dom.document);
+        links::rewrite_canonical(&dom.document, served.public_origin);
+        links::rewrite_upstream_urls(&dom.document, served.public_origin);
+
+        return timings
+            .time("serialize", || html_ops::serialize_to_html(dom))
+            .context("failed to serialize document");
+    }
+
+    let dom = timings
+        .time("parse", || html.build_document())
+        .context("failed to parse document")?;
+
+    let transform_started = Instant::now();
+    let _extending_lifecycle = match strategy {
+        Strategy::Patch(config) => Some(patch_document(&dom.document, config)),
+        Strategy::Obfuscation | Strategy::Tarpit => {
+            obfuscate_document(&dom.document, obfuscator, profile, served.style_attrs);
+
+            None
+        }
+        Strategy::Chain(config) => {
+            obfuscate_document(&dom.document, obfuscator, profile, served.style_attrs);
+
+            Some(patch_document(&dom.document, config))
+        }
+        Strategy::Passthrough => None,
+    };
+
+    if vars::strip_scripts() || !vars::strip_script_srcs().is_empty() {
+        strip_scripts(
+            &dom.document,
+            vars::strip_scripts(),
+            vars::strip_script_srcs(),
+        );
+    }
+
+    if honeypot::enabled() {
+        honeypot::inject_link(Rc::clone(&dom.document));
+    }
+
+    injections::rewrite_title(&dom.document);
+    if decoys::enabled() {
+        decoys::replace(&dom.document);
+    }
+    injections::inject(&dom.document);
+    injections::inject_meta_tags(&dom.document);
+    links::rewrite_canonical(&dom.document, served.public_origin);
+    links::rewrite_upstream_urls(&dom.document, served.public_origin);
+    timings.add("transform", transform_started.elapsed());
+
+    timings
+        .time("serialize", || html_ops::serialize_to_html(dom))
+        .context("failed to serialize document")
+}
+
+// The returned fragment owns the inserted nodes, keep it until serialized
+fn patch_document(document: &Handle, config: &PatchConfig<'_>) -> markup5ever_rcdom::RcDom {
+    let fragment_dom = config.content.build_fragment();
+    patch_node(
+        Rc::clone(document),
+        &config.targets,
+        html_ops::extract_contents(&fragment_dom.document),
+        config.mode,
+    );
+    for selector in config.remove_nodes {
+        for node in Rc::clone(document).find_all_by_selector(selector) {
+            html_ops::detach(&node);
+        }
+    }
+    remove_doc_metas(Rc::clone(document), config.remove_meta_tags);
+
+    fragment_dom
+}
+
+// Detach all the scripts, or only those whose source matches the patterns
+fn strip_scripts(document: &Handle, all: bool, src_patterns: &[String]) {
+    for script in Rc::clone(document).find_all_by_selector("<script>") {
+        let stripped = all
+            || script
+                .get_attribute(&local_name!("src"))
+                .is_some_and(|src| {
+                    src_patterns
+                        .iter()
+                        .any(|pattern| rules::glob_match(pattern, &src))
+                });
+        if stripped {
+            html_ops::detach(&script);
+        }
+    }
+}
+
+fn obfuscate_document(
+    document: &Handle,
+    obfuscator: &ObfuscatorConfig,
+    profile: &Profile,
+    style_attrs: bool,
+) {
+    obfuscate_doc_text(
+        Rc::clone(document),
+        vars::obfuscation_ignore_len(),
+        obfuscator,
+        &profile.ignore_nodes,
+        style_attrs,
+    );
+    obfuscate_doc_metas(Rc::clone(document), &profile.meta_tags, obfuscator);
+    obfuscate_json_ld(document, obfuscator);
+}
+
+// Contents of the `<style>` elements the obfuscation may add to the page
+fn inline_styles() -> Vec<String> {
+    let style = if vars::obfuscation_mode() == "css-shuffle" {
+        Some(SHUFFLE_STYLE.to_owned())
+    } else {
+        fontmap::style()
+    };
+
+    style.into_iter().collect()
+}
+
+// The honeypot link, the meta tags and the link rewriting apply to every page
+fn modifies_passthrough_pages() -> bool {
+    honeypot::enabled()
+        || injections::has_meta_tags()
+        || links::rewrites_canonical()
+        || links::rewrites_upstream_urls()
+}
+
+fn handle_json(
+    json: &str,
+    path: &str,
+    strategy: &Strategy<'_>,
+    obfuscator: &ObfuscatorConfig,
+) -> anyhow::Result<String> {
+    let value = match strategy {
+        Strategy::Passthrough => return Ok(json.to_owned()),
+        Strategy::Patch(_) => {
+            let Some(patch) = json_patch::find(path) else {
+                return Ok(json.to_owned());
+            };
+            let value: serde_json::Value =
+                serde_json::from_str(json).context("failed to parse JSON")?;
+            match patch.apply(&value) {
+                Ok(patched) => patched,
+                Err(e) => {
+                    warn!("failed to apply JSON patch: {}, forwarded unchanged", e);
+                    return Ok(json.to_owned());
+                }
+            }
+        }
+        Strategy::Obfuscation | Strategy::Tarpit => {
+            // Any shape, including the top-level arrays and scalars
+            let mut value: serde_json::Value =
+                serde_json::from_str(json).context("failed to parse JSON")?;
+            value.obfuscate(obfuscator);
+
+            value
+        }
+        Strategy::Chain(_) => {
+            let mut value: serde_json::Value =
+                serde_json::from_str(json).context("failed to parse JSON")?;
+            value.obfuscate(obfuscator);
+            match json_patch::find(path).map(|patch| patch.apply(&value)) {
+                Some(Ok(patched)) => patched,
+                Some(Err(e)) => {
+                    warn!("failed to apply JSON patch: {}, only obfuscated", e);
+                    value
+                }
+                None => value,
+            }
+        }
+    };
+
+    // Keep the pretty-printed documents pretty
+    if json.trim().contains('\n') {
+        serde_json::to_string_pretty(&value).context("failed to serialize JSON")
+    } else {
+        serde_json::to_string(&value).context("failed to serialize JSON")
+    }
+}
+
+// Insert the new nodes into or around the first target found, the `<body>` is patched
+// if none is found
+fn patch_node(handle: Handle, targets: &[String], new_nodes: Vec<Rc<Node>>, mode: &str) {
+    let found = targets
+        .iter()
+        .find_map(|target| Rc::clone(&handle).find_by_selector(target));
+    let (node, mode) = match found {
+        Some(node) => (node, mode),
+        None => match handle.get_body() {
+            Some(body) => {
+                warn!(
+                    "patch targets `{}` not found, patching the body",
+                    targets.join(",")
+                );
+                // Nothing is inserted outside the body
+                let mode = match mode {
+                    "before" => "prepend",
+                    "after" => "append",
+                    mode => mode,
+                };
+
+                (body, mode)
+            }
+            None => {
+                warn!(
+                    "patch targets `{}` and the body not found",
+                    targets.join(",")
+                );
+                return;
+            }
+        },
+    };
+    // The inserted nodes belong to the target from now on, so that they can be detached
+    let adopt = |parent: &Handle| {
+        for new_node in &new_nodes {
+            new_node.parent.set(Some(Rc::downgrade(parent)));
+        }
+    };
+    match mode {
+        "prepend" => {
+            adopt(&node);
+            node.children.borrow_mut().splice(0..0, new_nodes);
+        }
+        "append" => {
+            adopt(&node);
+            node.children.borrow_mut().extend(new_nodes);
+        }
+        "before" | "after" => {
+            let Some(parent) = node.parent.take().and_then(|parent| parent.upgrade()) else {
+                return;
+            };
+            node.parent.set(Some(Rc::downgrade(&parent)));
+            adopt(&parent);
+            let mut siblings = parent.children.borrow_mut();
+            if let Some(index) = siblings.iter().position(|child| Rc::ptr_eq(child, &node)) {
+                let index = if mode == "after" { index + 1 } else { index };
+                siblings.splice(index..index, new_nodes);
+            }
+        }
+        _ => {
+            adopt(&node);
+            for old_node in node.children.replace(new_nodes) {
+                old_node.parent.set(None);
+            }
+        }
+    }
+}
+
+fn handle_text(text: &str, strategy: &Strategy<'_>, obfuscator: &ObfuscatorConfig) -> String {
+    match strategy {
+        Strategy::Patch(_) | Strategy::Chain(_) => patch_contents().text,
+        Strategy::Passthrough => text.to_owned(),
+        Strategy::Obfuscation | Strategy::Tarpit => text.obfuscated(obfuscator),
+    }
+}
+
+// The patch content has no place in the XML documents
+fn handle_xml(xml: &str, strategy: &Strategy<'_>, obfuscator: &ObfuscatorConfig) -> String {
+    match strategy {
+        Strategy::Patch(_) | Strategy::Passthrough => xml.to_owned(),
+        Strategy::Obfuscation | Strategy::Tarpit | Strategy::Chain(_) => {
+            xml::map_text(xml, |text| text.obfuscated(obfuscator))
+        }
+    }
+}
+
+// The patch content can't replace the scripts, only their string literals are obfuscated
+fn handle_js(js: &str, strategy: &Strategy<'_>, obfuscator: &ObfuscatorConfig) -> String {
+    match strategy {
+        Strategy::Patch(_) | Strategy::Passthrough => js.to_owned(),
+        Strategy::Obfuscation | Strategy::Tarpit | Strategy::Chain(_) => {
+            js::map_strings(js, |text| text.obfuscated(obfuscator))
+        }
+    }
+}
+
+fn obfuscate_doc_text(
+    handle: Handle,
+    mut ignore_remaining: usize,
+    obfuscator: &ObfuscatorConfig,
+    ignore_nodes: &[&str],
+    style_attrs: bool,
+) {
+    let mut text_nodes: Vec<(Rc<Node>, bool)> = vec![];
+    let mut elements: Vec<Handle> = vec![];
+    collect_obfuscation_nodes(
+        &handle,
+        &mut text_nodes,
+        &mut elements,
+        ignore_nodes,
+        false,
+        false,
+    );
+    let markov = if vars::obfuscation_mode() == "markov" {
+        let texts: Vec<String> = text_nodes
+            .iter()
+            .filter_map(|(node, _)| match node.data {
+                markup5ever_rcdom::NodeData::Text { ref contents } => {
+                    Some(contents.borrow().to_string())
+                }
+                _ => None,
+            })
+            .collect();
+
+        Some(MarkovModel::train(texts.iter().map(String::as_str)))
+    } else {
+        None
+    };
+    // Seeded by the visitor, the same page is rewritten the same way on reloads
+    let mut rng = match obfuscator.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let word = vars::obfuscation_mode() == "word";
+    // The order of the shuffled chunks is kept in their `style` attributes
+    let css_shuffle = vars::obfuscation_mode() == "css-shuffle" && style_attrs;
+    let mut shuffled = false;
+    // let children = handle.children.borrow();
+    for (child, after_content) in text_nodes {
+        if let markup5ever_rcdom::NodeData::Text { ref contents } = child.data {
+            if fontmap::enabled() && can_hold_elements(&child) {
+                contents.replace_with(|text| fontmap::encode(text).into());
+
+                continue;
+            }
+            if css_shuffle && can_hold_elements(&child) {
+                let text = contents.borrow().to_string();
+                if !text.trim().is_empty() {
+                    html_ops::replace_node(&child, css_shuffled(&text, &mut rng));
+                    shuffled = true;
+                }
+
+                continue;
+            }
+            contents.replace_with(|text| {
+                let patterns = vars::obfuscation_preserve_patterns();
+                if !after_content || ignore_remaining == 0 {
+                    if word {
+                        return obfuscation::preserving(text, patterns, |segment| {
+                            obfuscation::word_obfuscated(segment, obfuscator.ratio, &mut rng)
+                        })
+                        .into();
+                    }
+                    // Generated text replaces the whole node, the ratio applies to the nodes
+                    if markov.is_some() && !rng.gen_bool(obfuscator.ratio) {
+                        return text.clone();
+                    }
+                    match &markov {
+                        Some(model) => obfuscation::preserving(text, patterns, |segment| {
+                            model
+                                .rewrite(segment, &mut rng)
+                                .unwrap_or_else(|| segment.obfuscated(obfuscator))
+                        })
+                        .into(),
+                        None => text.obfuscated(obfuscator),
+                    }
+                } else {
+                    obfuscation::preserving(text, patterns, |segment| {
+                        let (content, remaining) = obfuscated_with_remaining(
+                            segment.chars(),
+                            ignore_remaining,
+                            obfuscator,
+                        );
+                        ignore_remaining = remaining;
+
+                        content
+                    })
+                    .into()
+                }
+            });
+        }
+    }
+
+    // Restore the visual order of the shuffled chunks, or the text in the scrambled font
+    let style = if shuffled {
+        Some(SHUFFLE_STYLE.to_owned())
+    } else {
+        fontmap::style()
+    };
+    if let (Some(style), Some(head)) = (style, handle.get_head()) {
+        let style = html_ops::build_style(style.into());
+        style.parent.set(Some(Rc::downgrade(&head)));
+        head.children.borrow_mut().push(style);
+    }
+
+    let attr_names: Vec<LocalName> = vars::obfuscation_attributes()
+        .iter()
+        .map(|name| LocalName::from(name.as_str()))
+        .collect();
+    let href = local_name!("href");
+    for mut element in elements.iter().cloned() {
+        for name in &attr_names {
+            if let Some(value) = element.get_attribute(name) {
+                element.set_attribute(name, value.obfuscated(obfuscator).into());
+            }
+        }
+        let is_anchor =
+            matches!(element.data, Element { ref name, .. } if name.local == local_name!("a"));
+        if vars::obfuscation_scramble_links() && is_anchor {
+            // In-page anchors don't leak the link graph
+            if let Some(value) = element.get_attribute(&href) {
+                if !value.starts_with('#') {
+                    element.set_attribute(&href, honeypot::trap_url(&mut rng).into());
+                }
+            }
+        }
+    }
+
+    inject_decoys(&elements, vars::obfuscation_decoys(), obfuscator, &mut rng);
+}
+
+// Text of these elements can't hold elements
+fn can_hold_elements(node: &Handle) -> bool {
+    let parent = node.parent.take();
+    node.parent.set(parent.clone());
+
+    match parent.and_then(|parent| parent.upgrade()) {
+        Some(parent) => match parent.data {
+            Element { ref name, .. } => !RAW_TEXT_TAGS.contains(&name.local.as_ref()),
+            _ => true,
+        },
+        None => false,
+    }
+}
+
+// Emit the chunks of the text in shuffled order, the CSS `order` restores the reading order
+fn css_shuffled(text: &str, rng: &mut StdRng) -> Handle {
+    // Collapse the whitespaces like the browser, they are preserved in the chunks
+    let mut collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.starts_with(char::is_whitespace) {
+        collapsed.insert(0, ' ');
+    }
+    if text.ends_with(char::is_whitespace) {
+        collapsed.push(' ');
+    }
+    let chunks: Vec<String> = collapsed
+        .split_inclusive(' ')
+        .flat_map(|word| {
+            word.chars()
+                .collect::<Vec<_>>()
+                .chunks(SHUFFLE_CHUNK_LEN)
+                .map(|chunk| chunk.iter().collect::<String>())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    let mut order: Vec<usize> = (0..chunks.len()).collect();
+    order.shuffle(rng);
+    let children = order
+        .into_iter()
+        .map(|i| {
+            html_ops::build_element(
+                local_name!("span"),
+                vec![(local_name!("style"), format!("order:{}", i).into())],
+                vec![html_ops::build_text(chunks[i].as_str().into())],
+            )
+        })
+        .collect();
+
+    html_ops::build_element(
+        local_name!("span"),
+        vec![(local_name!("class"), SHUFFLE_CLASS.into())],
+        children,
+    )
+}
+
+// Hidden elements of garbage text, rendered pages are unchanged
+fn inject_decoys(
+    elements: &[Handle],
+    count: usize,
+    obfuscator: &ObfuscatorConfig,
+    rng: &mut StdRng,
+) {
+    let containers: Vec<&Handle> = elements
+        .iter()
+        .filter(|element| match element.data {
+            Element { ref name, .. } => DECOY_CONTAINER_TAGS.contains(&name.local.as_ref()),
+            _ => false,
+        })
+        .collect();
+    if containers.is_empty() {
+        return;
+    }
+
+    for _ in 0..count {
+        let container = containers[rng.gen_range(0..containers.len())];
+        let words = rng.gen_range(8..=32);
+        let decoy = html_ops::build_element(
+            local_name!("span"),
+            vec![
+                (local_name!("hidden"), "".into()),
+                (local_name!("aria-hidden"), "true".into()),
+            ],
+            vec![html_ops::build_text(
+                maze::garbage_text(rng, obfuscator, words).into(),
+            )],
+        );
+        decoy.parent.set(Some(Rc::downgrade(container)));
+        let mut children = container.children.borrow_mut();
+        let position = rng.gen_range(0..=children.len());
+        children.insert(position, decoy);
+    }
+}
+
+fn obfuscated_with_remaining(
+    chars: Chars<'_>,
+    mut ignore_remaining: usize,
+    obfuscator: &ObfuscatorConfig,
+) -> (String, usize) {
+    let mut parts = vec![];
+    for c in chars {
+        // 如果不是空白字符
+        let c = if ignore_remaining > 0 && !c.is_whitespace() {
+            ignore_remaining -= 1;
+
+            c
+        } else {
+            c.obfuscated(obfuscator)
+        };
+
+        parts.push(c);
+    }
+
+    (parts.into_iter().collect(), ignore_remaining)
+}
+
+fn collect_obfuscation_nodes(
+    handle: &Handle,
+    text_nodes: &mut Vec<(Handle, bool)>,
+    elements: &mut Vec<Handle>,
+    ignore_nodes: &[&str],
+    mut title_found: bool,
+    mut after_content: bool,
+) {
+    let children = handle.children.borrow();
+    // Between the `miragend:off` and `miragend:on` comments of the same parent
+    let mut disabled = false;
+    for child in children.iter() {
+        match child.data {
+            markup5ever_rcdom::NodeData::Comment { ref contents } => match contents.trim() {
+                OFF_DIRECTIVE => disabled = true,
+                ON_DIRECTIVE => disabled = false,
+                _ => {}
+            },
+            markup5ever_rcdom::NodeData::Text { .. } if disabled => {}
+            markup5ever_rcdom::NodeData::Text { .. } => {
+                let parent_is_title = || match handle.data {
+                    Element { ref name, .. } => name.local == local_name!("title"),
+                    _ => false,
+                };
+                if !title_found && vars::obfuscation_ignore_title() && parent_is_title() {
+                    // No obfuscation for title
+                    title_found = true;
+                } else {
+                    text_nodes.push((Rc::clone(child), after_content));
+                }
+            }
+            markup5ever_rcdom::NodeData::Element { ref name, .. } => {
+                // Check if node is in ignore list (from config) or opted out by the site,
+                // the forced nodes are obfuscated regardless
+                let forced = child.get_attribute(&LocalName::from(FORCE_ATTR)).is_some();
+                let ignored = disabled
+                    || child.get_attribute(&LocalName::from(IGNORE_ATTR)).is_some()
+                    || ignore_nodes
+                        .iter()
+                        .any(|selector| child.matches_selector(selector));
+                if ignored && !forced {
+                    // Skip obfuscation, except the forced descendants
+                    collect_forced_nodes(child, text_nodes, elements, ignore_nodes, after_content);
+                    continue;
+                }
+                if let Some(id) = child.get_attribute(&local_name!("id")) {
+                    // TODO: 提取此处的 obfuscation_ignore_after_node 作为参数
+                    if id.as_ref() == vars::obfuscation_ignore_after_node() {
+                        after_content = true;
+                    }
+                }
+
+                let tag_name = name.local.as_ref();
+                // Check if tag is in ignore list
+                if IGNORE_OBFUSCATION_TAGS.contains(&tag_name) {
+                    // Skip obfuscation
+                    continue;
+                } else {
+                    elements.push(Rc::clone(child));
+                    collect_obfuscation_nodes(
+                        child,
+                        text_nodes,
+                        elements,
+                        ignore_nodes,
+                        title_found,
+                        after_content,
+                    )
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Collect the nodes forced to be obfuscated inside an ignored node
+fn collect_forced_nodes(
+    handle: &Handle,
+    text_nodes: &mut Vec<(Handle, bool)>,
+    elements: &mut Vec<Handle>,
+    ignore_nodes: &[&str],
+    after_content: bool,
+) {
+    for child in handle.children.borrow().iter() {
+        let Element { ref name, .. } = child.data else {
+            continue;
+        };
+        if IGNORE_OBFUSCATION_TAGS.contains(&name.local.as_ref()) {
+            continue;
+        }
+
+        if child.get_attribute(&LocalName::from(FORCE_ATTR)).is_some() {
+            elements.push(Rc::clone(child));
+            collect_obfuscation_nodes(
+                child,
+                text_nodes,
+                elements,
+                ignore_nodes,
+                true,
+                after_content,
+            );
+        } else {
+            collect_forced_nodes(child, text_nodes, elements, ignore_nodes, after_content);
+        }
+    }
+}
+
+fn obfuscate_doc_metas(handle: Handle, include_tags: &[&str], obfuscator: &ObfuscatorConfig) {
+    for mut meta_tag in handle.find_meta_tags() {
+        let content_locale_name = local_name!("content");
+        let mut update_content = |attr_name: &LocalName| {
+            if let Some(meta_name) = meta_tag.get_attribute(attr_name) {
+                if include_tags.contains(&meta_name.as_ref()) {
+                    if let Some(content) = meta_tag.get_attribute(&content_locale_name).as_mut() {
+                        meta_tag
+                            .set_attribute(&content_locale_name, content.obfuscated(obfuscator));
+                    }
+                }
+            }
+        };
+        update_content(&local_name!("name"));
+        update_content(&local_name!("property"));
+    }
+}
+
+// Structured data in the `application/ld+json` scripts, skipped by the text walk
+fn obfuscate_json_ld(handle: &Handle, obfuscator: &ObfuscatorConfig) {
+    for child in handle.children.borrow().iter() {
+        let Element { ref name, .. } = child.data else {
+            continue;
+        };
+        let is_json_ld = name.local == local_name!("script")
+            && child
+                .get_attribute(&local_name!("type"))
+                .is_some_and(|t| t.trim().eq_ignore_ascii_case(JSON_LD_TYPE));
+        if !is_json_ld {
+            obfuscate_json_ld(child, obfuscator);
+            continue;
+        }
+
+        for text in child.children.borrow().iter() {
+            if let markup5ever_rcdom::NodeData::Text { ref contents } = text.data {
+                let parsed = serde_json::from_str::<serde_json::Value>(&contents.borrow());
+                match parsed {
+                    Ok(mut value) => {
+                        obfuscate_json_ld_value(&mut value, obfuscator);
+                        match serde_json::to_string(&value) {
+                            Ok(json) => *contents.borrow_mut() = script_safe_json(&json).into(),
+                            Err(e) => warn!("failed to serialize JSON-LD: {}", e),
+                        }
+                    }
+                    Err(e) => warn!("failed to parse JSON-LD: {}, left unchanged", e),
+                }
+            }
+        }
+    }
+}
+
+// The serialized JSON must not end the script element or open an HTML comment in it,
+// `<` only appears in the strings where the escapes are equivalent
+fn script_safe_json(json: &str) -> String {
+    json.replace("</", "<\\/").replace("<!--", "\\u003c!--")
+}
+
+// The keyword values (`@context`, `@type`, ...) are kept, the data stays valid JSON-LD
+fn obfuscate_json_ld_value(value: &mut serde_json::Value, obfuscator: &ObfuscatorConfig) {
+    match value {
+        serde_json::Value::Object(map) => map
+            .iter_mut()
+            .filter(|(key, _)| !key.starts_with('@'))
+            .for_each(|(_, value)| obfuscate_json_ld_value(value, obfuscator)),
+        serde_json::Value::Array(items) => items
+            .iter_mut()
+            .for_each(|value| obfuscate_json_ld_value(value, obfuscator)),
+        value => value.obfuscate(obfuscator),
+    }
+}
+
+fn remove_doc_metas(handle: Handle, tags: &[&str]) {
+    if let Some(head) = handle.get_head() {
+        let name_local_name = local_name!("name");
+        let property_local_name = local_name!("property");
+        let meta_local_name = local_name!("meta");
+        head.children.replace_with(|children| {
+            children.retain(|child| match child.data {
+                Element { ref name, .. } => {
+                    if name.local == meta_local_name {
+                        let mut is_retain = true;
+                        if let Some(meta_name) = child.get_attribute(&name_local_name) {
+                            if tags.contains(&meta_name.as_ref()) {
+                                is_retain = false;
+                            }
+                        }
+
+                        if let Some(meta_property) = child.get_attribute(&property_local_name) {
+                            if tags.contains(&meta_property.as_ref()) {
+                                is_retain = false;
+                            }
+                        }
+
+                        is_retain
+                    } else {
+                        true
+                    }
+                }
+                _ => true,
+            });
+
+            children.to_vec()
+        });
+    }
+}
+
+// Patch contents of `MIRAGEND_PATCH_CONTENT_FILE`, rendered once per change of the file
+fn patch_contents() -> patch_content::Rendered {
+    let file = vars::patch_content_file();
+
+    patch_content::rendered(|| patch_content::Rendered {
+        html: load_patch_html(file),
+        text: load_patch_text(file),
+    })
+}
+
+fn load_patch_html(patch_content_file: &str) -> String {
+    let name = patch_content::name(patch_content_file);
+    if patch_content_file.is_empty() {
+        let markdown = FALLBACK_PATCH_MARKDOWN.to_string();
+
+        markdown_to_html(&markdown)
+    } else if name.ends_with(".md") {
+        let markdown = patch_content::read(patch_content_file)
+            .unwrap_or_else(|| FALLBACK_PATCH_MARKDOWN.to_string());
+
+        markdown_to_html(&markdown)
+    } else if name.ends_with(".html") {
+        patch_content::read(patch_content_file).unwrap_or_else(|| FALLBACK_PATCH_HTML.to_string())
+    } else {
+        let text = patch_content::read(patch_content_file)
+            .unwrap_or_else(|| "Hello from Miragend!".to_owned());
+
+        // Split text by newlines and wrap each line in <p> tags
+        text.lines().fold(String::new(), |acc, line| {
+            format!("{}\n<p>{}</p>", acc, line)
+        })
+    }
+}
+
+// Patch content of the plain text responses, the file is used as is
+fn load_patch_text(patch_content_file: &str) -> String {
+    if patch_content_file.is_empty() {
+        FALLBACK_PATCH_MARKDOWN.to_string()
+    } else {
+        patch_content::read(patch_content_file)
+            .unwrap_or_else(|| FALLBACK_PATCH_MARKDOWN.to_string())
+    }
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    comrak::markdown_to_html(markdown, &comrak::ComrakOptions::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_obfuscate_json_ld() {
+        let obfuscator =
+            ObfuscatorConfig::load_from_csv(include_str!("../obfuscation_mapping.csv"));
+        let dom = r#"<html><head><script type="application/ld+json">{"@type":"Article","name":"x<\/script><img src=x onerror=alert(1)><!--"}</script></head><body></body></html>"#
+            .build_document()
+            .unwrap();
+        obfuscate_json_ld(&dom.document, &obfuscator);
+        let html = html_ops::serialize_to_html(dom).unwrap();
+        let start = html.find("application/ld+json\">").unwrap();
+        let script = &html[start..html.rfind("</script>").unwrap()];
+        assert!(!script.contains("</"));
+        assert!(!script.contains("<!--"));
+        assert!(script.contains(r#"<\/"#));
+        assert!(script.contains(r#""@type":"Article""#));
+    }
+
+    #[test]
+    fn test_buffered_builder() {
+        use http::header::{CONTENT_LENGTH, ETAG, LAST_MODIFIED};
+
+        let resp = fetching::Response {
+            status: StatusCode::OK,
+            headers: http::HeaderMap::from_iter([
+                (ETAG, "\"upstream\"".parse().unwrap()),
+                (
+                    LAST_MODIFIED,
+                    "Fri, 16 Oct 2026 00:00:00 GMT".parse().unwrap(),
+                ),
+                (CONTENT_LENGTH, "999".parse().unwrap()),
+            ]),
+            content_type: fetching::ContentType::Html,
+            body: "<p>原文</p>".to_owned(),
+        };
+        let parts = |builder: http::response::Builder| builder.body(()).unwrap().into_parts().0;
+
+        // The passthrough body keeps the upstream entity with its exact length
+        let (builder, etag) = buffered_builder(&resp, &resp.body, true, None, None);
+        assert_eq!(etag, None);
+        let headers = parts(builder).headers;
+        assert_eq!(headers[ETAG], "\"upstream\"");
+        assert!(headers.contains_key(LAST_MODIFIED));
+        assert_eq!(
+            headers[CONTENT_LENGTH],
+            resp.body.len().to_string().as_str()
+        );
+
+        // The transformed body gets a regenerated entity tag and its own length
+        let body = "<p>変換された</p>";
+        let (builder, etag) = buffered_builder(&resp, body, false, None, Some(1));
+        assert_eq!(etag, Some(headers::entity_tag(body, Some(1))));
+        let headers = parts(builder).headers;
+        assert!(!headers.contains_key(ETAG));
+        assert!(!headers.contains_key(LAST_MODIFIED));
+        assert_eq!(headers[CONTENT_LENGTH], body.len().to_string().as_str());
+
+        // The tag of the cached body is reused, the redirects have none
+        let (_, etag) = buffered_builder(&resp, body, false, Some("\"cached\"".to_owned()), None);
+        assert_eq!(etag.as_deref(), Some("\"cached\""));
+        let redirect = fetching::Response {
+            status: StatusCode::FOUND,
+            ..resp
+        };
+        assert_eq!(buffered_builder(&redirect, "", false, None, None).1, None);
+    }
+
+    #[test]
+    fn test_handle_json() {
+        // `a` is always mapped to `b`
+        let obfuscator = ObfuscatorConfig::load_from_csv(
+            "source_start,source_end,target_start,target_end,weight,comment\n\
+             0061,0061,0062,0062,1,single\n",
+        );
+        let handled = |json: &str, strategy: &Strategy<'_>| {
+            handle_json(json, "/api", strategy, &obfuscator).unwrap()
+        };
+        let obfuscated = |json: &str| handled(json, &Strategy::Obfuscation);
+
+        assert_eq!(
+            obfuscated(r#"{"a":"aa","l":[true,null],"n":1}"#),
+            r#"{"a":"bb","l":[true,null],"n":1}"#
+        );
+        assert_eq!(obfuscated(r#"["aa",{"a":"a"},1]"#), r#"["bb",{"a":"b"},1]"#);
+        assert_eq!(obfuscated(r#""aa""#), r#""bb""#);
+        assert_eq!(obfuscated("1.5"), "1.5");
+        assert!(handle_json("{", "/api", &Strategy::Obfuscation, &obfuscator).is_err());
+        assert_eq!(handled(" [\"a\"] ", &Strategy::Passthrough), " [\"a\"] ");
+    }
+
+    #[test]
+    fn test_handle_json_format() {
+        let obfuscator = ObfuscatorConfig::load_from_csv(
+            "source_start,source_end,target_start,target_end,weight,comment\n\
+             0061,0061,0062,0062,1,single\n",
+        );
+        let obfuscated =
+            |json: &str| handle_json(json, "/api", &Strategy::Obfuscation, &obfuscator).unwrap();
+
+        // The pretty-printed documents stay pretty, the rest is compacted
+        assert_eq!(
+            obfuscated("{\n    \"a\": [\"a\", 1]\n}\n"),
+            "{\n  \"a\": [\n    \"b\",\n    1\n  ]\n}"
+        );
+        assert_eq!(obfuscated(r#"{ "a" : [ "a", 1 ] }"#), r#"{"a":["b",1]}"#);
+        assert_eq!(obfuscated("[\n\"a\"\n]"), "[\n  \"b\"\n]");
+    }
+
+    #[test]
+    fn test_patch_node() {
+        let patched = |targets: &[&str], mode: &str| {
+            let dom =
+                r#"<html><head></head><body><div id="content"><p>Old</p></div></body></html>"#
+                    .build_document()
+                    .unwrap();
+            let fragment = "<b>New</b>".build_fragment();
+            let targets: Vec<String> = targets.iter().map(|target| target.to_string()).collect();
+            patch_node(
+                Rc::clone(&dom.document),
+                &targets,
+                html_ops::extract_contents(&fragment.document),
+                mode,
+            );
+            let html = html_ops::serialize_to_html(dom).unwrap();
+            let start = html.find("<body>").unwrap() + "<body>".len();
+
+            html[start..html.find("</body>").unwrap()].to_owned()
+        };
+
+        let targets = ["missing", "content"];
+        assert_eq!(
+            patched(&targets, "replace"),
+            r#"<div id="content"><b>New</b></div>"#
+        );
+        assert_eq!(
+            patched(&targets, "prepend"),
+            r#"<div id="content"><b>New</b><p>Old</p></div>"#
+        );
+        assert_eq!(
+            patched(&targets, "append"),
+            r#"<div id="content"><p>Old</p><b>New</b></div>"#
+        );
+        assert_eq!(
+            patched(&targets, "before"),
+            r#"<b>New</b><div id="content"><p>Old</p></div>"#
+        );
+        assert_eq!(
+            patched(&targets, "after"),
+            r#"<div id="content"><p>Old</p></div><b>New</b>"#
+        );
+
+        // The body is patched in the same mode when no target is found
+        assert_eq!(patched(&["missing"], "replace"), "<b>New</b>");
+        for mode in ["prepend", "before"] {
+            assert_eq!(
+                patched(&["missing"], mode),
+                r#"<b>New</b><div id="content"><p>Old</p></div>"#
+            );
+        }
+        for mode in ["append", "after"] {
+            assert_eq!(
+                patched(&["missing"], mode),
+                r#"<div id="content"><p>Old</p></div><b>New</b>"#
+            );
+        }
+    }
+
+    #[test]
+    fn test_strip_patched_scripts() {
+        for mode in ["replace", "prepend", "append", "before", "after"] {
+            let dom = r#"<html><head></head><body><div id="content"></div></body></html>"#
+                .build_document()
+                .unwrap();
+            let fragment = "<p>New</p><script>alert(1)</script>".build_fragment();
+            patch_node(
+                Rc::clone(&dom.document),
+                &["content".to_owned()],
+                html_ops::extract_contents(&fragment.document),
+                mode,
+            );
+            strip_scripts(&dom.document, true, &[]);
+            let html = html_ops::serialize_to_html(dom).unwrap();
+            assert!(html.contains("<p>New</p>"), "{}", mode);
+            assert!(!html.contains("<script>"), "{}", mode);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    html_ops::{self, DOMOps, NodeOps},
+    vars,
+};
+use html5ever::{local_name, LocalName};
+use markup5ever_rcdom::{Handle, NodeData::Element};
+use std::rc::Rc;
+
+// Link types whose URL names the page itself
+const CANONICAL_RELS: [&str; 3] = ["canonical", "alternate", "amphtml"];
+
+pub fn rewrites_canonical() -> bool {
+    !vars::canonical_rewrite().is_empty()
+}
+
+pub fn rewrites_upstream_urls() -> bool {
+    !vars::upstream_url_rewrite().is_empty()
+}
+
+// The rewritten links depend on the proxy origin of the request
+pub fn rewrites_to_proxy() -> bool {
+    vars::canonical_rewrite() == "proxy" || vars::upstream_url_rewrite() == "proxy"
+}
+
+// Point the canonical, alternate and AMP links and `og:url` at the proxy instead of the upstreams
+pub fn rewrite_canonical(document: &Handle, public_origin: Option<&str>) {
+    let base = match vars::canonical_rewrite() {
+        "" => return,
+        "proxy" => public_origin.unwrap_or_default(),
+        base => base,
+    };
+    let domains = upstream_domains();
+
+    for mut node in Rc::clone(document).find_all_by_selector("link[href]") {
+        let rel = node.get_attribute(&local_name!("rel")).unwrap_or_default();
+        let canonical = rel
+            .split_ascii_whitespace()
+            .any(|token| CANONICAL_RELS.iter().any(|r| token.eq_ignore_ascii_case(r)));
+        if !canonical {
+            continue;
+        }
+        let href = node.get_attribute(&local_name!("href")).unwrap_or_default();
+        if let Some(rewritten) = rewritten_url(&href, &domains, base) {
+            node.set_attribute(&local_name!("href"), rewritten.into());
+        }
+    }
+    for mut node in Rc::clone(document).find_meta_tags() {
+        let og_url = node
+            .get_attribute(&local_name!("property"))
+            .is_some_and(|property| property.eq_ignore_ascii_case("og:url"));
+        if !og_url {
+            continue;
+        }
+        let content = node
+            .get_attribute(&local_name!("content"))
+            .unwrap_or_default();
+        if let Some(rewritten) = rewritten_url(&content, &domains, base) {
+            node.set_attribute(&local_name!("content"), rewritten.into());
+        }
+    }
+}
+
+// Point the absolute URLs of the links, assets and forms to the upstreams at the proxy
+pub fn rewrite_upstream_urls(document: &Handle, public_origin: Option<&str>) {
+    let base = match vars::upstream_url_rewrite() {
+        "relative" => "",
+        "proxy" => public_origin.unwrap_or_default(),
+        _ => return,
+    };
+
+    rewrite_urls_in(document, None, &upstream_domains(), base);
+}
+
+// How an attribute holds its URLs
+enum UrlAttr {
+    Single,
+    Srcset,
+}
+
+fn rewrite_urls_in(node: &Handle, parent: Option<&LocalName>, domains: &[&str], base: &str) {
+    let tag = match &node.data {
+        Element { name, attrs, .. } => {
+            for attr in attrs.borrow_mut().iter_mut() {
+                let rewritten = match url_attr(parent, &name.local, &attr.name.local) {
+                    Some(UrlAttr::Single) => rewritten_url(&attr.value, domains, base),
+                    Some(UrlAttr::Srcset) => rewritten_srcset(&attr.value, domains, base),
+                    None => None,
+                };
+                if let Some(rewritten) = rewritten {
+                    attr.value = rewritten.into();
+                }
+            }
+
+            Some(&name.local)
+        }
+        _ => None,
+    };
+    for child in node.children.borrow().iter() {
+        rewrite_urls_in(child, tag, domains, base);
+    }
+}
+
+// The `<source>` elements take a `srcset` in a `<picture>`, a `src` in a `<video>` or `<audio>`
+fn url_attr(parent: Option<&LocalName>, tag: &LocalName, attr: &LocalName) -> Option<UrlAttr> {
+    match (parent, tag, attr) {
+        (
+            _,
+            &local_name!("a") | &local_name!("area") | &local_name!("link"),
+            &local_name!("href"),
+        )
+        | (
+            _,
+            &local_name!("img")
+            | &local_name!("script")
+            | &local_name!("iframe")
+            | &local_name!("embed")
+            | &local_name!("audio")
+            | &local_name!("video")
+            | &local_name!("track")
+            | &local_name!("input"),
+            &local_name!("src"),
+        )
+        | (_, &local_name!("video"), &local_name!("poster"))
+        | (_, &local_name!("form"), &local_name!("action"))
+        | (
+            Some(&local_name!("video") | &local_name!("audio")),
+            &local_name!("source"),
+            &local_name!("src"),
+        ) => Some(UrlAttr::Single),
+        (_, &local_name!("img"), &local_name!("srcset"))
+        | (Some(&local_name!("picture")), &local_name!("source"), &local_name!("srcset")) => {
+            Some(UrlAttr::Srcset)
+        }
+        _ => None,
+    }
+}
+
+fn upstream_domains() -> Vec<&'static str> {
+    vars::upstream_domains()
+        .iter()
+        .filter_map(|domain| domain.to_str().ok())
+        .collect()
+}
+
+fn rewritten_srcset(srcset: &str, upstream_domains: &[&str], base: &str) -> Option<String> {
+    let mut changed = false;
+    let candidates = html_ops::parse_srcset(srcset)
+        .into_iter()
+        .map(|(url, descriptors)| {
+            let url = match rewritten_url(url, upstream_domains, base) {
+                Some(rewritten) => {
+                    changed = true;
+                    rewritten
+                }
+                None => url.to_owned(),
+            };
+            if descriptors.is_empty() {
+                url
+            } else {
+                format!("{} {}", url, descriptors)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    changed.then(|| candidates.join(", "))
+}
+
+// The URL on the base if it is absolute and on an upstream host, the scheme and port may differ
+// unless the upstream is given with a port
+fn rewritten_url(url: &str, upstream_domains: &[&str], base: &str) -> Option<String> {
+    let parsed = reqwest::Url::parse(url.trim()).ok()?;
+    // IP hosts have no domain
+    let host = parsed.host_str()?;
+    let authority = match parsed.port_or_known_default() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    };
+    if !upstream_domains.iter().any(|upstream| {
+        upstream.eq_ignore_ascii_case(host) || upstream.eq_ignore_ascii_case(&authority)
+    }) {
+        return None;
+    }
+    let mut rewritten = format!("{}{}", base.trim_end_matches('/'), parsed.path());
+    if let Some(query) = parsed.query() {
+        rewritten.push('?');
+        rewritten.push_str(query);
+    }
+    if let Some(fragment) = parsed.fragment() {
+        rewritten.push('#');
+        rewritten.push_str(fragment);
+    }
+
+    Some(rewritten)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewritten_url() {
+        let domains = ["example.com"];
+        let base = "https://mirror.test";
+        assert_eq!(
+            rewritten_url("http://example.com:8080/a/b?c=1#d", &domains, base).as_deref(),
+            Some("https://mirror.test/a/b?c=1#d")
+        );
+        assert_eq!(
+            rewritten_url("https://EXAMPLE.com", &domains, "https://mirror.test/").as_deref(),
+            Some("https://mirror.test/")
+        );
+        assert_eq!(rewritten_url("https://other.com/a", &domains, base), None);
+        let hosts = ["10.0.0.1:8080", "[::1]"];
+        assert_eq!(
+            rewritten_url("http://10.0.0.1:8080/a", &hosts, base).as_deref(),
+            Some("https://mirror.test/a")
+        );
+        assert_eq!(rewritten_url("http://10.0.0.1/a", &hosts, base), None);
+        assert_eq!(
+            rewritten_url("http://[::1]:3000/a", &hosts, base).as_deref(),
+            Some("https://mirror.test/a")
+        );
+        assert_eq!(rewritten_url("/a", &domains, base), None);
+        assert_eq!(
+            rewritten_url("https://example.com/a", &domains, "").as_deref(),
+            Some("/a")
+        );
+    }
+
+    #[test]
+    fn test_rewritten_srcset() {
+        let domains = ["example.com"];
+        assert_eq!(
+            rewritten_srcset(
+                "https://example.com/a.png 1x, /b.png 2x,https://example.com/c,d.png",
+                &domains,
+                ""
+            )
+            .as_deref(),
+            Some("/a.png 1x, /b.png 2x, /c,d.png")
+        );
+        assert_eq!(rewritten_srcset("/a.png 1x", &domains, ""), None);
+    }
+
+    #[test]
+    fn test_rewrite_upstream_urls() {
+        use crate::html_ops::DOMBuilder;
+
+        let html = r#"
+            <picture><source srcset="https://example.com/a.webp 2x"><img src="https://example.com/a.png"></picture>
+            <video><source src="https://example.com/a.mp4"></video>
+            <audio><source srcset="https://example.com/a.ogg"></audio>"#;
+        let dom = html.build_document().unwrap();
+        rewrite_urls_in(&dom.document, None, &["example.com"], "");
+        let html = html_ops::serialize_to_html(dom).unwrap();
+        assert!(html.contains(r#"<source srcset="/a.webp 2x">"#));
+        assert!(html.contains(r#"<img src="/a.png">"#));
+        assert!(html.contains(r#"<source src="/a.mp4">"#));
+        assert!(html.contains(r#"<source srcset="https://example.com/a.ogg">"#));
+    }
+}
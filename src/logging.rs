@@ -1,13 +1,24 @@
-use crate::vars;
+use crate::{headers, metrics::Timings, vars};
+use anyhow::Context;
 use chrono::Local;
 use env_logger::Builder;
-use http::{header, HeaderMap, StatusCode, Uri};
-use log::{info, Level, LevelFilter};
-use std::io::Write;
+use flate2::{write::GzEncoder, Compression};
+use http::{header, HeaderMap, Method, StatusCode, Uri, Version};
+use log::{error, info, warn, Level, LevelFilter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, OnceLock};
+use std::time::Duration;
+
+// Set when `MIRAGEND_ACCESS_LOG_FILE` is enabled, the lines are written by a dedicated thread
+static ACCESS_LOG: OnceLock<mpsc::Sender<String>> = OnceLock::new();
 
 pub fn init_logger() {
-    Builder::new()
+    let spec = parse_spec(vars::log_spec());
+    let mut builder = Builder::new();
+    builder
         .format(|buf, record| {
             writeln!(
                 buf,
@@ -17,9 +28,57 @@ pub fn init_logger() {
                 record.args()
             )
         })
-        // todo: 级别由 MIRAGEND_LOG 变量决定
-        .filter(None, LevelFilter::Info)
-        .init();
+        .filter(None, spec.level);
+    for (module, level) in &spec.modules {
+        builder.filter(Some(module), *level);
+    }
+    builder.init();
+
+    for directive in &spec.invalid {
+        warn!("invalid `MIRAGEND_LOG` directive: {}", directive);
+    }
+}
+
+// Parsed `MIRAGEND_LOG`, e.g. `warn,miragend=info,miragend::fetching=debug`
+#[derive(Debug, PartialEq)]
+struct LogSpec {
+    level: LevelFilter,
+    // Overrides of the modules (including their submodules)
+    modules: Vec<(String, LevelFilter)>,
+    invalid: Vec<String>,
+}
+
+fn parse_spec(spec: &str) -> LogSpec {
+    let mut parsed = LogSpec {
+        level: LevelFilter::Info,
+        modules: vec![],
+        invalid: vec![],
+    };
+    for directive in spec.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        match directive.split_once('=') {
+            Some((module, level)) => match level.trim().parse() {
+                Ok(level) if !module.trim().is_empty() => {
+                    parsed.modules.push((module.trim().to_owned(), level))
+                }
+                _ => parsed.invalid.push(directive.to_owned()),
+            },
+            None => match directive.parse() {
+                Ok(level) => parsed.level = level,
+                // A bare module name enables all its logs
+                Err(_) if is_module_path(directive) => parsed
+                    .modules
+                    .push((directive.to_owned(), LevelFilter::Trace)),
+                Err(_) => parsed.invalid.push(directive.to_owned()),
+            },
+        }
+    }
+
+    parsed
+}
+
+fn is_module_path(s: &str) -> bool {
+    s.split("::")
+        .all(|part| !part.is_empty() && part.chars().all(|c| c.is_alphanumeric() || c == '_'))
 }
 
 fn colorized_level(level: Level) -> &'static str {
@@ -33,18 +92,180 @@ fn colorized_level(level: Level) -> &'static str {
     }
 }
 
+// Open the access log file, the requests are logged to stderr if not called
+pub fn open_access_log() -> anyhow::Result<()> {
+    let path = vars::access_log_file();
+    if path.is_empty() || ACCESS_LOG.get().is_some() {
+        return Ok(());
+    }
+    let mut file = RotatingFile::open(PathBuf::from(path))
+        .context(format!("failed to open access log `{}`", path))?;
+    let (sender, receiver) = mpsc::channel::<String>();
+    if ACCESS_LOG.set(sender).is_err() {
+        return Ok(());
+    }
+    // Keep the blocking file I/O off the async workers
+    std::thread::Builder::new()
+        .name("access-log".to_owned())
+        .spawn(move || {
+            for line in receiver {
+                if let Err(e) = file.write_line(&line) {
+                    error!("failed to write access log: {:?}", e);
+                }
+            }
+        })
+        .context("failed to spawn the access log writer")?;
+
+    Ok(())
+}
+
+// Check that the access log file can be written, without creating it
+pub fn check_access_log() -> anyhow::Result<()> {
+    let path = vars::access_log_file();
+    if path.is_empty() {
+        return Ok(());
+    }
+
+    check_writable(Path::new(path)).context(format!("invalid access log `{}`", path))
+}
+
+fn check_writable(path: &Path) -> anyhow::Result<()> {
+    // The missing file is created in its directory
+    let target = match path.parent() {
+        _ if path.exists() => path,
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let metadata =
+        fs::metadata(target).context(format!("failed to access `{}`", target.display()))?;
+    if metadata.permissions().readonly() {
+        anyhow::bail!("`{}` is read-only", target.display());
+    }
+
+    Ok(())
+}
+
+// Append-only file rotated by size and time, the rotated files are named `<file>.<time>[.gz]`
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    // Period of the current file, e.g. `2024101612` for the hourly rotation
+    period: Option<String>,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            file,
+            size,
+            period: current_period(),
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let max_size = vars::access_log_max_size();
+        let oversized = max_size > 0 && self.size > 0 && self.size + line.len() as u64 > max_size;
+        if oversized || current_period() != self.period {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = PathBuf::from(format!(
+            "{}.{}",
+            self.path.display(),
+            Local::now().format("%Y%m%d-%H%M%S%.3f")
+        ));
+        fs::rename(&self.path, &rotated)?;
+        *self = Self::open(self.path.clone())?;
+
+        // On the writer thread, so a prune never races with the compression of another rotation
+        if vars::access_log_compress() {
+            if let Err(e) = compress(&rotated) {
+                error!("failed to compress `{}`: {:?}", rotated.display(), e);
+            }
+        }
+        if let Err(e) = prune(&self.path, vars::access_log_keep()) {
+            error!("failed to prune the rotated access logs: {:?}", e);
+        }
+
+        Ok(())
+    }
+}
+
+fn current_period() -> Option<String> {
+    let format = match vars::access_log_rotate() {
+        "hourly" => "%Y%m%d%H",
+        "daily" => "%Y%m%d",
+        _ => return None,
+    };
+
+    Some(Local::now().format(format).to_string())
+}
+
+fn compress(path: &Path) -> io::Result<()> {
+    let gz_path = PathBuf::from(format!("{}.gz", path.display()));
+    let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+
+    fs::remove_file(path)
+}
+
+// Remove the oldest rotated files beyond the limit
+fn prune(path: &Path, keep: usize) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let prefix = format!(
+        "{}.",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    let mut rotated: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .map(|entry| entry.path())
+        .collect();
+    // The timestamps in the names sort chronologically
+    rotated.sort();
+    let excess = rotated.len().saturating_sub(keep);
+    for path in &rotated[..excess] {
+        fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
 pub struct RoutedInfo<'a> {
     pub status_code: &'a StatusCode,
+    pub method: &'a Method,
     pub path: &'a Uri,
+    pub version: Version,
     pub user_agent: &'a str,
     pub client_ip: String,
     pub referer: &'a str,
+    // Size of the response body, if known
+    pub bytes: Option<u64>,
+    pub timings: Option<&'a Timings>,
 }
 
 impl<'a> RoutedInfo<'a> {
     pub fn new(
         status_code: &'a StatusCode,
+        method: &'a Method,
         path: &'a Uri,
+        version: Version,
         req_headers: &'a HeaderMap,
         conn_addr: SocketAddr,
     ) -> Self {
@@ -53,21 +274,7 @@ impl<'a> RoutedInfo<'a> {
             .map(|v| v.to_str().unwrap_or_default())
             .unwrap_or_default();
 
-        let from_header = if let Some(v) = req_headers.get("X-Forwarded-For") {
-            if let Ok(v) = v.to_str() {
-                v.split(",").next()
-            } else {
-                None
-            }
-        } else {
-            None
-        };
-
-        let client_ip = if let Some(client_ip) = from_header {
-            client_ip.to_owned()
-        } else {
-            conn_addr.ip().to_string()
-        };
+        let client_ip = headers::client_ip(req_headers, conn_addr).to_string();
         let referer = if let Some(referer) = req_headers.get(header::REFERER) {
             referer.to_str().unwrap_or("-")
         } else {
@@ -76,22 +283,218 @@ impl<'a> RoutedInfo<'a> {
 
         RoutedInfo {
             status_code,
+            method,
             path,
+            version,
             user_agent,
             client_ip,
             referer,
+            bytes: None,
+            timings: None,
         }
     }
 
+    // Take the body size from the `Content-Length` of the response
+    pub fn sent(mut self, resp_headers: &HeaderMap) -> Self {
+        self.bytes = resp_headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+
+        self
+    }
+
+    pub fn timings(mut self, timings: &'a Timings) -> Self {
+        self.timings = Some(timings);
+
+        self
+    }
+
     pub fn print_log(&self) {
-        info!(
-            "{} \"{}\" [Sent-to {}] [Client {}] \"{}\" \"{}\"",
-            self.status_code,
-            self.path,
-            vars::upstream_base_url(),
+        let combined = vars::access_log_format() == "combined";
+        let message = if combined {
+            self.combined(&Local::now().format("%d/%b/%Y:%H:%M:%S %z").to_string())
+        } else {
+            let mut message = format!(
+                "{} \"{}\" [Sent-to {}] [Client {}] \"{}\" \"{}\"",
+                self.status_code,
+                self.path,
+                vars::upstream_base_url(),
+                self.client_ip,
+                self.user_agent,
+                self.referer
+            );
+            if let Some(timings) = self.timings {
+                message.push_str(&format_timings(timings));
+            }
+
+            message
+        };
+        match ACCESS_LOG.get() {
+            Some(access_log) => {
+                // The combined lines carry their own time
+                let line = if combined {
+                    format!("{}\n", message)
+                } else {
+                    format!(
+                        "[{}] {}\n",
+                        Local::now().format("%Y-%m-%dT%H:%M:%S"),
+                        message
+                    )
+                };
+                if access_log.send(line).is_err() {
+                    error!("failed to write access log: the writer has stopped");
+                }
+            }
+            None => info!("{}", message),
+        }
+    }
+
+    // The Apache/Nginx combined log format:
+    // `%h - - [%t] "%r" %>s %b "%{Referer}i" "%{User-Agent}i"`
+    fn combined(&self, time: &str) -> String {
+        // HTTP/2 requests carry the absolute URI
+        let target = self
+            .path
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        let bytes = match self.bytes {
+            Some(bytes) if bytes > 0 => bytes.to_string(),
+            _ => "-".to_owned(),
+        };
+
+        format!(
+            "{} - - [{}] \"{} {} {:?}\" {} {} \"{}\" \"{}\"",
             self.client_ip,
-            self.user_agent,
-            self.referer
+            time,
+            self.method,
+            escape(target),
+            self.version,
+            self.status_code.as_u16(),
+            bytes,
+            escape(self.referer),
+            escape(self.user_agent)
+        )
+    }
+}
+
+// E.g. ` [Timing total=25.1ms fetch=12.0ms parse=3.2ms]`
+fn format_timings(timings: &Timings) -> String {
+    let mut formatted = format!(" [Timing total={}", format_duration(timings.total()));
+    for (phase, duration) in timings.phases() {
+        formatted.push_str(&format!(" {}={}", phase, format_duration(*duration)));
+    }
+    formatted.push(']');
+
+    formatted
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+// Escape the quotes, backslashes and control characters like Apache does
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let spec = parse_spec("warn, miragend::fetching=debug,hyper,miragend=,bad-name");
+        assert_eq!(
+            spec,
+            LogSpec {
+                level: LevelFilter::Warn,
+                modules: vec![
+                    ("miragend::fetching".to_owned(), LevelFilter::Debug),
+                    ("hyper".to_owned(), LevelFilter::Trace),
+                ],
+                invalid: vec!["miragend=".to_owned(), "bad-name".to_owned()],
+            }
+        );
+        assert_eq!(parse_spec("").level, LevelFilter::Info);
+        assert_eq!(parse_spec("OFF").level, LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_combined() {
+        let method = Method::GET;
+        let path: Uri = "/search?q=a%20b".parse().unwrap();
+        let mut req_headers = HeaderMap::new();
+        req_headers.insert(header::USER_AGENT, "Mozilla/5.0 \"Bot\"".parse().unwrap());
+        let mut resp_headers = HeaderMap::new();
+        resp_headers.insert(header::CONTENT_LENGTH, "1024".parse().unwrap());
+        let info = RoutedInfo::new(
+            &StatusCode::OK,
+            &method,
+            &path,
+            Version::HTTP_11,
+            &req_headers,
+            SocketAddr::from(([203, 0, 113, 7], 443)),
+        )
+        .sent(&resp_headers);
+        assert_eq!(
+            info.combined("16/Oct/2024:12:00:00 +0000"),
+            r#"203.0.113.7 - - [16/Oct/2024:12:00:00 +0000] "GET /search?q=a%20b HTTP/1.1" 200 1024 "-" "Mozilla/5.0 \"Bot\"""#
+        );
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let dir = std::env::temp_dir().join(format!("miragend-check-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        let checked = check_writable(&path);
+        let created = path.exists();
+        let missing = check_writable(&dir.join("missing/access.log"));
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(checked.is_ok());
+        assert!(!created);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_prune() {
+        let dir = std::env::temp_dir().join(format!("miragend-prune-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+        for name in [
+            "access.log",
+            "access.log.20241014-000000.000.gz",
+            "access.log.20241015-000000.000.gz",
+            "access.log.20241016-000000.000",
+        ] {
+            File::create(dir.join(name)).unwrap();
+        }
+        prune(&path, 2).unwrap();
+        let mut names: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            names,
+            [
+                "access.log",
+                "access.log.20241015-000000.000.gz",
+                "access.log.20241016-000000.000"
+            ]
         );
     }
 }
@@ -1,67 +1,71 @@
 use anyhow::Context;
-use axum::body::Body;
-use axum::extract::ConnectInfo;
-use axum::{http::Request, routing::get, Router};
+use axum::{Extension, Router};
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
-use fetching::Loaded;
-use headers::AppendHeaders;
-use html5ever::LocalName;
-use html_ops::{DOMBuilder, DOMOps, NodeOps};
-use http::{Response, StatusCode};
-use log::{error, info, warn};
-use logging::RoutedInfo;
-use markup5ever::local_name;
-use markup5ever_rcdom::{Handle, Node, NodeData::Element};
-use obfuscation::Obfuscator;
+use log::{error, info};
+use miragend::{admin, check, config, doctor, logging, scaffold, simulate, vars, ListenerStrategy};
 use std::net::SocketAddr;
-use std::path::Path;
-use std::rc::Rc;
-use std::str::Chars;
 use tokio::signal;
 
 mod cli;
-mod fetching;
-mod headers;
-mod html_ops;
-mod logging;
-mod obfuscation;
-mod request;
-mod special_response;
-mod vars;
-
-// Fallback patch contents
-const FALLBACK_PATCH_MARKDOWN: &str = include_str!("../patch-content.md");
-const FALLBACK_PATCH_HTML: &str = include_str!("../patch-content.html");
-// Ignore obfuscation for these tags
-const IGNORE_OBFUSCATION_TAGS: [&str; 5] = ["script", "noscript", "style", "template", "iframe"];
-// Strategy configuration
-enum Strategy<'a> {
-    // Patch
-    Patch(PatchConfig<'a>),
-    // Obfuscation
-    Obfuscation,
-}
-
-struct PatchConfig<'a> {
-    target: String,
-    content: String,
-    remove_nodes: &'a Vec<&'a str>,
-    remove_meta_tags: &'a Vec<&'a str>,
-}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = cli::Args::parse();
+    // The flags take precedence over the environment and the config file
+    args.overrides.apply();
+    // `MIRAGEND_LOG` may come from the `.env` file or the config file
+    let dotenv_loaded = dotenvy::dotenv().is_ok();
+    let config_file = config::load(args.config.as_deref())?;
     logging::init_logger();
-    if dotenvy::dotenv().is_ok() {
+    if dotenv_loaded {
         info!("loaded .env file");
     }
-    validate_config()?;
-    let _args = cli::Args::parse();
-    let app = Router::new().route("/*path", get(handler));
-    let bind = vars::bind();
+    if let Some(file) = config_file {
+        info!("loaded config file: {}", file);
+    }
+    if let Some(command) = args.command {
+        return run_command(command).await;
+    }
+    miragend::validate_config()?;
+    let admin_bind = vars::admin_bind();
+    if !admin_bind.is_empty() {
+        tokio::spawn(async move {
+            if let Err(e) = admin::serve(admin_bind).await {
+                error!("{:?}", e);
+            }
+        });
+    }
+    tokio::spawn(miragend::watch_mapping_files());
+    tokio::spawn(miragend::watch_patch_content());
+    let app = miragend::router();
+    let tls = tls_config().await?;
+    let mut servers = tokio::task::JoinSet::new();
+    for (address, strategy) in vars::binds() {
+        let app = match strategy {
+            Some(strategy) => app.clone().layer(Extension(ListenerStrategy(strategy))),
+            None => app.clone(),
+        };
+        servers.spawn(serve(app, address, tls.clone()));
+    }
+    // Stop at the first listener failure
+    while let Some(result) = servers.join_next().await {
+        result.context("failed to join server")??;
+    }
+
+    Ok(())
+}
+
+async fn serve(app: Router, bind: &str, tls: Option<RustlsConfig>) -> anyhow::Result<()> {
+    if let Some(path) = bind.strip_prefix("unix:") {
+        return serve_unix(app, path).await;
+    }
+    if let Some(config) = tls {
+        return serve_tls(app, bind, config).await;
+    }
     let listener = tokio::net::TcpListener::bind(bind)
         .await
-        .context("failed to bind to address")?;
+        .context(format!("failed to bind to `{}`", bind))?;
 
     info!("listening on: http://{}", bind);
 
@@ -71,378 +75,164 @@ async fn main() -> anyhow::Result<()> {
     )
     .with_graceful_shutdown(shutdown_signal())
     .await
-    .context("failed to run server")?;
-
-    Ok(())
+    .context("failed to run server")
 }
 
-fn validate_config() -> anyhow::Result<()> {
-    vars::force_init();
-
-    Ok(())
-}
-
-async fn handler(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    request: Request<Body>,
-) -> Response<Body> {
-    match vars::strategy() {
-        "patch" => patch_handler(addr, request).await,
-        "obfuscation" | "obfus" => obfus_handler(addr, request).await,
-        s => {
-            error!("invalid strategy: {}, fallback to obfuscation", s);
-
-            obfus_handler(addr, request).await
-        }
+// Load the TLS config shared by the TCP listeners, `None` to serve plain HTTP
+async fn tls_config() -> anyhow::Result<Option<RustlsConfig>> {
+    #[cfg(feature = "acme")]
+    if !vars::acme_domains().is_empty() {
+        return acme_config().await.map(Some);
     }
-}
-
-async fn obfus_handler(conn_addr: SocketAddr, request: Request<Body>) -> Response<Body> {
-    handle(conn_addr, request, Strategy::Obfuscation).await
-}
-
-async fn patch_handler(conn_addr: SocketAddr, request: Request<Body>) -> Response<Body> {
-    let patch_html = load_patch_html(vars::patch_content_file());
-    let config = PatchConfig {
-        target: vars::patch_target().to_owned(),
-        content: patch_html,
-        remove_nodes: vars::patch_remove_nodes(),
-        remove_meta_tags: vars::patch_remove_meta_tags(),
-    };
+    if vars::tls_cert().is_empty() {
+        return Ok(None);
+    }
+    // Ignore the error if a provider has been installed
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let config = RustlsConfig::from_pem_file(vars::tls_cert(), vars::tls_key())
+        .await
+        .context("failed to load TLS certificate or key")?;
 
-    handle(conn_addr, request, Strategy::Patch(config)).await
+    Ok(Some(config))
 }
 
-async fn handle(
-    conn_addr: SocketAddr,
-    request: Request<Body>,
-    strategy: Strategy<'_>,
-) -> Response<Body> {
-    use fetching::ContentType::*;
-    use special_response::build_resp_with_fallback;
-
-    let path = request.uri();
-    let url = &format!("{}{}", vars::upstream_base_url(), path);
-    let build_resp = |resp: &fetching::Response, body: String| {
-        Response::builder()
-            .status(resp.status)
-            .append_headers(&resp.headers)
-            .body(Body::new(body))
-            .context("failed to create response")
-    };
-
-    let req_headers = request.headers();
-    let internal_err_log = move || {
-        RoutedInfo::new(
-            &StatusCode::INTERNAL_SERVER_ERROR,
-            path,
-            req_headers,
-            conn_addr,
-        )
-        .print_log();
-    };
-
-    match fetching::load(url, headers::build_from_request(request.headers())).await {
-        Loaded::Forward(resp) if resp.content_type == Html => {
-            match handle_page(&resp.body, &strategy).await {
-                Ok(html) => match build_resp(&resp, html) {
-                    Ok(resp) => {
-                        RoutedInfo::new(&resp.status(), path, request.headers(), conn_addr)
-                            .print_log();
-
-                        resp
-                    }
-                    Err(e) => {
-                        internal_err_log();
-
-                        error!("{}", e);
-                        build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
-                    }
-                },
-                Err(e) => {
-                    internal_err_log();
-
-                    error!("{}", e);
-                    build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
-                }
-            }
-        }
-        Loaded::Forward(resp) if resp.content_type == Json => {
-            match handle_json(&resp.body, &strategy) {
-                Ok(json) => match build_resp(&resp, json) {
-                    Ok(resp) => {
-                        RoutedInfo::new(&resp.status(), path, request.headers(), conn_addr)
-                            .print_log();
-
-                        resp
-                    }
-                    Err(e) => {
-                        internal_err_log();
-
-                        error!("{}", e);
-                        build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
-                    }
-                },
-                Err(e) => {
-                    internal_err_log();
-
-                    error!("{}", e);
-                    build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
-                }
+#[cfg(feature = "acme")]
+async fn acme_config() -> anyhow::Result<RustlsConfig> {
+    use axum::routing::get;
+    use miragend::acme;
+
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    let challenge = Router::new().route(
+        "/.well-known/acme-challenge/:token",
+        get(acme::challenge_handler),
+    );
+    let http_bind = vars::acme_http_bind();
+    if !http_bind.is_empty() {
+        let listener = tokio::net::TcpListener::bind(http_bind)
+            .await
+            .context("failed to bind to ACME HTTP address")?;
+        info!("answering ACME challenges on: http://{}", http_bind);
+        tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, challenge.into_make_service()).await {
+                error!("{:?}", e);
             }
-        }
-        Loaded::Forward(resp) => {
-            error!("unhandled content-type: {}", resp.content_type);
-
-            build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
-        }
-        Loaded::Special(status_code) => {
-            RoutedInfo::new(&status_code, path, request.headers(), conn_addr).print_log();
-
-            build_resp_with_fallback(status_code)
-        }
+        });
     }
-}
-
-async fn handle_page<'a>(html: &str, strategy: &'a Strategy<'_>) -> anyhow::Result<String> {
-    let dom = html.build_document().context("failed to parse document")?;
-
-    let _extending_lifecycle = match strategy {
-        Strategy::Patch(config) => {
-            let fragment_dom = config.content.build_fragment();
-            replace_children(
-                Rc::clone(&dom.document),
-                &config.target,
-                html_ops::extract_contents(&fragment_dom.document),
-            );
-            for node in config.remove_nodes {
-                remove_children(Rc::clone(&dom.document), node);
-            }
-            remove_doc_metas(Rc::clone(&dom.document), config.remove_meta_tags);
-
-            Some(fragment_dom)
-        }
-        Strategy::Obfuscation => {
-            obfuscate_doc_text(Rc::clone(&dom.document), vars::obfuscation_ignore_len());
-            obfuscate_doc_metas(Rc::clone(&dom.document), vars::obfuscation_meta_tags());
-
-            None
-        }
-    };
-
-    let inject_script = vars::inject_online_script();
-    if !inject_script.is_empty() {
-        inject_online_script(Rc::clone(&dom.document), inject_script);
+    if acme::needs_renewal() {
+        acme::provision().await?;
     }
+    let config = RustlsConfig::from_pem_file(acme::cert_path(), acme::key_path())
+        .await
+        .context("failed to load ACME certificate or key")?;
+    tokio::spawn(acme::renew_periodically(config.clone()));
 
-    html_ops::serialize_to_html(dom).context("failed to serialize document")
+    Ok(config)
 }
 
-fn handle_json(json: &str, strategy: &Strategy<'_>) -> anyhow::Result<String> {
-    let mut map: serde_json::Map<String, serde_json::Value> =
-        serde_json::from_str(json).context("failed to parse JSON")?;
-    match strategy {
-        Strategy::Patch(_) => Ok(json.to_owned()),
-        Strategy::Obfuscation => {
-            map.obfuscate(vars::obfuscator_config());
-
-            serde_json::to_string(&map).context("failed to serialize JSON")
+async fn serve_tls(app: Router, bind: &str, config: RustlsConfig) -> anyhow::Result<()> {
+    let listener =
+        std::net::TcpListener::bind(bind).context(format!("failed to bind to `{}`", bind))?;
+    listener
+        .set_nonblocking(true)
+        .context("failed to set non-blocking mode")?;
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown_signal().await;
+            handle.graceful_shutdown(None);
         }
-    }
-}
+    });
 
-fn replace_children(handle: Handle, node_id: &str, new_children: Vec<Rc<Node>>) {
-    if let Some(node) = handle.get_element_by_id(node_id) {
-        node.children.replace(new_children);
-    } else {
-        warn!("node with id `{}` not found", node_id);
-    }
-}
+    info!("listening on: https://{}", bind);
 
-fn remove_children(handle: Handle, node_id: &str) {
-    replace_children(handle, node_id, vec![])
-}
-
-fn obfuscate_doc_text(handle: Handle, mut ignore_remaining: usize) {
-    let mut text_nodes: Vec<(Rc<Node>, bool)> = vec![];
-    collect_obfuscation_nodes(&handle, &mut text_nodes, false, false);
-    // let children = handle.children.borrow();
-    for (child, after_content) in text_nodes {
-        if let markup5ever_rcdom::NodeData::Text { ref contents } = child.data {
-            contents.replace_with(|text| {
-                if !after_content || ignore_remaining == 0 {
-                    text.obfuscated(vars::obfuscator_config())
-                } else {
-                    let (content, remaining) =
-                        obfuscated_with_remaining(text.chars(), ignore_remaining);
-                    ignore_remaining = remaining;
-
-                    content.into()
-                }
-            });
-        }
-    }
+    axum_server::from_tcp_rustls(listener, config)
+        .handle(handle)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .context("failed to run server")
 }
 
-fn obfuscated_with_remaining(chars: Chars<'_>, mut ignore_remaining: usize) -> (String, usize) {
-    let mut parts = vec![];
-    for c in chars {
-        // 如果不是空白字符
-        let c = if ignore_remaining > 0 && !c.is_whitespace() {
-            ignore_remaining -= 1;
-
-            c
-        } else {
-            c.obfuscated(vars::obfuscator_config())
-        };
-
-        parts.push(c);
-    }
-
-    (parts.into_iter().collect(), ignore_remaining)
-}
+// Serve over a Unix domain socket, the client IP comes from `X-Forwarded-For` only
+#[cfg(unix)]
+async fn serve_unix(app: Router, path: &str) -> anyhow::Result<()> {
+    use axum::{extract::ConnectInfo, Extension};
+    use hyper_util::{
+        rt::{TokioExecutor, TokioIo},
+        server::conn::auto,
+        service::TowerToHyperService,
+    };
 
-fn collect_obfuscation_nodes(
-    handle: &Handle,
-    text_nodes: &mut Vec<(Handle, bool)>,
-    mut title_found: bool,
-    mut after_content: bool,
-) {
-    let children = handle.children.borrow();
-    for child in children.iter() {
-        match child.data {
-            markup5ever_rcdom::NodeData::Text { .. } => {
-                let parent_is_title = || match handle.data {
-                    Element { ref name, .. } => name.local == local_name!("title"),
-                    _ => false,
-                };
-                if !title_found && vars::obfuscation_ignore_title() && parent_is_title() {
-                    // No obfuscation for title
-                    title_found = true;
-                } else {
-                    text_nodes.push((Rc::clone(child), after_content));
-                }
-            }
-            markup5ever_rcdom::NodeData::Element { ref name, .. } => {
-                if let Some(id) = child.get_attribute(&local_name!("id")) {
-                    // Check if node is in ignore list (from config)
-                    if vars::obfuscation_ignore_nodes().contains(&id.as_ref()) {
-                        // Skip obfuscation
-                        continue;
-                    }
+    // Remove the socket left by the last run
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path).context("failed to bind to socket")?;
+    // There is no peer address on a Unix socket
+    let app = app.layer(Extension(ConnectInfo(SocketAddr::from(([0, 0, 0, 0], 0)))));
 
-                    // TODO: 提取此处的 obfuscation_ignore_after_node 作为参数
-                    if id.as_ref() == vars::obfuscation_ignore_after_node() {
-                        after_content = true;
-                    }
-                }
+    info!("listening on: unix:{}", path);
 
-                let tag_name = name.local.as_ref();
-                // Check if tag is in ignore list
-                if IGNORE_OBFUSCATION_TAGS.contains(&tag_name) {
-                    // Skip obfuscation
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("failed to accept connection: {:?}", e);
                     continue;
-                } else {
-                    collect_obfuscation_nodes(child, text_nodes, title_found, after_content)
                 }
-            }
-            _ => {}
-        }
-    }
-}
-
-fn obfuscate_doc_metas(handle: Handle, include_tags: &[&str]) {
-    for mut meta_tag in handle.find_meta_tags() {
-        let content_locale_name = local_name!("content");
-        let mut update_content = |attr_name: &LocalName| {
-            if let Some(meta_name) = meta_tag.get_attribute(attr_name) {
-                if include_tags.contains(&meta_name.as_ref()) {
-                    if let Some(content) = meta_tag.get_attribute(&content_locale_name).as_mut() {
-                        meta_tag.set_attribute(
-                            &content_locale_name,
-                            content.obfuscated(vars::obfuscator_config()),
-                        );
-                    }
-                }
-            }
+            },
+            _ = &mut shutdown => break,
         };
-        update_content(&local_name!("name"));
-        update_content(&local_name!("property"));
-    }
-}
-
-fn remove_doc_metas(handle: Handle, tags: &[&str]) {
-    if let Some(head) = handle.get_head() {
-        let name_local_name = local_name!("name");
-        let property_local_name = local_name!("property");
-        let meta_local_name = local_name!("meta");
-        head.children.replace_with(|children| {
-            children.retain(|child| match child.data {
-                Element { ref name, .. } => {
-                    if name.local == meta_local_name {
-                        let mut is_retain = true;
-                        if let Some(meta_name) = child.get_attribute(&name_local_name) {
-                            if tags.contains(&meta_name.as_ref()) {
-                                is_retain = false;
-                            }
-                        }
-
-                        if let Some(meta_property) = child.get_attribute(&property_local_name) {
-                            if tags.contains(&meta_property.as_ref()) {
-                                is_retain = false;
-                            }
-                        }
-
-                        is_retain
-                    } else {
-                        true
-                    }
-                }
-                _ => true,
-            });
-
-            children.to_vec()
+        let service = TowerToHyperService::new(app.clone());
+        tokio::spawn(async move {
+            if let Err(e) = auto::Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await
+            {
+                error!("failed to serve connection: {:?}", e);
+            }
         });
     }
-}
+    let _ = std::fs::remove_file(path);
 
-fn inject_online_script(handle: Handle, url: &str) {
-    if let Some(head) = handle.get_head() {
-        // 创建一个 script 节点
-        let mut head_children = head.children.borrow_mut();
-        head_children.push(html_ops::build_script(url.into()));
-        head_children.push(html_ops::build_newline());
-    }
+    Ok(())
 }
 
-fn load_patch_html(patch_content_file: &str) -> String {
-    if patch_content_file.is_empty() {
-        let markdown = FALLBACK_PATCH_MARKDOWN.to_string();
-
-        markdown_to_html(&markdown)
-    } else if patch_content_file.ends_with(".md") {
-        let markdown = std::fs::read_to_string(Path::new(patch_content_file))
-            .unwrap_or_else(|_| FALLBACK_PATCH_MARKDOWN.to_string());
+#[cfg(not(unix))]
+async fn serve_unix(_app: Router, _path: &str) -> anyhow::Result<()> {
+    anyhow::bail!("Unix domain sockets are not supported on this platform")
+}
 
-        markdown_to_html(&markdown)
-    } else if patch_content_file.ends_with(".html") {
-        std::fs::read_to_string(Path::new(patch_content_file))
-            .unwrap_or_else(|_| FALLBACK_PATCH_HTML.to_string())
-    } else {
-        let text = std::fs::read_to_string(Path::new(patch_content_file))
-            .unwrap_or_else(|_| "Hello from Miragend!".to_owned());
+async fn run_command(command: cli::Command) -> anyhow::Result<()> {
+    use cli::{Command, ConfigAction};
 
-        // Split text by newlines and wrap each line in <p> tags
-        text.lines().fold(String::new(), |acc, line| {
-            format!("{}\n<p>{}</p>", acc, line)
-        })
+    match command {
+        Command::Config {
+            action: ConfigAction::Dump { format },
+        } => config::dump(format),
+        Command::Init { force } => scaffold::run(force),
+        Command::Check => check::run(),
+        Command::Fetch {
+            path,
+            dry_run,
+            user_agent,
+            headers,
+            ip,
+        } => {
+            simulate::run(simulate::Options {
+                path: &path,
+                user_agent: user_agent.as_deref(),
+                headers: &headers,
+                client_ip: ip,
+                dry_run,
+            })
+            .await
+        }
+        Command::Doctor { path } => doctor::run(&path).await,
     }
 }
 
-fn markdown_to_html(markdown: &str) -> String {
-    comrak::markdown_to_html(markdown, &comrak::ComrakOptions::default())
-}
-
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
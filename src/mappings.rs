@@ -0,0 +1,151 @@
+use crate::{config, obfuscation::ObfuscatorConfig, rules::glob_match, vars};
+use log::{error, info};
+use rand::Rng;
+use std::{
+    collections::BTreeMap,
+    fs,
+    sync::{Arc, LazyLock},
+    time::{Duration, SystemTime},
+};
+
+// Interval of checking the modification of the mapping files
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+// Named mapping sets from the `mappings` section
+static MAPPINGS: LazyLock<Vec<Mapping>> = LazyLock::new(|| {
+    let specs: BTreeMap<String, MappingSpec> = config::section("mappings")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `mappings` section")
+        .unwrap_or_default();
+
+    specs
+        .into_iter()
+        .map(|(name, spec)| {
+            let content = fs::read_to_string(&spec.file)
+                .unwrap_or_else(|_| panic!("failed to read mapping file `{}`", spec.file));
+
+            Mapping {
+                name,
+                config: Some(Arc::new(ObfuscatorConfig::load_from_csv(&content))),
+                weight: spec.weight,
+                paths: spec.paths,
+            }
+        })
+        .collect()
+});
+static DEFAULT_MAPPING: LazyLock<Mapping> = LazyLock::new(|| Mapping {
+    name: "default".to_owned(),
+    config: None,
+    weight: 0,
+    paths: vec![],
+});
+
+#[derive(Debug, serde::Deserialize)]
+struct MappingSpec {
+    file: String,
+    // Relative weight of the percentage-based selection
+    #[serde(default)]
+    weight: u32,
+    // Path patterns always using this mapping
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+pub struct Mapping {
+    pub name: String,
+    // `None` follows the reloadable global configuration
+    config: Option<Arc<ObfuscatorConfig>>,
+    weight: u32,
+    paths: Vec<String>,
+}
+
+impl Mapping {
+    pub fn config(&self) -> Arc<ObfuscatorConfig> {
+        self.config.clone().unwrap_or_else(vars::obfuscator_config)
+    }
+
+    // Identifier of the mapping content, e.g. `v2-1a2b3c4d`
+    pub fn version_id(&self) -> String {
+        format!("{}-{}", self.name, self.config().version)
+    }
+}
+
+pub fn force_init() {
+    LazyLock::force(&MAPPINGS);
+}
+
+// Reload the global mapping files on SIGHUP or when any of them is modified
+pub async fn watch() {
+    let files = vars::obfuscation_mapping_files();
+    let mut modified = modified_times(files);
+    #[cfg(unix)]
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("failed to install signal handler");
+    loop {
+        #[cfg(unix)]
+        let signaled = tokio::select! {
+            _ = hangup.recv() => true,
+            _ = tokio::time::sleep(WATCH_INTERVAL) => false,
+        };
+        #[cfg(not(unix))]
+        let signaled = {
+            tokio::time::sleep(WATCH_INTERVAL).await;
+            false
+        };
+
+        let current = modified_times(files);
+        if !signaled && current == modified {
+            continue;
+        }
+        modified = current;
+        match vars::reload_obfuscator_config() {
+            Ok(config) => info!(
+                "reloaded obfuscation mapping files, version: {}",
+                config.version
+            ),
+            Err(e) => error!("failed to reload obfuscation mapping files: {:?}", e),
+        }
+    }
+}
+
+fn modified_times(files: &[String]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+// Find the mapping by the name, `default` is the global mapping
+pub fn get(name: &str) -> Option<&'static Mapping> {
+    if name == DEFAULT_MAPPING.name {
+        return Some(&DEFAULT_MAPPING);
+    }
+
+    MAPPINGS.iter().find(|mapping| mapping.name == name)
+}
+
+// Select the mapping by the path first, then by the weights
+pub fn select(path: &str) -> &'static Mapping {
+    if let Some(mapping) = MAPPINGS.iter().find(|mapping| {
+        mapping
+            .paths
+            .iter()
+            .any(|pattern| glob_match(pattern, path))
+    }) {
+        return mapping;
+    }
+
+    let total: u32 = MAPPINGS.iter().map(|mapping| mapping.weight).sum();
+    if total == 0 {
+        return &DEFAULT_MAPPING;
+    }
+    let mut point = rand::thread_rng().gen_range(0..total);
+    for mapping in MAPPINGS.iter() {
+        if point < mapping.weight {
+            return mapping;
+        }
+        point -= mapping.weight;
+    }
+
+    &DEFAULT_MAPPING
+}
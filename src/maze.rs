@@ -0,0 +1,106 @@
+use crate::{
+    obfuscation::{CharactersMapper, ObfuscatorConfig},
+    vars::{self, CONTENT_TYPE_VALUE_TEXT_HTML},
+};
+use axum::body::Body;
+use http::{header, Response, StatusCode};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+
+const PARAGRAPHS: std::ops::RangeInclusive<usize> = 3..=8;
+const SENTENCES: std::ops::RangeInclusive<usize> = 2..=6;
+const WORDS: std::ops::RangeInclusive<usize> = 4..=14;
+const WORD_LEN: std::ops::RangeInclusive<usize> = 1..=9;
+const LINKS: std::ops::RangeInclusive<usize> = 5..=12;
+
+// Build a garbage page, the same path always generates the same page
+pub fn build_resp(path: &str) -> Result<Response<Body>, http::Error> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, CONTENT_TYPE_VALUE_TEXT_HTML)
+        .body(Body::from(build_page(path, &vars::obfuscator_config())))
+}
+
+fn build_page(path: &str, config: &ObfuscatorConfig) -> String {
+    let digest = Sha256::digest(path.as_bytes());
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&digest);
+    let mut rng = StdRng::from_seed(seed);
+    // Use the same characters range for the whole page
+    let mapper = config.pick_mapper(rng.gen());
+    let text = |rng: &mut StdRng, words: usize| random_words(rng, mapper, words);
+
+    let title = text(&mut rng, 4);
+    let mut body = format!("<h1>{}</h1>\n", title);
+    for _ in 0..rng.gen_range(PARAGRAPHS) {
+        let sentences = (0..rng.gen_range(SENTENCES))
+            .map(|_| {
+                let words = rng.gen_range(WORDS);
+                format!("{}.", text(&mut rng, words))
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        body.push_str(&format!("<p>{}</p>\n", sentences));
+    }
+    body.push_str("<ul>\n");
+    for _ in 0..rng.gen_range(LINKS) {
+        let slug: String = (0..rng.gen_range(6..=16))
+            .map(|_| rng.gen_range(b'a'..=b'z') as char)
+            .collect();
+        let label = text(&mut rng, 3);
+        body.push_str(&format!(
+            "<li><a href=\"{}{}.html\">{}</a></li>\n",
+            vars::maze_link_prefix(),
+            slug,
+            label
+        ));
+    }
+    body.push_str("</ul>\n");
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n<body>\n{}</body>\n</html>\n",
+        title, body
+    )
+}
+
+// Generate garbage words in the characters of a random mapper
+pub fn garbage_text(rng: &mut impl Rng, config: &ObfuscatorConfig, words: usize) -> String {
+    let mapper = config.pick_mapper(rng.gen());
+
+    random_words(rng, mapper, words)
+}
+
+fn random_words(rng: &mut impl Rng, mapper: Option<&CharactersMapper>, words: usize) -> String {
+    (0..words)
+        .map(|_| {
+            (0..rng.gen_range(WORD_LEN))
+                .map(|_| random_char(rng, mapper))
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn random_char(rng: &mut impl Rng, mapper: Option<&CharactersMapper>) -> char {
+    match mapper {
+        Some(mapper) => {
+            let value = rng.gen_range(mapper.target_start as u32..=mapper.target_end as u32);
+            char::from_u32(value).unwrap_or('?')
+        }
+        None => rng.gen_range(b'a'..=b'z') as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_page() {
+        let config = ObfuscatorConfig::load_from_csv(include_str!("../obfuscation_mapping.csv"));
+        let page = build_page("/foo.html", &config);
+        assert_eq!(page, build_page("/foo.html", &config));
+        assert_ne!(page, build_page("/bar.html", &config));
+        assert!(page.contains("<a href=\""));
+    }
+}
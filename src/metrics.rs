@@ -0,0 +1,203 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+type Labels = Vec<(&'static str, String)>;
+
+static COUNTERS: LazyLock<Mutex<BTreeMap<&'static str, BTreeMap<Labels, u64>>>> =
+    LazyLock::new(Default::default);
+static HISTOGRAMS: LazyLock<Mutex<BTreeMap<&'static str, BTreeMap<Labels, Histogram>>>> =
+    LazyLock::new(Default::default);
+// Upper bounds of the histogram buckets in seconds
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    // Cumulative counts of the buckets
+    buckets: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+// Durations of the request phases, for the access log and metrics
+pub struct Timings {
+    started: Instant,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    pub fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            phases: vec![],
+        }
+    }
+
+    // Run the closure as a part of the phase
+    pub fn time<T>(&mut self, phase: &'static str, f: impl FnOnce() -> T) -> T {
+        let started = Instant::now();
+        let result = f();
+        self.add(phase, started.elapsed());
+
+        result
+    }
+
+    // The durations of a repeated phase are summed
+    pub fn add(&mut self, phase: &'static str, duration: Duration) {
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += duration,
+            None => self.phases.push((phase, duration)),
+        }
+    }
+
+    pub fn phases(&self) -> &[(&'static str, Duration)] {
+        &self.phases
+    }
+
+    pub fn total(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // Record the phases and the total into `miragend_request_duration_seconds`
+    pub fn observe(&self) {
+        for (phase, duration) in &self.phases {
+            observe(
+                "miragend_request_duration_seconds",
+                &[("phase", *phase)],
+                *duration,
+            );
+        }
+        observe(
+            "miragend_request_duration_seconds",
+            &[("phase", "total")],
+            self.total(),
+        );
+    }
+}
+
+// Increase the counter by one
+pub fn inc(name: &'static str, labels: &[(&'static str, &str)]) {
+    let labels = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+    let mut counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+    *counters.entry(name).or_default().entry(labels).or_default() += 1;
+}
+
+// Get the current value of the counter
+pub fn value(name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+    let labels: Labels = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+    let counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+    counters
+        .get(name)
+        .and_then(|series| series.get(&labels))
+        .copied()
+        .unwrap_or_default()
+}
+
+// Record the duration into the histogram
+pub fn observe(name: &'static str, labels: &[(&'static str, &str)], duration: Duration) {
+    let labels = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+    let seconds = duration.as_secs_f64();
+    let mut histograms = HISTOGRAMS.lock().unwrap_or_else(|e| e.into_inner());
+    let histogram = histograms
+        .entry(name)
+        .or_default()
+        .entry(labels)
+        .or_default();
+    for (bucket, bound) in histogram.buckets.iter_mut().zip(BUCKETS) {
+        if seconds <= bound {
+            *bucket += 1;
+        }
+    }
+    histogram.sum += seconds;
+    histogram.count += 1;
+}
+
+fn format_labels(labels: &[(&'static str, String)]) -> String {
+    labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Render all metrics in the Prometheus text format
+pub fn render() -> String {
+    let counters = COUNTERS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut output = String::new();
+    for (name, series) in counters.iter() {
+        let _ = writeln!(output, "# TYPE {} counter", name);
+        for (labels, value) in series {
+            let labels = format_labels(labels);
+            if labels.is_empty() {
+                let _ = writeln!(output, "{} {}", name, value);
+            } else {
+                let _ = writeln!(output, "{}{{{}}} {}", name, labels, value);
+            }
+        }
+    }
+    drop(counters);
+
+    let histograms = HISTOGRAMS.lock().unwrap_or_else(|e| e.into_inner());
+    for (name, series) in histograms.iter() {
+        let _ = writeln!(output, "# TYPE {} histogram", name);
+        for (labels, histogram) in series {
+            let labels = format_labels(labels);
+            let prefix = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{},", labels)
+            };
+            for (count, bound) in histogram.buckets.iter().zip(BUCKETS) {
+                let _ = writeln!(
+                    output,
+                    "{}_bucket{{{}le=\"{}\"}} {}",
+                    name, prefix, bound, count
+                );
+            }
+            let _ = writeln!(
+                output,
+                "{}_bucket{{{}le=\"+Inf\"}} {}",
+                name, prefix, histogram.count
+            );
+            let labels = if labels.is_empty() {
+                String::new()
+            } else {
+                format!("{{{}}}", labels)
+            };
+            let _ = writeln!(output, "{}_sum{} {}", name, labels, histogram.sum);
+            let _ = writeln!(output, "{}_count{} {}", name, labels, histogram.count);
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_histogram() {
+        observe(
+            "miragend_test_duration_seconds",
+            &[("phase", "parse")],
+            Duration::from_millis(20),
+        );
+        let output = render();
+        assert!(output.contains("# TYPE miragend_test_duration_seconds histogram"));
+        assert!(
+            output.contains("miragend_test_duration_seconds_bucket{phase=\"parse\",le=\"0.01\"} 0")
+        );
+        assert!(output
+            .contains("miragend_test_duration_seconds_bucket{phase=\"parse\",le=\"0.025\"} 1"));
+        assert!(
+            output.contains("miragend_test_duration_seconds_bucket{phase=\"parse\",le=\"+Inf\"} 1")
+        );
+        assert!(output.contains("miragend_test_duration_seconds_count{phase=\"parse\"} 1"));
+    }
+}
@@ -0,0 +1,123 @@
+// Integration mode, applies the bot policies and transforms of miragend to the responses of
+// the wrapped service in-process.
+//
+// ```ignore
+// miragend::validate_config()?;
+// let app = Router::new()
+//     .route("/", get(index))
+//     .layer(axum::middleware::from_fn(miragend::middleware::apply));
+// ```
+//
+// Or as a tower layer, with an optional default strategy:
+//
+// ```ignore
+// let app = Router::new()
+//     .route("/", get(index))
+//     .layer(MiragendLayer::new().strategy("patch"));
+// ```
+//
+// Spawn `watch_mapping_files` and `watch_patch_content` to pick up the changes of the files.
+use crate::{ListenerStrategy, Source};
+use axum::{body::Body, extract::ConnectInfo, middleware::Next};
+use futures_util::future::BoxFuture;
+use http::{Request, Response};
+use std::{
+    convert::Infallible,
+    net::{Ipv4Addr, SocketAddr},
+    task::{Context, Poll},
+};
+use tower::{Layer, Service};
+
+// Produce the original response of the wrapped service
+pub(crate) type RunInner =
+    Box<dyn FnOnce(Request<Body>) -> BoxFuture<'static, Response<Body>> + Send>;
+
+// Middleware function for `axum::middleware::from_fn`. The client address is taken from
+// `ConnectInfo` if the app is served with it, otherwise from `X-Forwarded-For`.
+pub async fn apply(request: Request<Body>, next: Next) -> Response<Body> {
+    let conn_addr = conn_addr(&request);
+    let run: RunInner = Box::new(move |request| Box::pin(next.run(request)));
+
+    crate::dispatch(conn_addr, request, Source::Inner(run)).await
+}
+
+fn conn_addr(request: &Request<Body>) -> SocketAddr {
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| *addr)
+        .unwrap_or((Ipv4Addr::UNSPECIFIED, 0).into())
+}
+
+// Tower layer wrapping a local service, e.g. an axum `Router`
+#[derive(Debug, Clone, Default)]
+pub struct MiragendLayer {
+    strategy: Option<&'static str>,
+}
+
+impl MiragendLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Default strategy of the wrapped service, overrides `MIRAGEND_STRATEGY`
+    pub fn strategy(self, strategy: &'static str) -> Self {
+        Self {
+            strategy: Some(strategy),
+        }
+    }
+}
+
+impl<S> Layer<S> for MiragendLayer {
+    type Service = MiragendService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MiragendService {
+            inner,
+            strategy: self.strategy,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MiragendService<S> {
+    inner: S,
+    strategy: Option<&'static str>,
+}
+
+impl<S> Service<Request<Body>> for MiragendService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = Infallible>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response<Body>, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        // Take the service driven to readiness, leave a clone for the next call
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        if let Some(strategy) = self.strategy {
+            request.extensions_mut().insert(ListenerStrategy(strategy));
+        }
+        let conn_addr = conn_addr(&request);
+        let run: RunInner = Box::new(move |request| {
+            Box::pin(async move {
+                match inner.call(request).await {
+                    Ok(resp) => resp,
+                    Err(e) => match e {},
+                }
+            })
+        });
+
+        Box::pin(async move { Ok(crate::dispatch(conn_addr, request, Source::Inner(run)).await) })
+    }
+}
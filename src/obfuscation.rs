@@ -1,32 +1,94 @@
+use crate::vars;
 use anyhow::Context;
 use html5ever::tendril::{fmt::UTF8, Tendril};
 use log::{info, warn};
 use rand::Rng;
+use regex::Regex;
 use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+const CSV_HEADER: &str = "source_start,source_end,target_start,target_end,weight,comment";
+// Built-in mapping sets selectable by name, in the format of the CSV records
+const CHARSETS: [(&str, &str); 5] = [
+    (
+        "chinese",
+        "4e00,9fff,3400,4dbf,1,\"from common Chinese characters to rare Chinese characters\"",
+    ),
+    (
+        "latin",
+        "0061,007a,0061,007a,1,\"from lowercase English letters to lowercase English letters\"\n\
+         0041,005A,0041,005A,1,\"from uppercase English letters to uppercase English letters\"",
+    ),
+    (
+        "cyrillic",
+        "0430,044f,0430,044f,1,\"from lowercase Cyrillic letters to lowercase Cyrillic letters\"\n\
+         0410,042f,0410,042f,1,\"from uppercase Cyrillic letters to uppercase Cyrillic letters\"",
+    ),
+    (
+        "japanese",
+        "3041,3096,3041,3096,1,\"from Hiragana to Hiragana\"\n\
+         30a1,30fa,30a1,30fa,1,\"from Katakana to Katakana\"",
+    ),
+    (
+        "korean",
+        "ac00,d7a3,ac00,d7a3,1,\"from Hangul syllables to Hangul syllables\"",
+    ),
+];
+
+// CSV content of the built-in mapping set
+pub fn builtin_charset(name: &str) -> Option<String> {
+    CHARSETS
+        .iter()
+        .find(|(charset, _)| *charset == name)
+        .map(|(_, records)| format!("{}\n{}\n", CSV_HEADER, records))
+}
+
+pub fn builtin_charset_names() -> Vec<&'static str> {
+    CHARSETS.iter().map(|(name, _)| *name).collect()
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ObfuscatorConfig {
     pub mappers: Vec<CharactersMapper>,
+    // Short hash of the mapping content
+    pub version: String,
+    // Seed of the visitor, characters are mapped deterministically when present
+    pub seed: Option<u64>,
+    // Fraction of the eligible characters being replaced
+    pub ratio: f64,
+    // Replace the digits: `false`, `true` or `magnitude`
+    pub digits: &'static str,
 }
 
-#[derive(Debug, serde::Deserialize)]
+// The ranges are inclusive
+#[derive(Debug, Clone, serde::Deserialize)]
 pub struct CharactersMapper {
     pub source_start: char,
     pub source_end: char,
     pub target_start: char,
     pub target_end: char,
+    // Relative weight among the mappers covering the same character
+    pub weight: u32,
     pub comment: String,
 }
 
+// Schema v1 has no `weight` column and excludes the end of the source range,
+// schema v2 adds the column and includes it
 #[derive(Debug, serde::Deserialize)]
 pub struct Record {
     pub source_start: String,
     pub source_end: String,
     pub target_start: String,
     pub target_end: String,
+    #[serde(default)]
+    pub weight: Option<u32>,
     pub comment: String,
 }
 
+const WEIGHT_COLUMN: &str = "weight";
+const DEFAULT_WEIGHT: u32 = 1;
+
 impl TryFrom<Record> for CharactersMapper {
     type Error = anyhow::Error;
 
@@ -39,34 +101,58 @@ impl TryFrom<Record> for CharactersMapper {
             .context("failed to convert u32 to char")
         };
 
-        Ok(Self {
+        let mapper = Self {
             source_start: conver_field_to_char("source_start", &record.source_start)?,
             source_end: conver_field_to_char("source_end", &record.source_end)?,
             target_start: conver_field_to_char("target_start", &record.target_start)?,
             target_end: conver_field_to_char("target_end", &record.target_end)?,
+            weight: record.weight.unwrap_or(DEFAULT_WEIGHT),
             comment: record.comment,
-        })
+        };
+        if mapper.source_start > mapper.source_end || mapper.target_start > mapper.target_end {
+            anyhow::bail!("the start of a range is greater than the end");
+        }
+
+        Ok(mapper)
     }
 }
 
 impl ObfuscatorConfig {
     pub fn load_from_csv(content: &str) -> Self {
+        Self::load_from_csvs(&[content])
+    }
+
+    // Merge the mappings of multiple CSV contents in order
+    pub fn load_from_csvs(contents: &[&str]) -> Self {
         let mut records = vec![];
-        let mut rdr = csv::Reader::from_reader(content.as_bytes());
-        for result in rdr.deserialize::<Record>() {
-            match result {
-                Err(e) => {
-                    warn!("failed to parse csv record: {}, ignored", e);
-                }
-                Ok(record) => {
-                    records.push(record);
-                }
+        let mut hasher = Sha256::new();
+        for content in contents {
+            let mut rdr = csv::Reader::from_reader(content.as_bytes());
+            let schema = match rdr.headers() {
+                Ok(headers) if headers.iter().any(|header| header == WEIGHT_COLUMN) => 2,
+                _ => 1,
             };
+            info!("loading characters mappings of schema v{}", schema);
+            for result in rdr.deserialize::<Record>() {
+                match result {
+                    Err(e) => {
+                        warn!("failed to parse csv record: {}, ignored", e);
+                    }
+                    Ok(record) => {
+                        records.push((schema, record));
+                    }
+                };
+            }
+            hasher.update(content.as_bytes());
         }
 
         let mut mappers = vec![];
-        for record in records.into_iter() {
-            match CharactersMapper::try_from(record) {
+        for (schema, record) in records.into_iter() {
+            let mapper = CharactersMapper::try_from(record).and_then(|mapper| match schema {
+                1 => mapper.from_v1(),
+                _ => Ok(mapper),
+            });
+            match mapper {
                 Ok(mapper) => {
                     info!("loaded characters mapping: {}", &mapper.comment);
                     mappers.push(mapper);
@@ -77,19 +163,162 @@ impl ObfuscatorConfig {
             }
         }
 
-        Self { mappers }
+        let digest = hasher.finalize();
+        let version = format!(
+            "{:08x}",
+            u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]])
+        );
+
+        Self {
+            mappers,
+            version,
+            seed: None,
+            ratio: 1.0,
+            digits: vars::obfuscation_digits(),
+        }
+    }
+
+    // Copy of the configuration bound to the visitor seed
+    pub fn with_seed(&self, seed: u64) -> Self {
+        Self {
+            seed: Some(seed),
+            ..self.clone()
+        }
+    }
+
+    // Copy of the configuration replacing only a fraction of the characters
+    pub fn with_ratio(&self, ratio: f64) -> Self {
+        Self {
+            ratio,
+            ..self.clone()
+        }
+    }
+
+    // Check if the character is covered by any mapper
+    pub fn is_mapped(&self, input: char) -> bool {
+        self.mappers.iter().any(|mapper| mapper.covers(input))
+    }
+
+    // Pick a mapper by the weights, `point` is a random number
+    pub fn pick_mapper(&self, point: u64) -> Option<&CharactersMapper> {
+        weighted(self.mappers.iter(), point)
+    }
+}
+
+impl CharactersMapper {
+    pub fn covers(&self, input: char) -> bool {
+        (self.source_start..=self.source_end).contains(&input)
+    }
+
+    // The v1 records are converted to the inclusive ranges
+    fn from_v1(mut self) -> anyhow::Result<Self> {
+        if self.source_start == self.source_end {
+            anyhow::bail!("the source range is empty");
+        }
+        // Only `\u{e000}` has a surrogate before it
+        self.source_end = char::from_u32(u32::from(self.source_end) - 1).unwrap_or('\u{d7ff}');
+
+        Ok(self)
     }
 }
 
+fn weighted<'a>(
+    mappers: impl Iterator<Item = &'a CharactersMapper> + Clone,
+    point: u64,
+) -> Option<&'a CharactersMapper> {
+    let total: u64 = mappers.clone().map(|mapper| mapper.weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = point % total;
+    for mapper in mappers {
+        if point < mapper.weight as u64 {
+            return Some(mapper);
+        }
+        point -= mapper.weight as u64;
+    }
+
+    None
+}
+
 /// Map to target character based on the obfuscation configuration
 fn random_char(config: &ObfuscatorConfig, input: char) -> char {
-    for mapper in config.mappers.iter() {
-        if (mapper.source_start..mapper.source_end).contains(&input) {
-            return random_unicode_char(mapper.target_start as u32, mapper.target_end as u32);
+    // The digits are replaced with other digits when opted in
+    let target = if input.is_ascii_digit() && config.digits != "false" {
+        Some(('0' as u32, '9' as u32))
+    } else {
+        let point = match config.seed {
+            Some(seed) => mix(seed.rotate_left(32), input),
+            None => rand::thread_rng().gen(),
+        };
+        let covering = config.mappers.iter().filter(|mapper| mapper.covers(input));
+        weighted(covering, point)
+            .map(|mapper| (mapper.target_start as u32, mapper.target_end as u32))
+    };
+    let Some((start, end)) = target else {
+        return input;
+    };
+    if !should_replace(config.seed, input, config.ratio) {
+        return input;
+    }
+
+    match config.seed {
+        Some(seed) => seeded_unicode_char(seed, input, start, end),
+        None => random_unicode_char(start, end),
+    }
+}
+
+fn obfuscate_text(config: &ObfuscatorConfig, text: &str) -> String {
+    let keep_leading_digits = config.digits == "magnitude";
+    preserving(text, vars::obfuscation_preserve_patterns(), |segment| {
+        map_text(segment, keep_leading_digits, |c| random_char(config, c))
+    })
+}
+
+/// Map the text except the matches of the patterns, which are kept intact
+pub fn preserving(text: &str, patterns: &[Regex], mut map: impl FnMut(&str) -> String) -> String {
+    if patterns.is_empty() {
+        return map(text);
+    }
+
+    let mut matches: Vec<(usize, usize)> = patterns
+        .iter()
+        .flat_map(|pattern| pattern.find_iter(text).map(|m| (m.start(), m.end())))
+        .collect();
+    matches.sort_unstable();
+    let mut output = String::with_capacity(text.len());
+    let mut pos = 0;
+    for (start, end) in matches {
+        // Overlapped with the previous match
+        if end <= pos {
+            continue;
         }
+        let start = start.max(pos);
+        output.push_str(&map(&text[pos..start]));
+        output.push_str(&text[start..end]);
+        pos = end;
     }
+    output.push_str(&map(&text[pos..]));
+
+    output
+}
 
-    input
+// Keep the leading digits of the numbers, so the replaced numbers have the same magnitude
+fn map_text(text: &str, keep_leading_digits: bool, mut map: impl FnMut(char) -> char) -> String {
+    let mut prev = [' ', ' '];
+    text.chars()
+        .map(|c| {
+            let continued = prev[1].is_ascii_digit()
+                || (matches!(prev[1], '.' | ',') && prev[0].is_ascii_digit());
+            prev = [prev[1], c];
+            if keep_leading_digits && c.is_ascii_digit() && !continued {
+                c
+            } else {
+                map(c)
+            }
+        })
+        .collect()
 }
 
 fn random_unicode_char(start: u32, end: u32) -> char {
@@ -98,6 +327,34 @@ fn random_unicode_char(start: u32, end: u32) -> char {
     std::char::from_u32(random_value).unwrap_or('?')
 }
 
+// The same input always maps to the same character under the same seed
+fn seeded_unicode_char(seed: u64, input: char, start: u32, end: u32) -> char {
+    let value = start + (mix(seed, input) % (end - start + 1) as u64) as u32;
+    std::char::from_u32(value).unwrap_or('?')
+}
+
+// Only a fraction of the characters is replaced, the same input is always (or never)
+// replaced under the same seed
+fn should_replace(seed: Option<u64>, input: char, ratio: f64) -> bool {
+    if ratio >= 1.0 {
+        return true;
+    }
+
+    match seed {
+        // Independent of the mapped character
+        Some(seed) => ((mix(!seed, input) >> 11) as f64 / (1u64 << 53) as f64) < ratio,
+        None => rand::thread_rng().gen_bool(ratio.max(0.0)),
+    }
+}
+
+// SplitMix64 finalizer
+fn mix(seed: u64, input: char) -> u64 {
+    let mut x = seed ^ (input as u64).wrapping_mul(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
 pub trait Obfuscator {
     type Output;
 
@@ -158,7 +415,7 @@ impl Obfuscator for &mut Tendril<UTF8> {
     }
 
     fn obfuscated(&self, config: &ObfuscatorConfig) -> Self::Output {
-        self.chars().map(|c| random_char(config, c)).collect()
+        obfuscate_text(config, self).into()
     }
 }
 
@@ -170,7 +427,7 @@ impl Obfuscator for &mut String {
     }
 
     fn obfuscated(&self, config: &ObfuscatorConfig) -> Self::Output {
-        self.chars().map(|c| random_char(config, c)).collect()
+        obfuscate_text(config, self)
     }
 }
 
@@ -182,7 +439,7 @@ impl Obfuscator for str {
     }
 
     fn obfuscated(&self, config: &ObfuscatorConfig) -> Self::Output {
-        self.chars().map(|c| random_char(config, c)).collect()
+        obfuscate_text(config, self)
     }
 }
 
@@ -197,3 +454,323 @@ impl Obfuscator for char {
         random_char(config, *self)
     }
 }
+
+// Latin letters weighted by their English frequency, generated words look pronounceable
+const LATIN_LETTERS: &str =
+    "eeeeeeeeeeeetttttttttaaaaaaaaooooooooiiiiiiinnnnnnnssssssshhhhhhrrrrrrddddlllluuucccmmwwffggyyppbbvkjxqz";
+// Ranges of the generated characters by script
+const SCRIPT_RANGES: [(char, char); 8] = [
+    ('0', '9'),
+    ('\u{03b1}', '\u{03c9}'), // Greek
+    ('\u{0430}', '\u{044f}'), // Cyrillic
+    ('\u{3041}', '\u{3093}'), // Hiragana
+    ('\u{30a1}', '\u{30f3}'), // Katakana
+    ('\u{4e00}', '\u{9fa5}'), // CJK Unified Ideographs
+    ('\u{ac00}', '\u{d7a3}'), // Hangul Syllables
+    ('\u{0e01}', '\u{0e2e}'), // Thai
+];
+
+/// Replace the words with generated words of the same length and script,
+/// keeping the spacing, punctuation and capitalization
+pub fn word_obfuscated(text: &str, ratio: f64, rng: &mut impl Rng) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut replace_word = false;
+    let mut in_word = false;
+    for c in text.chars() {
+        if !c.is_alphanumeric() {
+            in_word = false;
+            output.push(c);
+
+            continue;
+        }
+        if !in_word {
+            in_word = true;
+            replace_word = ratio >= 1.0 || rng.gen_bool(ratio.max(0.0));
+        }
+        output.push(if replace_word { word_char(c, rng) } else { c });
+    }
+
+    output
+}
+
+fn word_char(c: char, rng: &mut impl Rng) -> char {
+    let lower = c.to_lowercase().next().unwrap_or(c);
+    let generated = if lower.is_ascii_lowercase() {
+        let letters = LATIN_LETTERS.as_bytes();
+        letters[rng.gen_range(0..letters.len())] as char
+    } else if let Some((start, end)) = SCRIPT_RANGES
+        .iter()
+        .find(|(start, end)| (*start..=*end).contains(&lower))
+    {
+        std::char::from_u32(rng.gen_range(*start as u32..=*end as u32)).unwrap_or(c)
+    } else {
+        // Unknown script, keep the character
+        return c;
+    };
+
+    if c.is_uppercase() {
+        generated.to_uppercase().next().unwrap_or(generated)
+    } else {
+        generated
+    }
+}
+
+// Order of the Markov chain (characters of the state)
+const MARKOV_ORDER: usize = 2;
+
+/// Character-level Markov model trained from the page text
+#[derive(Debug, Default)]
+pub struct MarkovModel {
+    transitions: HashMap<Vec<char>, Vec<char>>,
+    starts: Vec<Vec<char>>,
+}
+
+impl MarkovModel {
+    pub fn train<'a>(texts: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut model = Self::default();
+        for text in texts {
+            let chars: Vec<char> = text.trim().chars().collect();
+            if chars.len() <= MARKOV_ORDER {
+                continue;
+            }
+
+            model.starts.push(chars[..MARKOV_ORDER].to_vec());
+            for window in chars.windows(MARKOV_ORDER + 1) {
+                model
+                    .transitions
+                    .entry(window[..MARKOV_ORDER].to_vec())
+                    .or_default()
+                    .push(window[MARKOV_ORDER]);
+            }
+        }
+
+        model
+    }
+
+    /// Generate text with the same number of characters, `None` if the model is empty
+    pub fn generate(&self, len: usize, rng: &mut impl Rng) -> Option<String> {
+        if self.starts.is_empty() {
+            return None;
+        }
+
+        let mut output: Vec<char> = Vec::with_capacity(len);
+        while output.len() < len {
+            let state = if output.len() >= MARKOV_ORDER {
+                output[output.len() - MARKOV_ORDER..].to_vec()
+            } else {
+                vec![]
+            };
+            match self.transitions.get(&state) {
+                Some(nexts) if !state.is_empty() => {
+                    output.push(nexts[rng.gen_range(0..nexts.len())]);
+                }
+                // Dead end, restart from a random start state
+                _ => {
+                    let start = &self.starts[rng.gen_range(0..self.starts.len())];
+                    if !output.is_empty() && !output[output.len() - 1].is_whitespace() {
+                        output.push(' ');
+                    }
+                    output.extend(start.iter());
+                }
+            }
+        }
+        output.truncate(len);
+
+        Some(output.into_iter().collect())
+    }
+
+    /// Replace the text with generated text, keeping the surrounding whitespace
+    pub fn rewrite(&self, text: &str, rng: &mut impl Rng) -> Option<String> {
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            return Some(text.to_owned());
+        }
+
+        let leading = &text[..text.len() - text.trim_start().len()];
+        let trailing = &text[text.trim_end().len()..];
+        let generated = self.generate(trimmed.chars().count(), rng)?;
+
+        Some(format!("{}{}{}", leading, generated, trailing))
+    }
+}
+
+#[cfg(test)]
+mod seed_tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_obfuscation() {
+        let config = ObfuscatorConfig::load_from_csv(include_str!("../obfuscation_mapping.csv"));
+        let a = config.with_seed(1);
+        let b = config.with_seed(2);
+        let text = "Hello, 世界";
+        assert_eq!(text.obfuscated(&a), text.obfuscated(&a));
+        assert_ne!(text.obfuscated(&a), text.obfuscated(&b));
+        assert_eq!(text.obfuscated(&a).chars().count(), text.chars().count());
+    }
+
+    #[test]
+    fn test_load_from_csvs() {
+        let builtin = include_str!("../obfuscation_mapping.csv");
+        let extra =
+            "source_start,source_end,target_start,target_end,comment\n0391,03A9,03B1,03C9,Greek\n";
+        let single = ObfuscatorConfig::load_from_csv(builtin);
+        let merged = ObfuscatorConfig::load_from_csvs(&[builtin, extra]);
+        assert_eq!(merged.mappers.len(), single.mappers.len() + 1);
+        assert_ne!(merged.version, single.version);
+        assert!(merged.is_mapped('\u{0392}') && !single.is_mapped('\u{0392}'));
+    }
+
+    #[test]
+    fn test_builtin_charset() {
+        for name in builtin_charset_names() {
+            let config = ObfuscatorConfig::load_from_csv(&builtin_charset(name).unwrap());
+            assert!(!config.mappers.is_empty(), "{}", name);
+        }
+        let config = ObfuscatorConfig::load_from_csv(&builtin_charset("cyrillic").unwrap());
+        assert!(config.is_mapped('б') && config.is_mapped('Б') && !config.is_mapped('b'));
+        assert!(builtin_charset("klingon").is_none());
+    }
+
+    #[test]
+    fn test_map_text() {
+        let zeroed = |c: char| if c.is_ascii_digit() { '0' } else { c };
+        assert_eq!(
+            map_text("Price: 1,234.50 (x7)", true, zeroed),
+            "Price: 1,000.00 (x7)"
+        );
+        assert_eq!(
+            map_text("Price: 1,234.50 (x7)", false, zeroed),
+            "Price: 0,000.00 (x0)"
+        );
+    }
+
+    #[test]
+    fn test_digits() {
+        let config = ObfuscatorConfig::load_from_csv(include_str!("../obfuscation_mapping.csv"));
+        let with_digits = |digits| ObfuscatorConfig {
+            digits,
+            ..config.clone()
+        };
+        assert_eq!(random_char(&with_digits("false"), '7'), '7');
+        assert!(random_char(&with_digits("true"), '7').is_ascii_digit());
+        let text = obfuscate_text(&with_digits("magnitude"), "1234");
+        assert!(text.starts_with('1') && text.chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn test_preserving() {
+        let patterns = [
+            Regex::new(r"[\w.]+@[\w.]+").unwrap(),
+            Regex::new(r"https?://\S+").unwrap(),
+            Regex::new(r"example").unwrap(),
+        ];
+        let upper = |segment: &str| segment.to_uppercase();
+        assert_eq!(
+            preserving(
+                "mail a@example.com or see https://example.com now",
+                &patterns,
+                upper
+            ),
+            "MAIL a@example.com OR SEE https://example.com NOW"
+        );
+        assert_eq!(preserving("hello", &[], upper), "HELLO");
+    }
+
+    #[test]
+    fn test_mapper_ranges() {
+        let content = "source_start,source_end,target_start,target_end,weight,comment\n\
+                       0061,0061,0062,0062,1,single\n\
+                       0063,0064,0065,0065,0,disabled\n\
+                       0063,0064,0066,0066,3,weighted\n";
+        let config = ObfuscatorConfig::load_from_csv(content);
+        assert_eq!(config.mappers.len(), 3);
+        assert!(config.is_mapped('a') && config.is_mapped('d') && !config.is_mapped('e'));
+        assert_eq!('a'.obfuscated(&config), 'b');
+        assert_eq!('d'.obfuscated(&config), 'f');
+        assert_eq!(config.pick_mapper(0).unwrap().comment, "single");
+        assert_eq!(config.pick_mapper(1).unwrap().comment, "weighted");
+
+        let builtin = ObfuscatorConfig::load_from_csv(include_str!("../obfuscation_mapping.csv"));
+        assert!(builtin.is_mapped('z') && builtin.is_mapped('Z'));
+    }
+
+    #[test]
+    fn test_v1_mapper_ranges() {
+        let content = "source_start,source_end,target_start,target_end,comment\n\
+                       0061,007a,0041,005a,letters\n\
+                       0030,0030,0031,0031,empty\n";
+        let v1 = ObfuscatorConfig::load_from_csv(content);
+        assert_eq!(v1.mappers.len(), 1);
+        assert_eq!(v1.mappers[0].weight, DEFAULT_WEIGHT);
+        assert_eq!(v1.mappers[0].target_end, 'Z');
+        assert!(v1.is_mapped('a') && v1.is_mapped('y') && !v1.is_mapped('z'));
+        assert!(!v1.is_mapped('0'));
+    }
+
+    #[test]
+    fn test_should_replace() {
+        assert!(('a'..='z').all(|c| should_replace(Some(1), c, 1.0)));
+        assert!(('a'..='z').all(|c| !should_replace(Some(1), c, 0.0)));
+        assert!(('a'..='z').all(|c| !should_replace(None, c, 0.0)));
+        let replaced = ('\u{4e00}'..'\u{5e00}')
+            .filter(|&c| should_replace(Some(1), c, 0.3))
+            .count();
+        assert!((1000..2000).contains(&replaced));
+        assert!(
+            ('a'..='z').all(|c| should_replace(Some(1), c, 0.5) == should_replace(Some(1), c, 0.5))
+        );
+    }
+}
+
+#[cfg(test)]
+mod word_tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_word_obfuscated() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let text = "Hello, World! It's 2024 — Привет 世界.";
+        let obfuscated = word_obfuscated(text, 1.0, &mut rng);
+        assert_ne!(obfuscated, text);
+        assert_eq!(obfuscated.chars().count(), text.chars().count());
+        for (a, b) in text.chars().zip(obfuscated.chars()) {
+            assert_eq!(a.is_alphanumeric(), b.is_alphanumeric());
+            assert_eq!(a.is_uppercase(), b.is_uppercase());
+            if !a.is_alphanumeric() {
+                assert_eq!(a, b);
+            }
+        }
+        assert_eq!(word_obfuscated(text, 0.0, &mut rng), text);
+    }
+}
+
+#[cfg(test)]
+mod markov_tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_generate() {
+        let model = MarkovModel::train([
+            "The quick brown fox jumps over the lazy dog",
+            "A quick movement of the enemy will jeopardize six gunboats",
+        ]);
+        let mut rng = StdRng::seed_from_u64(0);
+        for len in [0, 1, 5, 100] {
+            assert_eq!(model.generate(len, &mut rng).unwrap().chars().count(), len);
+        }
+        assert!(MarkovModel::train([]).generate(10, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let model = MarkovModel::train(["人工智能正在改变世界", "世界正在改变人工智能"]);
+        let mut rng = StdRng::seed_from_u64(0);
+        let rewritten = model.rewrite("\n  你好世界  ", &mut rng).unwrap();
+        assert!(rewritten.starts_with("\n  "));
+        assert!(rewritten.ends_with("  "));
+        assert_eq!(rewritten.trim().chars().count(), 4);
+    }
+}
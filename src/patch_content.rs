@@ -0,0 +1,190 @@
+// Source of the patch content, `MIRAGEND_PATCH_CONTENT_FILE` is a local file or an HTTP(S) URL.
+// The remote content is cached for `MIRAGEND_PATCH_CONTENT_TTL_SECS`, the last fetched copy is
+// kept when refreshing fails. The rendered content is cached until the source changes.
+use crate::{request, vars};
+use http::HeaderMap;
+use log::{error, info};
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        LazyLock, RwLock,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::Mutex;
+
+// Interval of checking the modification of the local file
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+// Patch content rendered for the pages and the plain text responses
+#[derive(Clone)]
+pub struct Rendered {
+    pub html: String,
+    pub text: String,
+}
+
+struct Remote {
+    content: Option<String>,
+    fetched_at: Instant,
+}
+
+static REMOTE: RwLock<Option<Remote>> = RwLock::new(None);
+static RENDERED: RwLock<Option<Rendered>> = RwLock::new(None);
+// Bumped on every invalidation, the content rendered from an older source is dropped
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+// Only one request refreshes the remote content at a time
+static REFRESHING: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+pub fn is_remote(file: &str) -> bool {
+    file.starts_with("http://") || file.starts_with("https://")
+}
+
+// Name deciding the format, without the query of the URL
+pub fn name(file: &str) -> &str {
+    if is_remote(file) {
+        file.split(['?', '#']).next().unwrap_or(file)
+    } else {
+        file
+    }
+}
+
+// Content of the file, the remote content is read from the cache
+pub fn read(file: &str) -> Option<String> {
+    if is_remote(file) {
+        REMOTE
+            .read()
+            .expect("patch content lock poisoned")
+            .as_ref()
+            .and_then(|remote| remote.content.clone())
+    } else {
+        fs::read_to_string(file).ok()
+    }
+}
+
+// The cached rendered content, rendered on the first use after a change
+pub fn rendered(render: impl FnOnce() -> Rendered) -> Rendered {
+    if let Some(rendered) = RENDERED
+        .read()
+        .expect("patch content lock poisoned")
+        .as_ref()
+    {
+        return rendered.clone();
+    }
+    let generation = GENERATION.load(Ordering::Acquire);
+    let rendered = render();
+    let mut cached = RENDERED.write().expect("patch content lock poisoned");
+    if GENERATION.load(Ordering::Acquire) == generation {
+        *cached = Some(rendered.clone());
+    }
+
+    rendered
+}
+
+fn invalidate() {
+    let mut cached = RENDERED.write().expect("patch content lock poisoned");
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    *cached = None;
+}
+
+// Re-render the local file when it's modified, run in the background
+pub async fn watch(file: &str) {
+    if file.is_empty() || is_remote(file) {
+        return;
+    }
+    let mut modified = modified_time(file);
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        let current = modified_time(file);
+        if current != modified {
+            modified = current;
+            invalidate();
+            info!("reloaded patch content file `{}`", file);
+        }
+    }
+}
+
+fn modified_time(file: &str) -> Option<SystemTime> {
+    fs::metadata(file).and_then(|m| m.modified()).ok()
+}
+
+// Fetch the remote content if it's missing or expired, call before `read`
+pub async fn refresh(file: &str) {
+    if !is_remote(file) || !expired() {
+        return;
+    }
+    let _refreshing = REFRESHING.lock().await;
+    // Refreshed by another request while waiting
+    if !expired() {
+        return;
+    }
+
+    let fetched = match request::get(file, HeaderMap::new()).await {
+        Ok(resp) if resp.status().is_success() => {
+            request::text(resp).await.map_err(|e| e.to_string())
+        }
+        Ok(resp) => Err(format!("status {}", resp.status())),
+        Err(request::RequestError::Timeout) => Err("timeout".to_owned()),
+        Err(request::RequestError::Reqwest(e)) => Err(e.to_string()),
+    };
+    let mut remote = REMOTE.write().expect("patch content lock poisoned");
+    let content = match fetched {
+        Ok(content) => {
+            info!("fetched patch content from `{}`", file);
+            invalidate();
+            Some(content)
+        }
+        Err(e) => {
+            error!("failed to fetch patch content from `{}`: {}", file, e);
+            // Retried after the TTL too, not on every request
+            remote.take().and_then(|remote| remote.content)
+        }
+    };
+    *remote = Some(Remote {
+        content,
+        fetched_at: Instant::now(),
+    });
+}
+
+fn expired() -> bool {
+    let ttl = Duration::from_secs(vars::patch_content_ttl_secs());
+
+    REMOTE
+        .read()
+        .expect("patch content lock poisoned")
+        .as_ref()
+        .map_or(true, |remote| remote.fetched_at.elapsed() >= ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name() {
+        assert_eq!(name("patch.md"), "patch.md");
+        assert_eq!(
+            name("https://example.com/patch.html?v=2"),
+            "https://example.com/patch.html"
+        );
+        assert!(is_remote("http://example.com/patch.md"));
+        assert!(!is_remote("patch.md"));
+    }
+
+    #[test]
+    fn test_rendered() {
+        let render = |html: &str| Rendered {
+            html: html.to_owned(),
+            text: String::new(),
+        };
+        invalidate();
+        // Invalidated while rendering, the stale content isn't cached
+        let stale = rendered(|| {
+            invalidate();
+            render("stale")
+        });
+        assert_eq!(stale.html, "stale");
+        assert_eq!(rendered(|| render("fresh")).html, "fresh");
+        assert_eq!(rendered(|| render("again")).html, "fresh");
+    }
+}
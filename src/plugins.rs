@@ -0,0 +1,316 @@
+// Host of the WASM plugins from `MIRAGEND_PLUGINS`, consulted in the listed order.
+//
+// A plugin exports its `memory` and `miragend_alloc(len: i32) -> i32`, plus any of the hooks
+// below. A hook is called with the address and the length of a JSON document written into the
+// allocated memory, and returns the address and the length of its JSON answer packed as
+// `(ptr << 32) | len`, or `0` to leave things unchanged:
+//
+// - `miragend_decide`: `{"method", "path", "headers", "client_ip"}` of the bot requests,
+//   answers `{"strategy": "..."}` to choose the strategy
+// - `miragend_transform`: `{"path", "status", "content_type", "headers", "body"}` of the
+//   transformed responses, answers `{"body": "...", "headers": {"name": "value"}}`, both
+//   optional, a `null` header value removes the header
+//
+// Every call runs on a blocking thread in a fresh instance limited by `MIRAGEND_PLUGIN_FUEL`
+// and `MIRAGEND_PLUGIN_MEMORY_BYTES`.
+use crate::{fetching, strategies, vars};
+use anyhow::Context;
+use axum::body::Body;
+use http::{HeaderMap, HeaderName, HeaderValue, Request};
+use log::error;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    net::IpAddr,
+    path::Path,
+    sync::{LazyLock, OnceLock},
+};
+use wasmtime::{
+    Config, Engine, InstancePre, Linker, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+const ALLOC_EXPORT: &str = "miragend_alloc";
+const DECIDE_EXPORT: &str = "miragend_decide";
+const TRANSFORM_EXPORT: &str = "miragend_transform";
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut config = Config::new();
+    config.consume_fuel(true);
+
+    Engine::new(&config).expect("failed to create the WASM engine")
+});
+static PLUGINS: OnceLock<Vec<Plugin>> = OnceLock::new();
+
+struct Plugin {
+    // File stem of the module
+    name: String,
+    instance_pre: InstancePre<StoreLimits>,
+    decide: bool,
+    transform: bool,
+}
+
+#[derive(Serialize)]
+struct RequestInfo<'a> {
+    method: &'a str,
+    path: &'a str,
+    headers: BTreeMap<&'a str, &'a str>,
+    client_ip: IpAddr,
+}
+
+#[derive(Deserialize)]
+struct Decision {
+    strategy: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ResponseInfo<'a> {
+    path: &'a str,
+    status: u16,
+    content_type: String,
+    headers: BTreeMap<&'a str, &'a str>,
+    body: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Mutation {
+    body: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, Option<String>>,
+}
+
+// Compile the plugins, call once from `validate_config`
+pub fn load() -> anyhow::Result<()> {
+    let plugins = vars::plugins()
+        .iter()
+        .map(|path| {
+            let module = Module::from_file(&ENGINE, path)
+                .with_context(|| format!("failed to load the plugin `{}`", path))?;
+            let name = Path::new(path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.to_owned());
+
+            Plugin::new(name, &module)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let _ = PLUGINS.set(plugins);
+
+    Ok(())
+}
+
+fn plugins() -> &'static [Plugin] {
+    PLUGINS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+// The strategy chosen by the first answering plugin, with the name of the plugin,
+// the request is read before the future is returned
+pub fn decide(
+    request: &Request<Body>,
+    client_ip: IpAddr,
+) -> impl Future<Output = Option<(&'static str, &'static str)>> + Send + 'static {
+    let input = serde_json::to_vec(&RequestInfo {
+        method: request.method().as_str(),
+        path: request.uri().path(),
+        headers: header_map(request.headers()),
+        client_ip,
+    });
+
+    async move {
+        let input = match input {
+            Ok(input) => input,
+            Err(e) => {
+                error!("failed to serialize the request for the plugins: {}", e);
+                return None;
+            }
+        };
+        for plugin in plugins().iter().filter(|plugin| plugin.decide) {
+            let decision: Decision = match plugin.call(DECIDE_EXPORT, input.clone()).await {
+                Ok(Some(decision)) => decision,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("plugin `{}` failed to decide: {:#}", plugin.name, e);
+                    continue;
+                }
+            };
+            let Some(strategy) = decision.strategy else {
+                continue;
+            };
+            match strategies::name(&strategy) {
+                Some(strategy) => return Some((strategy, plugin.name.as_str())),
+                None => error!(
+                    "plugin `{}` chose an invalid strategy: {}",
+                    plugin.name, strategy
+                ),
+            }
+        }
+
+        None
+    }
+}
+
+// Let the plugins mutate the transformed response in turn, a failed plugin is skipped
+pub async fn transform(path: &str, resp: &mut fetching::Response, mut body: String) -> String {
+    for plugin in plugins().iter().filter(|plugin| plugin.transform) {
+        let input = serde_json::to_vec(&ResponseInfo {
+            path,
+            status: resp.status.as_u16(),
+            content_type: resp.content_type.to_string().to_lowercase(),
+            headers: header_map(&resp.headers),
+            body: &body,
+        });
+        let mutation = match input {
+            Ok(input) => plugin.call(TRANSFORM_EXPORT, input).await,
+            Err(e) => Err(e.into()),
+        };
+        let mutation: Mutation = match mutation {
+            Ok(Some(mutation)) => mutation,
+            Ok(None) => continue,
+            Err(e) => {
+                error!("plugin `{}` failed to transform: {:#}", plugin.name, e);
+                continue;
+            }
+        };
+        for (name, value) in mutation.headers {
+            let Ok(name) = HeaderName::from_bytes(name.as_bytes()) else {
+                error!("plugin `{}` set an invalid header: {}", plugin.name, name);
+                continue;
+            };
+            match value.map(|value| HeaderValue::from_str(&value)) {
+                Some(Ok(value)) => {
+                    resp.headers.insert(name, value);
+                }
+                Some(Err(_)) => {
+                    error!(
+                        "plugin `{}` set an invalid value of `{}`",
+                        plugin.name, name
+                    )
+                }
+                None => {
+                    resp.headers.remove(name);
+                }
+            }
+        }
+        if let Some(mutated) = mutation.body {
+            body = mutated;
+        }
+    }
+
+    body
+}
+
+fn header_map(headers: &HeaderMap) -> BTreeMap<&str, &str> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect()
+}
+
+impl Plugin {
+    fn new(name: String, module: &Module) -> anyhow::Result<Self> {
+        let has_export = |export: &str| module.exports().any(|e| e.name() == export);
+        for required in ["memory", ALLOC_EXPORT] {
+            if !has_export(required) {
+                anyhow::bail!("plugin `{}` does not export `{}`", name, required);
+            }
+        }
+        let decide = has_export(DECIDE_EXPORT);
+        let transform = has_export(TRANSFORM_EXPORT);
+        // Plugins can't import anything from the host
+        let instance_pre = Linker::<StoreLimits>::new(&ENGINE)
+            .instantiate_pre(module)
+            .with_context(|| format!("failed to link the plugin `{}`", name))?;
+
+        Ok(Self {
+            name,
+            instance_pre,
+            decide,
+            transform,
+        })
+    }
+
+    // The instance runs on a blocking thread, it may take up to the fuel limit
+    async fn call<O: DeserializeOwned>(
+        &'static self,
+        export: &'static str,
+        input: Vec<u8>,
+    ) -> anyhow::Result<Option<O>> {
+        let output =
+            tokio::task::spawn_blocking(move || self.call_blocking(export, &input)).await??;
+
+        output
+            .map(|output| serde_json::from_slice(&output).context("invalid answer"))
+            .transpose()
+    }
+
+    fn call_blocking(&self, export: &str, input: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(vars::plugin_memory_bytes())
+            .build();
+        let mut store = Store::new(&ENGINE, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(vars::plugin_fuel())?;
+        let instance = self.instance_pre.instantiate(&mut store)?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("`memory` is not a memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut store, ALLOC_EXPORT)?;
+        let hook = instance.get_typed_func::<(i32, i32), i64>(&mut store, export)?;
+
+        let len = i32::try_from(input.len()).context("input is too large")?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input)?;
+        let packed = hook.call(&mut store, (ptr, len))? as u64;
+        if packed == 0 {
+            return Ok(None);
+        }
+        let mut output = vec![0; (packed & 0xffff_ffff) as usize];
+        memory.read(&store, (packed >> 32) as usize, &mut output)?;
+
+        Ok(Some(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Always chooses the maze, the answer is the data at address 0
+    const MAZE_PLUGIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "{\"strategy\":\"maze\"}")
+          (func (export "miragend_alloc") (param i32) (result i32) i32.const 1024)
+          (func (export "miragend_decide") (param i32 i32) (result i64) i64.const 19))
+    "#;
+
+    #[test]
+    fn test_call() {
+        let module = Module::new(&ENGINE, MAZE_PLUGIN).unwrap();
+        let plugin: &'static Plugin =
+            Box::leak(Box::new(Plugin::new("maze".to_owned(), &module).unwrap()));
+        assert!(plugin.decide);
+        assert!(!plugin.transform);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let decision: Decision = runtime
+            .block_on(plugin.call(DECIDE_EXPORT, br#"{"path":"/"}"#.to_vec()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(decision.strategy.as_deref(), Some("maze"));
+
+        let module = Module::new(&ENGINE, r#"(module (memory (export "memory") 1))"#).unwrap();
+        assert!(Plugin::new("empty".to_owned(), &module).is_err());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        // 2048 pages of 64 KiB are over the default limit
+        let plugin = MAZE_PLUGIN.replace(r#"(export "memory") 1)"#, r#"(export "memory") 2048)"#);
+        let module = Module::new(&ENGINE, plugin).unwrap();
+        let plugin = Plugin::new("greedy".to_owned(), &module).unwrap();
+        assert!(plugin.call_blocking(DECIDE_EXPORT, b"{}").is_err());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{config, mappings, rules::glob_match, vars};
+use std::{collections::BTreeMap, sync::LazyLock};
+
+// Named obfuscation profiles from the `profiles` section
+static PROFILES: LazyLock<Vec<Profile>> = LazyLock::new(|| {
+    let specs: BTreeMap<String, ProfileSpec> = config::section("profiles")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `profiles` section")
+        .unwrap_or_default();
+
+    specs
+        .into_iter()
+        .map(|(name, spec)| {
+            if let Some(mapping) = &spec.mapping {
+                if mappings::get(mapping).is_none() {
+                    panic!("unknown mapping `{}` of the profile `{}`", mapping, name);
+                }
+            }
+            if let Some(ratio) = spec.ratio {
+                if !(0.0..=1.0).contains(&ratio) {
+                    panic!("invalid ratio `{}` of the profile `{}`", ratio, name);
+                }
+            }
+
+            Profile {
+                name,
+                mapping: spec.mapping,
+                ratio: spec.ratio.unwrap_or(vars::obfuscation_ratio()),
+                ignore_nodes: spec
+                    .ignore_nodes
+                    .map(leak_all)
+                    .unwrap_or_else(|| vars::obfuscation_ignore_nodes().clone()),
+                meta_tags: spec
+                    .meta_tags
+                    .map(leak_all)
+                    .unwrap_or_else(|| vars::obfuscation_meta_tags().clone()),
+                stale_secs: spec.stale_secs.unwrap_or(vars::cache_stale_secs()),
+                paths: spec.paths,
+            }
+        })
+        .collect()
+});
+static DEFAULT_PROFILE: LazyLock<Profile> = LazyLock::new(|| Profile {
+    name: "default".to_owned(),
+    mapping: None,
+    ratio: vars::obfuscation_ratio(),
+    ignore_nodes: vars::obfuscation_ignore_nodes().clone(),
+    meta_tags: vars::obfuscation_meta_tags().clone(),
+    stale_secs: vars::cache_stale_secs(),
+    paths: vec![],
+});
+
+// The omitted fields fall back to the global variables
+#[derive(Debug, serde::Deserialize)]
+struct ProfileSpec {
+    // Path patterns using this profile
+    paths: Vec<String>,
+    // Name of a mapping in the `mappings` section
+    mapping: Option<String>,
+    ratio: Option<f64>,
+    ignore_nodes: Option<Vec<String>>,
+    meta_tags: Option<Vec<String>>,
+    stale_secs: Option<u64>,
+}
+
+pub struct Profile {
+    pub name: String,
+    // Overrides the mapping selection
+    pub mapping: Option<String>,
+    pub ratio: f64,
+    pub ignore_nodes: Vec<&'static str>,
+    pub meta_tags: Vec<&'static str>,
+    // Seconds an expired cached page is still served while refreshing
+    pub stale_secs: u64,
+    paths: Vec<String>,
+}
+
+fn leak_all(values: Vec<String>) -> Vec<&'static str> {
+    values
+        .into_iter()
+        .map(|s| Box::leak(s.into_boxed_str()) as &'static str)
+        .collect()
+}
+
+pub fn force_init() {
+    LazyLock::force(&PROFILES);
+}
+
+// The first profile matching the path, in the order of names
+pub fn select(path: &str) -> &'static Profile {
+    PROFILES
+        .iter()
+        .find(|profile| {
+            profile
+                .paths
+                .iter()
+                .any(|pattern| glob_match(pattern, path))
+        })
+        .unwrap_or(&DEFAULT_PROFILE)
+}
@@ -1,23 +1,97 @@
 use crate::vars;
-use http::HeaderMap;
-use reqwest::Response;
-use std::time::Duration;
+use axum::body::Bytes;
+use http::{header, HeaderMap, Method};
+use log::warn;
+use reqwest::{redirect, Certificate, Client, Identity, Response};
+use std::{sync::LazyLock, time::Duration};
+
+// Shared by all requests for the connection pooling
+static CLIENT: LazyLock<Client> = LazyLock::new(|| {
+    let redirect = match vars::follow_redirects() {
+        0 => redirect::Policy::none(),
+        max => redirect::Policy::limited(max),
+    };
+    // No total timeout, it would cut the streamed bodies
+    let mut builder = Client::builder().redirect(redirect);
+    // The port of the upstream URL is always used, reqwest ignores the pinned one
+    for (host, addr) in vars::upstream_resolve() {
+        builder = builder.resolve(host, *addr);
+    }
+    if !vars::upstream_ca_file().is_empty() {
+        let pem = std::fs::read(vars::upstream_ca_file()).expect("failed to read upstream CA file");
+        let cert = Certificate::from_pem(&pem).expect("invalid upstream CA certificate");
+        builder = builder.add_root_certificate(cert);
+    }
+    if !vars::upstream_client_cert().is_empty() {
+        let cert = std::fs::read(vars::upstream_client_cert())
+            .expect("failed to read upstream client cert");
+        let key =
+            std::fs::read(vars::upstream_client_key()).expect("failed to read upstream client key");
+        let identity =
+            Identity::from_pkcs8_pem(&cert, &key).expect("invalid upstream client cert or key");
+        builder = builder.identity(identity);
+    }
+    if vars::upstream_insecure() {
+        warn!("the upstream certificate will not be verified");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder.build().expect("failed to build HTTP client")
+});
 
 pub enum RequestError {
     Timeout,
     Reqwest(reqwest::Error),
 }
 
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Timeout => write!(f, "timeout"),
+            RequestError::Reqwest(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+// Call on startup to avoid runtime initialization errors
+pub fn force_init() {
+    LazyLock::force(&CLIENT);
+}
+// The timeout only covers the response head, read the body with `text` to bound it too
+// The timeout only covers the response head, the buffered bodies are bounded by `bytes` and `text`
 pub async fn get(url: &str, headers: HeaderMap) -> Result<Response, RequestError> {
-    let client = reqwest::Client::builder()
-        .timeout(Duration::from_secs(vars::connect_timeout_secs()))
-        .default_headers(headers)
-        .build()
-        .map_err(RequestError::Reqwest)?;
-
-    match client.get(url).send().await {
-        Ok(resp) => Ok(resp),
-        Err(e) => Err(map_error(e)),
+    send(Method::GET, url, headers, Bytes::new()).await
+}
+
+pub async fn send(
+    method: Method,
+    url: &str,
+    mut headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, RequestError> {
+    // Framed again by the client for the buffered body
+    headers.remove(header::CONTENT_LENGTH);
+    headers.remove(header::TRANSFER_ENCODING);
+    let mut builder = CLIENT.request(method, url).headers(headers);
+    if !body.is_empty() {
+        builder = builder.body(body);
+    }
+
+    bounded(builder.send()).await
+}
+
+// Read the whole body within the timeout
+pub async fn text(resp: Response) -> Result<String, RequestError> {
+    bounded(resp.text()).await
+}
+
+async fn bounded<T>(
+    future: impl std::future::Future<Output = reqwest::Result<T>>,
+) -> Result<T, RequestError> {
+    let timeout = Duration::from_secs(vars::connect_timeout_secs());
+    match tokio::time::timeout(timeout, future).await {
+        Ok(result) => result.map_err(map_error),
+        Err(_) => Err(RequestError::Timeout),
     }
 }
 
@@ -0,0 +1,113 @@
+use crate::{config, rules::glob_match, vars};
+use http::{header::HeaderName, HeaderMap, HeaderValue};
+use std::sync::LazyLock;
+
+static X_ROBOTS_TAG: HeaderName = HeaderName::from_static("x-robots-tag");
+// Policies of the `X-Robots-Tag` header from the `x_robots_tag` section, the first match applies
+static POLICIES: LazyLock<Vec<Policy>> = LazyLock::new(|| {
+    let mut policies: Vec<Policy> = config::section("x_robots_tag")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `x_robots_tag` section")
+        .unwrap_or_default();
+    // The variable is the fallback of the bot-facing responses, `strip` removes the header
+    let value = vars::x_robots_tag();
+    if !value.is_empty() {
+        policies.push(Policy {
+            path: default_path(),
+            value: if value == "strip" { "" } else { value }.to_owned(),
+            all_clients: false,
+        });
+    }
+    for policy in &policies {
+        if !policy.value.is_empty() && HeaderValue::from_str(&policy.value).is_err() {
+            panic!("invalid `X-Robots-Tag` value `{}`", policy.value);
+        }
+    }
+
+    policies
+});
+
+#[derive(Debug, serde::Deserialize)]
+struct Policy {
+    // Path pattern, `*` matches any characters
+    #[serde(default = "default_path")]
+    path: String,
+    // An empty value strips the header
+    value: String,
+    // Apply to the humans too, not only the bots
+    #[serde(default)]
+    all_clients: bool,
+}
+
+fn default_path() -> String {
+    "*".to_owned()
+}
+
+pub fn force_init() {
+    LazyLock::force(&POLICIES);
+}
+
+// Set or strip the header of the response by the first matching policy
+pub fn apply(headers: &mut HeaderMap, path: &str, bot: bool) {
+    apply_policies(&POLICIES, headers, path, bot);
+}
+
+fn apply_policies(policies: &[Policy], headers: &mut HeaderMap, path: &str, bot: bool) {
+    let Some(policy) = policies
+        .iter()
+        .find(|policy| (bot || policy.all_clients) && glob_match(&policy.path, path))
+    else {
+        return;
+    };
+    match HeaderValue::from_str(&policy.value) {
+        Ok(value) if !policy.value.is_empty() => {
+            headers.insert(&X_ROBOTS_TAG, value);
+        }
+        _ => {
+            headers.remove(&X_ROBOTS_TAG);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_policies() {
+        let policies = vec![
+            Policy {
+                path: "/private/*".to_owned(),
+                value: "noindex".to_owned(),
+                all_clients: true,
+            },
+            Policy {
+                path: "/public/*".to_owned(),
+                value: String::new(),
+                all_clients: false,
+            },
+            Policy {
+                path: "*".to_owned(),
+                value: "noai, noimageai".to_owned(),
+                all_clients: false,
+            },
+        ];
+        let mut headers = HeaderMap::new();
+        headers.insert(&X_ROBOTS_TAG, HeaderValue::from_static("all"));
+
+        let mut human = headers.clone();
+        apply_policies(&policies, &mut human, "/private/a", false);
+        assert_eq!(human[&X_ROBOTS_TAG], "noindex");
+        let mut human = headers.clone();
+        apply_policies(&policies, &mut human, "/post", false);
+        assert_eq!(human[&X_ROBOTS_TAG], "all");
+
+        let mut bot = headers.clone();
+        apply_policies(&policies, &mut bot, "/public/a.png", true);
+        assert!(bot.get(&X_ROBOTS_TAG).is_none());
+        let mut bot = headers.clone();
+        apply_policies(&policies, &mut bot, "/post", true);
+        assert_eq!(bot[&X_ROBOTS_TAG], "noai, noimageai");
+    }
+}
@@ -0,0 +1,227 @@
+use crate::{config, metrics, strategies};
+use anyhow::Context;
+use regex::Regex;
+use std::{net::IpAddr, sync::LazyLock};
+
+static RULES: LazyLock<Vec<CompiledRule>> = LazyLock::new(|| {
+    let rules: Vec<Rule> = config::section("rules")
+        .map(|section| section.clone().try_into())
+        .transpose()
+        .expect("invalid `rules` section")
+        .unwrap_or_default();
+
+    compile(&rules).expect("invalid `rules` section")
+});
+
+// Policy rule, all conditions must match
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Rule {
+    pub name: String,
+    // Regex matching the User-Agent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    // Client IP ranges, e.g. `10.0.0.0/8`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cidr: Vec<String>,
+    // Path pattern, `*` matches any characters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub strategy: String,
+}
+
+pub struct CompiledRule {
+    pub rule: Rule,
+    user_agent: Option<Regex>,
+    cidr: Vec<(IpAddr, u8)>,
+}
+
+// Request attributes used by the rules
+#[derive(Debug, serde::Deserialize)]
+pub struct Subject {
+    pub path: String,
+    #[serde(default)]
+    pub user_agent: String,
+    pub ip: IpAddr,
+}
+
+pub fn force_init() {
+    LazyLock::force(&RULES);
+}
+
+pub fn rules() -> &'static [CompiledRule] {
+    &RULES
+}
+
+pub fn compile(rules: &[Rule]) -> anyhow::Result<Vec<CompiledRule>> {
+    rules
+        .iter()
+        .map(|rule| {
+            if strategies::name(&rule.strategy).is_none() {
+                anyhow::bail!(
+                    "rule `{}` has an invalid strategy `{}`",
+                    rule.name,
+                    rule.strategy
+                );
+            }
+            let user_agent = rule
+                .user_agent
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .context(format!(
+                    "rule `{}` has an invalid `user_agent` regex",
+                    rule.name
+                ))?;
+            let cidr = rule
+                .cidr
+                .iter()
+                .map(|cidr| parse_cidr(cidr))
+                .collect::<anyhow::Result<_>>()
+                .context(format!("rule `{}` has an invalid `cidr`", rule.name))?;
+
+            Ok(CompiledRule {
+                rule: rule.clone(),
+                user_agent,
+                cidr,
+            })
+        })
+        .collect()
+}
+
+// Find the first rule matching the subject, the hit counter is increased
+pub fn find(subject: &Subject) -> Option<&'static Rule> {
+    let rule = find_in(rules(), subject)?;
+    metrics::inc("miragend_rule_hits_total", &[("rule", &rule.name)]);
+
+    Some(rule)
+}
+
+// Find the first rule matching the subject without side effects
+pub fn find_in<'a>(rules: &'a [CompiledRule], subject: &Subject) -> Option<&'a Rule> {
+    rules
+        .iter()
+        .find(|compiled| compiled.is_match(subject))
+        .map(|compiled| &compiled.rule)
+}
+
+impl CompiledRule {
+    fn is_match(&self, subject: &Subject) -> bool {
+        if let Some(ref regex) = self.user_agent {
+            if !regex.is_match(&subject.user_agent) {
+                return false;
+            }
+        }
+        if !self.cidr.is_empty()
+            && !self
+                .cidr
+                .iter()
+                .any(|(network, prefix)| cidr_contains(*network, *prefix, subject.ip))
+        {
+            return false;
+        }
+        if let Some(ref pattern) = self.rule.path {
+            if !glob_match(pattern, &subject.path) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+pub fn parse_cidr(text: &str) -> anyhow::Result<(IpAddr, u8)> {
+    let (ip, prefix) = match text.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (text, None),
+    };
+    let ip: IpAddr = ip.trim().parse().context(format!("invalid IP `{}`", ip))?;
+    let max_prefix = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .trim()
+            .parse::<u8>()
+            .ok()
+            .filter(|prefix| *prefix <= max_prefix)
+            .context(format!("invalid prefix length `{}`", prefix))?,
+        None => max_prefix,
+    };
+
+    Ok((ip, prefix))
+}
+
+pub fn cidr_contains(network: IpAddr, prefix: u8, ip: IpAddr) -> bool {
+    let ip = match (network, ip) {
+        // IPv4-mapped IPv6 addresses
+        (IpAddr::V4(_), IpAddr::V6(v6)) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        _ => ip,
+    };
+
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+// Match the text with a pattern, `*` matches any characters
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("/docs", "/docs"));
+        assert!(!glob_match("/docs", "/docs/a"));
+        assert!(glob_match("/docs/*", "/docs/a/b"));
+        assert!(glob_match("*.json", "/api/data.json"));
+        assert!(glob_match("/a/*/c/*", "/a/b/c/d"));
+        assert!(!glob_match("/a/*/c", "/a/b/d"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_cidr() {
+        let (network, prefix) = parse_cidr("10.1.0.0/16").unwrap();
+        assert!(cidr_contains(network, prefix, "10.1.2.3".parse().unwrap()));
+        assert!(!cidr_contains(network, prefix, "10.2.0.1".parse().unwrap()));
+        assert!(cidr_contains(
+            network,
+            prefix,
+            "::ffff:10.1.0.1".parse().unwrap()
+        ));
+        let (network, prefix) = parse_cidr("2001:db8::/32").unwrap();
+        assert!(cidr_contains(
+            network,
+            prefix,
+            "2001:db8::1".parse().unwrap()
+        ));
+        assert!(parse_cidr("10.0.0.0/33").is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Context;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+const CONFIG_FILE: &str = "miragend.toml";
+const MAPPING_FILE: &str = "obfuscation_mapping.csv";
+const STRATEGIES: [&str; 7] = [
+    "obfuscation",
+    "patch",
+    "challenge",
+    "tarpit",
+    "maze",
+    "block",
+    "passthrough",
+];
+const CONFIG_TEMPLATE: &str = r#"# Miragend configuration, top-level keys map to the `MIRAGEND_*` variables.
+# Environment variables and command-line flags take precedence over this file.
+
+# Base URLs of the upstream, comma-separated for multiple upstreams
+upstream_base_url = "{upstream}"
+# Listener addresses, `address[=strategy],...`
+bind = "0.0.0.0:8080"
+# Strategy of the bots: obfuscation, patch, challenge, tarpit, maze, block or passthrough
+strategy = "{strategy}"
+# Only log the decisions, every client receives the original content
+# observe = true
+
+# Obfuscation mapping files, comma-separated
+obfuscation_mapping_file = "{mapping_file}"
+# Ratio of the obfuscated characters
+# obfuscation_ratio = 1.0
+# Nodes left unchanged, ids, `.class` or `<tag>` selectors
+# obfuscation_ignore_nodes = "<pre>,<code>"
+
+# IDs or selectors receiving the patch content, tried in order
+# patch_target = "content,<article>"
+# patch_content_file = "patch-content.md"
+
+# Cache the transformed pages
+# cache_ttl_secs = 60
+
+# Scripts and styles injected into the transformed pages
+# [[injections]]
+# kind = "stylesheet"  # script, inline-script, stylesheet or inline-style
+# position = "head-end"  # head-start, head-end or body-end
+# content = "/notice.css"
+
+# Meta tags added to every proxied page, `name = "content"`
+# [meta_tags]
+# robots = "noai, noimageai"
+
+# `X-Robots-Tag` of the responses, the first matching path applies, an empty value strips it
+# [[x_robots_tag]]
+# path = "/assets/*"
+# value = "noai, noimageai"
+# all_clients = false
+
+# `Cache-Control` of the responses, the first match applies, an empty value strips it
+# [[cache_control]]
+# path = "*"
+# strategies = ["obfuscation", "patch"]
+# value = "no-store"
+
+# CORS of the paths, the first matching path applies and the preflight requests are answered
+# [[cors]]
+# path = "/api/*"
+# allow_origins = ["https://app.example.com"]
+# allow_methods = ["GET", "POST"]
+# allow_headers = ["Content-Type"]
+# allow_credentials = false
+# max_age = 600
+
+# Rules are matched in order, the first match decides the strategy
+# [[rules]]
+# name = "assets"
+# path = "/static/*"
+# strategy = "passthrough"
+#
+# [[rules]]
+# name = "gptbot"
+# user_agent = "GPTBot"
+# strategy = "maze"
+"#;
+
+// Write a commented config file and a starter mapping file into the current directory
+pub fn run(force: bool) -> anyhow::Result<()> {
+    for file in [CONFIG_FILE, MAPPING_FILE] {
+        if !force && Path::new(file).exists() {
+            anyhow::bail!("`{}` already exists, use `--force` to overwrite", file);
+        }
+    }
+
+    // The values given by the flags or variables skip the prompts
+    let upstream = match std::env::var("MIRAGEND_UPSTREAM_BASE_URL") {
+        Ok(upstream) if !upstream.is_empty() => upstream,
+        _ => prompt("Upstream URL", "http://localhost:3000", |v| {
+            v.starts_with("http://") || v.starts_with("https://")
+        })?,
+    };
+    let strategy = match std::env::var("MIRAGEND_STRATEGY") {
+        Ok(strategy) if !strategy.is_empty() => strategy,
+        _ => prompt(
+            &format!("Strategy ({})", STRATEGIES.join(", ")),
+            STRATEGIES[0],
+            |v| STRATEGIES.contains(&v),
+        )?,
+    };
+
+    let config = CONFIG_TEMPLATE
+        .replace("{upstream}", &upstream)
+        .replace("{strategy}", &strategy)
+        .replace("{mapping_file}", MAPPING_FILE);
+    std::fs::write(CONFIG_FILE, config).context(format!("failed to write `{}`", CONFIG_FILE))?;
+    std::fs::write(MAPPING_FILE, include_str!("../obfuscation_mapping.csv"))
+        .context(format!("failed to write `{}`", MAPPING_FILE))?;
+
+    println!("Created `{}` and `{}`", CONFIG_FILE, MAPPING_FILE);
+    println!(
+        "Run `miragend --config {} check` to validate it",
+        CONFIG_FILE
+    );
+
+    Ok(())
+}
+
+// Ask until the answer is valid, an empty answer takes the default
+fn prompt(question: &str, default: &str, valid: impl Fn(&str) -> bool) -> anyhow::Result<String> {
+    let stdin = io::stdin();
+    loop {
+        print!("{} [{}]: ", question, default);
+        io::stdout().flush().context("failed to flush stdout")?;
+        let mut answer = String::new();
+        if stdin
+            .lock()
+            .read_line(&mut answer)
+            .context("failed to read answer")?
+            == 0
+        {
+            // No input, e.g. not a terminal
+            return Ok(default.to_owned());
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return Ok(default.to_owned());
+        }
+        if valid(answer) {
+            return Ok(answer.to_owned());
+        }
+        println!("invalid value `{}`", answer);
+    }
+}
@@ -0,0 +1,151 @@
+// Decision script from `MIRAGEND_DECISION_SCRIPT`, a Rhai script defining `decide(request)`.
+// The request is a map of `method`, `path`, `query`, `headers` and `client_ip`, the function
+// returns the name of a strategy, or nothing to leave the decision to the rules:
+//
+// ```rhai
+// fn decide(request) {
+//     if request.path.starts_with("/api/") && request.headers["accept"] == "*/*" {
+//         "block"
+//     }
+// }
+// ```
+use crate::{strategies, vars};
+use axum::body::Body;
+use http::Request;
+use log::error;
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::{
+    net::IpAddr,
+    path::PathBuf,
+    sync::{LazyLock, OnceLock},
+};
+
+// Rule name of the decisions made by the script
+const RULE_NAME: &str = "script";
+
+static ENGINE: LazyLock<Engine> = LazyLock::new(|| {
+    let mut engine = Engine::new();
+    engine.set_max_operations(vars::decision_script_max_operations());
+
+    engine
+});
+static SCRIPT: OnceLock<AST> = OnceLock::new();
+
+// Compile the script, call once from `validate_config`
+pub fn load() -> anyhow::Result<()> {
+    let path = vars::decision_script();
+    if path.is_empty() {
+        return Ok(());
+    }
+    let ast = ENGINE
+        .compile_file(PathBuf::from(path))
+        .map_err(|e| anyhow::anyhow!("failed to compile `{}`: {}", path, e))?;
+    if !ast
+        .iter_functions()
+        .any(|f| f.name == "decide" && f.params.len() == 1)
+    {
+        anyhow::bail!("`{}` does not define `decide(request)`", path);
+    }
+    let _ = SCRIPT.set(ast);
+
+    Ok(())
+}
+
+// The strategy returned by the script, with the rule name
+pub fn decide(request: &Request<Body>, client_ip: IpAddr) -> Option<(&'static str, &'static str)> {
+    run(SCRIPT.get()?, request, client_ip)
+}
+
+fn run(
+    ast: &AST,
+    request: &Request<Body>,
+    client_ip: IpAddr,
+) -> Option<(&'static str, &'static str)> {
+    let headers: Map = request
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| {
+            Some((name.as_str().into(), value.to_str().ok()?.to_owned().into()))
+        })
+        .collect();
+    let mut info = Map::new();
+    info.insert("method".into(), request.method().as_str().into());
+    info.insert("path".into(), request.uri().path().into());
+    info.insert(
+        "query".into(),
+        request.uri().query().unwrap_or_default().into(),
+    );
+    info.insert("headers".into(), Dynamic::from_map(headers));
+    info.insert("client_ip".into(), client_ip.to_string().into());
+
+    let result = match ENGINE.call_fn::<Dynamic>(&mut Scope::new(), ast, "decide", (info,)) {
+        Ok(result) => result,
+        Err(e) => {
+            error!("decision script failed: {}", e);
+            return None;
+        }
+    };
+    if result.is_unit() {
+        return None;
+    }
+    let strategy = match result.into_string() {
+        Ok(strategy) => strategy,
+        Err(type_name) => {
+            error!(
+                "decision script returned a {}, expected a string",
+                type_name
+            );
+            return None;
+        }
+    };
+    match strategies::name(&strategy) {
+        Some(strategy) => Some((strategy, RULE_NAME)),
+        None => {
+            error!("decision script returned an invalid strategy: {}", strategy);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let ast = ENGINE
+            .compile(
+                r#"
+                fn decide(request) {
+                    if request.path.starts_with("/api/") {
+                        "block"
+                    } else if request.headers["user-agent"] == "GPTBot" {
+                        "maze"
+                    } else if request.path == "/typo" {
+                        "mazes"
+                    }
+                }
+                "#,
+            )
+            .unwrap();
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let request = |path: &str, user_agent: &str| {
+            Request::builder()
+                .uri(path)
+                .header(http::header::USER_AGENT, user_agent)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert_eq!(
+            run(&ast, &request("/api/posts", "curl"), ip),
+            Some(("block", RULE_NAME))
+        );
+        assert_eq!(
+            run(&ast, &request("/", "GPTBot"), ip),
+            Some(("maze", RULE_NAME))
+        );
+        assert_eq!(run(&ast, &request("/", "curl"), ip), None);
+        assert_eq!(run(&ast, &request("/typo", "curl"), ip), None);
+    }
+}
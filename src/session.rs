@@ -0,0 +1,37 @@
+use crate::{headers, vars};
+use http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+use std::net::IpAddr;
+
+// Seed of the visitor on the page, `None` if the session-consistent obfuscation is disabled.
+// The configured cookie is preferred, otherwise the IP and the User-Agent are used.
+// The mapping differs across the pages, so it can't be learned from one of them.
+pub fn seed(req_headers: &HeaderMap, client_ip: IpAddr, page: &str) -> Option<u64> {
+    if !vars::session_consistent() {
+        return None;
+    }
+
+    let cookie_name = vars::session_cookie();
+    let visitor = match headers::get_cookie(req_headers, cookie_name) {
+        Some(value) if !cookie_name.is_empty() => format!("cookie|{}", value),
+        _ => {
+            let user_agent = req_headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+
+            format!("{}|{}", client_ip, user_agent)
+        }
+    };
+    // Keyed with the secret so that the seed can't be derived by the client
+    let digest = Sha256::new()
+        .chain_update(vars::challenge_secret())
+        .chain_update(visitor)
+        .chain_update(b"|")
+        .chain_update(page)
+        .finalize();
+
+    Some(u64::from_be_bytes([
+        digest[0], digest[1], digest[2], digest[3], digest[4], digest[5], digest[6], digest[7],
+    ]))
+}
@@ -0,0 +1,103 @@
+use crate::{classification, headers, vars, Source};
+use anyhow::Context;
+use axum::body::Body;
+use http::{header, HeaderName, HeaderValue, Request};
+use std::net::{IpAddr, SocketAddr};
+
+// Options of the simulated request
+pub struct Options<'a> {
+    pub path: &'a str,
+    pub user_agent: Option<&'a str>,
+    // Extra headers in the `Name: value` form
+    pub headers: &'a [String],
+    pub client_ip: IpAddr,
+    // Print the forwarded headers instead of sending the request
+    pub dry_run: bool,
+}
+
+// Perform one proxied request and print the decision and what the client would receive
+pub async fn run(options: Options<'_>) -> anyhow::Result<()> {
+    crate::validate_config()?;
+
+    let mut request = Request::builder()
+        .uri(options.path)
+        .body(Body::empty())
+        .context("invalid path")?;
+    let req_headers = request.headers_mut();
+    if let Some(user_agent) = options.user_agent {
+        req_headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_str(user_agent).context("invalid user agent")?,
+        );
+    }
+    for line in options.headers {
+        let (name, value) = line
+            .split_once(':')
+            .with_context(|| format!("invalid header `{}`, expected `Name: value`", line))?;
+        req_headers.append(
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .with_context(|| format!("invalid header name `{}`", name))?,
+            HeaderValue::from_str(value.trim())
+                .with_context(|| format!("invalid header value `{}`", value))?,
+        );
+    }
+    let addr = SocketAddr::new(options.client_ip, 0);
+
+    print_decision(&request, addr).await;
+    if options.dry_run {
+        print_forwarded(&request, addr, options.path);
+
+        return Ok(());
+    }
+
+    let (parts, body) = crate::dispatch(addr, request, Source::Upstream)
+        .await
+        .into_parts();
+    println!("< {}", parts.status);
+    for (name, value) in parts.headers.iter() {
+        println!("< {}: {}", name, value.to_str().unwrap_or("<binary>"));
+    }
+    println!();
+    let body = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .context("failed to read response body")?;
+    println!("{}", String::from_utf8_lossy(&body));
+
+    Ok(())
+}
+
+// Print the strategy and the rule the request is served by
+async fn print_decision(request: &Request<Body>, addr: SocketAddr) {
+    let client_ip = headers::client_ip(request.headers(), addr);
+    let classification = classification::classify(request.headers(), client_ip);
+    #[cfg(feature = "wasm")]
+    let plugin = if classification == classification::Classification::Bot {
+        crate::plugins::decide(request, client_ip).await
+    } else {
+        None
+    };
+    #[cfg(not(feature = "wasm"))]
+    let plugin = None;
+    let decision = crate::decide(classification, request, client_ip, plugin);
+    println!("Client IP: {}", client_ip);
+    println!("Classification: {}", classification);
+    println!("Strategy: {}", decision.strategy);
+    println!("Rule: {}", decision.rule.unwrap_or("-"));
+    println!();
+}
+
+// Print the headers the upstream would receive, nothing is sent
+fn print_forwarded(request: &Request<Body>, addr: SocketAddr, path: &str) {
+    // The same headers as the proxied request, sent to the primary upstream
+    let mut upstream_headers = headers::build_from_request(request.headers());
+    let host = request
+        .headers()
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok());
+    headers::set_forwarded(&mut upstream_headers, addr, host, headers::proto());
+    headers::set_upstream_host(&mut upstream_headers, vars::upstream_domain());
+    println!("> GET {}{}", vars::upstream_base_url(), path);
+    for (name, value) in upstream_headers.iter() {
+        println!("> {}: {}", name, value.to_str().unwrap_or("<binary>"));
+    }
+}
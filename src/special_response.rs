@@ -1,28 +1,50 @@
 use crate::vars::{self, CONTENT_TYPE_VALUE_TEXT_HTML};
 use axum::{
-    body::Body,
+    body::{Body, Bytes},
     response::{IntoResponse, Response},
 };
 use http::{header, StatusCode};
 use log::error;
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, strum::Display)]
+#[strum(serialize_all = "lowercase")]
 pub enum Style {
     Nginx,
+    Apache,
+    Caddy,
+    Iis,
+    Cloudflare,
     None,
 }
 
+impl Style {
+    // The `Server` header sent along the page
+    fn server(self) -> Option<&'static str> {
+        match self {
+            Style::Nginx => Some("nginx"),
+            Style::Apache => Some("Apache"),
+            Style::Caddy => Some("Caddy"),
+            Style::Iis => Some("Microsoft-IIS/10.0"),
+            Style::Cloudflare => Some("cloudflare"),
+            Style::None => None,
+        }
+    }
+}
+
 pub fn build_resp(status_code: StatusCode) -> Result<Response<Body>, http::Error> {
     let builder = Response::builder().status(status_code);
     let special_page_style = vars::special_page_style();
-    let builder = if special_page_style == Style::Nginx {
-        builder.header(header::CONTENT_TYPE, CONTENT_TYPE_VALUE_TEXT_HTML)
-    } else {
-        // No need to set the Content-Type header for the default style
-        builder
+    let builder = match special_page_style {
+        // No need to set the Content-Type header for the plain text or empty pages
+        Style::None | Style::Caddy => builder,
+        _ => builder.header(header::CONTENT_TYPE, CONTENT_TYPE_VALUE_TEXT_HTML),
+    };
+    let builder = match special_page_style.server() {
+        Some(server) => builder.header(header::SERVER, server),
+        None => builder,
     };
 
-    builder.body(build_body(status_code, vars::special_page_style()))
+    builder.body(build_body(status_code, special_page_style))
 }
 
 pub fn build_resp_with_fallback(status_code: StatusCode) -> Response {
@@ -39,9 +61,23 @@ pub fn build_resp_with_fallback(status_code: StatusCode) -> Response {
     }
 }
 
+// Close the connection without a response, like the 444 of nginx
+pub fn build_drop_resp() -> Response {
+    let body = Body::from_stream(futures_util::stream::once(async {
+        Err::<Bytes, _>(std::io::Error::from(std::io::ErrorKind::ConnectionAborted))
+    }));
+
+    Response::new(body)
+}
+
 fn build_body(status_code: StatusCode, style: Style) -> Body {
     match style {
-        Style::Nginx => build_nginx_page(status_code),
+        Style::Nginx => build_nginx_page(status_code, "nginx"),
+        Style::Apache => build_apache_page(status_code),
+        // Caddy answers the errors with an empty body
+        Style::Caddy => Body::empty(),
+        Style::Iis => build_iis_page(status_code),
+        Style::Cloudflare => build_nginx_page(status_code, "cloudflare"),
         Style::None => build_page(status_code),
     }
 }
@@ -51,11 +87,15 @@ fn build_page(status_code: StatusCode) -> Body {
 }
 
 // Reference: https://github.com/nginx/nginx/blob/master/src/http/ngx_http_special_response.c
-fn build_nginx_page(status_code: StatusCode) -> Body {
+fn build_nginx_page(status_code: StatusCode, footer: &str) -> Body {
     let content = match status_code {
         StatusCode::GATEWAY_TIMEOUT => "504 Gateway Time-out".to_owned(),
         StatusCode::INTERNAL_SERVER_ERROR => "500 Internal Server Error".to_owned(),
         StatusCode::BAD_GATEWAY => "502 Bad Gateway".to_owned(),
+        StatusCode::FORBIDDEN => "403 Forbidden".to_owned(),
+        StatusCode::NOT_FOUND => "404 Not Found".to_owned(),
+        StatusCode::GONE => "410 Gone".to_owned(),
+        StatusCode::TOO_MANY_REQUESTS => "429 Too Many Requests".to_owned(),
         _ => status_code.as_u16().to_string(),
     };
     let html = format!(
@@ -64,7 +104,7 @@ fn build_nginx_page(status_code: StatusCode) -> Body {
 <head><title>{}</title></head>
 <body>
 <center><h1>{}</h1></center>
-<hr><center>nginx</center>
+<hr><center>{}</center>
 </body>
 </html>
 <!-- a padding to disable MSIE and Chrome friendly error page -->
@@ -74,7 +114,95 @@ fn build_nginx_page(status_code: StatusCode) -> Body {
 <!-- a padding to disable MSIE and Chrome friendly error page -->
 <!-- a padding to disable MSIE and Chrome friendly error page -->
 ",
-        content, content
+        content, content, footer
+    );
+
+    Body::from(html)
+}
+
+// Reference: https://github.com/apache/httpd/blob/trunk/modules/http/http_protocol.c
+fn build_apache_page(status_code: StatusCode) -> Body {
+    let message = match status_code {
+        StatusCode::NOT_FOUND => "The requested URL was not found on this server.",
+        StatusCode::FORBIDDEN => "You don't have permission to access this resource.",
+        StatusCode::GONE => "The requested resource is no longer available on this server and there is no forwarding address. Please remove all references to this resource.",
+        StatusCode::BAD_GATEWAY => "The proxy server received an invalid response from an upstream server.",
+        StatusCode::GATEWAY_TIMEOUT => "The gateway did not receive a timely response from the upstream server or application.",
+        StatusCode::TOO_MANY_REQUESTS => "The user has sent too many requests in a given amount of time.",
+        _ => "The server encountered an internal error or misconfiguration and was unable to complete your request.",
+    };
+    let reason = status_code.canonical_reason().unwrap_or_default();
+    let html = format!(
+        "\
+<!DOCTYPE HTML PUBLIC \"-//IETF//DTD HTML 2.0//EN\">
+<html><head>
+<title>{} {}</title>
+</head><body>
+<h1>{}</h1>
+<p>{}</p>
+</body></html>
+",
+        status_code.as_u16(),
+        reason,
+        reason,
+        message
+    );
+
+    Body::from(html)
+}
+
+fn build_iis_page(status_code: StatusCode) -> Body {
+    let (title, message) = match status_code {
+        StatusCode::NOT_FOUND => (
+            "404 - File or directory not found.",
+            "The resource you are looking for might have been removed, had its name changed, or is temporarily unavailable.",
+        ),
+        StatusCode::FORBIDDEN => (
+            "403 - Forbidden: Access is denied.",
+            "You do not have permission to view this directory or page using the credentials that you supplied.",
+        ),
+        StatusCode::BAD_GATEWAY => (
+            "502 - Web server received an invalid response while acting as a gateway or proxy server.",
+            "There is a problem with the page you are looking for, and it cannot be displayed. When the Web server (while acting as a gateway or proxy) contacted the upstream content server, it received an invalid response from the content server.",
+        ),
+        _ => (
+            "500 - Internal server error.",
+            "There is a problem with the resource you are looking for, and it cannot be displayed.",
+        ),
+    };
+    let html = format!(
+        "\
+<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Strict//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-strict.dtd\">
+<html xmlns=\"http://www.w3.org/1999/xhtml\">
+<head>
+<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-1\"/>
+<title>{}</title>
+<style type=\"text/css\">
+<!--
+body{{margin:0;font-size:.7em;font-family:Verdana, Arial, Helvetica, sans-serif;background:#EEEEEE;}}
+fieldset{{padding:0 15px 10px 15px;}}
+h1{{font-size:2.4em;margin:0;color:#FFF;}}
+h2{{font-size:1.7em;margin:0;color:#CC0000;}}
+h3{{font-size:1.2em;margin:10px 0 0 0;color:#000000;}}
+#header{{width:96%;margin:0 0 0 0;padding:6px 2% 6px 2%;font-family:\"trebuchet MS\", Verdana, sans-serif;color:#FFF;
+background-color:#555555;}}
+#content{{margin:0 0 0 2%;position:relative;}}
+.content-container{{background:#FFF;width:96%;margin-top:8px;padding:10px;position:relative;}}
+-->
+</style>
+</head>
+<body>
+<div id=\"header\"><h1>Server Error</h1></div>
+<div id=\"content\">
+ <div class=\"content-container\"><fieldset>
+  <h2>{}</h2>
+  <h3>{}</h3>
+ </fieldset></div>
+</div>
+</body>
+</html>
+",
+        title, title, message
     );
 
     Body::from(html)
@@ -0,0 +1,287 @@
+// Registry of the strategies, rules and variables select them by name.
+//
+// Custom strategies are registered before `validate_config`, those only transforming the
+// content may skip `apply` and are usable by the embedding API too:
+//
+// ```ignore
+// struct Teapot;
+//
+// impl Strategy for Teapot {
+//     fn name(&self) -> &str {
+//         "teapot"
+//     }
+//
+//     fn apply(self: Arc<Self>, ctx: Context) -> BoxFuture<'static, Response<Body>> {
+//         Box::pin(async move { teapot_resp(ctx.request()) })
+//     }
+// }
+//
+// miragend::strategies::register("teapot", Teapot);
+// miragend::validate_config()?;
+// ```
+use crate::{
+    classification::Classification, fetching, fetching::ContentType, special_response, vars, Source,
+};
+use axum::body::Body;
+use futures_util::future::BoxFuture;
+use http::{header, Request, Response, StatusCode};
+use log::error;
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+// Fallback of the unknown strategies
+pub const DEFAULT: &str = "obfuscation";
+// Alternative names of the built-in strategies
+const ALIASES: [(&str, &str); 2] = [
+    ("obfus", "obfuscation"),
+    ("obfuscation+patch", "patch+obfuscation"),
+];
+
+static REGISTRY: LazyLock<RwLock<HashMap<&'static str, Arc<dyn Strategy>>>> = LazyLock::new(|| {
+    let mut registry: HashMap<&'static str, Arc<dyn Strategy>> = HashMap::new();
+    registry.insert("patch", Arc::new(Builtin::Patch));
+    registry.insert("obfuscation", Arc::new(Builtin::Obfuscation));
+    registry.insert("challenge", Arc::new(Builtin::Challenge));
+    registry.insert("tarpit", Arc::new(Builtin::Tarpit));
+    registry.insert("maze", Arc::new(Builtin::Maze));
+    registry.insert("passthrough", Arc::new(Builtin::Passthrough));
+    registry.insert("block", Arc::new(Builtin::Block));
+    registry.insert("patch+obfuscation", Arc::new(Builtin::Chain));
+
+    RwLock::new(registry)
+});
+
+/// Behavior applied to the requests of the bots, or to the content by the embedding API.
+pub trait Strategy: Send + Sync + 'static {
+    /// Name of the strategy, for logs and metrics.
+    fn name(&self) -> &str;
+
+    /// Transform the body of the given content type, unchanged by default.
+    fn transform(&self, body: &str, _content_type: ContentType) -> anyhow::Result<String> {
+        Ok(body.to_owned())
+    }
+
+    /// Response to the request, the original one with the transformed body by default.
+    fn apply(self: Arc<Self>, ctx: Context) -> BoxFuture<'static, Response<Body>> {
+        Box::pin(async move {
+            let resp = ctx.forward().await;
+            transform_resp(&*self, resp).await
+        })
+    }
+}
+
+// The bodies of the unsupported content types are kept as is
+async fn transform_resp<S: Strategy + ?Sized>(
+    strategy: &S,
+    resp: Response<Body>,
+) -> Response<Body> {
+    let Some(Ok(content_type)) = fetching::declared_content_type(resp.headers()) else {
+        return resp;
+    };
+    let (mut parts, body) = resp.into_parts();
+    let transformed = match axum::body::to_bytes(body, vars::max_body_bytes()).await {
+        Ok(bytes) => String::from_utf8(bytes.to_vec())
+            .map_err(anyhow::Error::from)
+            .and_then(|body| strategy.transform(&body, content_type)),
+        Err(e) => Err(anyhow::anyhow!("failed to read the response body: {}", e)),
+    };
+    match transformed {
+        Ok(body) => {
+            parts.headers.remove(header::CONTENT_LENGTH);
+
+            Response::from_parts(parts, Body::from(body))
+        }
+        Err(e) => {
+            error!("strategy `{}` failed: {}", strategy.name(), e);
+            special_response::build_resp_with_fallback(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+// The request with what is needed to produce its response
+pub struct Context {
+    conn_addr: SocketAddr,
+    request: Request<Body>,
+    classification: Classification,
+    source: Source,
+}
+
+impl Context {
+    pub(crate) fn new(
+        conn_addr: SocketAddr,
+        request: Request<Body>,
+        classification: Classification,
+        source: Source,
+    ) -> Self {
+        Self {
+            conn_addr,
+            request,
+            classification,
+            source,
+        }
+    }
+
+    pub fn conn_addr(&self) -> SocketAddr {
+        self.conn_addr
+    }
+
+    pub fn request(&self) -> &Request<Body> {
+        &self.request
+    }
+
+    // The original response, fetched from the upstream or the wrapped service
+    pub async fn forward(self) -> Response<Body> {
+        crate::handle(
+            self.conn_addr,
+            self.request,
+            crate::Strategy::Passthrough,
+            self.classification,
+            self.source,
+        )
+        .await
+    }
+}
+
+// Add or replace the strategy of the name
+pub fn register(name: &'static str, strategy: impl Strategy + 'static) {
+    REGISTRY
+        .write()
+        .expect("strategy registry poisoned")
+        .insert(name, Arc::new(strategy));
+}
+
+// The registered name, `None` if unknown
+pub fn name(name: &str) -> Option<&'static str> {
+    let name = ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, name)| name);
+
+    REGISTRY
+        .read()
+        .expect("strategy registry poisoned")
+        .keys()
+        .find(|registered| **registered == name)
+        .copied()
+}
+
+pub fn get(name: &str) -> Option<Arc<dyn Strategy>> {
+    REGISTRY
+        .read()
+        .expect("strategy registry poisoned")
+        .get(name)
+        .cloned()
+}
+
+enum Builtin {
+    Patch,
+    Obfuscation,
+    Challenge,
+    Tarpit,
+    Maze,
+    Passthrough,
+    Block,
+    // Patch the target, obfuscate the rest
+    Chain,
+}
+
+impl Strategy for Builtin {
+    fn name(&self) -> &str {
+        match self {
+            Builtin::Patch => "patch",
+            Builtin::Obfuscation => "obfuscation",
+            Builtin::Challenge => "challenge",
+            Builtin::Tarpit => "tarpit",
+            Builtin::Maze => "maze",
+            Builtin::Passthrough => "passthrough",
+            Builtin::Block => "block",
+            Builtin::Chain => "patch+obfuscation",
+        }
+    }
+
+    fn apply(self: Arc<Self>, ctx: Context) -> BoxFuture<'static, Response<Body>> {
+        let Context {
+            conn_addr,
+            request,
+            classification,
+            source,
+        } = ctx;
+        match *self {
+            Builtin::Patch => Box::pin(crate::patch_handler(
+                conn_addr,
+                request,
+                classification,
+                source,
+            )),
+            Builtin::Obfuscation => Box::pin(crate::obfus_handler(
+                conn_addr,
+                request,
+                classification,
+                source,
+            )),
+            Builtin::Challenge => {
+                let resp = crate::challenge_handler(conn_addr, request);
+                Box::pin(async move { resp })
+            }
+            Builtin::Tarpit => Box::pin(crate::handle(
+                conn_addr,
+                request,
+                crate::Strategy::Tarpit,
+                classification,
+                source,
+            )),
+            Builtin::Maze => {
+                let resp = crate::maze_handler(conn_addr, request);
+                Box::pin(async move { resp })
+            }
+            Builtin::Passthrough => Box::pin(crate::handle(
+                conn_addr,
+                request,
+                crate::Strategy::Passthrough,
+                classification,
+                source,
+            )),
+            Builtin::Chain => Box::pin(crate::chain_handler(
+                conn_addr,
+                request,
+                classification,
+                source,
+            )),
+            Builtin::Block => {
+                let resp = crate::block_handler(conn_addr, request);
+                Box::pin(async move { resp })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Teapot;
+
+    impl Strategy for Teapot {
+        fn name(&self) -> &str {
+            "teapot"
+        }
+
+        fn apply(self: Arc<Self>, _ctx: Context) -> BoxFuture<'static, Response<Body>> {
+            Box::pin(async { Response::new(Body::empty()) })
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        assert_eq!(name("patch"), Some("patch"));
+        assert_eq!(name("obfus"), Some("obfuscation"));
+        assert_eq!(name("obfuscation+patch"), Some("patch+obfuscation"));
+        assert_eq!(name("teapot"), None);
+        register("teapot", Teapot);
+        assert_eq!(name("teapot"), Some("teapot"));
+        assert!(get("teapot").is_some());
+    }
+}
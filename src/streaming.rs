@@ -0,0 +1,42 @@
+use axum::body::{Body, Bytes};
+use futures_util::stream;
+use std::{convert::Infallible, time::Duration};
+
+// Number of chunks sent per second
+const CHUNKS_PER_SEC: u64 = 10;
+
+// Stream the body at the given rate
+pub fn throttled(body: impl Into<Bytes>, bytes_per_sec: u64) -> Body {
+    let body: Bytes = body.into();
+    let bytes_per_sec = bytes_per_sec.max(1);
+    let chunk_size = (bytes_per_sec / CHUNKS_PER_SEC).max(1) as usize;
+    let interval = Duration::from_secs_f64(chunk_size as f64 / bytes_per_sec as f64);
+
+    let chunks = stream::unfold(body, move |mut remaining| async move {
+        if remaining.is_empty() {
+            return None;
+        }
+
+        tokio::time::sleep(interval).await;
+        let chunk = remaining.split_to(chunk_size.min(remaining.len()));
+
+        Some((Ok::<_, Infallible>(chunk), remaining))
+    });
+
+    Body::from_stream(chunks)
+}
+
+// Forward the body of the upstream response chunk by chunk, as it arrives
+pub fn forwarded(resp: reqwest::Response) -> Body {
+    let chunks = stream::unfold(Some(resp), |resp| async move {
+        let mut resp = resp?;
+        match resp.chunk().await {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(resp))),
+            Ok(None) => None,
+            // The stream ends after the error
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+
+    Body::from_stream(chunks)
+}
@@ -1,37 +1,233 @@
-use crate::{obfuscation::ObfuscatorConfig, special_response};
+use crate::{
+    balancer::Balance,
+    config::{self, mask_url},
+    fetching::UnknownContent,
+    obfuscation::{self, ObfuscatorConfig},
+    rules, special_response,
+};
+use anyhow::Context;
 use http::HeaderValue;
 use log::warn;
-use std::{fs, path::PathBuf, sync::LazyLock};
+use rand::Rng;
+use regex::Regex;
+use std::{
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, LazyLock, RwLock},
+};
 
+// Log level and module filters, e.g. `warn,miragend::fetching=debug`
+static LOG: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_LOG").unwrap_or("info".to_owned()));
+// Access log file, empty to log the requests to stderr
+static ACCESS_LOG_FILE: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_ACCESS_LOG_FILE").unwrap_or_default());
+const DEFAULT_ACCESS_LOG_MAX_SIZE: u64 = 100 * 1024 * 1024;
+// Rotate the access log beyond this size, `0` to disable
+static ACCESS_LOG_MAX_SIZE: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_ACCESS_LOG_MAX_SIZE")
+        .unwrap_or(DEFAULT_ACCESS_LOG_MAX_SIZE.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_ACCESS_LOG_MAX_SIZE)
+});
+static ACCESS_LOG_ROTATE: LazyLock<String> = LazyLock::new(|| {
+    let v = std::env::var("MIRAGEND_ACCESS_LOG_ROTATE").unwrap_or("daily".to_owned());
+    if ["hourly", "daily", "never"].contains(&v.as_str()) {
+        v
+    } else {
+        warn!(
+            "invalid value for `MIRAGEND_ACCESS_LOG_ROTATE`, expected `hourly`, `daily` or `never`, got `{}`",
+            v
+        );
+        "daily".to_owned()
+    }
+});
+// `default` or the Apache/Nginx `combined` format
+static ACCESS_LOG_FORMAT: LazyLock<String> = LazyLock::new(|| {
+    let v = std::env::var("MIRAGEND_ACCESS_LOG_FORMAT").unwrap_or("default".to_owned());
+    if ["default", "combined"].contains(&v.as_str()) {
+        v
+    } else {
+        warn!(
+            "invalid value for `MIRAGEND_ACCESS_LOG_FORMAT`, expected `default` or `combined`, got `{}`",
+            v
+        );
+        "default".to_owned()
+    }
+});
+// Number of the rotated files to keep
+static ACCESS_LOG_KEEP: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_ACCESS_LOG_KEEP")
+        .unwrap_or("7".to_owned())
+        .parse()
+        .unwrap_or(7)
+});
+static ACCESS_LOG_COMPRESS: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_ACCESS_LOG_COMPRESS") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_ACCESS_LOG_COMPRESS`, expected `true` or `false`, got `{}`",
+                v
+            );
+            true
+        }
+    } else {
+        true
+    }
+});
 static BIND: LazyLock<String> =
     LazyLock::new(|| std::env::var("MIRAGEND_BIND").unwrap_or("0.0.0.0:8080".to_owned()));
-static UPSTREAM_BASE_URL: LazyLock<String> = LazyLock::new(|| {
-    std::env::var("MIRAGEND_UPSTREAM_BASE_URL").expect("missing `UPSTREAM_BASE_URL` env var")
+// Format: `address[=strategy],...`
+static BINDS: LazyLock<Vec<(String, Option<String>)>> = LazyLock::new(|| {
+    BIND.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| match s.rsplit_once('=') {
+            Some((address, strategy)) => (address.to_owned(), Some(strategy.to_owned())),
+            None => (s.to_owned(), None),
+        })
+        .collect()
+});
+static TLS_CERT: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_TLS_CERT").unwrap_or_default());
+static TLS_KEY: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_TLS_KEY").unwrap_or_default());
+static ACME_DOMAINS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_ACME_DOMAINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+static ACME_EMAIL: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_ACME_EMAIL").unwrap_or_default());
+static FALLBACK_DIR: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_FALLBACK_DIR").unwrap_or_default());
+static ACME_DIR: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_ACME_DIR").unwrap_or("acme".to_owned()));
+static ACME_DIRECTORY_URL: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_ACME_DIRECTORY_URL")
+        .unwrap_or("https://acme-v02.api.letsencrypt.org/directory".to_owned())
 });
-static UPSTREAM_DOAMIN: LazyLock<HeaderValue> = LazyLock::new(|| {
-    let url = reqwest::Url::parse(&UPSTREAM_BASE_URL).expect("invalid `UPSTREAM_BASE_URL` value");
-    let domain = url
-        .domain()
-        .expect("missing domain in `UPSTREAM_BASE_URL` value")
-        .to_owned();
+static ACME_HTTP_BIND: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_ACME_HTTP_BIND").unwrap_or("0.0.0.0:80".to_owned()));
+// Priority-ordered, the rest are failovers of the first
+static UPSTREAM_BASE_URLS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    let urls: Vec<String> = std::env::var("MIRAGEND_UPSTREAM_BASE_URL")
+        .expect("missing `UPSTREAM_BASE_URL` env var")
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if urls.is_empty() {
+        panic!("empty `UPSTREAM_BASE_URL` value");
+    }
+
+    urls
+});
+static UPSTREAM_DOAMINS: LazyLock<Vec<HeaderValue>> = LazyLock::new(|| {
+    UPSTREAM_BASE_URLS
+        .iter()
+        .map(|url| {
+            let url = reqwest::Url::parse(url).expect("invalid `UPSTREAM_BASE_URL` value");
+            // IP addresses are valid hosts too
+            let domain = url
+                .host_str()
+                .expect("missing host in `UPSTREAM_BASE_URL` value")
+                .to_owned();
 
-    HeaderValue::from_str(&domain).expect("invalid header value in `UPSTREAM_BASE_URL` value")
+            HeaderValue::from_str(&domain)
+                .expect("invalid header value in `UPSTREAM_BASE_URL` value")
+        })
+        .collect()
+});
+static UPSTREAM_BALANCE: LazyLock<Balance> = LazyLock::new(|| {
+    let balance = std::env::var("MIRAGEND_UPSTREAM_BALANCE").unwrap_or("failover".to_owned());
+    Balance::from_str(&balance).unwrap_or_else(|_| {
+        warn!(
+            "invalid value for `MIRAGEND_UPSTREAM_BALANCE`, expected `failover`, `round-robin` or `least-connections`, got `{}`",
+            balance
+        );
+        Balance::Failover
+    })
+});
+static UNKNOWN_CONTENT: LazyLock<UnknownContent> = LazyLock::new(|| {
+    let value = std::env::var("MIRAGEND_UNKNOWN_CONTENT").unwrap_or("error".to_owned());
+    UnknownContent::from_str(&value).unwrap_or_else(|_| {
+        warn!(
+            "invalid value for `MIRAGEND_UNKNOWN_CONTENT`, expected `passthrough`, `block` or `error`, got `{}`",
+            value
+        );
+        UnknownContent::Error
+    })
+});
+const DEFAULT_MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+static MAX_BODY_BYTES: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_MAX_BODY_BYTES")
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+});
+const DEFAULT_UPSTREAM_UNHEALTHY_SECS: u64 = 10;
+static UPSTREAM_UNHEALTHY_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_UPSTREAM_UNHEALTHY_SECS")
+        .unwrap_or(DEFAULT_UPSTREAM_UNHEALTHY_SECS.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_UPSTREAM_UNHEALTHY_SECS)
 });
 static STRATEGY: LazyLock<String> =
     LazyLock::new(|| std::env::var("MIRAGEND_STRATEGY").unwrap_or("obfuscation".to_owned()));
-static PATCH_TARGET: LazyLock<String> =
-    LazyLock::new(|| std::env::var("MIRAGEND_PATCH_TARGET").unwrap_or_default());
+// Tried in order, the `<body>` contents are replaced if none is found
+static PATCH_TARGETS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_PATCH_TARGET")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+static PATCH_MODE: LazyLock<String> = LazyLock::new(|| {
+    match std::env::var("MIRAGEND_PATCH_MODE")
+        .unwrap_or_default()
+        .as_str()
+    {
+        "" | "replace" => "replace".to_owned(),
+        "prepend" => "prepend".to_owned(),
+        "append" => "append".to_owned(),
+        "before" => "before".to_owned(),
+        "after" => "after".to_owned(),
+        v => {
+            warn!(
+                "invalid value for `MIRAGEND_PATCH_MODE`, expected `replace`, `prepend`, `append`, `before` or `after`, got `{}`",
+                v
+            );
+            "replace".to_owned()
+        }
+    }
+});
 static PATCH_CONTENT_FILE: LazyLock<String> =
     LazyLock::new(|| std::env::var("MIRAGEND_PATCH_CONTENT_FILE").unwrap_or_default());
+const DEFAULT_PATCH_CONTENT_TTL_SECS: u64 = 300;
+static PATCH_CONTENT_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_PATCH_CONTENT_TTL_SECS")
+        .unwrap_or(DEFAULT_PATCH_CONTENT_TTL_SECS.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_PATCH_CONTENT_TTL_SECS)
+});
+// IDs or selectors of the nodes detached by the patch
 static PATCH_REMOVE_NODES: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
-    let text = std::env::var("MIRAGEND_PATCH_REMOVE_NODES").unwrap_or_default();
-    if !text.is_empty() {
-        text.split(',')
-            .map(|s| Box::leak(s.to_owned().into_boxed_str()) as &'static str)
-            .collect()
-    } else {
-        Vec::new()
-    }
+    std::env::var("MIRAGEND_PATCH_REMOVE_NODES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| Box::leak(s.to_owned().into_boxed_str()) as &'static str)
+        .collect()
 });
 static PATCH_REMOVE_META_TAGS: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
     std::env::var("MIRAGEND_PATCH_REMOVE_META_TAGS")
@@ -52,6 +248,83 @@ static OBFUSCATION_MESTA_TAGS: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
         FALLBACK_OBFUSCATION_MESTA_TAGS.to_vec()
     }
 });
+static OBFUSCATION_ATTRIBUTES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_OBFUSCATION_ATTRIBUTES")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_ascii_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+static OBFUSCATION_SCRAMBLE_LINKS: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_OBFUSCATION_SCRAMBLE_LINKS") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_OBFUSCATION_SCRAMBLE_LINKS`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
+    } else {
+        false
+    }
+});
+static OBFUSCATION_DECOYS: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_OBFUSCATION_DECOYS")
+        .unwrap_or("0".to_owned())
+        .parse()
+        .unwrap_or(0)
+});
+static OBFUSCATION_DIGITS: LazyLock<String> = LazyLock::new(|| {
+    match std::env::var("MIRAGEND_OBFUSCATION_DIGITS")
+        .unwrap_or_default()
+        .as_str()
+    {
+        "" | "false" => "false".to_owned(),
+        "true" => "true".to_owned(),
+        "magnitude" => "magnitude".to_owned(),
+        v => {
+            warn!(
+                "invalid value for `MIRAGEND_OBFUSCATION_DIGITS`, expected `true`, `false` or `magnitude`, got `{}`",
+                v
+            );
+            "false".to_owned()
+        }
+    }
+});
+// Comma-separated, write the commas inside the patterns as `\x2c`
+static OBFUSCATION_PRESERVE_PATTERNS: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_OBFUSCATION_PRESERVE_PATTERNS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|pattern| !pattern.is_empty())
+        .filter_map(|pattern| match Regex::new(pattern) {
+            Ok(regex) => Some(regex),
+            Err(e) => {
+                warn!(
+                    "invalid pattern `{}` in `MIRAGEND_OBFUSCATION_PRESERVE_PATTERNS`: {}, ignored",
+                    pattern, e
+                );
+                None
+            }
+        })
+        .collect()
+});
+static OBFUSCATION_RATIO: LazyLock<f64> = LazyLock::new(|| {
+    let v = std::env::var("MIRAGEND_OBFUSCATION_RATIO").unwrap_or("1.0".to_owned());
+    match v.parse::<f64>() {
+        Ok(ratio) if (0.0..=1.0).contains(&ratio) => ratio,
+        _ => {
+            warn!(
+                "invalid value for `MIRAGEND_OBFUSCATION_RATIO`, expected a number between 0.0 and 1.0, got `{}`",
+                v
+            );
+            1.0
+        }
+    }
+});
 static OBFUSCATION_IGNORE_NDOES: LazyLock<Vec<&'static str>> = LazyLock::new(|| {
     std::env::var("MIRAGEND_OBFUSCATION_IGNORE_NODES")
         .unwrap_or_default()
@@ -79,8 +352,42 @@ static OBFUSCATION_IGNORE_LEN: LazyLock<usize> = LazyLock::new(|| {
         .parse()
         .unwrap_or(0)
 });
-static OBFUSCATION_MAPPING_FILE: LazyLock<String> =
-    LazyLock::new(|| std::env::var("MIRAGEND_OBFUSCATION_MAPPING_FILE").unwrap_or_default());
+static OBFUSCATION_MODE: LazyLock<String> = LazyLock::new(|| {
+    match std::env::var("MIRAGEND_OBFUSCATION_MODE")
+        .unwrap_or_default()
+        .as_str()
+    {
+        "" | "char" => "char".to_owned(),
+        "markov" => "markov".to_owned(),
+        "word" => "word".to_owned(),
+        "css-shuffle" => "css-shuffle".to_owned(),
+        "fontmap" => "fontmap".to_owned(),
+        v => {
+            warn!(
+                "invalid value for `MIRAGEND_OBFUSCATION_MODE`, expected `char`, `markov`, `word`, `css-shuffle` or `fontmap`, got `{}`",
+                v
+            );
+            "char".to_owned()
+        }
+    }
+});
+static FONTMAP_FONT: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_FONTMAP_FONT").unwrap_or_default());
+static FONTMAP_PATH: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_FONTMAP_PATH").unwrap_or("/.miragend/font.ttf".to_owned())
+});
+static FONTMAP_FALLBACK: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_FONTMAP_FALLBACK").unwrap_or("sans-serif".to_owned()));
+// Merged in order, the overlapping mappings are chosen by their weights
+static OBFUSCATION_MAPPING_FILES: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_OBFUSCATION_MAPPING_FILE")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|file| !file.is_empty())
+        .map(str::to_owned)
+        .collect()
+});
 const DEFAULT_TIMEOUT_SECS: u64 = 60;
 static CONNECT_TIMEOUT_SECS: LazyLock<u64> = LazyLock::new(|| {
     std::env::var("MIRAGEND_CONNECT_TIMEOUT_SECS")
@@ -95,56 +402,796 @@ static SPECIAL_PAGE_STYLE: LazyLock<special_response::Style> =
             .as_str()
         {
             "nginx" => special_response::Style::Nginx,
+            "apache" => special_response::Style::Apache,
+            "caddy" => special_response::Style::Caddy,
+            "iis" => special_response::Style::Iis,
+            "cloudflare" => special_response::Style::Cloudflare,
             _ => special_response::Style::None,
         }
     });
+static TITLE_TEMPLATE: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_TITLE_TEMPLATE").unwrap_or_default());
+static TITLE_TEMPLATE_OG_TITLE: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_TITLE_TEMPLATE_OG_TITLE") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_TITLE_TEMPLATE_OG_TITLE`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
+    } else {
+        false
+    }
+});
+static DECOY_FAVICON: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_DECOY_FAVICON").unwrap_or_default());
+static DECOY_IMAGE: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_DECOY_IMAGE").unwrap_or_default());
+static DECOY_PLACEHOLDER_PATH: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_DECOY_PLACEHOLDER_PATH")
+        .unwrap_or("/.miragend/placeholder.png".to_owned())
+});
 static INJECT_ONLINE_SCRIPT: LazyLock<String> =
     LazyLock::new(|| std::env::var("MIRAGEND_INJECT_ONLINE_SCRIPT").unwrap_or_default());
-static OBFUSCATOR_CONFIG: LazyLock<ObfuscatorConfig> = LazyLock::new(|| {
-    let csv_content = if OBFUSCATION_MAPPING_FILE.is_empty()
-        || !PathBuf::from(&*OBFUSCATION_MAPPING_FILE).exists()
-    {
-        include_str!("../obfuscation_mapping.csv")
+static X_ROBOTS_TAG: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_X_ROBOTS_TAG").unwrap_or_default());
+static CANONICAL_REWRITE: LazyLock<String> = LazyLock::new(|| {
+    let value = std::env::var("MIRAGEND_CANONICAL_REWRITE").unwrap_or_default();
+    let value = value.trim().trim_end_matches('/');
+    let valid = value.is_empty()
+        || value == "proxy"
+        || reqwest::Url::parse(value).is_ok_and(|url| ["http", "https"].contains(&url.scheme()));
+    if valid {
+        value.to_owned()
+    } else {
+        warn!(
+            "invalid value for `MIRAGEND_CANONICAL_REWRITE`, expected `proxy` or an HTTP(S) base URL, got `{}`",
+            value
+        );
+        String::new()
+    }
+});
+static UPSTREAM_URL_REWRITE: LazyLock<String> = LazyLock::new(|| {
+    let value = std::env::var("MIRAGEND_UPSTREAM_URL_REWRITE").unwrap_or_default();
+    if ["", "relative", "proxy"].contains(&value.as_str()) {
+        value
     } else {
-        &fs::read_to_string(&*OBFUSCATION_MAPPING_FILE)
-            .expect("failed to read obfuscator mapping file")
+        warn!(
+            "invalid value for `MIRAGEND_UPSTREAM_URL_REWRITE`, expected `relative` or `proxy`, got `{}`",
+            value
+        );
+        String::new()
+    }
+});
+static STRIP_SCRIPTS: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_STRIP_SCRIPTS") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_STRIP_SCRIPTS`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
+    } else {
+        false
+    }
+});
+static STRIP_SCRIPT_SRCS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_STRIP_SCRIPT_SRCS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+static XML_OBFUSCATION_PATHS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_XML_OBFUSCATION_PATHS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+static JS_OBFUSCATION_PATHS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_JS_OBFUSCATION_PATHS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+// Built-in mapping sets merged after the mapping files
+static OBFUSCATION_CHARSETS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_OBFUSCATION_CHARSET")
+        .unwrap_or_default()
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .filter(|name| {
+            let known = obfuscation::builtin_charset(name).is_some();
+            if !known {
+                warn!(
+                    "invalid value for `MIRAGEND_OBFUSCATION_CHARSET`, expected {}, got `{}`",
+                    obfuscation::builtin_charset_names()
+                        .iter()
+                        .map(|name| format!("`{}`", name))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    name
+                );
+            }
+
+            known
+        })
+        .collect()
+});
+// Replaced on reload, the in-flight requests keep their clones of the previous one
+static OBFUSCATOR_CONFIG: LazyLock<RwLock<Arc<ObfuscatorConfig>>> = LazyLock::new(|| {
+    let config = load_obfuscator_config().expect("failed to read obfuscator mapping file");
+
+    RwLock::new(Arc::new(config))
+});
+static CHALLENGE_SECRET: LazyLock<String> = LazyLock::new(|| {
+    match std::env::var("MIRAGEND_CHALLENGE_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret,
+        // Random secret, the issued cookies become invalid after restart
+        _ => rand::thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect(),
+    }
+});
+const DEFAULT_CHALLENGE_DIFFICULTY: u32 = 16;
+static CHALLENGE_DIFFICULTY: LazyLock<u32> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_CHALLENGE_DIFFICULTY")
+        .unwrap_or(DEFAULT_CHALLENGE_DIFFICULTY.to_string())
+        .parse::<u32>()
+        .unwrap_or(DEFAULT_CHALLENGE_DIFFICULTY)
+        .min(32)
+});
+const DEFAULT_CHALLENGE_TTL_SECS: u64 = 60 * 60 * 24;
+static CHALLENGE_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_CHALLENGE_TTL_SECS")
+        .unwrap_or(DEFAULT_CHALLENGE_TTL_SECS.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_CHALLENGE_TTL_SECS)
+});
+static CRAWLER_ALLOWLIST: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_CRAWLER_ALLOWLIST")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+static CACHE_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_CACHE_TTL_SECS")
+        .unwrap_or("0".to_owned())
+        .parse()
+        .unwrap_or(0)
+});
+// Seconds an expired page may still be served while refreshing it
+static CACHE_STALE_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_CACHE_STALE_SECS")
+        .unwrap_or("0".to_owned())
+        .parse()
+        .unwrap_or(0)
+});
+const DEFAULT_CACHE_MAX_SIZE: u64 = 64 * 1024 * 1024;
+static CACHE_MAX_SIZE: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_CACHE_MAX_SIZE")
+        .unwrap_or(DEFAULT_CACHE_MAX_SIZE.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_CACHE_MAX_SIZE)
+});
+const DEFAULT_UPSTREAM_CACHE_TTL_SECS: u64 = 3600;
+static UPSTREAM_CACHE_TTL_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_UPSTREAM_CACHE_TTL_SECS")
+        .unwrap_or(DEFAULT_UPSTREAM_CACHE_TTL_SECS.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_UPSTREAM_CACHE_TTL_SECS)
+});
+const DEFAULT_UPSTREAM_CACHE_MAX_SIZE: u64 = 64 * 1024 * 1024;
+static UPSTREAM_CACHE_MAX_SIZE: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_UPSTREAM_CACHE_MAX_SIZE")
+        .unwrap_or(DEFAULT_UPSTREAM_CACHE_MAX_SIZE.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_UPSTREAM_CACHE_MAX_SIZE)
+});
+static UPSTREAM_REVALIDATE: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_UPSTREAM_REVALIDATE") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_UPSTREAM_REVALIDATE`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
+    } else {
+        false
+    }
+});
+// Same as the default policy of reqwest
+const DEFAULT_FOLLOW_REDIRECTS: usize = 10;
+static FOLLOW_REDIRECTS: LazyLock<usize> = LazyLock::new(|| {
+    match std::env::var("MIRAGEND_FOLLOW_REDIRECTS").as_deref() {
+        Err(_) | Ok("") | Ok("true") => DEFAULT_FOLLOW_REDIRECTS,
+        Ok("false") => 0,
+        Ok(v) => v.parse().unwrap_or_else(|_| {
+            warn!(
+                "invalid value for `MIRAGEND_FOLLOW_REDIRECTS`, expected a hop count, `true` or `false`, got `{}`",
+                v
+            );
+
+            DEFAULT_FOLLOW_REDIRECTS
+        }),
+    }
+});
+const DEFAULT_RETRY_COUNT: u32 = 2;
+static RETRY_COUNT: LazyLock<u32> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_RETRY_COUNT")
+        .unwrap_or(DEFAULT_RETRY_COUNT.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_RETRY_COUNT)
+});
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 100;
+static RETRY_BACKOFF_MS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_RETRY_BACKOFF_MS")
+        .unwrap_or(DEFAULT_RETRY_BACKOFF_MS.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_RETRY_BACKOFF_MS)
+});
+static RETRY_STATUSES: LazyLock<Vec<u16>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_RETRY_STATUSES")
+        .unwrap_or("502,503,504".to_owned())
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .filter_map(|s| match s.trim().parse() {
+            Ok(status) => Some(status),
+            Err(_) => {
+                warn!(
+                    "invalid value for `MIRAGEND_RETRY_STATUSES`, expected a status code, got `{}`",
+                    s
+                );
+
+                None
+            }
+        })
+        .collect()
+});
+static UPSTREAM_CA_FILE: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_UPSTREAM_CA_FILE").unwrap_or_default());
+static UPSTREAM_INSECURE: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_UPSTREAM_INSECURE") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_UPSTREAM_INSECURE`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
+    } else {
+        false
+    }
+});
+static UPSTREAM_CLIENT_CERT: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_UPSTREAM_CLIENT_CERT").unwrap_or_default());
+static UPSTREAM_CLIENT_KEY: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_UPSTREAM_CLIENT_KEY").unwrap_or_default());
+// Format: `host:port:address,...`, like the `--resolve` option of curl
+static UPSTREAM_RESOLVE: LazyLock<Vec<(String, SocketAddr)>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_UPSTREAM_RESOLVE")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| {
+            let parts = s.trim().splitn(3, ':').collect::<Vec<_>>();
+            let [host, port, address] = parts[..] else {
+                panic!(
+                    "invalid value for `MIRAGEND_UPSTREAM_RESOLVE`, expected `host:port:address`, got `{}`",
+                    s
+                );
+            };
+            let port: u16 = port
+                .parse()
+                .expect("invalid port in `MIRAGEND_UPSTREAM_RESOLVE` value");
+            let address: IpAddr = address
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .expect("invalid address in `MIRAGEND_UPSTREAM_RESOLVE` value");
+
+            (host.to_owned(), SocketAddr::new(address, port))
+        })
+        .collect()
+});
+static CACHE_BYPASS_HEADER: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_CACHE_BYPASS_HEADER").unwrap_or_default());
+const DEFAULT_TARPIT_BYTES_PER_SEC: u64 = 64;
+static TARPIT_BYTES_PER_SEC: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_TARPIT_BYTES_PER_SEC")
+        .unwrap_or(DEFAULT_TARPIT_BYTES_PER_SEC.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_TARPIT_BYTES_PER_SEC)
+});
+static MAZE_LINK_PREFIX: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_MAZE_LINK_PREFIX").unwrap_or("/".to_owned()));
+static OBSERVE: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_OBSERVE") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_OBSERVE`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
+    } else {
+        false
+    }
+});
+static ADMIN_BIND: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_ADMIN_BIND").unwrap_or_default());
+static HONEYPOT_PREFIX: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_HONEYPOT_PREFIX").unwrap_or_default());
+const DEFAULT_HONEYPOT_BAN_SECS: u64 = 60 * 60 * 24;
+static HONEYPOT_BAN_SECS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_HONEYPOT_BAN_SECS")
+        .unwrap_or(DEFAULT_HONEYPOT_BAN_SECS.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_HONEYPOT_BAN_SECS)
+});
+static HONEYPOT_STRATEGY: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_HONEYPOT_STRATEGY").unwrap_or("obfuscation".to_owned())
+});
+static PLUGINS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_PLUGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+});
+static DECISION_SCRIPT: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_DECISION_SCRIPT").unwrap_or_default());
+const DEFAULT_DECISION_SCRIPT_MAX_OPERATIONS: u64 = 100_000;
+static DECISION_SCRIPT_MAX_OPERATIONS: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_DECISION_SCRIPT_MAX_OPERATIONS")
+        .unwrap_or(DEFAULT_DECISION_SCRIPT_MAX_OPERATIONS.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_DECISION_SCRIPT_MAX_OPERATIONS)
+});
+const DEFAULT_PLUGIN_FUEL: u64 = 100_000_000;
+static PLUGIN_FUEL: LazyLock<u64> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_PLUGIN_FUEL")
+        .unwrap_or(DEFAULT_PLUGIN_FUEL.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_PLUGIN_FUEL)
+});
+const DEFAULT_PLUGIN_MEMORY_BYTES: usize = 64 * 1024 * 1024;
+static PLUGIN_MEMORY_BYTES: LazyLock<usize> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_PLUGIN_MEMORY_BYTES")
+        .unwrap_or(DEFAULT_PLUGIN_MEMORY_BYTES.to_string())
+        .parse()
+        .unwrap_or(DEFAULT_PLUGIN_MEMORY_BYTES)
+});
+static BLOCK_STATUS: LazyLock<u16> = LazyLock::new(|| {
+    let status = std::env::var("MIRAGEND_BLOCK_STATUS").unwrap_or("403".to_owned());
+    match status.parse::<u16>() {
+        Ok(status) if http::StatusCode::from_u16(status).is_ok() => status,
+        _ => {
+            warn!(
+                "invalid value for `MIRAGEND_BLOCK_STATUS`, expected a status code, got `{}`",
+                status
+            );
+            403
+        }
+    }
+});
+// Format: `{classification}={bytes_per_sec},...`
+static BANDWIDTH_LIMITS: LazyLock<Vec<(String, u64)>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_BANDWIDTH_LIMITS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .filter_map(|pair| {
+            let limit = pair
+                .split_once('=')
+                .and_then(|(k, v)| Some((k.trim().to_owned(), v.trim().parse().ok()?)));
+            if limit.is_none() {
+                warn!(
+                    "invalid value for `MIRAGEND_BANDWIDTH_LIMITS`, expected `{{classification}}={{bytes_per_sec}}`, got `{}`",
+                    pair
+                );
+            }
+
+            limit
+        })
+        .collect()
+});
+static SESSION_CONSISTENT: LazyLock<bool> = LazyLock::new(|| {
+    if let Ok(v) = std::env::var("MIRAGEND_SESSION_CONSISTENT") {
+        if ["true", "false"].contains(&v.as_str()) {
+            v == "true"
+        } else {
+            warn!(
+                "invalid value for `MIRAGEND_SESSION_CONSISTENT`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
+    } else {
+        false
+    }
+});
+static SESSION_COOKIE: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_SESSION_COOKIE").unwrap_or_default());
+// Format: `header=value,...`, or the `[upstream_headers]` table of the config file
+static UPSTREAM_HEADERS: LazyLock<Vec<(http::HeaderName, HeaderValue)>> =
+    LazyLock::new(|| header_pairs("MIRAGEND_UPSTREAM_HEADERS", "upstream_headers"));
+// Format: `header=value,...`, or the `[response_headers]` table of the config file
+static RESPONSE_HEADERS: LazyLock<Vec<(http::HeaderName, HeaderValue)>> =
+    LazyLock::new(|| header_pairs("MIRAGEND_RESPONSE_HEADERS", "response_headers"));
+static COOKIE_DOMAIN: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_COOKIE_DOMAIN").unwrap_or_default());
+static COOKIE_PATH: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_COOKIE_PATH").unwrap_or_default());
+static COOKIE_SECURE: LazyLock<Option<bool>> =
+    LazyLock::new(
+        || match std::env::var("MIRAGEND_COOKIE_SECURE").as_deref() {
+            Ok("true") => Some(true),
+            Ok("false") => Some(false),
+            Ok("") | Err(_) => None,
+            Ok(v) => {
+                warn!(
+                "invalid value for `MIRAGEND_COOKIE_SECURE`, expected `true` or `false`, got `{}`",
+                v
+            );
+                None
+            }
+        },
+    );
+static COOKIE_SAMESITE: LazyLock<String> = LazyLock::new(|| {
+    let value = std::env::var("MIRAGEND_COOKIE_SAMESITE").unwrap_or_default();
+    if !["", "Strict", "Lax", "None"].contains(&value.as_str()) {
+        warn!(
+            "invalid value for `MIRAGEND_COOKIE_SAMESITE`, expected `Strict`, `Lax` or `None`, got `{}`",
+            value
+        );
+
+        return String::new();
+    }
+
+    value
+});
+// Opt-in hardening headers, each can be changed by its variable or omitted if empty
+static SECURITY_HEADERS: LazyLock<Vec<(http::HeaderName, HeaderValue)>> = LazyLock::new(|| {
+    let enabled = match std::env::var("MIRAGEND_SECURITY_HEADERS").as_deref() {
+        Ok("true") => true,
+        Ok("false") | Ok("") | Err(_) => false,
+        Ok(v) => {
+            warn!(
+                "invalid value for `MIRAGEND_SECURITY_HEADERS`, expected `true` or `false`, got `{}`",
+                v
+            );
+            false
+        }
     };
-    ObfuscatorConfig::load_from_csv(csv_content)
+    if !enabled {
+        return vec![];
+    }
+
+    [
+        (
+            http::header::STRICT_TRANSPORT_SECURITY,
+            "MIRAGEND_HSTS",
+            "max-age=31536000; includeSubDomains",
+        ),
+        (
+            http::header::X_CONTENT_TYPE_OPTIONS,
+            "MIRAGEND_X_CONTENT_TYPE_OPTIONS",
+            "nosniff",
+        ),
+        (
+            http::header::REFERRER_POLICY,
+            "MIRAGEND_REFERRER_POLICY",
+            "strict-origin-when-cross-origin",
+        ),
+        (
+            http::HeaderName::from_static("permissions-policy"),
+            "MIRAGEND_PERMISSIONS_POLICY",
+            "camera=(), microphone=(), geolocation=()",
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(name, env, default)| {
+        let value = std::env::var(env).unwrap_or(default.to_owned());
+        if value.trim().is_empty() {
+            return None;
+        }
+        match HeaderValue::from_str(value.trim()) {
+            Ok(value) => Some((name, value)),
+            Err(_) => {
+                warn!(
+                    "invalid value for `{}`, expected a header value, got `{}`",
+                    env, value
+                );
+                None
+            }
+        }
+    })
+    .collect()
+});
+static REMOVE_RESPONSE_HEADERS: LazyLock<Vec<http::HeaderName>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_REMOVE_RESPONSE_HEADERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .filter_map(|s| match http::HeaderName::from_bytes(s.trim().as_bytes()) {
+            Ok(name) => Some(name),
+            Err(_) => {
+                warn!(
+                    "invalid value for `MIRAGEND_REMOVE_RESPONSE_HEADERS`, expected a header name, got `{}`",
+                    s
+                );
+
+                None
+            }
+        })
+        .collect()
+});
+// Format: `{header}={template},...`
+static DECISION_HEADERS: LazyLock<Vec<(http::HeaderName, String)>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_DECISION_HEADERS")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .filter_map(|pair| {
+            let header = pair.split_once('=').and_then(|(k, v)| {
+                Some((
+                    http::HeaderName::from_bytes(k.trim().as_bytes()).ok()?,
+                    v.trim().to_owned(),
+                ))
+            });
+            if header.is_none() {
+                warn!(
+                    "invalid value for `MIRAGEND_DECISION_HEADERS`, expected `{{header}}={{template}}`, got `{}`",
+                    pair
+                );
+            }
+
+            header
+        })
+        .collect()
 });
+static TRUSTED_PROXIES: LazyLock<Vec<(IpAddr, u8)>> = LazyLock::new(|| {
+    std::env::var("MIRAGEND_TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+        .filter_map(|cidr| match rules::parse_cidr(cidr) {
+            Ok(network) => Some(network),
+            Err(e) => {
+                warn!("invalid value for `MIRAGEND_TRUSTED_PROXIES`: {}", e);
+
+                None
+            }
+        })
+        .collect()
+});
+static MAPPING_VERSION_HEADER: LazyLock<String> =
+    LazyLock::new(|| std::env::var("MIRAGEND_MAPPING_VERSION_HEADER").unwrap_or_default());
 pub const CONTENT_TYPE_VALUE_TEXT_HTML: &str = "text/html; charset=utf-8";
 
 // Call on startup to avoid runtime initialization errors
 pub fn force_init() {
-    LazyLock::force(&UPSTREAM_BASE_URL);
-    LazyLock::force(&UPSTREAM_DOAMIN);
+    LazyLock::force(&UPSTREAM_BASE_URLS);
+    LazyLock::force(&UPSTREAM_DOAMINS);
+    LazyLock::force(&UPSTREAM_BALANCE);
+    LazyLock::force(&UNKNOWN_CONTENT);
     LazyLock::force(&OBFUSCATOR_CONFIG);
     LazyLock::force(&OBFUSCATION_IGNORE_TITLE);
+    LazyLock::force(&TITLE_TEMPLATE_OG_TITLE);
+    LazyLock::force(&CHALLENGE_SECRET);
+    LazyLock::force(&OBSERVE);
+    LazyLock::force(&BLOCK_STATUS);
+    LazyLock::force(&SESSION_CONSISTENT);
+    LazyLock::force(&UPSTREAM_REVALIDATE);
+    LazyLock::force(&RETRY_STATUSES);
+    LazyLock::force(&DECISION_HEADERS);
+    LazyLock::force(&TRUSTED_PROXIES);
+    LazyLock::force(&BINDS);
+    LazyLock::force(&UPSTREAM_RESOLVE);
+    LazyLock::force(&UPSTREAM_INSECURE);
+    LazyLock::force(&UPSTREAM_HEADERS);
+    LazyLock::force(&RESPONSE_HEADERS);
+    LazyLock::force(&REMOVE_RESPONSE_HEADERS);
+    LazyLock::force(&SECURITY_HEADERS);
+    LazyLock::force(&COOKIE_SECURE);
+    LazyLock::force(&COOKIE_SAMESITE);
+    LazyLock::force(&FOLLOW_REDIRECTS);
+    LazyLock::force(&OBFUSCATION_SCRAMBLE_LINKS);
+}
+
+// Merge the header pairs of the config table and the environment variable
+fn header_pairs(env: &str, section: &str) -> Vec<(http::HeaderName, HeaderValue)> {
+    let mut pairs: Vec<(String, String)> = config::section(section)
+        .and_then(|section| section.as_table())
+        .map(|table| {
+            table
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.clone(),
+                        v.as_str().map(str::to_owned).unwrap_or(v.to_string()),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    for pair in std::env::var(env)
+        .unwrap_or_default()
+        .split(',')
+        .filter(|s| !s.trim().is_empty())
+    {
+        match pair.split_once('=') {
+            Some((k, v)) => pairs.push((k.trim().to_owned(), v.trim().to_owned())),
+            None => warn!(
+                "invalid value for `{}`, expected `{{header}}={{value}}`, got `{}`",
+                env, pair
+            ),
+        }
+    }
+
+    pairs
+        .into_iter()
+        .filter_map(|(k, v)| {
+            let header = http::HeaderName::from_bytes(k.as_bytes())
+                .ok()
+                .zip(HeaderValue::from_str(&v).ok());
+            if header.is_none() {
+                warn!("invalid header in `{}`: `{}`", env, k);
+            }
+
+            header
+        })
+        .collect()
 }
 
-pub fn bind() -> &'static str {
-    &BIND
+pub fn log_spec() -> &'static str {
+    &LOG
 }
 
+pub fn access_log_file() -> &'static str {
+    &ACCESS_LOG_FILE
+}
+
+pub fn access_log_format() -> &'static str {
+    &ACCESS_LOG_FORMAT
+}
+
+pub fn access_log_max_size() -> u64 {
+    *ACCESS_LOG_MAX_SIZE
+}
+
+pub fn access_log_rotate() -> &'static str {
+    &ACCESS_LOG_ROTATE
+}
+
+pub fn access_log_keep() -> usize {
+    *ACCESS_LOG_KEEP
+}
+
+pub fn access_log_compress() -> bool {
+    *ACCESS_LOG_COMPRESS
+}
+
+// Listener addresses with their optional default strategies
+pub fn binds() -> &'static Vec<(String, Option<String>)> {
+    &BINDS
+}
+
+// Path of the PEM certificate chain, HTTPS is served if not empty
+pub fn tls_cert() -> &'static str {
+    &TLS_CERT
+}
+
+pub fn tls_key() -> &'static str {
+    &TLS_KEY
+}
+
+// Domains of the ACME certificate, enabled if not empty (requires the `acme` feature)
+pub fn acme_domains() -> &'static Vec<String> {
+    &ACME_DOMAINS
+}
+
+pub fn acme_email() -> &'static str {
+    &ACME_EMAIL
+}
+
+// Storage of the account, certificate and key
+pub fn acme_dir() -> &'static str {
+    &ACME_DIR
+}
+
+pub fn acme_directory_url() -> &'static str {
+    &ACME_DIRECTORY_URL
+}
+
+// Plain HTTP listener answering the HTTP-01 challenges, empty to disable
+pub fn acme_http_bind() -> &'static str {
+    &ACME_HTTP_BIND
+}
+
+// The primary upstream
 pub fn upstream_base_url() -> &'static str {
-    &UPSTREAM_BASE_URL
+    &UPSTREAM_BASE_URLS[0]
 }
 
 pub fn upstream_domain() -> &'static HeaderValue {
-    &UPSTREAM_DOAMIN
+    &UPSTREAM_DOAMINS[0]
+}
+
+pub fn upstream_base_urls() -> &'static Vec<String> {
+    &UPSTREAM_BASE_URLS
+}
+
+pub fn upstream_domains() -> &'static Vec<HeaderValue> {
+    &UPSTREAM_DOAMINS
+}
+
+pub fn upstream_balance() -> Balance {
+    *UPSTREAM_BALANCE
+}
+
+// Answer of the upstream content neither HTML, JSON nor text
+pub fn unknown_content() -> UnknownContent {
+    *UNKNOWN_CONTENT
+}
+
+// Largest body buffered in memory, of the forwarded requests and the transformed responses
+pub fn max_body_bytes() -> usize {
+    *MAX_BODY_BYTES
+}
+
+pub fn upstream_unhealthy_secs() -> u64 {
+    *UPSTREAM_UNHEALTHY_SECS
 }
 
 pub fn strategy() -> &'static str {
     &STRATEGY
 }
 
-pub fn patch_target() -> &'static str {
-    &PATCH_TARGET
+// IDs or selectors of the nodes receiving the patch content
+pub fn patch_targets() -> &'static Vec<String> {
+    &PATCH_TARGETS
+}
+
+// Where the patch content goes relative to the target node
+pub fn patch_mode() -> &'static str {
+    &PATCH_MODE
 }
 
 pub fn patch_content_file() -> &'static str {
     &PATCH_CONTENT_FILE
 }
 
+// Lifetime of the remote patch content
+pub fn patch_content_ttl_secs() -> u64 {
+    *PATCH_CONTENT_TTL_SECS
+}
+
 pub fn patch_remove_nodes() -> &'static Vec<&'static str> {
     &PATCH_REMOVE_NODES
 }
@@ -157,10 +1204,41 @@ pub fn obfuscation_meta_tags() -> &'static Vec<&'static str> {
     &OBFUSCATION_MESTA_TAGS
 }
 
+// Selectors of the ignored nodes: `#id`, `.class`, or a bare id or tag name
 pub fn obfuscation_ignore_nodes() -> &'static Vec<&'static str> {
     &OBFUSCATION_IGNORE_NDOES
 }
 
+// Attributes of all elements to obfuscate, such as `alt` and `title`
+pub fn obfuscation_attributes() -> &'static Vec<String> {
+    &OBFUSCATION_ATTRIBUTES
+}
+
+// Point the links to trap (or non-existent) URLs, the ignored nodes keep working
+pub fn obfuscation_scramble_links() -> bool {
+    *OBFUSCATION_SCRAMBLE_LINKS
+}
+
+// Number of hidden garbage elements inserted into the body
+pub fn obfuscation_decoys() -> usize {
+    *OBFUSCATION_DECOYS
+}
+
+// Replace the digits with other digits, `magnitude` keeps the leading digits of the numbers
+pub fn obfuscation_digits() -> &'static str {
+    &OBFUSCATION_DIGITS
+}
+
+// Matches in the text kept intact, e.g. email addresses or URLs
+pub fn obfuscation_preserve_patterns() -> &'static Vec<Regex> {
+    &OBFUSCATION_PRESERVE_PATTERNS
+}
+
+// Fraction of the eligible characters being replaced
+pub fn obfuscation_ratio() -> f64 {
+    *OBFUSCATION_RATIO
+}
+
 pub fn obfuscation_ignore_title() -> bool {
     *OBFUSCATION_IGNORE_TITLE
 }
@@ -173,8 +1251,70 @@ pub fn obfuscation_ignore_len() -> usize {
     *OBFUSCATION_IGNORE_LEN
 }
 
-pub fn obfuscator_config() -> &'static ObfuscatorConfig {
-    &OBFUSCATOR_CONFIG
+pub fn obfuscation_mode() -> &'static str {
+    &OBFUSCATION_MODE
+}
+
+// Source TrueType font of the `fontmap` mode
+pub fn fontmap_font() -> &'static str {
+    &FONTMAP_FONT
+}
+
+// Path serving the scrambled font
+pub fn fontmap_path() -> &'static str {
+    &FONTMAP_PATH
+}
+
+// Font family of the characters not in the scrambled font
+pub fn fontmap_fallback() -> &'static str {
+    &FONTMAP_FALLBACK
+}
+
+pub fn obfuscator_config() -> Arc<ObfuscatorConfig> {
+    OBFUSCATOR_CONFIG
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+pub fn obfuscation_mapping_files() -> &'static Vec<String> {
+    &OBFUSCATION_MAPPING_FILES
+}
+
+pub fn obfuscation_charsets() -> &'static Vec<String> {
+    &OBFUSCATION_CHARSETS
+}
+
+// The built-in mapping is used without any existing file or selected charset
+fn load_obfuscator_config() -> anyhow::Result<ObfuscatorConfig> {
+    let mut contents = vec![];
+    for file in OBFUSCATION_MAPPING_FILES.iter() {
+        if !PathBuf::from(file).exists() {
+            warn!("obfuscation mapping file `{}` not found, ignored", file);
+            continue;
+        }
+        contents.push(fs::read_to_string(file).context(format!("failed to read `{}`", file))?);
+    }
+    contents.extend(
+        OBFUSCATION_CHARSETS
+            .iter()
+            .filter_map(|name| obfuscation::builtin_charset(name)),
+    );
+    if contents.is_empty() {
+        contents.push(include_str!("../obfuscation_mapping.csv").to_owned());
+    }
+
+    Ok(ObfuscatorConfig::load_from_csvs(
+        &contents.iter().map(String::as_str).collect::<Vec<_>>(),
+    ))
+}
+
+// Read the mapping files again, the current configuration is kept on failure
+pub fn reload_obfuscator_config() -> anyhow::Result<Arc<ObfuscatorConfig>> {
+    let config = Arc::new(load_obfuscator_config()?);
+    *OBFUSCATOR_CONFIG.write().unwrap_or_else(|e| e.into_inner()) = config.clone();
+
+    Ok(config)
 }
 
 pub fn connect_timeout_secs() -> u64 {
@@ -185,6 +1325,495 @@ pub fn special_page_style() -> special_response::Style {
     *SPECIAL_PAGE_STYLE
 }
 
+// Static site served when the upstream is down
+pub fn fallback_dir() -> &'static str {
+    &FALLBACK_DIR
+}
+
+// Title of the transformed pages, `{{original}}` is replaced by the original title
+pub fn title_template() -> &'static str {
+    &TITLE_TEMPLATE
+}
+
+// Apply the title template to `og:title` too
+pub fn title_template_og_title() -> bool {
+    *TITLE_TEMPLATE_OG_TITLE
+}
+
+// Icon URL of the transformed pages, `placeholder` uses the bundled image
+pub fn decoy_favicon() -> &'static str {
+    &DECOY_FAVICON
+}
+
+// Preview image URL of `og:image` and `twitter:image`, `placeholder` uses the bundled image
+pub fn decoy_image() -> &'static str {
+    &DECOY_IMAGE
+}
+
+// Path serving the bundled placeholder image
+pub fn decoy_placeholder_path() -> &'static str {
+    &DECOY_PLACEHOLDER_PATH
+}
+
+// Script appended to the head, same as a `script` injection
 pub fn inject_online_script() -> &'static str {
     &INJECT_ONLINE_SCRIPT
 }
+
+// `X-Robots-Tag` of the bot-facing responses, `strip` removes the header
+pub fn x_robots_tag() -> &'static str {
+    &X_ROBOTS_TAG
+}
+
+// Base URL of the canonical links pointing at the upstreams, `proxy` uses the proxy origin
+pub fn canonical_rewrite() -> &'static str {
+    &CANONICAL_REWRITE
+}
+
+// How the absolute URLs to the upstreams are rewritten: `relative` paths or the `proxy` origin
+pub fn upstream_url_rewrite() -> &'static str {
+    &UPSTREAM_URL_REWRITE
+}
+
+// Remove all the scripts of the transformed pages
+pub fn strip_scripts() -> bool {
+    *STRIP_SCRIPTS
+}
+
+// Patterns of the script sources removed from the transformed pages, `*` matches any characters
+pub fn strip_script_srcs() -> &'static Vec<String> {
+    &STRIP_SCRIPT_SRCS
+}
+
+// Path patterns of the XML documents whose text is obfuscated, the others are forwarded as is
+pub fn xml_obfuscation_paths() -> &'static Vec<String> {
+    &XML_OBFUSCATION_PATHS
+}
+
+// Path patterns of the scripts whose string literals are obfuscated, the others are forwarded as is
+pub fn js_obfuscation_paths() -> &'static Vec<String> {
+    &JS_OBFUSCATION_PATHS
+}
+
+pub fn crawler_allowlist() -> &'static Vec<String> {
+    &CRAWLER_ALLOWLIST
+}
+
+pub fn cache_ttl_secs() -> u64 {
+    *CACHE_TTL_SECS
+}
+
+pub fn cache_stale_secs() -> u64 {
+    *CACHE_STALE_SECS
+}
+
+pub fn cache_max_size() -> u64 {
+    *CACHE_MAX_SIZE
+}
+
+pub fn upstream_revalidate() -> bool {
+    *UPSTREAM_REVALIDATE
+}
+
+// Lifetime of the raw upstream responses kept for the revalidation
+pub fn upstream_cache_ttl_secs() -> u64 {
+    *UPSTREAM_CACHE_TTL_SECS
+}
+
+// Budget of the raw upstream responses, apart from the transformed pages
+pub fn upstream_cache_max_size() -> u64 {
+    *UPSTREAM_CACHE_MAX_SIZE
+}
+
+// Max hops of the upstream redirects followed internally, 0 to forward them to the client
+pub fn follow_redirects() -> usize {
+    *FOLLOW_REDIRECTS
+}
+
+pub fn retry_count() -> u32 {
+    *RETRY_COUNT
+}
+
+pub fn retry_backoff_ms() -> u64 {
+    *RETRY_BACKOFF_MS
+}
+
+pub fn retry_statuses() -> &'static Vec<u16> {
+    &RETRY_STATUSES
+}
+
+// Pinned addresses of the upstream hosts, bypassing DNS
+pub fn upstream_resolve() -> &'static Vec<(String, SocketAddr)> {
+    &UPSTREAM_RESOLVE
+}
+
+// Extra root certificate (PEM) trusted for the upstream
+pub fn upstream_ca_file() -> &'static str {
+    &UPSTREAM_CA_FILE
+}
+
+// Skip the verification of the upstream certificate
+pub fn upstream_insecure() -> bool {
+    *UPSTREAM_INSECURE
+}
+
+// Client certificate (PEM) presented to the upstream, enables mTLS with the key
+pub fn upstream_client_cert() -> &'static str {
+    &UPSTREAM_CLIENT_CERT
+}
+
+// PKCS#8 private key (PEM) of the client certificate
+pub fn upstream_client_key() -> &'static str {
+    &UPSTREAM_CLIENT_KEY
+}
+
+// Headers set on the proxied responses, replacing the ones of the upstream
+pub fn response_headers() -> &'static Vec<(http::HeaderName, HeaderValue)> {
+    &RESPONSE_HEADERS
+}
+
+// Replacement of the upstream `Domain` of cookies, they become host-only if empty
+pub fn cookie_domain() -> &'static str {
+    &COOKIE_DOMAIN
+}
+
+// Replacement of the `Path` of cookies, kept if empty
+pub fn cookie_path() -> &'static str {
+    &COOKIE_PATH
+}
+
+// Add (`true`) or strip (`false`) the `Secure` attribute of cookies, kept if unset
+pub fn cookie_secure() -> Option<bool> {
+    *COOKIE_SECURE
+}
+
+// Replacement of the `SameSite` of cookies, kept if empty
+pub fn cookie_samesite() -> &'static str {
+    &COOKIE_SAMESITE
+}
+
+// HSTS, `X-Content-Type-Options`, `Referrer-Policy` and `Permissions-Policy` added to the
+// responses lacking them
+pub fn security_headers() -> &'static Vec<(http::HeaderName, HeaderValue)> {
+    &SECURITY_HEADERS
+}
+
+// Headers removed from the proxied responses
+pub fn remove_response_headers() -> &'static Vec<http::HeaderName> {
+    &REMOVE_RESPONSE_HEADERS
+}
+
+// Extra headers sent to the upstream, replacing the ones of the client
+pub fn upstream_headers() -> &'static Vec<(http::HeaderName, HeaderValue)> {
+    &UPSTREAM_HEADERS
+}
+
+pub fn cache_bypass_header() -> &'static str {
+    &CACHE_BYPASS_HEADER
+}
+
+pub fn tarpit_bytes_per_sec() -> u64 {
+    *TARPIT_BYTES_PER_SEC
+}
+
+pub fn maze_link_prefix() -> &'static str {
+    &MAZE_LINK_PREFIX
+}
+
+pub fn observe() -> bool {
+    *OBSERVE
+}
+
+pub fn admin_bind() -> &'static str {
+    &ADMIN_BIND
+}
+
+pub fn honeypot_prefix() -> &'static str {
+    &HONEYPOT_PREFIX
+}
+
+pub fn honeypot_ban_secs() -> u64 {
+    *HONEYPOT_BAN_SECS
+}
+
+pub fn honeypot_strategy() -> &'static str {
+    &HONEYPOT_STRATEGY
+}
+
+// Rhai script deciding the strategies
+pub fn decision_script() -> &'static str {
+    &DECISION_SCRIPT
+}
+
+// Operations a script call may run before it's aborted
+pub fn decision_script_max_operations() -> u64 {
+    *DECISION_SCRIPT_MAX_OPERATIONS
+}
+
+// Paths of the WASM plugins
+pub fn plugins() -> &'static Vec<String> {
+    &PLUGINS
+}
+
+// Instructions a plugin call may run before it's aborted
+pub fn plugin_fuel() -> u64 {
+    *PLUGIN_FUEL
+}
+
+// Largest linear memory of a plugin instance
+pub fn plugin_memory_bytes() -> usize {
+    *PLUGIN_MEMORY_BYTES
+}
+
+pub fn block_status() -> u16 {
+    *BLOCK_STATUS
+}
+
+pub fn bandwidth_limits() -> &'static Vec<(String, u64)> {
+    &BANDWIDTH_LIMITS
+}
+
+pub fn session_consistent() -> bool {
+    *SESSION_CONSISTENT
+}
+
+pub fn session_cookie() -> &'static str {
+    &SESSION_COOKIE
+}
+
+pub fn decision_headers() -> &'static Vec<(http::HeaderName, String)> {
+    &DECISION_HEADERS
+}
+
+pub fn trusted_proxies() -> &'static Vec<(IpAddr, u8)> {
+    &TRUSTED_PROXIES
+}
+
+pub fn mapping_version_header() -> &'static str {
+    &MAPPING_VERSION_HEADER
+}
+
+pub fn challenge_secret() -> &'static str {
+    &CHALLENGE_SECRET
+}
+
+pub fn challenge_difficulty() -> u32 {
+    *CHALLENGE_DIFFICULTY
+}
+
+pub fn challenge_ttl_secs() -> u64 {
+    *CHALLENGE_TTL_SECS
+}
+
+// Entry of the effective configuration
+pub struct Entry {
+    pub key: &'static str,
+    pub value: serde_json::Value,
+    pub secret: bool,
+}
+
+impl Entry {
+    fn new(key: &'static str, value: impl Into<serde_json::Value>) -> Self {
+        Self {
+            key,
+            value: value.into(),
+            secret: false,
+        }
+    }
+
+    fn secret(key: &'static str, value: impl Into<serde_json::Value>) -> Self {
+        Self {
+            secret: true,
+            ..Self::new(key, value)
+        }
+    }
+}
+
+// All configuration entries with resolved values, keys are without the `MIRAGEND_` prefix
+pub fn entries() -> Vec<Entry> {
+    vec![
+        Entry::new("LOG", log_spec()),
+        Entry::new("ACCESS_LOG_FILE", access_log_file()),
+        Entry::new("ACCESS_LOG_FORMAT", access_log_format()),
+        Entry::new("ACCESS_LOG_MAX_SIZE", access_log_max_size()),
+        Entry::new("ACCESS_LOG_ROTATE", access_log_rotate()),
+        Entry::new("ACCESS_LOG_KEEP", access_log_keep()),
+        Entry::new("ACCESS_LOG_COMPRESS", access_log_compress()),
+        Entry::new("BIND", BIND.as_str()),
+        Entry::new("TLS_CERT", tls_cert()),
+        Entry::new("TLS_KEY", tls_key()),
+        Entry::new("ACME_DOMAINS", acme_domains().clone()),
+        Entry::new("ACME_EMAIL", acme_email()),
+        Entry::new("ACME_DIR", acme_dir()),
+        Entry::new("ACME_DIRECTORY_URL", acme_directory_url()),
+        Entry::new("ACME_HTTP_BIND", acme_http_bind()),
+        Entry::new(
+            "UPSTREAM_BASE_URL",
+            upstream_base_urls()
+                .iter()
+                .map(|url| mask_url(url))
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new("UPSTREAM_BALANCE", upstream_balance().to_string()),
+        Entry::new("UNKNOWN_CONTENT", unknown_content().to_string()),
+        Entry::new("MAX_BODY_BYTES", max_body_bytes()),
+        Entry::new("UPSTREAM_UNHEALTHY_SECS", upstream_unhealthy_secs()),
+        Entry::new("UPSTREAM_CA_FILE", upstream_ca_file()),
+        Entry::new("UPSTREAM_INSECURE", upstream_insecure()),
+        Entry::new("UPSTREAM_CLIENT_CERT", upstream_client_cert()),
+        Entry::new("UPSTREAM_CLIENT_KEY", upstream_client_key()),
+        // The values may be credentials
+        Entry::new(
+            "UPSTREAM_HEADERS",
+            upstream_headers()
+                .iter()
+                .map(|(name, _)| format!("{}=******", name))
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new(
+            "RESPONSE_HEADERS",
+            response_headers()
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value.to_str().unwrap_or_default()))
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new("COOKIE_DOMAIN", cookie_domain()),
+        Entry::new("COOKIE_PATH", cookie_path()),
+        Entry::new("COOKIE_SECURE", cookie_secure()),
+        Entry::new("COOKIE_SAMESITE", cookie_samesite()),
+        Entry::new(
+            "SECURITY_HEADERS",
+            security_headers()
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value.to_str().unwrap_or_default()))
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new(
+            "REMOVE_RESPONSE_HEADERS",
+            remove_response_headers()
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new(
+            "UPSTREAM_RESOLVE",
+            upstream_resolve()
+                .iter()
+                .map(|(host, addr)| match addr {
+                    SocketAddr::V4(addr) => format!("{}:{}:{}", host, addr.port(), addr.ip()),
+                    SocketAddr::V6(addr) => format!("{}:{}:[{}]", host, addr.port(), addr.ip()),
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new("STRATEGY", strategy()),
+        Entry::new("PATCH_TARGET", patch_targets().clone()),
+        Entry::new("PATCH_MODE", patch_mode()),
+        Entry::new("PATCH_CONTENT_FILE", patch_content_file()),
+        Entry::new("PATCH_CONTENT_TTL_SECS", patch_content_ttl_secs()),
+        Entry::new("PATCH_REMOVE_NODES", patch_remove_nodes().clone()),
+        Entry::new("PATCH_REMOVE_META_TAGS", patch_remove_meta_tags().clone()),
+        Entry::new("OBFUSCATION_META_TAGS", obfuscation_meta_tags().clone()),
+        Entry::new("OBFUSCATION_ATTRIBUTES", obfuscation_attributes().clone()),
+        Entry::new("OBFUSCATION_SCRAMBLE_LINKS", obfuscation_scramble_links()),
+        Entry::new("OBFUSCATION_DECOYS", obfuscation_decoys()),
+        Entry::new("OBFUSCATION_RATIO", obfuscation_ratio()),
+        Entry::new("OBFUSCATION_DIGITS", obfuscation_digits()),
+        Entry::new("OBFUSCATION_MODE", obfuscation_mode()),
+        Entry::new(
+            "OBFUSCATION_PRESERVE_PATTERNS",
+            obfuscation_preserve_patterns()
+                .iter()
+                .map(|pattern| pattern.as_str().to_owned())
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new("FONTMAP_FONT", fontmap_font()),
+        Entry::new("FONTMAP_PATH", fontmap_path()),
+        Entry::new("FONTMAP_FALLBACK", fontmap_fallback()),
+        Entry::new(
+            "OBFUSCATION_IGNORE_NODES",
+            obfuscation_ignore_nodes().clone(),
+        ),
+        Entry::new("OBFUSCATION_IGNORE_TITLE", obfuscation_ignore_title()),
+        Entry::new(
+            "OBFUSCATION_IGNORE_AFTER_NODE",
+            obfuscation_ignore_after_node(),
+        ),
+        Entry::new("OBFUSCATION_IGNORE_LEN", obfuscation_ignore_len()),
+        Entry::new(
+            "OBFUSCATION_MAPPING_FILE",
+            OBFUSCATION_MAPPING_FILES.clone(),
+        ),
+        Entry::new("OBFUSCATION_CHARSET", obfuscation_charsets().clone()),
+        Entry::new("MAPPING_VERSION_HEADER", mapping_version_header()),
+        Entry::new("CONNECT_TIMEOUT_SECS", connect_timeout_secs()),
+        Entry::new("SPECIAL_PAGE_STYLE", special_page_style().to_string()),
+        Entry::new("TITLE_TEMPLATE", title_template()),
+        Entry::new("TITLE_TEMPLATE_OG_TITLE", title_template_og_title()),
+        Entry::new("DECOY_FAVICON", decoy_favicon()),
+        Entry::new("DECOY_IMAGE", decoy_image()),
+        Entry::new("DECOY_PLACEHOLDER_PATH", decoy_placeholder_path()),
+        Entry::new("INJECT_ONLINE_SCRIPT", inject_online_script()),
+        Entry::new("X_ROBOTS_TAG", x_robots_tag()),
+        Entry::new("CANONICAL_REWRITE", canonical_rewrite()),
+        Entry::new("UPSTREAM_URL_REWRITE", upstream_url_rewrite()),
+        Entry::new("STRIP_SCRIPTS", strip_scripts()),
+        Entry::new("STRIP_SCRIPT_SRCS", strip_script_srcs().clone()),
+        Entry::new("XML_OBFUSCATION_PATHS", xml_obfuscation_paths().clone()),
+        Entry::new("JS_OBFUSCATION_PATHS", js_obfuscation_paths().clone()),
+        Entry::new("FALLBACK_DIR", fallback_dir()),
+        Entry::secret("CHALLENGE_SECRET", challenge_secret()),
+        Entry::new("CHALLENGE_DIFFICULTY", challenge_difficulty()),
+        Entry::new("CHALLENGE_TTL_SECS", challenge_ttl_secs()),
+        Entry::new("CRAWLER_ALLOWLIST", crawler_allowlist().clone()),
+        Entry::new("CACHE_TTL_SECS", cache_ttl_secs()),
+        Entry::new("CACHE_STALE_SECS", cache_stale_secs()),
+        Entry::new("CACHE_MAX_SIZE", cache_max_size()),
+        Entry::new("CACHE_BYPASS_HEADER", cache_bypass_header()),
+        Entry::new("UPSTREAM_REVALIDATE", upstream_revalidate()),
+        Entry::new("UPSTREAM_CACHE_TTL_SECS", upstream_cache_ttl_secs()),
+        Entry::new("UPSTREAM_CACHE_MAX_SIZE", upstream_cache_max_size()),
+        Entry::new("FOLLOW_REDIRECTS", follow_redirects()),
+        Entry::new("RETRY_COUNT", retry_count()),
+        Entry::new("RETRY_BACKOFF_MS", retry_backoff_ms()),
+        Entry::new("RETRY_STATUSES", retry_statuses().clone()),
+        Entry::new("TARPIT_BYTES_PER_SEC", tarpit_bytes_per_sec()),
+        Entry::new("MAZE_LINK_PREFIX", maze_link_prefix()),
+        Entry::new("OBSERVE", observe()),
+        Entry::new("ADMIN_BIND", admin_bind()),
+        Entry::new("BLOCK_STATUS", block_status()),
+        Entry::new(
+            "BANDWIDTH_LIMITS",
+            bandwidth_limits()
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new("HONEYPOT_PREFIX", honeypot_prefix()),
+        Entry::new("HONEYPOT_BAN_SECS", honeypot_ban_secs()),
+        Entry::new("HONEYPOT_STRATEGY", honeypot_strategy()),
+        Entry::new("DECISION_SCRIPT", decision_script()),
+        Entry::new(
+            "DECISION_SCRIPT_MAX_OPERATIONS",
+            decision_script_max_operations(),
+        ),
+        Entry::new("PLUGINS", plugins().clone()),
+        Entry::new("PLUGIN_FUEL", plugin_fuel()),
+        Entry::new("PLUGIN_MEMORY_BYTES", plugin_memory_bytes()),
+        Entry::new("SESSION_CONSISTENT", session_consistent()),
+        Entry::new("SESSION_COOKIE", session_cookie()),
+        Entry::new(
+            "DECISION_HEADERS",
+            decision_headers()
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>(),
+        ),
+        Entry::new(
+            "TRUSTED_PROXIES",
+            trusted_proxies()
+                .iter()
+                .map(|(ip, prefix)| format!("{}/{}", ip, prefix))
+                .collect::<Vec<_>>(),
+        ),
+    ]
+}
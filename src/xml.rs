@@ -0,0 +1,146 @@
+// Text pass over the XML documents like sitemaps and feeds, the markup is copied as is
+use crate::{rules::glob_match, vars};
+
+const CDATA_START: &str = "<![CDATA[";
+const CDATA_END: &str = "]]>";
+
+// Only the XML of these paths is transformed, the rest is forwarded byte-for-byte
+pub fn transforms(path: &str) -> bool {
+    vars::xml_obfuscation_paths()
+        .iter()
+        .any(|pattern| glob_match(pattern, path))
+}
+
+// Rewrite the character data and the text of the CDATA sections, the comments, the entity
+// references and the URLs are kept
+pub fn map_text(xml: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let mut output = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while !rest.is_empty() {
+        let end = match rest.strip_prefix(CDATA_START) {
+            Some(section) => {
+                let len = section.find(CDATA_END).unwrap_or(section.len());
+                output.push_str(CDATA_START);
+                output.push_str(&map_cdata(&section[..len], &mut f));
+                let end = (CDATA_START.len() + len + CDATA_END.len()).min(rest.len());
+                output.push_str(&rest[CDATA_START.len() + len..end]);
+                end
+            }
+            None => map_next(rest, &mut output, &mut f),
+        };
+        rest = &rest[end..];
+    }
+
+    output
+}
+
+// Copy the markup or map the character data at the start, the length consumed is returned
+fn map_next(xml: &str, output: &mut String, f: &mut impl FnMut(&str) -> String) -> usize {
+    if xml.starts_with('<') {
+        let end = markup_len(xml);
+        output.push_str(&xml[..end]);
+        end
+    } else {
+        let end = xml.find('<').unwrap_or(xml.len());
+        map_char_data(&xml[..end], output, f);
+        end
+    }
+}
+
+// The sections of the feeds usually hold HTML, its markup is kept as well
+fn map_cdata(section: &str, f: &mut impl FnMut(&str) -> String) -> String {
+    let mut output = String::with_capacity(section.len());
+    let mut rest = section;
+    while !rest.is_empty() {
+        let end = map_next(rest, &mut output, f);
+        rest = &rest[end..];
+    }
+
+    // The mapped text must not end the section early
+    output.replace(CDATA_END, "]]]]><![CDATA[>")
+}
+
+// Length of the markup at the start, unterminated markup runs to the end
+fn markup_len(xml: &str) -> usize {
+    let terminated = |start: &str, end: &str| {
+        xml.starts_with(start)
+            .then(|| xml.find(end).map_or(xml.len(), |i| i + end.len()))
+    };
+    if let Some(len) = terminated("<!--", "-->")
+        .or_else(|| terminated("<![CDATA[", "]]>"))
+        .or_else(|| terminated("<?", "?>"))
+    {
+        return len;
+    }
+
+    // The quoted attribute values may contain `>`, and the DTD its internal subset
+    let mut quote = None;
+    let mut brackets = 0;
+    for (i, c) in xml.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[') => brackets += 1,
+            (None, ']') => brackets -= 1,
+            (None, '>') if brackets <= 0 => return i + 1,
+            _ => {}
+        }
+    }
+
+    xml.len()
+}
+
+fn map_char_data(text: &str, output: &mut String, f: &mut impl FnMut(&str) -> String) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        let end = rest.find('&').unwrap_or(rest.len());
+        let run = &rest[..end];
+        // The indentation between the elements is not content, the links of the sitemaps
+        // and the feeds must keep working
+        if run.trim().is_empty() || run.contains("://") {
+            output.push_str(run);
+        } else {
+            output.push_str(&f(run));
+        }
+        rest = &rest[end..];
+        if rest.is_empty() {
+            break;
+        }
+        let reference = rest.find(';').map_or(rest.len(), |i| i + 1);
+        output.push_str(&rest[..reference]);
+        rest = &rest[reference..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_text() {
+        let xml = r#"<?xml version="1.0"?>
+<!DOCTYPE feed [<!ENTITY x "y">]>
+<feed a="1 > 0">
+  <!-- keep -->
+  <title>Tom &amp; Jerry</title>
+  <summary><![CDATA[<p>raw</p>]]></summary>
+  <link>https://a.test/feed</link>
+</feed>"#;
+        assert_eq!(
+            map_text(xml, |text| text.to_uppercase()),
+            r#"<?xml version="1.0"?>
+<!DOCTYPE feed [<!ENTITY x "y">]>
+<feed a="1 > 0">
+  <!-- keep -->
+  <title>TOM &amp; JERRY</title>
+  <summary><![CDATA[<p>RAW</p>]]></summary>
+  <link>https://a.test/feed</link>
+</feed>"#
+        );
+        assert_eq!(
+            map_text("<a><![CDATA[x]]></a><![CDATA[y", |_| "]]>".to_owned()),
+            "<a><![CDATA[]]]]><![CDATA[>]]></a><![CDATA[]]]]><![CDATA[>"
+        );
+    }
+}